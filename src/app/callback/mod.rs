@@ -70,35 +70,16 @@ let signup = create_resource(cx, move || sign_up_schema.clone(), move |sign_up_s
                         { move || signup.read(cx).map(|val| {
                         view!{cx, <p class="text-center mx-2">
                             { match val {
-                                    Ok(FormValidation::Success {random_string}) => { 
-                                    database_connection_result_setter.set(String::from("Sending Verification Email..."));
-                                    let verification = create_server_action::<crate::server_function::routes::VerifyEmail>(cx);
-                                    verification.dispatch(crate::server_function::routes::VerifyEmail { first_name: 
-                                        info.first_name.get().unwrap().value(), email: info.email.get().unwrap().value(),
-                                        random_string: random_string.unwrap()} );
-
-                                    view!{cx,
-                                        <div>
-                                            <Suspense fallback = || ()>
-                                            {move ||
-                                                verification.value().get().map(|val| {
-                                                    view!{cx, {
-                                                        {
-                                                        loading_indicator_setter.set(String::from("hidden"));
-                                                        }
-                                                    {
-                                                        queue_microtask(move || use_navigate(cx)("/validate", NavigateOptions{
-                                                        replace: false,
-                                                        resolve: false,
-                                                        scroll: false,
-                                                        state: Default::default()}).unwrap());
-                                                    }
-                                                        val.unwrap()                                           
-                                                    }}
-                                            })}
-                                            </Suspense>
-                                        </div>
-                                    }
+                                    Ok(FormValidation::Success) => {
+                                    // The server already emailed the code
+                                    database_connection_result_setter.set(String::from("Verification email sent"));
+                                    loading_indicator_setter.set(String::from("hidden"));
+                                    queue_microtask(move || use_navigate(cx)("/validate", NavigateOptions{
+                                        replace: false,
+                                        resolve: false,
+                                        scroll: false,
+                                        state: Default::default()}).unwrap());
+                                    view!{cx, <div>"Verification email sent"</div>}
                                 },
                                      Ok(FormValidation::Error) => {
                                     loading_indicator_setter.set(String::from("hidden"));
@@ -121,6 +102,13 @@ let signup = create_resource(cx, move || sign_up_schema.clone(), move |sign_up_s
                                              <Icon icon=Icon::from(AiIcon::AiCloseCircleFilled) width="12px" height="12px" style="color: red"/>
                                             </div>
                                         }
+                                    },
+                                     Ok(FormValidation::Limited(limit)) => {
+                                        loading_indicator_setter.set(String::from("hidden"));
+                                        view!{cx, <div class="items-center">{limit.message()}
+                                             <Icon icon=Icon::from(AiIcon::AiCloseCircleFilled) width="12px" height="12px" style="color: red"/>
+                                            </div>
+                                        }
                                     },
                                      Err(_) => {
                                         loading_indicator_setter.set(String::from("hidden"));
@@ -173,15 +161,16 @@ let signup = create_resource(cx, move || sign_up_schema.clone(), move |sign_up_s
                                     use_context::<SignupContext>(cx).unwrap().status_setter.set(true);
                                     let navigate = use_navigate(cx);
                                     queue_microtask(move || navigate("/login", Default::default()).unwrap());
-                                    "Successful Signup"
+                                    "Successful Signup".to_string()
                                 },
-                            VerificationValidation::IncorrectValidationCode => "Incorrect Validation Code",
-                            VerificationValidation::EmailNotPresent => "Email is not present. Signup again",
-                            VerificationValidation::ServerError => "Server Error has occured. Try again later",
+                            VerificationValidation::IncorrectValidationCode => "Incorrect Validation Code".to_string(),
+                            VerificationValidation::EmailNotPresent => "Email is not present. Signup again".to_string(),
+                            VerificationValidation::ServerError => "Server Error has occured. Try again later".to_string(),
+                            VerificationValidation::Limited(limit) => limit.message(),
                         }
                     },
                     Err(_) => {
-                        "Server Error has occured. Try again later."
+                        "Server Error has occured. Try again later.".to_string()
                     }
                 }}
                 <Icon icon=Icon::from(AiIcon::AiCloseCircleFilled) width="16px" height="16px" style="color: red"/>
@@ -280,7 +269,14 @@ let signup = create_resource(cx, move || sign_up_schema.clone(), move |sign_up_s
                                 .await
                                 .unwrap()
                             {
-                                FormValidation::Success { random_string: _ } => {}
+                                FormValidation::Success => {}
+                                FormValidation::Limited(limit) => {
+                                    validator.set(view! {cx,
+                                              <div class="flex justify-center">
+                                                    <p class="text-center text-sm mx-1">{limit.message()}</p>
+                                              </div>
+                                    });
+                                }
                                 _ => {
                                     if toggle != AppState::Login {
                                         background_color_setter.set("border-red-500");
@@ -330,7 +326,14 @@ let signup = create_resource(cx, move || sign_up_schema.clone(), move |sign_up_s
                                 .await
                                 .unwrap()
                             {
-                                FormValidation::Success { random_string: _ } => {}
+                                FormValidation::Success => {}
+                                FormValidation::Limited(limit) => {
+                                    validator.set(view! {cx,
+                                              <div class="flex justify-center">
+                                                  <p class="text-center text-sm mx-1">{limit.message()}</p>
+                                              </div>
+                                              });
+                                }
                                 _ => {
                                     background_color_setter.set("border-red-500");
                                     label_background_color_setter.set("bg-red-500");
@@ -379,7 +382,12 @@ let signup = create_resource(cx, move || sign_up_schema.clone(), move |sign_up_s
                                                     </>}
                                             },
                                             VerifyPassword::IncorrectCredentials => view!{cx, <><p class="text-center">"Incorrect Credentials"</p></>},
-                                            VerifyPassword::ServerError => view!{cx, <><p class="text-center">"Server Error. Please Try again later."</p></>}
+                                            VerifyPassword::ServerError => view!{cx, <><p class="text-center">"Server Error. Please Try again later."</p></>},
+                                            VerifyPassword::Limited(limit) => view!{cx, <><p class="text-center">{limit.message()}</p></>},
+                                            VerifyPassword::TwoFactorRequired => {
+                                            queue_microtask(move || use_navigate(cx)("/login/two-factor", Default::default()).unwrap());
+                                                view!{cx, <><p class="text-center">"Enter your two factor code"</p></>}
+                                            }
                                         },
                                     Err(_) => view!{cx, <><p class="text-center">"Server Error. Please Try again later."</p></>}
                                     }
@@ -118,6 +118,12 @@ impl Banner {
             href: "/",
         };
 
+        let forgot_password_banner = BannerSchema {
+            banner_string: "Forgot your password?",
+            display_case: AppState::Login,
+            href: "/forgot-password",
+        };
+
         let signup_banner = BannerSchema {
             banner_string: "Already a member? Log In Instead",
             display_case: AppState::Signup,
@@ -130,6 +136,11 @@ impl Banner {
             href: "/validate",
         };
 
-        vec![login_banner, signup_banner, verification_banner]
+        vec![
+            login_banner,
+            forgot_password_banner,
+            signup_banner,
+            verification_banner,
+        ]
     }
 }
@@ -0,0 +1,578 @@
+//! Markdown-lite for message bodies: `**bold**`, `*italics*`/`_italics_`, `` `code` ``,
+//! fenced code blocks, `[text](https://link)`, bare links and `@mentions`.
+//!
+//! The parser is shared by the SSR and hydrate builds and only ever produces a tree of plain
+//! text nodes, rendering goes through `view!` so markup in a message is never interpreted
+//! as HTML. Every input parses, unmatched delimiters are kept as literal text.
+
+use leptos::*;
+
+/// Nesting deeper than this is rendered literally, keeps recursion bounded on hostile input
+const MAX_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Block {
+    Paragraph(Vec<Inline>),
+    Code {
+        language: Option<String>,
+        code: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inline {
+    Text(String),
+    Bold(Vec<Inline>),
+    Italic(Vec<Inline>),
+    Code(String),
+    Link { href: String, text: String },
+    /// The mentioned name or user id, without the leading `@`
+    Mention(String),
+}
+
+/// Parses a message body. `mentionable` holds the display names (`First Last`) that may
+/// follow an `@`, anything else after an `@` is taken up to the end of the word.
+pub fn parse(input: &str, mentionable: &[String]) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut rest = input;
+
+    while !rest.is_empty() {
+        let Some(fence) = rest.find("```") else {
+            push_paragraph(&mut blocks, rest, mentionable);
+            break;
+        };
+
+        let after_fence = &rest[fence + 3..];
+        let Some(close) = after_fence.find("```") else {
+            push_paragraph(&mut blocks, rest, mentionable);
+            break;
+        };
+
+        push_paragraph(&mut blocks, &rest[..fence], mentionable);
+
+        let inner = &after_fence[..close];
+        let (language, code) = match inner.split_once('\n') {
+            Some((language, code))
+                if !language.trim().is_empty()
+                    && language
+                        .trim()
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '#')) =>
+            {
+                (Some(language.trim().to_string()), code)
+            }
+            Some(("", code)) => (None, code),
+            _ => (None, inner),
+        };
+        blocks.push(Block::Code {
+            language,
+            code: code.trim_end_matches('\n').to_string(),
+        });
+
+        rest = after_fence[close + 3..].trim_start_matches('\n');
+    }
+
+    blocks
+}
+
+fn push_paragraph(blocks: &mut Vec<Block>, text: &str, mentionable: &[String]) {
+    let text = text.trim_matches('\n');
+    if !text.is_empty() {
+        blocks.push(Block::Paragraph(parse_inline(text, mentionable, 0)));
+    }
+}
+
+/// Collects every mention in a body, in order of appearance.
+pub fn mentions(blocks: &[Block]) -> Vec<String> {
+    fn walk(inlines: &[Inline], found: &mut Vec<String>) {
+        for inline in inlines {
+            match inline {
+                Inline::Mention(name) => found.push(name.clone()),
+                Inline::Bold(children) | Inline::Italic(children) => walk(children, found),
+                _ => (),
+            }
+        }
+    }
+
+    let mut found = Vec::new();
+    for block in blocks {
+        if let Block::Paragraph(inlines) = block {
+            walk(inlines, &mut found);
+        }
+    }
+    found
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Only links that cannot run script are turned into anchors.
+pub fn is_safe_href(href: &str) -> bool {
+    let lower = href.to_ascii_lowercase();
+    (lower.starts_with("https://") || lower.starts_with("http://") || lower.starts_with("mailto:"))
+        && !href.chars().any(|c| c.is_whitespace() || c.is_control() || matches!(c, '<' | '>' | '"'))
+}
+
+fn push_text(inlines: &mut Vec<Inline>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    match inlines.last_mut() {
+        Some(Inline::Text(existing)) => existing.push_str(text),
+        _ => inlines.push(Inline::Text(text.to_string())),
+    }
+}
+
+fn parse_inline(input: &str, mentionable: &[String], depth: usize) -> Vec<Inline> {
+    let mut inlines = Vec::new();
+    let mut index = 0;
+    let mut previous: Option<char> = None;
+
+    while let Some(c) = input[index..].chars().next() {
+        let rest = &input[index..];
+        let at_word_start = !previous.map_or(false, is_word);
+
+        if let Some((inline, consumed)) = match c {
+            '`' => inline_code(rest),
+            '*' if rest.starts_with("**") => {
+                delimited(rest, "**", mentionable, depth).map(|(children, consumed)| (Inline::Bold(children), consumed))
+            }
+            '*' | '_' if at_word_start => {
+                delimited(rest, &rest[..1], mentionable, depth)
+                    .map(|(children, consumed)| (Inline::Italic(children), consumed))
+            }
+            '[' => link(rest),
+            'h' if at_word_start && (rest.starts_with("https://") || rest.starts_with("http://")) => {
+                bare_link(rest)
+            }
+            '@' if at_word_start => mention(rest, mentionable),
+            _ => None,
+        } {
+            inlines.push(inline);
+            previous = input[..index + consumed].chars().last();
+            index += consumed;
+        } else {
+            push_text(&mut inlines, &rest[..c.len_utf8()]);
+            previous = Some(c);
+            index += c.len_utf8();
+        }
+    }
+
+    inlines
+}
+
+fn inline_code(rest: &str) -> Option<(Inline, usize)> {
+    let close = rest[1..].find('`')? + 1;
+    (close > 1).then(|| (Inline::Code(rest[1..close].to_string()), close + 1))
+}
+
+fn delimited(
+    rest: &str,
+    delimiter: &str,
+    mentionable: &[String],
+    depth: usize,
+) -> Option<(Vec<Inline>, usize)> {
+    if depth >= MAX_DEPTH {
+        return None;
+    }
+    let inner = &rest[delimiter.len()..];
+    // `**` must not close a single `*`, and the content cannot start or end with a space
+    let close = inner
+        .match_indices(delimiter)
+        .map(|(close, _)| close)
+        .find(|close| {
+            *close > 0
+                && !inner[*close + delimiter.len()..].starts_with(delimiter)
+                && !inner[..*close].ends_with(char::is_whitespace)
+        })?;
+    let content = &inner[..close];
+    if content.starts_with(char::is_whitespace) {
+        return None;
+    }
+    // `_` inside snake_case words is not emphasis
+    if delimiter == "_" && inner[close + 1..].chars().next().map_or(false, is_word) {
+        return None;
+    }
+
+    Some((
+        parse_inline(content, mentionable, depth + 1),
+        delimiter.len() * 2 + close,
+    ))
+}
+
+fn link(rest: &str) -> Option<(Inline, usize)> {
+    let text_end = rest.find("](")?;
+    let text = &rest[1..text_end];
+    if text.is_empty() || text.contains('\n') || text.contains('[') {
+        return None;
+    }
+    let href_start = text_end + 2;
+    let href_end = href_start + rest[href_start..].find(')')?;
+    let href = &rest[href_start..href_end];
+
+    is_safe_href(href).then(|| {
+        (
+            Inline::Link {
+                href: href.to_string(),
+                text: text.to_string(),
+            },
+            href_end + 1,
+        )
+    })
+}
+
+fn bare_link(rest: &str) -> Option<(Inline, usize)> {
+    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    let href = rest[..end].trim_end_matches(|c| {
+        matches!(c, '.' | ',' | '!' | '?' | ';' | ':' | ')' | ']' | '}' | '"' | '\'' | '*' | '_')
+    });
+
+    (href.len() > "https://".len() && is_safe_href(href)).then(|| {
+        (
+            Inline::Link {
+                href: href.to_string(),
+                text: href.to_string(),
+            },
+            href.len(),
+        )
+    })
+}
+
+fn mention(rest: &str, mentionable: &[String]) -> Option<(Inline, usize)> {
+    let after = &rest[1..];
+
+    // Longest known name first so "@Ann Lee" wins over "@Ann"
+    let known = mentionable
+        .iter()
+        .filter(|name| !name.is_empty())
+        .filter(|name| {
+            after
+                .get(..name.len())
+                .map_or(false, |candidate| candidate.eq_ignore_ascii_case(name))
+                && !after[name.len()..].chars().next().map_or(false, is_word)
+        })
+        .max_by_key(|name| name.len());
+    if let Some(name) = known {
+        return Some((Inline::Mention(name.clone()), name.len() + 1));
+    }
+
+    let end = after.find(|c: char| !is_word(c)).unwrap_or(after.len());
+    (end > 0).then(|| (Inline::Mention(after[..end].to_string()), end + 1))
+}
+
+fn render_inlines(cx: Scope, inlines: Vec<Inline>) -> View {
+    inlines
+        .into_iter()
+        .map(|inline| match inline {
+            Inline::Text(text) => text.into_view(cx),
+            Inline::Bold(children) => {
+                view! {cx, <strong class="font-semibold">{render_inlines(cx, children)}</strong>}.into_view(cx)
+            }
+            Inline::Italic(children) => {
+                view! {cx, <em>{render_inlines(cx, children)}</em>}.into_view(cx)
+            }
+            Inline::Code(code) => view! {cx,
+                <code class="rounded bg-black/10 px-1 font-mono text-[0.8rem]">{code}</code>
+            }
+            .into_view(cx),
+            Inline::Link { href, text } => view! {cx,
+                <a href=href target="_blank" rel="noopener noreferrer nofollow" class="underline break-all">{text}</a>
+            }
+            .into_view(cx),
+            Inline::Mention(name) => view! {cx,
+                <span class="font-semibold">{format!("@{name}")}</span>
+            }
+            .into_view(cx),
+        })
+        .collect_view(cx)
+}
+
+#[component]
+pub fn RichText(cx: Scope, body: String, #[prop(optional)] mentionable: Vec<String>) -> impl IntoView {
+    parse(&body, &mentionable)
+        .into_iter()
+        .map(|block| match block {
+            Block::Paragraph(inlines) => view! {cx,
+                <p class="whitespace-pre-wrap break-words">{render_inlines(cx, inlines)}</p>
+            }
+            .into_view(cx),
+            Block::Code { language, code } => view! {cx,
+                <pre class="my-1 overflow-x-auto rounded bg-black/10 p-2 font-mono text-xs" data-language=language>
+                    <code>{code}</code>
+                </pre>
+            }
+            .into_view(cx),
+        })
+        .collect_view(cx)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    fn text(value: &str) -> Inline {
+        Inline::Text(value.to_string())
+    }
+
+    fn paragraph(inlines: Vec<Inline>) -> Vec<Block> {
+        vec![Block::Paragraph(inlines)]
+    }
+
+    /// The text a reader sees once the body is rendered, markup removed.
+    fn plain_text(blocks: &[Block]) -> String {
+        fn walk(inlines: &[Inline], out: &mut String) {
+            for inline in inlines {
+                match inline {
+                    Inline::Text(text) | Inline::Code(text) => out.push_str(text),
+                    Inline::Bold(children) | Inline::Italic(children) => walk(children, out),
+                    Inline::Link { text, .. } => out.push_str(text),
+                    Inline::Mention(name) => {
+                        out.push('@');
+                        out.push_str(name);
+                    }
+                }
+            }
+        }
+
+        let mut out = String::new();
+        for block in blocks {
+            match block {
+                Block::Paragraph(inlines) => walk(inlines, &mut out),
+                Block::Code { code, .. } => out.push_str(code),
+            }
+        }
+        out
+    }
+
+    fn depth(inlines: &[Inline]) -> usize {
+        inlines
+            .iter()
+            .map(|inline| match inline {
+                Inline::Bold(children) | Inline::Italic(children) => 1 + depth(children),
+                _ => 0,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn cases() {
+        let mentionable = vec![String::from("Ann Lee"), String::from("Ann")];
+        let cases: Vec<(&str, Vec<Block>)> = vec![
+            // Unmatched delimiters stay literal
+            ("*open", paragraph(vec![text("*open")])),
+            ("**open", paragraph(vec![text("**open")])),
+            ("_open", paragraph(vec![text("_open")])),
+            ("closed*", paragraph(vec![text("closed*")])),
+            ("`open", paragraph(vec![text("`open")])),
+            ("``", paragraph(vec![text("``")])),
+            ("```open fence", paragraph(vec![text("```open fence")])),
+            ("* not italic *", paragraph(vec![text("* not italic *")])),
+            ("snake_case_name", paragraph(vec![text("snake_case_name")])),
+            (
+                "**bold _and italic_**",
+                paragraph(vec![Inline::Bold(vec![
+                    text("bold "),
+                    Inline::Italic(vec![text("and italic")]),
+                ])]),
+            ),
+            (
+                "`*not bold*`",
+                paragraph(vec![Inline::Code(String::from("*not bold*"))]),
+            ),
+            (
+                "```rust\nlet x = 1;\n```\nafter",
+                vec![
+                    Block::Code {
+                        language: Some(String::from("rust")),
+                        code: String::from("let x = 1;"),
+                    },
+                    Block::Paragraph(vec![text("after")]),
+                ],
+            ),
+            // Links that could run script are left as text
+            (
+                "[click](javascript:alert(1))",
+                paragraph(vec![text("[click](javascript:alert(1))")]),
+            ),
+            (
+                "[click](JavaScript:alert(1))",
+                paragraph(vec![text("[click](JavaScript:alert(1))")]),
+            ),
+            (
+                "[img](data:text/html;base64,PHNjcmlwdD4=)",
+                paragraph(vec![text("[img](data:text/html;base64,PHNjcmlwdD4=)")]),
+            ),
+            (
+                "[x](https://a.io/\"onmouseover=alert(1))",
+                paragraph(vec![text("[x](https://a.io/\"onmouseover=alert(1))")]),
+            ),
+            (
+                "javascript:alert(1)",
+                paragraph(vec![text("javascript:alert(1)")]),
+            ),
+            (
+                "[docs](https://zing.chat/docs)",
+                paragraph(vec![Inline::Link {
+                    href: String::from("https://zing.chat/docs"),
+                    text: String::from("docs"),
+                }]),
+            ),
+            (
+                "see https://zing.chat.",
+                paragraph(vec![
+                    text("see "),
+                    Inline::Link {
+                        href: String::from("https://zing.chat"),
+                        text: String::from("https://zing.chat"),
+                    },
+                    text("."),
+                ]),
+            ),
+            // Multibyte text around the delimiters and mentions
+            (
+                "héllo *wörld*",
+                paragraph(vec![text("héllo "), Inline::Italic(vec![text("wörld")])]),
+            ),
+            (
+                "**粗体**",
+                paragraph(vec![Inline::Bold(vec![text("粗体")])]),
+            ),
+            ("_é_", paragraph(vec![Inline::Italic(vec![text("é")])])),
+            ("x_é_", paragraph(vec![text("x_é_")])),
+            ("ü*ü*", paragraph(vec![text("ü*ü*")])),
+            ("你好@Ann", paragraph(vec![text("你好@Ann")])),
+            (
+                "🙂@José!",
+                paragraph(vec![
+                    text("🙂"),
+                    Inline::Mention(String::from("José")),
+                    text("!"),
+                ]),
+            ),
+            (
+                "@ann lee, hi",
+                paragraph(vec![Inline::Mention(String::from("Ann Lee")), text(", hi")]),
+            ),
+            (
+                "@Annabel",
+                paragraph(vec![Inline::Mention(String::from("Annabel"))]),
+            ),
+            ("@", paragraph(vec![text("@")])),
+            ("mail@zing.chat", paragraph(vec![text("mail@zing.chat")])),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(parse(input, &mentionable), expected, "{input:?}");
+        }
+    }
+
+    #[test]
+    fn mentions_in_order() {
+        let blocks = parse("@Ann and **@Bo** but not `@Cy`", &[String::from("Ann")]);
+        assert_eq!(
+            mentions(&blocks),
+            vec![String::from("Ann"), String::from("Bo")]
+        );
+    }
+
+    #[test]
+    fn nesting_is_bounded() {
+        assert_eq!(parse_inline("*deep*", &[], MAX_DEPTH), vec![text("*deep*")]);
+        assert_eq!(
+            parse_inline("*deep*", &[], MAX_DEPTH - 1),
+            vec![Inline::Italic(vec![text("deep")])]
+        );
+
+        let hostile = format!("{}x{}", "*_**".repeat(200), "**_*".repeat(200));
+        let blocks = parse(&hostile, &[]);
+        for block in &blocks {
+            if let Block::Paragraph(inlines) = block {
+                assert!(depth(inlines) <= MAX_DEPTH);
+            }
+        }
+    }
+
+    /// Whether `needle` can be read out of `haystack` by skipping characters.
+    fn is_subsequence(needle: &str, haystack: &str) -> bool {
+        let mut haystack = haystack.chars();
+        needle.chars().all(|c| haystack.any(|h| h == c))
+    }
+
+    fn random_body(rng: &mut StdRng, pieces: &[&str]) -> String {
+        (0..rng.gen_range(0..40))
+            .map(|_| pieces[rng.gen_range(0..pieces.len())])
+            .collect()
+    }
+
+    #[test]
+    fn random_input_never_panics_and_keeps_its_text() {
+        let pieces = [
+            "*",
+            "**",
+            "_",
+            "`",
+            "```",
+            "[",
+            "]",
+            "(",
+            ")",
+            "](",
+            "@",
+            "@Ann Lee",
+            "https://",
+            "javascript:",
+            "a",
+            "b",
+            " ",
+            "\n",
+            "é",
+            "你",
+            "🙂",
+            ".",
+            "x_y",
+        ];
+        let mentionable = vec![String::from("Ann Lee")];
+        let mut rng = StdRng::seed_from_u64(0x5A11);
+
+        for _ in 0..5_000 {
+            let body = random_body(&mut rng, &pieces);
+            let blocks = parse(&body, &mentionable);
+            let plain = plain_text(&blocks);
+            assert!(is_subsequence(&plain, &body), "{body:?} rendered {plain:?}");
+            for block in &blocks {
+                if let Block::Paragraph(inlines) = block {
+                    assert!(depth(inlines) <= MAX_DEPTH, "{body:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn plain_text_round_trips_without_markup() {
+        let pieces = [
+            "a",
+            "Zing",
+            " ",
+            "\n",
+            "é",
+            "你好",
+            "🙂",
+            ".",
+            ",",
+            "@bo",
+            "https://zing.chat",
+        ];
+        let mut rng = StdRng::seed_from_u64(0xC0DE);
+
+        for _ in 0..5_000 {
+            let body = random_body(&mut rng, &pieces);
+            let plain = plain_text(&parse(&body, &[]));
+            assert_eq!(plain, body.trim_matches('\n'), "{body:?}");
+            assert_eq!(plain_text(&parse(&plain, &[])), plain);
+        }
+    }
+}
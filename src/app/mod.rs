@@ -10,6 +10,7 @@ use validator::{Validate, ValidationError};
 use web_sys::SubmitEvent;
 mod callback;
 mod form_items;
+pub mod markup;
 pub mod pages;
 mod validation;
 
@@ -19,13 +20,15 @@ use crate::{
         form_items::{Banner, InputProps, InputValidation, ValidationGetter},
         pages::{
             conversation::{ConversationId, Conversations},
+            password_reset::{ForgotPassword, ResetPassword},
+            two_factor::TwoFactorLogin,
             Users, components::anciliary::EmptyState,
         },
     },
-    server_function::UserLogin,
+    server_function::{routes::resend_verification_email, UserLogin},
 };
 
-use self::pages::components::avatar::{ICONVEC, SINKVEC, STREAMVEC};
+use self::pages::components::avatar::{MEDIACACHE, SINKVEC, STREAMVEC};
 
 #[derive(Debug, Clone)]
 pub struct EmailContext {
@@ -59,6 +62,65 @@ pub struct SeenContextInner {
     pub last_message_id: i32,
 }
 
+#[derive(Debug, Clone)]
+pub struct MentionContext {
+    pub unread: RwSignal<Vec<crate::server_function::MentionEvent>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct UnreadContext {
+    pub counts: RwSignal<Vec<crate::server_function::UnreadCount>>,
+}
+
+impl UnreadContext {
+    pub fn unread(&self, conversation_id: i32) -> u64 {
+        self.counts.with(|counts| {
+            counts
+                .iter()
+                .find(|count| count.unread_conversation_id == conversation_id)
+                .map_or(0, |count| count.unread)
+        })
+    }
+
+    /// Muted conversations keep their own badge but stay out of the total.
+    pub fn total(&self) -> u64 {
+        self.counts.with(|counts| {
+            counts
+                .iter()
+                .filter(|count| !count.muted)
+                .map(|count| count.unread)
+                .sum()
+        })
+    }
+
+    pub fn clear(&self, conversation_id: i32) {
+        self.counts.update(|counts| {
+            if let Some(count) = counts
+                .iter_mut()
+                .find(|count| count.unread_conversation_id == conversation_id)
+            {
+                count.unread = 0
+            }
+        });
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PinContext {
+    pub pins: RwSignal<Vec<crate::server_function::PinnedMessage>>,
+}
+
+/// Messages the retention sweeper removed while the page was open.
+#[derive(Debug, Clone)]
+pub struct DeletedContext {
+    pub messages: RwSignal<Vec<i32>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReceiptContext {
+    pub receipts: RwSignal<Vec<crate::server_function::ReadReceipt>>,
+}
+
 #[derive(Debug, Clone)]
 pub struct IsOpen {
     pub status: RwSignal<bool>,
@@ -154,10 +216,11 @@ pub struct PhoneSchema {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum FormValidation {
-    Success { random_string: Option<String> },
+    Success,
     Error,
     EmailPresent,
     PhonePresent,
+    Limited(RateLimit),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -166,6 +229,14 @@ pub enum VerificationValidation {
     IncorrectValidationCode,
     EmailNotPresent,
     ServerError,
+    Limited(RateLimit),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ResendValidation {
+    Sent,
+    TooSoon { retry_after_seconds: i64 },
+    LimitReached,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -173,6 +244,52 @@ pub enum VerifyPassword {
     Success(UserLogin),
     IncorrectCredentials,
     ServerError,
+    Limited(RateLimit),
+    /// The password was right, the identity waits for `verify_two_factor`
+    TwoFactorRequired,
+}
+
+/// Why an authentication attempt was refused before it was checked.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimit {
+    /// Too many attempts from this address or for this account in a short time
+    TooManyAttempts { retry_after_seconds: i64 },
+    /// Locked after repeated wrong passwords or codes
+    LockedOut { retry_after_seconds: i64 },
+}
+
+impl RateLimit {
+    pub fn message(&self) -> String {
+        match self {
+            RateLimit::TooManyAttempts {
+                retry_after_seconds,
+            } => format!(
+                "Too many attempts, try again in {}",
+                wait_time(*retry_after_seconds)
+            ),
+            RateLimit::LockedOut {
+                retry_after_seconds,
+            } => format!(
+                "Locked after too many failed attempts, try again in {}",
+                wait_time(*retry_after_seconds)
+            ),
+        }
+    }
+}
+
+fn wait_time(seconds: i64) -> String {
+    match seconds {
+        ..=59 => format!("{} seconds", seconds.max(1)),
+        60..=3599 => format!("{} minutes", (seconds + 59) / 60),
+        _ => format!("{} hours", (seconds + 3599) / 3600),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ResetValidation {
+    Success,
+    InvalidToken,
+    WeakPassword,
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
@@ -223,6 +340,9 @@ pub fn App(cx: Scope) -> impl IntoView {
                     <Route path="/" view=|cx| view! { cx, <HomePage toggle=AppState::Signup/> } ssr = SsrMode::Async/>
                     <Route path="/login" view=|cx| view! { cx, <HomePage toggle=AppState::Login/> } ssr = SsrMode::Async/>
                     <Route path="/validate" view=|cx| view! { cx, <HomePage toggle=AppState::Validate/> } ssr = SsrMode::Async/>
+                    <Route path="/login/two-factor" view=|cx| view! { cx, <TwoFactorLogin/> } ssr = SsrMode::Async/>
+                    <Route path="/forgot-password" view=|cx| view! { cx, <ForgotPassword/> } ssr = SsrMode::Async/>
+                    <Route path="/reset-password" view=|cx| view! { cx, <ResetPassword/> } ssr = SsrMode::Async/>
                     <Route path="/user" view=|cx| view! { cx, <Users /> } ssr = SsrMode::Async/>
                     <Route path="/conversations" view=|cx| view! { cx, <Conversations/>  } ssr = SsrMode::Async>
                         <Route path="/" view=|cx| view! { cx,
@@ -246,7 +366,7 @@ pub fn App(cx: Scope) -> impl IntoView {
 #[component]
 fn HomePage(cx: Scope, toggle: AppState) -> impl IntoView {
     // Creates a reactive value to update the button
-    ICONVEC.write().clear();
+    MEDIACACHE.write().clear();
     STREAMVEC.write().clear();
     SINKVEC.write().clear();
 
@@ -481,6 +601,7 @@ fn EmailValidation(cx: Scope, toggle: AppState) -> impl IntoView {
                          "Input the verification code in the section below:"
                     </p>
                 </div>
+                <ResendCode/>
             </Show>
             { move || use_context::<SignupContext>(cx).unwrap().status.get().then(||
                         view! {cx,
@@ -497,6 +618,36 @@ fn EmailValidation(cx: Scope, toggle: AppState) -> impl IntoView {
     }
 }
 
+#[component]
+fn ResendCode(cx: Scope) -> impl IntoView {
+    let (status, status_setter) = create_signal(cx, String::new());
+
+    let on_click = move |_| {
+        let email = use_context::<EmailContext>(cx).unwrap().email.get_untracked();
+        spawn_local(async move {
+            status_setter(match resend_verification_email(cx, email).await {
+                Ok(ResendValidation::Sent) => String::from("A new email is on its way"),
+                Ok(ResendValidation::TooSoon {
+                    retry_after_seconds,
+                }) => format!("Wait {retry_after_seconds} seconds before resending"),
+                Ok(ResendValidation::LimitReached) => {
+                    String::from("Too many emails were sent. Signup again later")
+                }
+                Err(_) => String::from("Server error has occured. Please try again later"),
+            });
+        });
+    };
+
+    view! {cx,
+        <div class="flex flex-col items-center mx-6 text-sm">
+            <button type="button" class="text-white underline hover:text-amber-200" on:click=on_click>
+                "Didn't get the email? Send it again"
+            </button>
+            <p class="text-center">{status}</p>
+        </div>
+    }
+}
+
 #[component]
 fn LoadingStatus(
     cx: Scope,
@@ -4,10 +4,17 @@ use leptos_router::*;
 
 use crate::{
     app::{
-        pages::{conversation::ConversationParams, Avatar, SettingsModal, ICONVEC, SINKVEC},
-        IsOpen, SideBarContext,
+        pages::{
+            conversation::ConversationParams, websocket::HandleWebSocket, Avatar, SettingsModal,
+            MEDIACACHE, SINKVEC,
+        },
+        IsOpen, SideBarContext, UnreadContext,
+    },
+    server_function::{
+        self,
+        routes::{get_unread_counts, login_status},
+        UnreadCount, UserLogin,
     },
-    server_function::{self, routes::login_status, UserLogin},
 };
 
 use super::avatar::STREAMVEC;
@@ -26,6 +33,7 @@ pub struct SidebarIcon<'a> {
     icon: Icon,
     active: Box<dyn Fn(Scope) -> &'a str>,
     on_click: Option<Box<dyn std::ops::Fn(Scope)>>,
+    badge: Option<Box<dyn Fn(Scope) -> u64>>,
 }
 
 impl<'a> SidebarIcon<'a> {
@@ -41,6 +49,9 @@ impl<'a> SidebarIcon<'a> {
                     .map_or_else(|| "", |v| v)
             }),
             on_click: None,
+            badge: Some(Box::new(|cx| {
+                use_context::<UnreadContext>(cx).map_or(0, |unread| unread.total())
+            })),
         };
 
         let users = SidebarIcon {
@@ -53,6 +64,7 @@ impl<'a> SidebarIcon<'a> {
                     .map_or_else(|| "", |v| v)
             }),
             on_click: None,
+            badge: None,
         };
 
         let logout = SidebarIcon {
@@ -68,6 +80,7 @@ impl<'a> SidebarIcon<'a> {
                 );
                 // queue_microtask(move || use_navigate(cx)("/login", Default::default()).unwrap());
             })),
+            badge: None,
         };
 
         vec![chat, users, logout]
@@ -115,6 +128,12 @@ impl UserContexts {
                 status: create_rw_signal(cx, false),
             },
         );
+        provide_context(
+            cx,
+            UnreadContext {
+                counts: create_rw_signal(cx, Vec::new()),
+            },
+        );
     }
 }
 
@@ -178,6 +197,21 @@ fn DesktopSidebar(cx: Scope) -> impl IntoView {
                      user_context.first_name.set(first_name);
                      user_context.last_name.set(last_name);
 
+                     let unread_context = use_context::<UnreadContext>(cx).unwrap();
+                     spawn_local(async move {
+                         if let Ok(unread) = get_unread_counts(cx).await {
+                             unread_context.counts.set(unread.conversations);
+                         }
+                         HandleWebSocket::handle_split_stream::<Vec<UnreadCount>, UnreadCount>(
+                             cx,
+                             id,
+                             Some(unread_context.counts),
+                             "ws://localhost:8000/ws/icons/",
+                             |counts, unread: UnreadCount| UnreadCount::apply(counts.unwrap(), unread),
+                         )
+                         .await
+                     });
+
                      view!{cx,
                      <SettingsModal settings_modal_setter/>
                      <div class="hidden lg:fixed lg:inset-y-0 lg:left-0
@@ -238,7 +272,7 @@ fn MobileItem(cx: Scope, item: SidebarIcon<'static>) -> impl IntoView {
             on:click=move |_|
             if let Some(function) = &item.on_click {
                     function(cx);
-                    ICONVEC.write().clear();
+                    MEDIACACHE.write().clear();
                     STREAMVEC.write().clear();
                     SINKVEC::send_clear();
             }
@@ -256,16 +290,24 @@ fn DesktopItem(cx: Scope, item: SidebarIcon<'static>) -> impl IntoView {
          <A on:click=move |_|
             if let Some(function) = &item.on_click {
                     function(cx);
-                    ICONVEC.write().clear();
+                    MEDIACACHE.write().clear();
                     STREAMVEC.write().clear();
                     SINKVEC::send_clear();
             }
                 href=item.href
                   class=move || format!("group flex gap-x-3 rounded-md p-4 text-sm leading-6 font-semibold
                      text-gray-800 hover:text-black hover:bg-gray-100 {}", (item.active)(cx))>
-                  <Icon icon=item.icon class="h-6 w-6 shrink-0"
-                    style="fill: currentColor"
-                  />
+                  <span class="relative">
+                      <Icon icon=item.icon class="h-6 w-6 shrink-0"
+                        style="fill: currentColor"
+                      />
+                      {move || item.badge.as_ref().map(|badge| badge(cx)).filter(|unread| *unread > 0).map(|unread| view!{cx,
+                          <span class="absolute -top-2 -right-3 min-w-[20px] rounded-full bg-sky-500 px-1.5
+                              text-center text-xs font-semibold leading-5 text-white">
+                              {if unread > 99 { String::from("99+") } else { unread.to_string() }}
+                          </span>
+                      })}
+                  </span>
          </A>
     }
 }
@@ -1,8 +1,9 @@
-use crate::app::pages::{
-    components::anciliary::loading_fallback, HandleWebSocket, StreamData, SyncChannel, WsData,
+use crate::{
+    app::pages::{
+        components::anciliary::loading_fallback, HandleWebSocket, StreamData, SyncChannel, WsData,
+    },
+    server_function::routes::get_presence,
 };
-use crate::server_function::routes::get_icon;
-use base64::{engine::general_purpose, Engine};
 use lazy_static::lazy_static;
 use leptos::*;
 use leptos_icons::*;
@@ -10,13 +11,9 @@ use parking_lot::{RwLockReadGuard, RwLockWriteGuard};
 use std::{collections::HashMap, sync::Arc};
 
 lazy_static! {
+    /// Client side media cache keyed by the requested URL, e.g. `/icons/4` or `/upload/123.png`.
     #[derive(Debug)]
-    pub static ref ICONVEC: Arc<parking_lot::RwLock<Vec<UserIcon>>> = Arc::new(parking_lot::RwLock::new(Vec::new()));
-}
-
-lazy_static! {
-    #[derive(Debug)]
-    pub static ref IMAGEVEC: Arc<parking_lot::RwLock<Vec<UserImage>>> = Arc::new(parking_lot::RwLock::new(Vec::new()));
+    pub static ref MEDIACACHE: Arc<parking_lot::RwLock<HashMap<String, MediaSource>>> = Arc::new(parking_lot::RwLock::new(HashMap::new()));
 }
 
 pub type WsVecType = Arc<parking_lot::RwLock<HashMap<(WsData, i32), SyncChannel>>>;
@@ -65,7 +62,7 @@ impl SINKVEC {
             },
             None => {
                 let channel = match data {
-                    WsData::IconData => {
+                    WsData::IconData | WsData::UserData => {
                         let (tx, rx) = async_broadcast::broadcast::<StreamData>(100000);
                         SyncChannel::BroadCast(tx, rx)
                     }
@@ -103,7 +100,7 @@ impl STREAMVEC {
             }
             None => {
                 let channel = match data {
-                    WsData::IconData => {
+                    WsData::IconData | WsData::UserData => {
                         let (tx, rx) = async_broadcast::broadcast::<StreamData>(100000);
                         SyncChannel::BroadCast(tx, rx)
                     }
@@ -119,11 +116,11 @@ impl STREAMVEC {
     }
 }
 
-pub fn base_64_encode_uri(base64_encoded_image: String) -> String {
-    format!("data:image/png;base64, {}", base64_encoded_image)
+pub fn icon_url(id: i32) -> String {
+    format!("/icons/{id}")
 }
 
-impl ICONVEC {
+impl MEDIACACHE {
     fn icon_class(_cx: Scope, is_group: bool, sidebar: bool) -> String {
         match is_group {
             false => {
@@ -136,11 +133,22 @@ impl ICONVEC {
         }
     }
 
-    fn image_view(cx: Scope, image: &str, sidebar: bool, image_signal: RwSignal<Fragment>) {
+    fn image_view(
+        cx: Scope,
+        id: i32,
+        image: &str,
+        is_group: bool,
+        sidebar: bool,
+        image_signal: RwSignal<Fragment>,
+    ) {
         image_signal.set(view! {cx,
             <>
                 <img src=image.to_string() alt="Image"
                     class=move || if sidebar {"w-12 h-12 rounded-full"} else {""}
+                    on:error=move |_| {
+                        MEDIACACHE.write().insert(icon_url(id), MediaSource::Missing);
+                        Self::icon_view(cx, Icon::Bi(BiIcon::BiUserCircleSolid), is_group, sidebar, image_signal);
+                    }
                 />
             </>
         })
@@ -170,51 +178,50 @@ impl ICONVEC {
         message_string: Option<String>,
         image_signal: RwSignal<Fragment>,
     ) {
+        let url = icon_url(id);
+
         if let Some(message_string) = message_string {
-            Self::image_view(cx, &message_string, sidebar, image_signal);
-            let mut lock = ICONVEC.write();
+            MEDIACACHE
+                .write()
+                .insert(url, MediaSource::Available(message_string.clone()));
+            Self::image_view(cx, id, &message_string, is_group, sidebar, image_signal);
+            return;
+        }
 
-            if let Some(position) = lock.iter().position(|icon| icon.user_id == id) {
-                lock.remove(position);
-            };
+        let cached = MEDIACACHE.read().get(&url).cloned();
+        match cached {
+            Some(MediaSource::Available(image)) => {
+                Self::image_view(cx, id, &image, is_group, sidebar, image_signal)
+            }
+            Some(MediaSource::Missing) => Self::icon_view(
+                cx,
+                Icon::Bi(BiIcon::BiUserCircleSolid),
+                is_group,
+                sidebar,
+                image_signal,
+            ),
+            None => {
+                MEDIACACHE
+                    .write()
+                    .insert(url.clone(), MediaSource::Available(url.clone()));
+                Self::image_view(cx, id, &url, is_group, sidebar, image_signal)
+            }
+        }
+    }
 
-            lock.push(UserIcon {
-                user_id: id,
-                image: message_string,
-            });
-            
-        } else if let Some(image) = ICONVEC.read().iter().find(|item| item.user_id == id) {
-            Self::image_view(cx, image.image.as_str(), sidebar, image_signal);
-        } else {
-            let image =
-                create_local_resource(cx, || (), move |_| async move { get_icon(cx, id).await });
+    pub fn image_status(url: &str) -> Option<MediaSource> {
+        MEDIACACHE.read().get(url).cloned()
+    }
 
-            view! {
-                     cx,
-                    <>
-                     <Suspense fallback=loading_fallback(cx)>
-                         {move || {
-                            image.read(cx).map(|image| {
-                                 if let Some(image) = image.unwrap() {
-                                     let base64_encoded_image = general_purpose::STANDARD_NO_PAD.encode(image);
-                                     ICONVEC.write().push(UserIcon { user_id: id, image: base_64_encode_uri(base64_encoded_image.clone()) });
-                                     Self::image_view(cx, base_64_encode_uri(base64_encoded_image).as_str(), sidebar, image_signal);
-                                 } else {
-                                     Self::icon_view(cx, Icon::Bi(BiIcon::BiUserCircleSolid), is_group, sidebar, image_signal);
-                                 }
-                            })
-                        }}
-                    </Suspense>
-                    </>
-            };
-        }
+    pub fn set_image_status(url: String, status: MediaSource) {
+        MEDIACACHE.write().insert(url, status);
     }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub struct UserIcon {
-    pub user_id: i32,
-    pub image: String,
+pub enum MediaSource {
+    Available(String),
+    Missing,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
@@ -223,12 +230,6 @@ pub struct IconData {
     pub data: String,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct UserImage {
-    pub path: String,
-    pub image: String,
-}
-
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum IconType {
     String(String),
@@ -266,7 +267,7 @@ pub fn Avatar(cx: Scope, id: i32) -> impl IntoView {
             move |_signal, value: crate::app::pages::avatar::IconData| {
                 match value.user_id == id {
                     true => {
-                        ICONVEC::fetch_image(cx, id, false, false, Some(value.data), image_signal);
+                        MEDIACACHE::fetch_image(cx, id, false, false, Some(value.data), image_signal);
                     }
                     false => (),
                 };
@@ -278,7 +279,17 @@ pub fn Avatar(cx: Scope, id: i32) -> impl IntoView {
     let image = create_local_resource(
         cx,
         move || (),
-        move |_| async move { ICONVEC::fetch_image(cx, id, false, false, None, image_signal) },
+        move |_| async move { MEDIACACHE::fetch_image(cx, id, false, false, None, image_signal) },
+    );
+    let online = create_local_resource(
+        cx,
+        move || (),
+        move |_| async move {
+            get_presence(cx, vec![id])
+                .await
+                .map(|online| online.contains(&id))
+                .unwrap_or_default()
+        },
     );
 
     view! {cx,
@@ -288,9 +299,11 @@ pub fn Avatar(cx: Scope, id: i32) -> impl IntoView {
                 <Suspense fallback=loading_fallback(cx)>
                     {move || image.read(cx)}
                     {move || image_signal}
-                    <span class="absolute block rounded-full
-                    bg-green-500 ring-2 ring-white top-0
-                    right-0 h-2 w-2 md:h-3 md:w-3"/>
+                    {move || online.read(cx).unwrap_or_default().then(|| view! {cx,
+                        <span class="absolute block rounded-full
+                        bg-green-500 ring-2 ring-white top-0
+                        right-0 h-2 w-2 md:h-3 md:w-3"/>
+                    })}
                 </Suspense>
         </div>
     }
@@ -322,7 +335,7 @@ pub fn AvatarGroup(cx: Scope, user_ids: Vec<i32>) -> impl IntoView {
                          "ws://localhost:8000/ws/icons/",
                          move |_signal, value: crate::app::pages::avatar::IconData| {
                                 match value.user_id == user.1 {
-                                    true => {ICONVEC::fetch_image(cx, user.1, true, false, Some(value.data), image_signal);},
+                                    true => {MEDIACACHE::fetch_image(cx, user.1, true, false, Some(value.data), image_signal);},
                                     false => ()
                             };
                         }
@@ -333,7 +346,7 @@ pub fn AvatarGroup(cx: Scope, user_ids: Vec<i32>) -> impl IntoView {
                     let image = create_local_resource(cx, move || (),
                         move |_|
                         async move {
-                        ICONVEC::fetch_image(cx, user.1, true, false, None, image_signal)
+                        MEDIACACHE::fetch_image(cx, user.1, true, false, None, image_signal)
                     });
                           view! {
                             cx,
@@ -1,3 +1,4 @@
 pub mod modal;
 pub mod avatar;
 pub mod anciliary;
+pub mod recorder;
@@ -1,23 +1,27 @@
-use base64::engine::general_purpose;
-use base64::Engine;
 use leptos::html::{Div, Input};
 use leptos::{prelude::*, *};
 use leptos_icons::*;
 use leptos_router::{use_navigate, ActionForm};
+use validator::Validate;
 use web_sys::MouseEvent;
 
 use crate::app::pages::components::anciliary::{loading_fallback, Button, ButtonVal, UserInput};
 use crate::app::pages::conversation::get_current_id;
 use crate::app::{
-    pages::{components::avatar::base_64_encode_uri, websocket::HandleWebSocket, UserContext},
-    DrawerContext, MessageDrawerContext,
+    pages::{websocket::HandleWebSocket, UserContext},
+    DrawerContext, EmailSchema, MessageDrawerContext, PasswordSchema,
 };
 
 use crate::server_function::{
     routes::{
-        delete_conversations, get_users, login_status, upload_user_info, CreateGroupConversation,
+        begin_two_factor, change_password, confirm_email_change, delete_conversations,
+        disable_two_factor, enable_two_factor, get_notification_settings, get_two_factor_status,
+        get_user, get_users, list_sessions, login_status, regenerate_recovery_codes,
+        request_email_change, revoke_other_sessions, revoke_session, set_notification_settings,
+        set_read_receipts, upload_user_info, CreateGroupConversation,
     },
-    UserModel,
+    NotificationSettings, SessionInfo, TwoFactorEnrollment, TwoFactorStatus, UserModel,
+    UserQuery,
 };
 
 use super::avatar::{self, *};
@@ -189,14 +193,12 @@ pub fn SettingsModal(cx: Scope, settings_modal_setter: RwSignal<bool>) -> impl I
             if let Some(files) = image_ref.get_untracked().unwrap().files() {
                 let list = gloo_file::FileList::from(files);
                 if let Some(file) = list.first() {
-                    let file = Box::new(gloo_file::futures::read_as_bytes(file).await.unwrap());
-                    upload_user_info(cx, Some(*file.clone()), first_name_val, last_name_val)
-                        .await
-                        .unwrap();
+                    let file = gloo_file::futures::read_as_bytes(file).await.unwrap();
+                    let image_path =
+                        upload_user_info(cx, Some(file), first_name_val, last_name_val)
+                            .await
+                            .unwrap();
 
-                    let base64_encoded_image =
-                        general_purpose::STANDARD_NO_PAD.encode(*file.clone());
-                    let data_uri = base_64_encode_uri(base64_encoded_image);
                     let id = if let 0 = use_context::<UserContext>(cx).unwrap().id.get_untracked() {
                         login_status(cx).await.unwrap().id
                     } else {
@@ -206,7 +208,7 @@ pub fn SettingsModal(cx: Scope, settings_modal_setter: RwSignal<bool>) -> impl I
                     HandleWebSocket::handle_sink_stream(
                         avatar::IconData {
                             user_id: id,
-                            data: data_uri,
+                            data: image_path.unwrap_or_else(|| avatar::icon_url(id)),
                         },
                         id,
                     )
@@ -224,6 +226,50 @@ pub fn SettingsModal(cx: Scope, settings_modal_setter: RwSignal<bool>) -> impl I
     };
 
     let status = create_resource(cx, || (), move |_| async move { login_status(cx).await });
+
+    // Applied as soon as it is toggled, independent of "Save Changes"
+    let read_receipts = create_rw_signal(cx, true);
+    create_local_resource(
+        cx,
+        move || settings_modal_setter.get(),
+        move |_| async move {
+            if let Ok(user) = get_user(cx).await {
+                read_receipts.set(user.send_read_receipts);
+            }
+        },
+    );
+    let toggle_read_receipts = move |_| {
+        let enabled = !read_receipts.get_untracked();
+        read_receipts.set(enabled);
+        spawn_local(async move {
+            if set_read_receipts(cx, enabled).await.is_err() {
+                read_receipts.set(!enabled);
+            }
+        });
+    };
+
+    // Applied as soon as they are toggled, like read receipts
+    let notifications = create_rw_signal(cx, NotificationSettings::default());
+    create_local_resource(
+        cx,
+        move || settings_modal_setter.get(),
+        move |_| async move {
+            if let Ok(settings) = get_notification_settings(cx).await {
+                notifications.set(settings);
+            }
+        },
+    );
+    let update_notifications = move |update: fn(&mut NotificationSettings)| {
+        let previous = notifications.get_untracked();
+        notifications.update(update);
+        let settings = notifications.get_untracked();
+        spawn_local(async move {
+            if set_notification_settings(cx, settings).await.is_err() {
+                notifications.set(previous);
+            }
+        });
+    };
+
     view! {cx,
         <Modal context=settings_modal_setter>
             <form>
@@ -259,7 +305,33 @@ pub fn SettingsModal(cx: Scope, settings_modal_setter: RwSignal<bool>) -> impl I
                                 </div>
                             }})}
                         </Suspense>
+                        <div class="mt-10 flex items-center gap-x-3">
+                            <input id="read_receipts" type="checkbox" class="h-4 w-4 rounded border-gray-300 text-sky-600"
+                                prop:checked=move || read_receipts.get() on:change=toggle_read_receipts/>
+                            <label for="read_receipts" class="text-sm leading-6 text-gray-900">
+                                "Send read receipts"
+                            </label>
+                        </div>
+                        <div class="mt-4 flex items-center gap-x-3">
+                            <input id="email_missed_messages" type="checkbox" class="h-4 w-4 rounded border-gray-300 text-sky-600"
+                                prop:checked=move || notifications.get().missed_messages
+                                on:change=move |_| update_notifications(|settings| settings.missed_messages = !settings.missed_messages)/>
+                            <label for="email_missed_messages" class="text-sm leading-6 text-gray-900">
+                                "Email me about messages I missed"
+                            </label>
+                        </div>
+                        <div class="mt-4 flex items-center gap-x-3">
+                            <input id="email_digest" type="checkbox" class="h-4 w-4 rounded border-gray-300 text-sky-600"
+                                prop:checked=move || notifications.get().daily_digest
+                                on:change=move |_| update_notifications(|settings| settings.daily_digest = !settings.daily_digest)/>
+                            <label for="email_digest" class="text-sm leading-6 text-gray-900">
+                                "Email me a daily digest of unread conversations"
+                            </label>
+                        </div>
                     </div>
+                    <AccountSettings/>
+                    <TwoFactorSettings/>
+                    <SessionList/>
                     <div class="mt-6 flex items-center justify-end gap-x-6">
                          <Button on_click=clear_val button_type="button" disabled=ButtonVal::Bool(false) color="bg-sky-500 hover:bg-sky-600 focus-visible:outline-sky-600">
                           "Cancel"
@@ -274,6 +346,355 @@ pub fn SettingsModal(cx: Scope, settings_modal_setter: RwSignal<bool>) -> impl I
     }
 }
 
+/// Password and email changes, each applied by its own button rather than "Save Changes".
+#[component]
+fn AccountSettings(cx: Scope) -> impl IntoView {
+    let current_password_ref = create_node_ref::<Input>(cx);
+    let new_password_ref = create_node_ref::<Input>(cx);
+    let new_email_ref = create_node_ref::<Input>(cx);
+    let email_password_ref = create_node_ref::<Input>(cx);
+    let code_ref = create_node_ref::<Input>(cx);
+    let disabled = create_rw_signal(cx, false);
+    let code_pending = create_rw_signal(cx, false);
+    let password_status = create_rw_signal(cx, String::new());
+    let email_status = create_rw_signal(cx, String::new());
+
+    let on_change_password = move |_: MouseEvent| {
+        let current_password = current_password_ref.get_untracked().unwrap().value();
+        let new_password = PasswordSchema {
+            entry: new_password_ref.get_untracked().unwrap().value(),
+        };
+        if new_password.validate().is_err() {
+            password_status.set(String::from(
+                "Use 8 characters with an uppercase letter, a number and one of $!@*",
+            ));
+            return;
+        }
+
+        disabled.set(true);
+        spawn_local(async move {
+            match change_password(cx, current_password, new_password).await {
+                Ok(()) => {
+                    current_password_ref.get_untracked().unwrap().set_value("");
+                    new_password_ref.get_untracked().unwrap().set_value("");
+                    password_status.set(String::from("Password changed"));
+                }
+                Err(e) => password_status.set(e.to_string()),
+            }
+            disabled.set(false);
+        });
+    };
+
+    let on_request_email = move |_: MouseEvent| {
+        let current_password = email_password_ref.get_untracked().unwrap().value();
+        let new_email = EmailSchema {
+            entry: new_email_ref.get_untracked().unwrap().value(),
+        };
+
+        disabled.set(true);
+        spawn_local(async move {
+            match request_email_change(cx, current_password, new_email).await {
+                Ok(()) => {
+                    email_password_ref.get_untracked().unwrap().set_value("");
+                    code_pending.set(true);
+                    email_status.set(String::from("Enter the code sent to the new address"));
+                }
+                Err(e) => email_status.set(e.to_string()),
+            }
+            disabled.set(false);
+        });
+    };
+
+    let on_confirm_email = move |_: MouseEvent| {
+        let code = code_ref.get_untracked().unwrap().value();
+
+        disabled.set(true);
+        spawn_local(async move {
+            match confirm_email_change(cx, code).await {
+                Ok(email) => {
+                    use_context::<UserContext>(cx).unwrap().email.set(email.clone());
+                    new_email_ref.get_untracked().unwrap().set_value("");
+                    code_ref.get_untracked().unwrap().set_value("");
+                    code_pending.set(false);
+                    email_status.set(format!("Email changed to {email}"));
+                }
+                Err(e) => email_status.set(e.to_string()),
+            }
+            disabled.set(false);
+        });
+    };
+
+    view! {cx,
+        <div class="border-b border-gray-900/10 pb-12">
+            <p class="mt-1 text-sm leading-6 text-gray-600">
+                "Change your password."
+            </p>
+            <div class="mt-10 flex flex-col gap-y-8">
+                <UserInput id="current_password" _node_ref=current_password_ref input_type="password" label="Current Password" required=false disabled=ButtonVal::RwSignal(disabled) placeholder=String::new()/>
+                <UserInput id="new_password" _node_ref=new_password_ref input_type="password" label="New Password" required=false disabled=ButtonVal::RwSignal(disabled) placeholder=String::new()/>
+            </div>
+            <div class="mt-6 flex items-center justify-between gap-x-6">
+                <p class="text-sm text-gray-600">{move || password_status.get()}</p>
+                <Button on_click=on_change_password button_type="button" disabled=ButtonVal::RwSignal(disabled) color="bg-sky-500 hover:bg-sky-600 focus-visible:outline-sky-600">
+                    "Change Password"
+                </Button>
+            </div>
+            <p class="mt-10 text-sm leading-6 text-gray-600">
+                "Change your email, the new address has to be verified first."
+            </p>
+            <div class="mt-10 flex flex-col gap-y-8">
+                <UserInput id="new_email" _node_ref=new_email_ref input_type="email" label="New Email" required=false disabled=ButtonVal::RwSignal(disabled) placeholder=use_context::<UserContext>(cx).unwrap().email.get_untracked()/>
+                <UserInput id="email_password" _node_ref=email_password_ref input_type="password" label="Current Password" required=false disabled=ButtonVal::RwSignal(disabled) placeholder=String::new()/>
+                <div class=move || if code_pending.get() {"block"} else {"hidden"}>
+                    <UserInput id="email_code" _node_ref=code_ref input_type="text" label="Verification Code" required=false disabled=ButtonVal::RwSignal(disabled) placeholder=String::new()/>
+                </div>
+            </div>
+            <div class="mt-6 flex items-center justify-between gap-x-6">
+                <p class="text-sm text-gray-600">{move || email_status.get()}</p>
+                <div class="flex gap-x-3">
+                    <Button on_click=on_request_email button_type="button" disabled=ButtonVal::RwSignal(disabled) color="bg-sky-500 hover:bg-sky-600 focus-visible:outline-sky-600">
+                        "Send Code"
+                    </Button>
+                    <div class=move || if code_pending.get() {"block"} else {"hidden"}>
+                        <Button on_click=on_confirm_email button_type="button" disabled=ButtonVal::RwSignal(disabled) color="bg-sky-500 hover:bg-sky-600 focus-visible:outline-sky-600">
+                            "Confirm Email"
+                        </Button>
+                    </div>
+                </div>
+            </div>
+        </div>
+    }
+}
+
+/// TOTP enrollment and the recovery codes, see `server_function::two_factor`.
+#[component]
+fn TwoFactorSettings(cx: Scope) -> impl IntoView {
+    let code_ref = create_node_ref::<Input>(cx);
+    let password_ref = create_node_ref::<Input>(cx);
+    let disabled = create_rw_signal(cx, false);
+    let two_factor = create_rw_signal(cx, TwoFactorStatus::default());
+    let enrollment = create_rw_signal(cx, None::<TwoFactorEnrollment>);
+    let recovery_codes = create_rw_signal(cx, Vec::<String>::new());
+    let status = create_rw_signal(cx, String::new());
+
+    let reload = create_rw_signal(cx, 0);
+
+    create_local_resource(
+        cx,
+        move || reload.get(),
+        move |_| async move {
+            if let Ok(current) = get_two_factor_status(cx).await {
+                two_factor.set(current);
+            }
+        },
+    );
+    let refresh = move || reload.update(|count| *count += 1);
+
+    let take_code = move || {
+        let input = code_ref.get_untracked().unwrap();
+        let code = input.value();
+        input.set_value("");
+        code
+    };
+
+    let on_begin = move |_: MouseEvent| {
+        disabled.set(true);
+        spawn_local(async move {
+            match begin_two_factor(cx).await {
+                Ok(started) => {
+                    enrollment.set(Some(started));
+                    status.set(String::from(
+                        "Scan the code with your authenticator app and enter the code it shows",
+                    ));
+                }
+                Err(e) => status.set(e.to_string()),
+            }
+            disabled.set(false);
+        });
+    };
+
+    let on_enable = move |_: MouseEvent| {
+        let code = take_code();
+        disabled.set(true);
+        spawn_local(async move {
+            match enable_two_factor(cx, code).await {
+                Ok(codes) => {
+                    enrollment.set(None);
+                    recovery_codes.set(codes);
+                    status.set(String::from(
+                        "Two factor authentication is on. Keep these recovery codes somewhere safe, each works once",
+                    ));
+                    refresh();
+                }
+                Err(e) => status.set(e.to_string()),
+            }
+            disabled.set(false);
+        });
+    };
+
+    let on_regenerate = move |_: MouseEvent| {
+        let code = take_code();
+        disabled.set(true);
+        spawn_local(async move {
+            match regenerate_recovery_codes(cx, code).await {
+                Ok(codes) => {
+                    recovery_codes.set(codes);
+                    status.set(String::from("Your earlier recovery codes no longer work"));
+                    refresh();
+                }
+                Err(e) => status.set(e.to_string()),
+            }
+            disabled.set(false);
+        });
+    };
+
+    let on_disable = move |_: MouseEvent| {
+        let code = take_code();
+        let current_password = password_ref.get_untracked().unwrap().value();
+        disabled.set(true);
+        spawn_local(async move {
+            match disable_two_factor(cx, current_password, code).await {
+                Ok(()) => {
+                    password_ref.get_untracked().unwrap().set_value("");
+                    recovery_codes.set(Vec::new());
+                    status.set(String::from("Two factor authentication is off"));
+                    refresh();
+                }
+                Err(e) => status.set(e.to_string()),
+            }
+            disabled.set(false);
+        });
+    };
+
+    view! {cx,
+        <div class="border-b border-gray-900/10 pb-12">
+            <p class="mt-1 text-sm leading-6 text-gray-600">
+                {move || match two_factor.get() {
+                    TwoFactorStatus { enabled: true, recovery_codes_left } => format!(
+                        "Two factor authentication is on, {recovery_codes_left} recovery codes left."
+                    ),
+                    _ => String::from("Ask for a code from an authenticator app when logging in."),
+                }}
+            </p>
+            {move || enrollment.get().map(|started| view! {cx,
+                <div class="mt-6 flex flex-col items-center gap-y-3">
+                    <div inner_html=started.qr_svg></div>
+                    <p class="text-sm font-mono text-gray-600 break-all">{started.secret}</p>
+                </div>
+            })}
+            <div class=move || if two_factor.get().enabled || enrollment.get().is_some() {"mt-10 flex flex-col gap-y-8"} else {"hidden"}>
+                <UserInput id="two_factor_code" _node_ref=code_ref input_type="text" label="Code" required=false disabled=ButtonVal::RwSignal(disabled) placeholder=String::new()/>
+                <div class=move || if two_factor.get().enabled {"block"} else {"hidden"}>
+                    <UserInput id="two_factor_password" _node_ref=password_ref input_type="password" label="Current Password (to turn it off)" required=false disabled=ButtonVal::RwSignal(disabled) placeholder=String::new()/>
+                </div>
+            </div>
+            <ul class="mt-6 grid grid-cols-2 gap-2 text-sm font-mono text-gray-900">
+                {move || recovery_codes.get().into_iter().map(|code| view! {cx, <li>{code}</li>}).collect_view(cx)}
+            </ul>
+            <div class="mt-6 flex items-center justify-between gap-x-6">
+                <p class="text-sm text-gray-600">{move || status.get()}</p>
+                <div class="flex gap-x-3">
+                    <div class=move || if two_factor.get().enabled || enrollment.get().is_some() {"hidden"} else {"block"}>
+                        <Button on_click=on_begin button_type="button" disabled=ButtonVal::RwSignal(disabled) color="bg-sky-500 hover:bg-sky-600 focus-visible:outline-sky-600">
+                            "Set Up"
+                        </Button>
+                    </div>
+                    <div class=move || if enrollment.get().is_some() {"block"} else {"hidden"}>
+                        <Button on_click=on_enable button_type="button" disabled=ButtonVal::RwSignal(disabled) color="bg-sky-500 hover:bg-sky-600 focus-visible:outline-sky-600">
+                            "Turn On"
+                        </Button>
+                    </div>
+                    <div class=move || if two_factor.get().enabled {"flex gap-x-3"} else {"hidden"}>
+                        <Button on_click=on_regenerate button_type="button" disabled=ButtonVal::RwSignal(disabled) color="bg-sky-500 hover:bg-sky-600 focus-visible:outline-sky-600">
+                            "New Recovery Codes"
+                        </Button>
+                        <Button on_click=on_disable button_type="button" disabled=ButtonVal::RwSignal(disabled) color="bg-red-500 hover:bg-red-600 focus-visible:outline-red-600">
+                            "Turn Off"
+                        </Button>
+                    </div>
+                </div>
+            </div>
+        </div>
+    }
+}
+
+/// The user's logged in sessions, each can be logged out from here.
+#[component]
+fn SessionList(cx: Scope) -> impl IntoView {
+    let reload = create_rw_signal(cx, 0);
+    let status = create_rw_signal(cx, String::new());
+    let sessions = create_local_resource(
+        cx,
+        move || reload.get(),
+        move |_| async move { list_sessions(cx).await.unwrap_or_default() },
+    );
+
+    let on_revoke = move |session: SessionInfo| {
+        spawn_local(async move {
+            match revoke_session(cx, session.id).await {
+                // Revoking this session is a logout
+                Ok(()) if session.current => {
+                    use_navigate(cx)("/login", Default::default()).unwrap();
+                }
+                Ok(()) => reload.update(|count| *count += 1),
+                Err(e) => status.set(e.to_string()),
+            }
+        });
+    };
+
+    let on_revoke_others = move |_: MouseEvent| {
+        spawn_local(async move {
+            match revoke_other_sessions(cx).await {
+                Ok(()) => {
+                    status.set(String::from("Every other session was logged out"));
+                    reload.update(|count| *count += 1);
+                }
+                Err(e) => status.set(e.to_string()),
+            }
+        });
+    };
+
+    view! {cx,
+        <div class="border-b border-gray-900/10 pb-12">
+            <p class="mt-1 text-sm leading-6 text-gray-600">
+                "Your sessions."
+            </p>
+            <ul class="mt-6 divide-y divide-gray-100">
+                <Suspense fallback=loading_fallback(cx)>
+                    {move || sessions.read(cx).map(|sessions| sessions.into_iter().map(|session| {
+                        let last_seen = chrono::NaiveDateTime::from_timestamp_opt(session.last_seen_at, 0)
+                            .map(|time| time.format("%Y-%m-%d %H:%M UTC").to_string())
+                            .unwrap_or_default();
+                        let revoked = session.clone();
+                        view! {cx,
+                            <li class="flex items-center justify-between gap-x-6 py-3">
+                                <div class="text-sm">
+                                    <p class="font-semibold text-gray-900">
+                                        {session.device.clone()}
+                                        {session.current.then_some(" (this device)")}
+                                    </p>
+                                    <p class="text-gray-500">
+                                        {session.ip.clone().unwrap_or_default()} " · last active " {last_seen}
+                                    </p>
+                                </div>
+                                <Button on_click=move |_| on_revoke(revoked.clone()) button_type="button" disabled=ButtonVal::Bool(false) color="bg-red-500 hover:bg-red-600 focus-visible:outline-red-600">
+                                    "Log Out"
+                                </Button>
+                            </li>
+                        }
+                    }).collect_view(cx))}
+                </Suspense>
+            </ul>
+            <div class="mt-6 flex items-center justify-between gap-x-6">
+                <p class="text-sm text-gray-600">{move || status.get()}</p>
+                <Button on_click=on_revoke_others button_type="button" disabled=ButtonVal::Bool(false) color="bg-sky-500 hover:bg-sky-600 focus-visible:outline-sky-600">
+                    "Log Out Other Sessions"
+                </Button>
+            </div>
+        </div>
+    }
+}
+
 #[component]
 fn Select(
     cx: Scope,
@@ -287,7 +708,17 @@ fn Select(
     let users = create_local_resource(
         cx,
         move || hidden_state.get(),
-        move |_| async move { get_users(cx).await },
+        move |_| async move {
+            get_users(
+                cx,
+                UserQuery {
+                    limit: Some(UserQuery::MAX_LIMIT),
+                    ..Default::default()
+                },
+            )
+            .await
+            .map(|page| page.users)
+        },
     );
     view! {cx,
         <div class="z-[100]">
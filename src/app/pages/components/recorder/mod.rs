@@ -0,0 +1,101 @@
+use leptos::*;
+use leptos_icons::*;
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+
+/// Records a voice note with the browser's MediaRecorder. The container is whatever the
+/// browser prefers (webm on Chromium/Firefox, mp4 on Safari), the server validates it.
+#[component]
+pub fn VoiceRecorder<F>(cx: Scope, on_recorded: F) -> impl IntoView
+where
+    F: Fn(Vec<u8>) + Clone + 'static,
+{
+    let recording = create_rw_signal(cx, false);
+    let recorder = store_value::<Option<web_sys::MediaRecorder>>(cx, None);
+
+    let start = move || {
+        let on_recorded = on_recorded.clone();
+        spawn_local(async move {
+            match start_recording(on_recorded).await {
+                Ok(media_recorder) => {
+                    recorder.set_value(Some(media_recorder));
+                    recording.set(true);
+                }
+                Err(e) => log!("Unable to record audio: {:?}", e),
+            }
+        })
+    };
+
+    let stop = move || {
+        recorder.with_value(|media_recorder| {
+            if let Some(media_recorder) = media_recorder {
+                let _ = media_recorder.stop();
+            }
+        });
+        recorder.set_value(None);
+        recording.set(false);
+    };
+
+    view! {cx,
+        <button type="button"
+            on:click=move |_| if recording.get() { stop() } else { start() }
+            class=move || format!("rounded-full p-2 cursor-pointer transition {}",
+                if recording.get() {"bg-rose-500 hover:bg-rose-600 animate-pulse"} else {"hover:bg-neutral-100"})>
+            {move || if recording.get() {
+                view!{cx, <><Icon icon=Icon::from(BsIcon::BsStopFill) width="18px" class="text-white" style="fill: white"/></>}
+            } else {
+                view!{cx, <><Icon icon=Icon::from(BsIcon::BsMicFill) width="18px" class="text-sky-500" style="fill: currentColor"/></>}
+            }}
+        </button>
+    }
+}
+
+async fn start_recording<F>(on_recorded: F) -> Result<web_sys::MediaRecorder, JsValue>
+where
+    F: Fn(Vec<u8>) + Clone + 'static,
+{
+    let devices = window().navigator().media_devices()?;
+    let mut constraints = web_sys::MediaStreamConstraints::new();
+    constraints.audio(&JsValue::TRUE);
+
+    let stream: web_sys::MediaStream =
+        wasm_bindgen_futures::JsFuture::from(devices.get_user_media_with_constraints(&constraints)?)
+            .await?
+            .unchecked_into();
+    let media_recorder = web_sys::MediaRecorder::new_with_media_stream(&stream)?;
+    let chunks = js_sys::Array::new();
+
+    let chunks_clone = chunks.clone();
+    let on_data = Closure::<dyn FnMut(web_sys::BlobEvent)>::new(move |event: web_sys::BlobEvent| {
+        if let Some(blob) = event.data() {
+            chunks_clone.push(&blob);
+        }
+    });
+    media_recorder.set_ondataavailable(Some(on_data.as_ref().unchecked_ref()));
+    on_data.forget();
+
+    let mime_type = media_recorder.mime_type();
+    let on_stop = Closure::<dyn FnMut()>::new(move || {
+        // Release the microphone so the browser stops showing the recording indicator
+        stream
+            .get_tracks()
+            .for_each(&mut |track, _, _| track.unchecked_into::<web_sys::MediaStreamTrack>().stop());
+
+        let blob = web_sys::Blob::new_with_blob_sequence_and_options(
+            &chunks,
+            web_sys::BlobPropertyBag::new().type_(&mime_type),
+        );
+        let on_recorded = on_recorded.clone();
+        if let Ok(blob) = blob {
+            spawn_local(async move {
+                if let Ok(bytes) = gloo_file::futures::read_as_bytes(&gloo_file::Blob::from(blob)).await {
+                    on_recorded(bytes);
+                }
+            });
+        }
+    });
+    media_recorder.set_onstop(Some(on_stop.as_ref().unchecked_ref()));
+    on_stop.forget();
+
+    media_recorder.start()?;
+    Ok(media_recorder)
+}
@@ -1,25 +1,28 @@
 use leptos::*;
 use leptos_icons::*;
 use leptos_router::*;
-use std::borrow::Cow;
 use web_sys::SubmitEvent;
 
-use base64::{engine::general_purpose, Engine as _};
 use chrono::SubsecRound;
-use fancy_regex::Regex;
 
 use crate::{
     app::{
+        markup::RichText,
         pages::components::anciliary::{loading_fallback, EmptyState, Sidebar},
-        DrawerContext, IsOpen, MessageDrawerContext, SeenContext, SeenContextInner,
+        DeletedContext, DrawerContext, IsOpen, MentionContext, MessageDrawerContext, PinContext,
+        ReceiptContext, SeenContext, SeenContextInner, UnreadContext,
     },
     server_function::{
         self,
         routes::{
-            find_image, get_conversations, handle_seen, login_status, validate_conversation,
-            view_messages,
+            cancel_scheduled_message, edit_scheduled_message, find_image, get_contacts,
+            get_conversations, get_link_previews, get_pinned_messages, get_read_receipts,
+            get_scheduled_messages, handle_seen, login_status, pin_message, schedule_message,
+            set_conversation_flag, set_retention, unread_mentions, update_contact,
+            validate_conversation, view_messages,
         },
-        ConversationMeta, ImageAvailability, MergedConversation, MergedMessages, SeenMessageFacing,
+        ContactAction, ConversationFlag, ConversationMeta, ImageAvailability, LinkPreview, MentionEvent, MergedConversation,
+        MergedMessages, MessagesDeleted, PinUpdate, PinnedMessage, ReadReceipt, Retention,
         UserLogin,
     },
 };
@@ -28,9 +31,9 @@ use super::{
     avatar::*,
     components::{
         anciliary::UserContexts,
-        avatar::{ICONVEC, STREAMVEC},
+        avatar::{MediaSource, MEDIACACHE, STREAMVEC},
+        recorder::VoiceRecorder,
     },
-    get_image,
     modal::*,
     GroupChatModal, HandleWebSocket, UserContext, UserInputHandler,
 };
@@ -45,12 +48,18 @@ pub enum ImageEnum {
 pub struct Message {
     pub message: Option<String>,
     pub image: Option<String>,
+    #[serde(default)]
+    pub audio: Option<String>,
+    #[serde(default)]
+    pub audio_duration: Option<i32>,
     pub conversation_id: i32,
     pub user_id: i32,
     pub first_name: String,
     pub last_name: String,
-    pub seen: Option<Vec<(String, String)>>,
     pub message_id: i32,
+    /// Posted by the server, shown as a notice rather than a bubble
+    #[serde(default)]
+    pub system: bool,
 }
 impl UserContexts {
     fn init_conversation(cx: Scope) {
@@ -74,6 +83,34 @@ impl UserContexts {
                 status: create_rw_signal(cx, false),
             },
         );
+
+        provide_context(
+            cx,
+            MentionContext {
+                unread: create_rw_signal(cx, Vec::new()),
+            },
+        );
+
+        provide_context(
+            cx,
+            ReceiptContext {
+                receipts: create_rw_signal(cx, Vec::new()),
+            },
+        );
+
+        provide_context(
+            cx,
+            PinContext {
+                pins: create_rw_signal(cx, Vec::new()),
+            },
+        );
+
+        provide_context(
+            cx,
+            DeletedContext {
+                messages: create_rw_signal(cx, Vec::new()),
+            },
+        );
     }
 
     fn init_all(cx: Scope) {
@@ -104,6 +141,11 @@ fn format_created_at(created_at: String) -> String {
     time.format("%-I:%M %p").to_string()
 }
 
+fn format_duration(duration_ms: i32) -> String {
+    let seconds = (duration_ms + 500) / 1000;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
 #[derive(Params, PartialEq, Clone, Debug, Eq)]
 struct ConversationIdParams {
     id: i32,
@@ -116,7 +158,7 @@ pub fn Conversations(cx: Scope) -> impl IntoView {
     use_context::<IsOpen>(cx).unwrap().status.set(false);
 
     leptos::on_cleanup(cx, || {
-        ICONVEC.write().clear();
+        MEDIACACHE.write().clear();
         STREAMVEC.write().clear();
         SINKVEC::send_clear();
     });
@@ -142,19 +184,130 @@ pub fn Conversations(cx: Scope) -> impl IntoView {
 
 #[component]
 fn ConversationsLayout(cx: Scope, children: Children) -> impl IntoView {
+    let show_archived = create_rw_signal(cx, false);
+    let refresh = create_rw_signal(cx, 0_u32);
     let conversations = create_resource(
         cx,
         move || {
-            use_context::<MessageDrawerContext>(cx)
-                .unwrap()
-                .status
-                .get()
+            (
+                use_context::<MessageDrawerContext>(cx)
+                    .unwrap()
+                    .status
+                    .get(),
+                show_archived.get(),
+                refresh.get(),
+            )
         },
-        move |_| async move { get_conversations(cx).await },
+        move |(_, archived, _)| async move { get_conversations(cx, archived).await },
     );
 
+    // A message in an archived or brand new conversation puts it back in the list
+    let unread_context = use_context::<UnreadContext>(cx).unwrap();
+    create_effect(cx, move |previous: Option<Vec<i32>>| {
+        let listed: Vec<i32> = conversations
+            .read(cx)
+            .and_then(Result::ok)
+            .map(|conversations| conversations.iter().map(|item| item.conversation_id).collect())
+            .unwrap_or_default();
+        let missing: Vec<i32> = unread_context.counts.with(|counts| {
+            counts
+                .iter()
+                .filter(|count| count.unread > 0 && !listed.contains(&count.unread_conversation_id))
+                .map(|count| count.unread_conversation_id)
+                .collect()
+        });
+        if !show_archived.get_untracked()
+            && !missing.is_empty()
+            && previous.as_ref() != Some(&missing)
+        {
+            refresh.update(|refresh| *refresh += 1);
+        }
+        missing
+    });
+
     let group_chat_context = create_rw_signal(cx, false);
 
+    let mention_context = use_context::<MentionContext>(cx).unwrap();
+    spawn_local(async move {
+        if let Ok(mentions) = unread_mentions(cx).await {
+            mention_context.unread.set(mentions);
+        }
+        HandleWebSocket::handle_split_stream::<Vec<MentionEvent>, MentionEvent>(
+            cx,
+            use_context::<UserContext>(cx).unwrap().id.get_untracked(),
+            Some(mention_context.unread),
+            "ws://localhost:8000/ws/icons/",
+            move |unread, mention: MentionEvent| {
+                let unread = unread.unwrap();
+                if get_current_id(cx)() != mention.mention_conversation_id
+                    && !unread
+                        .iter()
+                        .any(|known| known.mention_message_id == mention.mention_message_id)
+                {
+                    unread.insert(0, mention);
+                }
+            },
+        )
+        .await
+    });
+
+    // Receipts share the socket with mentions, a watermark only ever moves forward
+    let receipt_context = use_context::<ReceiptContext>(cx).unwrap();
+    spawn_local(async move {
+        HandleWebSocket::handle_split_stream::<Vec<ReadReceipt>, ReadReceipt>(
+            cx,
+            use_context::<UserContext>(cx).unwrap().id.get_untracked(),
+            Some(receipt_context.receipts),
+            "ws://localhost:8000/ws/icons/",
+            move |receipts, receipt: ReadReceipt| {
+                let receipts = receipts.unwrap();
+                match receipts.iter_mut().find(|known| {
+                    known.receipt_conversation_id == receipt.receipt_conversation_id
+                        && known.reader_id == receipt.reader_id
+                }) {
+                    Some(known) => known.read_up_to = known.read_up_to.max(receipt.read_up_to),
+                    None => receipts.push(receipt),
+                }
+            },
+        )
+        .await
+    });
+
+    let pin_context = use_context::<PinContext>(cx).unwrap();
+    spawn_local(async move {
+        HandleWebSocket::handle_split_stream::<Vec<PinnedMessage>, PinUpdate>(
+            cx,
+            use_context::<UserContext>(cx).unwrap().id.get_untracked(),
+            Some(pin_context.pins),
+            "ws://localhost:8000/ws/icons/",
+            |pins, update: PinUpdate| update.apply(pins.unwrap()),
+        )
+        .await
+    });
+
+    // Swept messages leave the open conversation, and their pins go with them
+    let deleted_context = use_context::<DeletedContext>(cx).unwrap();
+    spawn_local(async move {
+        HandleWebSocket::handle_split_stream::<Vec<i32>, MessagesDeleted>(
+            cx,
+            use_context::<UserContext>(cx).unwrap().id.get_untracked(),
+            Some(deleted_context.messages),
+            "ws://localhost:8000/ws/icons/",
+            move |deleted, update: MessagesDeleted| {
+                pin_context.pins.update(|pins| {
+                    pins.retain(|pin| !update.deleted_message_ids.contains(&pin.message.message_id))
+                });
+                let deleted = deleted.unwrap();
+                for message_id in update.deleted_message_ids {
+                    if !deleted.contains(&message_id) {
+                        deleted.push(message_id);
+                    }
+                }
+            },
+        )
+        .await
+    });
+
     view! {cx,
         <Sidebar>
             <div class="h-screen">
@@ -169,11 +322,19 @@ fn ConversationsLayout(cx: Scope, children: Children) -> impl IntoView {
                                     <div class="px-5">
                                         <div class="flex justify-between mb-4 pt-4">
                                             <div class="text-2xl font-bold text-neutral-800">
-                                                "Messages"
+                                                {move || if show_archived.get() {"Archived"} else {"Messages"}}
                                             </div>
-                                            <div class="rounded-full p-2 bg-gray-100 text-gray-600
-                                            hover:opacity-75 transition cursor-pointer" on:click=move |_| group_chat_context.set(true)>
-                                                <Icon icon=Icon::from(AiIcon::AiUserAddOutlined) class="text-20 text-gray-500"/>
+                                            <div class="flex gap-2">
+                                                <div class=move || format!("rounded-full p-2 bg-gray-100 hover:opacity-75 transition cursor-pointer {}",
+                                                    if show_archived.get() {"text-sky-500"} else {"text-gray-500"})
+                                                    title=move || if show_archived.get() {"Back to messages"} else {"Archived conversations"}
+                                                    on:click=move |_| show_archived.update(|archived| *archived = !*archived)>
+                                                    <Icon icon=Icon::from(BsIcon::BsArchiveFill) class="text-20" style="fill: currentColor"/>
+                                                </div>
+                                                <div class="rounded-full p-2 bg-gray-100 text-gray-600
+                                                hover:opacity-75 transition cursor-pointer" on:click=move |_| group_chat_context.set(true)>
+                                                    <Icon icon=Icon::from(AiIcon::AiUserAddOutlined) class="text-20 text-gray-500"/>
+                                                </div>
                                             </div>
                                         </div>
                                      <For
@@ -181,7 +342,7 @@ fn ConversationsLayout(cx: Scope, children: Children) -> impl IntoView {
                                        key=|val| val.conversation_id
                                        view=move |cx, item: MergedConversation| {
                                         view! {cx,
-                                                     <ConversationBox item/>
+                                                     <ConversationBox item refresh/>
                                                }}/>
                                     </div>
                                 </aside>
@@ -196,18 +357,56 @@ fn ConversationsLayout(cx: Scope, children: Children) -> impl IntoView {
 }
 
 #[component]
-fn ConversationBox(cx: Scope, item: MergedConversation) -> impl IntoView {
-    let seen_status = create_rw_signal(cx, false);
+fn ConversationBox(cx: Scope, item: MergedConversation, refresh: RwSignal<u32>) -> impl IntoView {
+    let pinned = create_rw_signal(cx, item.conversation.pinned);
+    let archived = create_rw_signal(cx, item.conversation.archived);
+    let muted = create_rw_signal(cx, item.conversation.muted);
     let cloned_item = item.clone();
     let secondary_cloned_item = item.clone();
 
     let message_signal = create_rw_signal(cx, String::new());
 
+    let conversation_id = item.conversation_id;
+    let mention_count = move || {
+        use_context::<MentionContext>(cx)
+            .unwrap()
+            .unread
+            .get()
+            .iter()
+            .filter(|mention| mention.mention_conversation_id == conversation_id)
+            .count()
+    };
+    let unread_context = use_context::<UnreadContext>(cx).unwrap();
+    let unread = move || unread_context.unread(conversation_id);
+
+    // The buttons sit inside the link, they must not open the conversation
+    let toggle = move |event: web_sys::MouseEvent, flag: ConversationFlag, state: RwSignal<bool>| {
+        event.prevent_default();
+        event.stop_propagation();
+        let enabled = !state.get_untracked();
+        spawn_local(async move {
+            if set_conversation_flag(cx, conversation_id, flag, enabled).await.is_ok() {
+                state.set(enabled);
+                if flag != ConversationFlag::Muted {
+                    refresh.update(|refresh| *refresh += 1);
+                }
+            }
+        });
+    };
+    let flag_class = move |state: RwSignal<bool>| {
+        format!(
+            "p-1 rounded hover:bg-neutral-200 {}",
+            if state.get() { "text-sky-500" } else { "text-gray-400" }
+        )
+    };
+
     if let Some(message) = item.conversation.messages.last() {
         if let Some(message_body) = &message.message_body {
             message_signal.set(message_body.to_owned())
         } else if message.message_image.is_some() {
             message_signal.set(String::from("Sent an image"))
+        } else if message.message_audio.is_some() {
+            message_signal.set(String::from("Sent a voice note"))
         } else {
             message_signal.set(String::from("Started a conversation"))
         }
@@ -215,11 +414,6 @@ fn ConversationBox(cx: Scope, item: MergedConversation) -> impl IntoView {
         message_signal.set(String::from("Started a conversation"))
     };
 
-    if let Some(message) = cloned_item.conversation.messages.last() {
-        seen_status.set(message.seen_status.iter().any(|messages| {
-            messages.seen_id.unwrap() == use_context::<UserContext>(cx).unwrap().id.get()
-        }))
-    }
     let query = move || {
         use_location(cx)
             .pathname
@@ -237,9 +431,10 @@ fn ConversationBox(cx: Scope, item: MergedConversation) -> impl IntoView {
             Some(message_signal),
             "ws://localhost:8000/ws/",
             |signal, value: Message| {
-                match value.image {
-                    Some(_) => *signal.unwrap() = String::from("Image Sent in Chat"),
-                    None => *signal.unwrap() = value.message.unwrap(),
+                match (value.image, value.audio) {
+                    (Some(_), _) => *signal.unwrap() = String::from("Image Sent in Chat"),
+                    (None, Some(_)) => *signal.unwrap() = String::from("Voice Note Sent in Chat"),
+                    (None, None) => *signal.unwrap() = value.message.unwrap(),
                 };
             },
         )
@@ -248,7 +443,7 @@ fn ConversationBox(cx: Scope, item: MergedConversation) -> impl IntoView {
 
     view! {cx,
         <A href=format!("{}", &cloned_item.conversation_id.to_string())
-                class=move || format!("w-full relative flex items-center space-x-3 hover:bg-neutral-100 rounded-lg transition cursor-pointer p-3 {}",
+                class=move || format!("group w-full relative flex items-center space-x-3 hover:bg-neutral-100 rounded-lg transition cursor-pointer p-3 {}",
                 if query() {"bg-neutral-100"} else {"bg-white"})>
                 <Suspense fallback=||()>
                     {let cloned_item = cloned_item.clone();
@@ -263,18 +458,51 @@ fn ConversationBox(cx: Scope, item: MergedConversation) -> impl IntoView {
             <div class="min-w-0 flex-1">
                 <div class="focus:outline-none">
                     <div class="flex justify-between items-center mb-1">
-                        <p class="text-md font-medium font-bold text-gray-900">
+                        <p class="flex items-center gap-1 text-md font-medium font-bold text-gray-900">
                             {
                                 match secondary_cloned_item.conversation.is_group {
                                     true => secondary_cloned_item.conversation.name.unwrap(),
                                     false => secondary_cloned_item.conversation.first_name + " " + &secondary_cloned_item.conversation.last_name
                                 }
                             }
+                            {move || pinned.get().then(|| view!{cx,
+                                <Icon icon=Icon::from(BsIcon::BsPinAngleFill) class="h-3 w-3 text-gray-400" style="fill: currentColor"/>
+                            })}
+                            {move || muted.get().then(|| view!{cx,
+                                <Icon icon=Icon::from(BsIcon::BsBellSlashFill) class="h-3 w-3 text-gray-400" style="fill: currentColor"/>
+                            })}
                         </p>
-                        <p>
+                        <p class="flex items-center gap-1">
+                            <span class="hidden group-hover:flex items-center">
+                                <span class=move || flag_class(pinned) title=move || if pinned.get() {"Unpin"} else {"Pin"}
+                                    on:click=move |event| toggle(event, ConversationFlag::Pinned, pinned)>
+                                    <Icon icon=Icon::from(BsIcon::BsPinAngleFill) class="h-4 w-4" style="fill: currentColor"/>
+                                </span>
+                                <span class=move || flag_class(muted) title=move || if muted.get() {"Unmute"} else {"Mute"}
+                                    on:click=move |event| toggle(event, ConversationFlag::Muted, muted)>
+                                    <Icon icon=Icon::from(BsIcon::BsBellSlashFill) class="h-4 w-4" style="fill: currentColor"/>
+                                </span>
+                                <span class=move || flag_class(archived) title=move || if archived.get() {"Unarchive"} else {"Archive"}
+                                    on:click=move |event| toggle(event, ConversationFlag::Archived, archived)>
+                                    <Icon icon=Icon::from(BsIcon::BsArchiveFill) class="h-4 w-4" style="fill: currentColor"/>
+                                </span>
+                            </span>
+                            {move || (unread() > 0).then(|| view!{cx,
+                                <span class=move || format!("rounded-full px-2 py-0.5 text-xs font-semibold text-white {}",
+                                    if muted.get() {"bg-gray-400"} else {"bg-sky-500"})
+                                    title=format!("{} unread messages", unread())>
+                                    {if unread() > 99 { String::from("99+") } else { unread().to_string() }}
+                                </span>
+                            })}
+                            {move || (mention_count() > 0).then(|| view!{cx,
+                                <span class="rounded-full bg-sky-500 px-2 py-0.5 text-xs font-semibold text-white"
+                                    title=format!("{} unread mentions", mention_count())>
+                                    "@"
+                                </span>
+                            })}
                         </p>
                     </div>
-                <p class=move || format!("text-sm {}", if seen_status.get()
+                <p class=move || format!("text-sm {}", if unread() == 0
                         {"text-gray-500"} else {"text-black font-medium"})>
                                 {move || message_signal}
                 </p>
@@ -297,8 +525,26 @@ pub fn ConversationId(cx: Scope) -> impl IntoView {
     });
 
     create_effect(cx, move |_| {
+        let conversation_id = current_id();
+        use_context::<MentionContext>(cx)
+            .unwrap()
+            .unread
+            .update(|unread| unread.retain(|mention| mention.mention_conversation_id != conversation_id));
+        use_context::<UnreadContext>(cx).unwrap().clear(conversation_id);
         spawn_local(async move {
-            handle_seen(cx, current_id()).await.unwrap();
+            handle_seen(cx, conversation_id).await.unwrap();
+            if let Ok(receipts) = get_read_receipts(cx, conversation_id).await {
+                use_context::<ReceiptContext>(cx).unwrap().receipts.update(|known| {
+                    known.retain(|receipt| receipt.receipt_conversation_id != conversation_id);
+                    known.extend(receipts);
+                });
+            }
+            if let Ok(pins) = get_pinned_messages(cx, conversation_id).await {
+                use_context::<PinContext>(cx).unwrap().pins.update(|known| {
+                    known.retain(|pin| pin.message.message_conversation_id != conversation_id);
+                    known.extend(pins);
+                });
+            }
         })
     });
 
@@ -404,6 +650,7 @@ fn Body(cx: Scope, messages: Vec<MergedMessages>) -> impl IntoView {
     let messages_signal = create_rw_signal(cx, Vec::new());
 
     let seen_context = use_context::<SeenContext>(cx).unwrap().status;
+    let deleted = use_context::<DeletedContext>(cx).unwrap().messages;
     let id = get_current_id(cx)();
 
     let boxed_messages = Box::new(messages);
@@ -434,7 +681,6 @@ fn Body(cx: Scope, messages: Vec<MergedMessages>) -> impl IntoView {
         }
     };
 
-    let last_clone = last.clone();
     spawn_local(async move {
         HandleWebSocket::handle_split_stream::<Vec<MergedMessages>, Message>(
             cx,
@@ -443,31 +689,29 @@ fn Body(cx: Scope, messages: Vec<MergedMessages>) -> impl IntoView {
             "ws://localhost:8000/ws/",
             move |message_vec, value: Message| {
                 seen_context.update(|last| {
-                    last.iter_mut()
-                        .find(|context| context.conversation_id == id)
-                        .map(|context| context.last_message_id += 1)
-                        .unwrap_or_default()
+                    if let Some(context) = last.iter_mut().find(|context| context.conversation_id == id) {
+                        context.last_message_id = context.last_message_id.max(value.message_id)
+                    }
                 });
+                // The conversation is open, whatever arrives is read straight away
+                if value.user_id != use_context::<UserContext>(cx).unwrap().id.get_untracked() {
+                    spawn_local(async move {
+                        let _ = handle_seen(cx, id).await;
+                    });
+                }
                 message_vec.unwrap().push(MergedMessages {
                     first_name: value.first_name.clone(),
                     last_name: value.last_name.clone(),
                     created_at: chrono::Utc::now().trunc_subsecs(0).to_string(),
                     message_sender_id: value.user_id,
+                    message_system: value.system,
                     message_body: value.message.clone(),
                     message_image: value.image,
+                    message_audio: value.audio,
+                    message_audio_duration: value.audio_duration,
+                    link_previews: Vec::new(),
                     message_conversation_id: value.conversation_id,
-                    seen_status: value
-                        .seen
-                        .unwrap()
-                        .iter()
-                        .map(|(first_name, last_name)| SeenMessageFacing {
-                            seen_id: Some(value.user_id),
-                            message_id: None,
-                            first_name: Some(first_name.to_string()),
-                            last_name: Some(last_name.to_string()),
-                        })
-                        .collect(),
-                    message_id: last() + 1,
+                    message_id: value.message_id,
                 })
             },
         )
@@ -477,18 +721,26 @@ fn Body(cx: Scope, messages: Vec<MergedMessages>) -> impl IntoView {
     view! {cx,
             <div class="flex-1 overflow-y-auto ">
                  <For
-                      each=move || *messages.clone()
+                      each=move || deleted.with(|deleted| {
+                          messages.iter().filter(|message| !deleted.contains(&message.message_id)).cloned().collect::<Vec<_>>()
+                      })
                       key=|val| val.message_id
                       view=move |cx, item: MergedMessages| {
-                      view! { cx,
-                         <MessageBox message=item.clone()
-                              is_last=(last_clone() == item.message_id)
-                          />
+                      match item.message_system {
+                          true => view! { cx, <SystemNotice message=item/> }.into_view(cx),
+                          false => view! { cx,
+                             <MessageBox message=item.clone()
+                                  is_last=(last() == item.message_id)
+                              />
+                          }.into_view(cx),
                       }
                 }/>
                     {move ||
-                        messages_signal.get().iter().map(|item|
-                            view! {cx,
+                        messages_signal.get().iter()
+                        .filter(|item| !deleted.with(|deleted| deleted.contains(&item.message_id)))
+                        .map(|item| match item.message_system {
+                            true => view! {cx, <SystemNotice message=item.clone()/> }.into_view(cx),
+                            false => view! {cx,
                                 <MessageBox message=item.clone() is_last=(
                                     if let Some(messages) = messages_signal.get().last() {
                                         messages.message_id == item.message_id
@@ -496,8 +748,8 @@ fn Body(cx: Scope, messages: Vec<MergedMessages>) -> impl IntoView {
                                         false
                                     }
                                 )/>
-                            }
-                        ).collect_view(cx)
+                            }.into_view(cx),
+                        }).collect_view(cx)
                     }
              <div id="bottom_ref" class="pt-24"/>
         </div>
@@ -508,19 +760,44 @@ fn Body(cx: Scope, messages: Vec<MergedMessages>) -> impl IntoView {
 fn MessageForm(cx: Scope) -> impl IntoView {
     let _input_ref = create_node_ref::<html::Input>(cx);
     let image_ref = create_node_ref::<html::Input>(cx);
+    let schedule_ref = create_node_ref::<html::Input>(cx);
+    let editing = create_rw_signal::<Option<i32>>(cx, None);
+    let refresh = create_rw_signal(cx, 0u32);
 
     let on_submit_callback = move |event: SubmitEvent| {
         event.prevent_default();
         event.stop_propagation();
 
+        let deliver_at = schedule_ref.get_untracked().unwrap().value();
         spawn_local(async move {
-            UserInputHandler::handle_message(cx, image_ref, _input_ref, get_current_id(cx)()).await;
-            image_ref.get_untracked().unwrap().set_value("");
-            _input_ref.get_untracked().unwrap().set_value("");
+            if deliver_at.is_empty() {
+                UserInputHandler::handle_message(cx, image_ref, _input_ref, get_current_id(cx)()).await;
+                image_ref.get_untracked().unwrap().set_value("");
+                _input_ref.get_untracked().unwrap().set_value("");
+                return;
+            }
+
+            // datetime-local has no zone, the browser reads it as local time
+            let deliver_at = js_sys::Date::new(&deliver_at.into()).get_time() as i64;
+            let body = _input_ref.get_untracked().unwrap().value();
+            let result = match editing.get_untracked() {
+                Some(id) => edit_scheduled_message(cx, id, body, deliver_at).await,
+                None => schedule_message(cx, get_current_id(cx)(), body, deliver_at).await,
+            };
+            match result {
+                Ok(_) => {
+                    editing.set(None);
+                    schedule_ref.get_untracked().unwrap().set_value("");
+                    _input_ref.get_untracked().unwrap().set_value("");
+                    refresh.update(|count| *count += 1);
+                }
+                Err(e) => log!("Scheduling rejected: {}", e),
+            }
         });
     };
 
     view! {cx,
+         <ScheduledMessages refresh editing input_ref=_input_ref schedule_ref/>
          <form on:submit=on_submit_callback class="py-4 px-4 bg-white border-t flex items-center gap-2 lg:gap-4 w-full ">
              <label for="submission">
                      <Icon icon=Icon::from(TbIcon::TbPhotoFilled) class="text-sky-500"
@@ -530,6 +807,11 @@ fn MessageForm(cx: Scope) -> impl IntoView {
              <div class="flex items-center gap-2 lg:gap-4 w-full">
                  <MessageInput _input_ref/>
              </div>
+             <input type="datetime-local" node_ref=schedule_ref title="Send later"
+                 class="text-sm text-neutral-500 bg-neutral-100 rounded-full py-2 px-3 focus:outline-none"/>
+             <VoiceRecorder on_recorded=move |audio| spawn_local(async move {
+                 UserInputHandler::handle_voice_note(cx, audio, get_current_id(cx)()).await;
+             })/>
              <button type="submit" class="rounded-full p-2 bg-sky-500 cursor-pointer hover:bg-sky-600 transition">
                  <Icon icon=Icon::from(HiIcon::HiPaperAirplaneOutlineLg) width="18px" class="text-white" style="stroke: white; fill: white"/>
              </button>
@@ -537,6 +819,93 @@ fn MessageForm(cx: Scope) -> impl IntoView {
     }
 }
 
+/// The value a `datetime-local` input expects for the given time, in the browser's zone.
+fn datetime_local_value(millis: i64) -> String {
+    let date = js_sys::Date::new(&(millis as f64).into());
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}",
+        date.get_full_year(),
+        date.get_month() + 1,
+        date.get_date(),
+        date.get_hours(),
+        date.get_minutes()
+    )
+}
+
+#[component]
+fn ScheduledMessages(
+    cx: Scope,
+    refresh: RwSignal<u32>,
+    editing: RwSignal<Option<i32>>,
+    input_ref: NodeRef<html::Input>,
+    schedule_ref: NodeRef<html::Input>,
+) -> impl IntoView {
+    let current_id = get_current_id(cx);
+    let scheduled = create_resource(
+        cx,
+        move || (current_id(), refresh.get()),
+        move |(conversation_id, _)| async move {
+            get_scheduled_messages(cx, conversation_id)
+                .await
+                .unwrap_or_default()
+        },
+    );
+
+    // Delivered messages leave the list on the next fetch after the scheduler picked them up
+    create_effect(cx, move |_| {
+        if let Some(next) = scheduled.read(cx).and_then(|pending| pending.first().cloned()) {
+            let delay = (next.deliver_at as f64 - js_sys::Date::now()).max(0.0) as u64;
+            set_timeout(
+                move || refresh.update(|count| *count += 1),
+                std::time::Duration::from_millis(delay + 15_000),
+            );
+        }
+    });
+
+    view! {cx,
+        <Transition fallback=||()>
+            {move || scheduled.read(cx).filter(|pending| !pending.is_empty()).map(|pending| view!{cx,
+                <div class="bg-white border-t px-4 py-2 flex flex-col gap-1 max-h-40 overflow-y-auto">
+                    <div class="text-xs font-semibold text-neutral-500">"Scheduled"</div>
+                    {pending.into_iter().map(|item| {
+                        let body = item.body.clone();
+                        let deliver_at = item.deliver_at;
+                        view!{cx,
+                            <div class=move || format!("flex items-center justify-between gap-2 text-sm rounded {}",
+                                if editing.get() == Some(item.id) { "bg-sky-50" } else { "" })>
+                                <div class="truncate">
+                                    <span class="text-neutral-500 mr-2">
+                                        {String::from(js_sys::Date::new(&(deliver_at as f64).into())
+                                            .to_locale_string("default", &wasm_bindgen::JsValue::UNDEFINED))}
+                                    </span>
+                                    {item.body}
+                                </div>
+                                <div class="flex gap-2 shrink-0">
+                                    <button type="button" class="text-sky-500 hover:text-sky-600" on:click=move |_| {
+                                        input_ref.get_untracked().unwrap().set_value(&body);
+                                        schedule_ref.get_untracked().unwrap().set_value(&datetime_local_value(deliver_at));
+                                        editing.set(Some(item.id));
+                                    }>"Edit"</button>
+                                    <button type="button" class="text-rose-500 hover:text-rose-600" on:click=move |_| {
+                                        spawn_local(async move {
+                                            if cancel_scheduled_message(cx, item.id).await.is_ok() {
+                                                if editing.get_untracked() == Some(item.id) {
+                                                    editing.set(None);
+                                                }
+                                                refresh.update(|count| *count += 1);
+                                            }
+                                        })
+                                    }>"Cancel"</button>
+                                </div>
+                            </div>
+                        }
+                    }).collect_view(cx)}
+                </div>
+            })}
+        </Transition>
+    }
+}
+
 #[component]
 fn MessageInput(cx: Scope, _input_ref: NodeRef<html::Input>) -> impl IntoView {
     view! {cx,
@@ -548,6 +917,18 @@ fn MessageInput(cx: Scope, _input_ref: NodeRef<html::Input>) -> impl IntoView {
     }
 }
 
+/// Messages the server posts itself, e.g. when the retention changes.
+#[component]
+fn SystemNotice(cx: Scope, message: MergedMessages) -> impl IntoView {
+    view! {cx,
+        <div class="flex justify-center p-4">
+            <div class="text-xs text-gray-500 bg-gray-100 rounded-full py-1 px-3">
+                {message.message_body.unwrap_or_default()}
+            </div>
+        </div>
+    }
+}
+
 #[component]
 fn MessageBox(cx: Scope, message: MergedMessages, is_last: bool) -> impl IntoView {
     let is_own =
@@ -555,21 +936,31 @@ fn MessageBox(cx: Scope, message: MergedMessages, is_last: bool) -> impl IntoVie
 
     let image_modal_context = create_rw_signal(cx, false);
 
-    let regex = Regex::new(&format!(
-        r"^(.*?){}(.*?){}(.*)$",
-        message.first_name, message.last_name
-    ))
-    .unwrap();
-
-    let seen_list: Cow<str> = message
-        .seen_status
-        .into_iter()
-        .map(|messages| messages.first_name.unwrap() + " " + &messages.last_name.unwrap() + " ")
-        .collect();
-
-    let seen_list = match seen_list {
-        Cow::Borrowed(s) => regex.replace(s, "$1$3").into_owned(),
-        Cow::Owned(s) => regex.replace(&s, "$1$3").into_owned(),
+    let receipts = use_context::<ReceiptContext>(cx).unwrap().receipts;
+    let (conversation_id, message_id, sender_id) = (
+        message.message_conversation_id,
+        message.message_id,
+        message.message_sender_id,
+    );
+    let seen_by = move || {
+        receipts.with(|receipts| {
+            ReadReceipt::seen_by(receipts, conversation_id, message_id, sender_id)
+                .map(|receipt| format!("{} {}", receipt.reader_first_name, receipt.reader_last_name))
+                .collect::<Vec<_>>()
+        })
+    };
+
+    // The change comes back over the websocket like everyone else's
+    let pins = use_context::<PinContext>(cx).unwrap().pins;
+    let is_pinned =
+        move || pins.with(|pins| pins.iter().any(|pin| pin.message.message_id == message_id));
+    let toggle_pin = move |_| {
+        let pinned = !is_pinned();
+        spawn_local(async move {
+            if let Err(e) = pin_message(cx, message_id, pinned).await {
+                log!("Pin rejected: {}", e);
+            }
+        });
     };
 
     let message_image = message.message_image.clone();
@@ -578,13 +969,10 @@ fn MessageBox(cx: Scope, message: MergedMessages, is_last: bool) -> impl IntoVie
         move || message_image.clone(),
         move |message_image| async move {
             if let Some(image) = message_image {
-                match IMAGEVEC
-                    .read()
-                    .iter()
-                    .any(|image_vec| image_vec.path == image.clone())
-                {
-                    true => ImageEnum::Some(Ok(ImageAvailability::Found)),
-                    false => ImageEnum::Some(find_image(cx, image).await),
+                match MEDIACACHE::image_status(&image) {
+                    Some(MediaSource::Available(_)) => ImageEnum::Some(Ok(ImageAvailability::Found)),
+                    Some(MediaSource::Missing) => ImageEnum::Some(Ok(ImageAvailability::Missing)),
+                    None => ImageEnum::Some(find_image(cx, image).await),
                 }
             } else {
                 ImageEnum::None
@@ -594,15 +982,45 @@ fn MessageBox(cx: Scope, message: MergedMessages, is_last: bool) -> impl IntoVie
 
     let image_signal = create_rw_signal(cx, view! {cx, <><div/></>});
 
+    // Messages pushed over the websocket arrive before their previews have been cached
+    let preview_body = message.message_body.clone();
+    let initial_previews = message.link_previews.clone();
+    let link_previews = create_resource(
+        cx,
+        || (),
+        move |_| {
+            let body = preview_body.clone();
+            let initial_previews = initial_previews.clone();
+            async move {
+                match body {
+                    Some(body)
+                        if initial_previews.is_empty()
+                            && !LinkPreview::extract_urls(&body).is_empty() =>
+                    {
+                        get_link_previews(cx, message_id).await.unwrap_or_default()
+                    }
+                    _ => initial_previews,
+                }
+            }
+        },
+    );
+
     let message_class = format!(
         "text-sm w-fit overflow-hidden {} {}",
-        if is_own() && message.message_image.is_none() {
+        if is_own() && message.message_image.is_none() && message.message_audio.is_none() {
             "bg-sky-500 text-white"
         } else {
             "bg-gray-100"
         },
         if message.message_image.is_some() {
             "rounded-md p-0 "
+        } else if message.message_audio.is_some()
+            || message
+                .message_body
+                .as_ref()
+                .map_or(false, |body| body.contains('\n'))
+        {
+            "rounded-2xl py-2 px-3"
         } else {
             "rounded-full py-2 px-3"
         }
@@ -623,6 +1041,10 @@ fn MessageBox(cx: Scope, message: MergedMessages, is_last: bool) -> impl IntoVie
                             format_created_at(message.created_at)
                         }
                     </div>
+                    <span class=move || format!("cursor-pointer {}", if is_pinned() {"text-sky-500"} else {"text-gray-300 hover:text-gray-500"})
+                        title=move || if is_pinned() {"Unpin"} else {"Pin"} on:click=toggle_pin>
+                        <Icon icon=Icon::from(BsIcon::BsPinAngleFill) class="h-3 w-3" style="fill: currentColor"/>
+                    </span>
                 </div>
                 <div class=message_class>
                         {
@@ -635,46 +1057,18 @@ fn MessageBox(cx: Scope, message: MergedMessages, is_last: bool) -> impl IntoVie
                                     let image = image.clone();
                                      match status {
                                              ImageEnum::Some(Ok(ImageAvailability::Found)) => {
-                                                match IMAGEVEC.read().iter().find(|&image_vec| image_vec.path == image.clone()) {
-                                                    Some(image) => {
-                                                        image_signal.set(
-                                                            view!{cx,
-                                                                   <>
-                                                                       <ImageModal src=image.image.clone() context=image_modal_context/>
-                                                                       <img on:click=move |_| image_modal_context.set(true) alt="Image"
-                                                                        src=image.image.clone() class="object-cover cursor-pointer hover:scale-110
-                                                                        transition translate w-auto max-w-[288px] max-h-[288px]"/>
-                                                                   </>
-                                                            })
-                                                    }
-                                                    None => {
-                                                            let cloned_image = image.clone();
-                                                            let retrieved_vec = create_resource(cx, move || cloned_image.clone(), move |image| async move {get_image(cx, image.clone()).await.unwrap()});
-                                                            image_signal.set(
-                                                                    view!{cx,
-                                                                        <>
-                                                                             <Suspense fallback=loading_fallback(cx)>
-                                                                                 {let image = image.clone();
-                                                                                 move || retrieved_vec.read(cx).map(|vec_u8| {
-                                                                                     let base64_encoded_image = general_purpose::STANDARD_NO_PAD.encode(vec_u8.unwrap());
-                                                                                     let data_uri = base_64_encode_uri(base64_encoded_image);
-                                                                                     IMAGEVEC.write().push(UserImage {
-                                                                                         path: image.clone(),
-                                                                                         image: data_uri.clone()
-                                                                                     });
-                                                                                     view!{cx,
-                                                                                         <ImageModal src=data_uri.clone() context=image_modal_context/>
-                                                                                         <img on:click=move |_| image_modal_context.set(true) alt="Image"
-                                                                                          src=data_uri.clone() class="object-cover cursor-pointer hover:scale-110
-                                                                                          transition translate w-auto max-w-[288px] max-h-[288px]"/>
-                                                                                     }
-                                                                                 })}
-                                                                             </Suspense>
-                                                                        </>
-                                                                    }
-                                                        )
-                                                        }
-                                                    }
+                                                MEDIACACHE::set_image_status(image.clone(), MediaSource::Available(image.clone()));
+                                                let failed_image = image.clone();
+                                                image_signal.set(
+                                                    view!{cx,
+                                                           <>
+                                                               <ImageModal src=image.clone() context=image_modal_context/>
+                                                               <img on:click=move |_| image_modal_context.set(true) alt="Image"
+                                                                on:error=move |_| MEDIACACHE::set_image_status(failed_image.clone(), MediaSource::Missing)
+                                                                src=image.clone() class="object-cover cursor-pointer hover:scale-110
+                                                                transition translate w-auto max-w-[288px] max-h-[288px]"/>
+                                                           </>
+                                                    });
                                                   view!{cx,
                                                       <>
                                                          {move || image_signal.get()}
@@ -691,20 +1085,40 @@ fn MessageBox(cx: Scope, message: MergedMessages, is_last: bool) -> impl IntoVie
                                      }})}
                                 </Suspense>
                                 </>
-                            }} else {
+                            }} else if let Some(audio) = message.message_audio {
+                                view!{cx,
+                                    <>
+                                        <div class="flex items-center gap-2">
+                                            <audio controls preload="metadata" src=audio class="max-w-[240px] h-10"/>
+                                            {message.message_audio_duration.map(|duration| view!{cx,
+                                                <span class="text-xs text-gray-500">{format_duration(duration)}</span>
+                                            })}
+                                        </div>
+                                    </>
+                                }
+                            } else {
                                 view!{cx,
                                     <>
-                                        {message.message_body}
+                                        <RichText body=message.message_body.unwrap_or_default()/>
                                     </>
                                 }
                             }
                         }
                 </div>
+                <Suspense fallback=||()>
+                    {move || link_previews.read(cx).map(|previews| previews.into_iter().map(|preview|
+                        view!{cx, <LinkPreviewCard preview/>}
+                    ).collect_view(cx))}
+                </Suspense>
                 {move || {
-                    (is_last && is_own() && seen_list.trim().len().gt(&0)).then(||
+                    let seen_by = seen_by();
+                    (is_last && is_own() && !seen_by.is_empty()).then(||
                     view!{cx,
-                        <div class="text-xs font-light text-gray-500">
-                            {format!("Seen by {}", seen_list)}
+                        <div class="text-xs font-light text-gray-500" title=seen_by.join(", ")>
+                            {match seen_by.len() {
+                                1 => format!("Seen by {}", seen_by[0]),
+                                count => format!("Seen by {count}"),
+                            }}
                         </div>
                     }
                 )}}
@@ -713,6 +1127,29 @@ fn MessageBox(cx: Scope, message: MergedMessages, is_last: bool) -> impl IntoVie
     }
 }
 
+#[component]
+fn LinkPreviewCard(cx: Scope, preview: LinkPreview) -> impl IntoView {
+    view! {cx,
+        <a href=preview.url target="_blank" rel="noopener noreferrer nofollow"
+            class="flex w-72 flex-col overflow-hidden rounded-md border border-gray-200 bg-white hover:bg-neutral-50 transition">
+            {preview.image.map(|image| view!{cx,
+                <img src=image alt="" loading="lazy" referrerpolicy="no-referrer" class="max-h-36 w-full object-cover"/>
+            })}
+            <div class="flex flex-col gap-1 p-2">
+                {preview.site_name.map(|site_name| view!{cx,
+                    <div class="text-xs uppercase text-gray-400 truncate">{site_name}</div>
+                })}
+                {preview.title.map(|title| view!{cx,
+                    <div class="text-sm font-semibold text-gray-900 line-clamp-2">{title}</div>
+                })}
+                {preview.description.map(|description| view!{cx,
+                    <div class="text-xs text-gray-500 line-clamp-3">{description}</div>
+                })}
+            </div>
+        </a>
+    }
+}
+
 #[component]
 fn ProfileDrawer<F, FN, 'a>(
     cx: Scope,
@@ -741,6 +1178,68 @@ where
 
     let drawer_context = use_context::<DrawerContext>(cx).unwrap();
 
+    // Only direct conversations can be blocked from the drawer
+    let direct_user = (data.is_group == 0)
+        .then(|| data.other_users.first().map(|(_, _, id)| *id))
+        .flatten();
+    let blocked = create_rw_signal(cx, false);
+    create_local_resource(
+        cx,
+        move || (),
+        move |_| async move {
+            if let (Some(user_id), Ok(contacts)) = (direct_user, get_contacts(cx).await) {
+                blocked.set(
+                    contacts
+                        .iter()
+                        .any(|contact| contact.user.id == user_id && contact.blocked),
+                );
+            }
+        },
+    );
+    let conversation_id = data.id;
+    let pins = use_context::<PinContext>(cx).unwrap().pins;
+    let conversation_pins = move || {
+        pins.with(|pins| {
+            pins.iter()
+                .filter(|pin| pin.message.message_conversation_id == conversation_id)
+                .cloned()
+                .collect::<Vec<_>>()
+        })
+    };
+    let unpin = move |message_id: i32| {
+        spawn_local(async move {
+            if let Err(e) = pin_message(cx, message_id, false).await {
+                log!("Unpin rejected: {}", e);
+            }
+        });
+    };
+
+    // The notice posted by the server tells everyone else about the change
+    let retention = create_rw_signal(cx, data.retention);
+    let change_retention = move |event: web_sys::Event| {
+        let selected = Retention::ALL
+            .into_iter()
+            .find(|choice| format!("{choice:?}") == event_target_value(&event));
+        spawn_local(async move {
+            match set_retention(cx, conversation_id, selected).await {
+                Ok(()) => retention.set(selected),
+                Err(e) => log!("Retention rejected: {}", e),
+            }
+        });
+    };
+
+    let toggle_block = move |user_id: i32| {
+        let action = match blocked.get() {
+            true => ContactAction::Unblock,
+            false => ContactAction::Block,
+        };
+        spawn_local(async move {
+            if update_contact(cx, user_id, action).await.is_ok() {
+                blocked.set(action == ContactAction::Block);
+            }
+        });
+    };
+
     view! {cx,
         <div class=move || format!("transition ease-in delay-300 {}", if is_open() {"block"} else {"hidden"})>
             <div class="relative z-40">
@@ -787,6 +1286,16 @@ where
                                                     "Delete"
                                                 </div>
                                             </div>
+                                            {direct_user.map(|user_id| view!{cx,
+                                                <div class="flex flex-col gap-3 items-center hover:opacity-75 cursor-pointer" on:click=move |_| toggle_block(user_id)>
+                                                    <div class="w-10 h-10 bg-neutral-100 rounded-full flex items-center justify-center">
+                                                              <Icon icon=Icon::from(IoIcon::IoBan) width="20px" height="24px"/>
+                                                    </div>
+                                                    <div class="text-sm font-light text-neutral-600">
+                                                        {move || if blocked.get() {"Unblock"} else {"Block"}}
+                                                    </div>
+                                                </div>
+                                            })}
                                         </div>
                                         <div class="w-full pb-5 pt-5 sm:px-0 sm:pt-0">
                                             <dl class="space-y-8 px-4 sm:space-y-6 sm:px-6">
@@ -845,6 +1354,63 @@ where
                                                     }
                                                   })
                                                 }
+                                                <hr />
+                                                <div>
+                                                    <dt class="text-sm font-medium text-gray-500 sm:w-40 sm:flex-shrink-0">
+                                                        "Disappearing messages"
+                                                    </dt>
+                                                    <dd class="mt-1 text-sm text-gray-900 sm:col-span-2">
+                                                        <select class="w-full rounded-md border border-gray-200 py-1 px-2 focus:outline-none"
+                                                            on:change=change_retention>
+                                                            <option value="" selected=move || retention.get().is_none()>"Off"</option>
+                                                            {Retention::ALL.into_iter().map(|choice| view!{cx,
+                                                                <option value=format!("{choice:?}") selected=move || retention.get() == Some(choice)>
+                                                                    {choice.label()}
+                                                                </option>
+                                                            }).collect_view(cx)}
+                                                        </select>
+                                                    </dd>
+                                                </div>
+                                                <hr />
+                                                <div>
+                                                    <dt class="text-sm font-medium text-gray-500 sm:w-40 sm:flex-shrink-0">
+                                                        "Pinned messages"
+                                                    </dt>
+                                                    <dd class="mt-1 text-sm text-gray-900 sm:col-span-2">
+                                                        {move || conversation_pins().is_empty().then(|| view!{cx,
+                                                            <p class="text-gray-500">"Nothing pinned yet"</p>
+                                                        })}
+                                                        <For
+                                                          each=conversation_pins
+                                                          key=|pin| pin.message.message_id
+                                                          view=move |cx, pin: PinnedMessage| {
+                                                            let message_id = pin.message.message_id;
+                                                            let preview = match (pin.message.message_body, pin.message.message_image, pin.message.message_audio) {
+                                                                (Some(body), _, _) => body,
+                                                                (None, Some(_), _) => String::from("Image"),
+                                                                (None, None, Some(_)) => String::from("Voice note"),
+                                                                (None, None, None) => String::new(),
+                                                            };
+                                                            view!{cx,
+                                                                <div class="flex justify-between gap-3 py-2 border-b border-gray-100">
+                                                                    <div class="min-w-0">
+                                                                        <p class="font-medium">
+                                                                            {format!("{} {}", pin.message.first_name, pin.message.last_name)}
+                                                                        </p>
+                                                                        <p class="truncate">{preview}</p>
+                                                                        <p class="text-xs text-gray-400">
+                                                                            {format!("Pinned by {} {}", pin.pinned_by_first_name, pin.pinned_by_last_name)}
+                                                                        </p>
+                                                                    </div>
+                                                                    <button type="button" class="text-xs text-sky-500 hover:text-sky-600"
+                                                                        on:click=move |_| unpin(message_id)>
+                                                                        "Unpin"
+                                                                    </button>
+                                                                </div>
+                                                            }
+                                                          }/>
+                                                    </dd>
+                                                </div>
                                             </dl>
                                         </div>
                                     </div>
@@ -1,10 +1,9 @@
-use crate::{
-    app::pages::components::{anciliary::UserContext, avatar},
-    server_function::routes::get_image,
-};
+use crate::app::pages::components::{anciliary::UserContext, avatar};
 
 pub mod components;
 pub mod conversation;
+pub mod password_reset;
+pub mod two_factor;
 pub mod users;
 pub mod websocket;
 
@@ -0,0 +1,138 @@
+use leptos::{html::Input, *};
+use leptos_router::*;
+use validator::Validate;
+
+use crate::app::{PasswordSchema, ResetValidation};
+use crate::server_function::routes::{request_password_reset, reset_password};
+
+#[component]
+pub(crate) fn ResetCard(cx: Scope, title: &'static str, children: Children) -> impl IntoView {
+    view! { cx,
+        <div class="flex relative justify-center bg-amber-600" >
+            <h1 class="text-8xl absolute top-20 xl:top-16">"ZING!"</h1>
+        </div>
+        <div class="flex w-screen h-screen bg-[url('/Strike.svg')]
+                relative bg-no-repeat bg-cover bg-center bg-amber-600
+                flex relative items-center justify-center">
+            <div class="card flex flex-col w-96 p-6 space-y-4 bg-white rounded-xl text-sm">
+                <div class="bg-gradient-to-r from-amber-400 via-amber-500 to-amber-400
+                    rounded-xl h-10 flex items-center justify-center text-white">
+                    {title}
+                </div>
+                {children(cx)}
+                <A class="text-center text-amber-600 hover:underline" href="/login">
+                    "Back to Log In"
+                </A>
+            </div>
+        </div>
+    }
+}
+
+/// Asks for the email of the account to reset, "/forgot-password".
+#[component]
+pub fn ForgotPassword(cx: Scope) -> impl IntoView {
+    let email = create_node_ref::<Input>(cx);
+    let (sent, sent_setter) = create_signal(cx, false);
+    let (status, status_setter) = create_signal(cx, String::new());
+
+    let on_submit = move |ev: web_sys::SubmitEvent| {
+        ev.prevent_default();
+        let value = email.get_untracked().unwrap().value();
+        spawn_local(async move {
+            match request_password_reset(cx, value).await {
+                Ok(None) => sent_setter(true),
+                Ok(Some(limit)) => status_setter(limit.message()),
+                Err(e) => status_setter(e.to_string()),
+            }
+        });
+    };
+
+    view! { cx,
+        <ResetCard title="Forgot Password">
+            <Show
+                when=move || sent()
+                fallback=move |cx| view! { cx,
+                    <form class="flex flex-col space-y-4" on:submit=on_submit>
+                        <input _ref=email type="email" required placeholder="Email..."
+                            class="border-2 border-amber-300 rounded-lg px-3 py-1 focus:outline-none"/>
+                        <p class="text-red-600 text-center">{status}</p>
+                        <button type="submit" class="bg-amber-500 hover:bg-amber-600 text-white rounded-lg py-1">
+                            "Send Reset Link"
+                        </button>
+                    </form>
+                }
+            >
+                <p class="text-center">
+                    "If an account uses that email, a reset link is on its way."
+                </p>
+            </Show>
+        </ResetCard>
+    }
+}
+
+/// Sets a new password with the token from the emailed link, "/reset-password?token=...".
+#[component]
+pub fn ResetPassword(cx: Scope) -> impl IntoView {
+    let query = use_query_map(cx);
+    let password = create_node_ref::<Input>(cx);
+    let confirm = create_node_ref::<Input>(cx);
+    let (status, status_setter) = create_signal(cx, None::<Result<ResetValidation, String>>);
+
+    let on_submit = move |ev: web_sys::SubmitEvent| {
+        ev.prevent_default();
+        let entry = password.get_untracked().unwrap().value();
+        if entry != confirm.get_untracked().unwrap().value() {
+            status_setter(Some(Err(String::from("Passwords do not match"))));
+            return;
+        }
+        let schema = PasswordSchema { entry };
+        if schema.validate().is_err() {
+            status_setter(Some(Ok(ResetValidation::WeakPassword)));
+            return;
+        }
+
+        let token = query.with_untracked(|query| query.get("token").cloned().unwrap_or_default());
+        spawn_local(async move {
+            status_setter(Some(
+                reset_password(cx, token, schema)
+                    .await
+                    .map_err(|e| e.to_string()),
+            ));
+        });
+    };
+
+    view! { cx,
+        <ResetCard title="Reset Password">
+            <Show
+                when=move || status() == Some(Ok(ResetValidation::Success))
+                fallback=move |cx| view! { cx,
+                    <form class="flex flex-col space-y-4" on:submit=on_submit>
+                        <input _ref=password type="password" required placeholder="New Password..."
+                            class="border-2 border-amber-300 rounded-lg px-3 py-1 focus:outline-none"/>
+                        <input _ref=confirm type="password" required placeholder="Confirm Password..."
+                            class="border-2 border-amber-300 rounded-lg px-3 py-1 focus:outline-none"/>
+                        <p class="text-red-600 text-center">
+                            {move || match status() {
+                                Some(Ok(ResetValidation::InvalidToken)) => {
+                                    String::from("This reset link is invalid or has expired")
+                                }
+                                Some(Ok(ResetValidation::WeakPassword)) => String::from(
+                                    "Use 8 characters with an uppercase letter, a number and one of $!@*",
+                                ),
+                                Some(Err(e)) => e,
+                                _ => String::new(),
+                            }}
+                        </p>
+                        <button type="submit" class="bg-amber-500 hover:bg-amber-600 text-white rounded-lg py-1">
+                            "Reset Password"
+                        </button>
+                    </form>
+                }
+            >
+                <p class="text-center">
+                    "Your password was changed and every session was logged out."
+                </p>
+            </Show>
+        </ResetCard>
+    }
+}
@@ -0,0 +1,47 @@
+use leptos::{html::Input, *};
+use leptos_router::*;
+
+use super::password_reset::ResetCard;
+use crate::app::VerifyPassword;
+use crate::server_function::routes::verify_two_factor;
+
+/// The second login step of accounts with 2FA, "/login/two-factor".
+#[component]
+pub fn TwoFactorLogin(cx: Scope) -> impl IntoView {
+    let code = create_node_ref::<Input>(cx);
+    let (status, status_setter) = create_signal(cx, String::new());
+
+    let on_submit = move |ev: web_sys::SubmitEvent| {
+        ev.prevent_default();
+        let value = code.get_untracked().unwrap().value();
+        spawn_local(async move {
+            match verify_two_factor(cx, value).await {
+                Ok(VerifyPassword::Success(_)) => {
+                    use_navigate(cx)("/user", Default::default()).unwrap();
+                }
+                Ok(VerifyPassword::IncorrectCredentials) => {
+                    status_setter(String::from("Code is incorrect"))
+                }
+                Ok(VerifyPassword::Limited(limit)) => status_setter(limit.message()),
+                Ok(_) => status_setter(String::from("Server Error. Please Try again later.")),
+                Err(e) => status_setter(e.to_string()),
+            }
+        });
+    };
+
+    view! { cx,
+        <ResetCard title="Two Factor Authentication">
+            <form class="flex flex-col space-y-4" on:submit=on_submit>
+                <p class="text-center">
+                    "Enter the code from your authenticator app, or one of your recovery codes."
+                </p>
+                <input _ref=code type="text" required autocomplete="one-time-code" placeholder="Code..."
+                    class="border-2 border-amber-300 rounded-lg px-3 py-1 focus:outline-none"/>
+                <p class="text-red-600 text-center">{status}</p>
+                <button type="submit" class="bg-amber-500 hover:bg-amber-600 text-white rounded-lg py-1">
+                    "Verify"
+                </button>
+            </form>
+        </ResetCard>
+    }
+}
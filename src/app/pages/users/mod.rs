@@ -4,10 +4,11 @@ use leptos_router::use_navigate;
 use crate::{
     app::pages::{
         components::anciliary::{loading_fallback, EmptyState, Sidebar, UserContexts},
-        Avatar, ICONVEC, SINKVEC, STREAMVEC,
+        Avatar, MEDIACACHE, SINKVEC, STREAMVEC,
     },
     server_function::{
         routes::associated_conversation, routes::conversation_action, routes::get_users, UserModel,
+        UserQuery,
     },
 };
 
@@ -15,7 +16,7 @@ use crate::{
 pub fn Users(cx: Scope) -> impl IntoView {
     UserContexts::init_users(cx);
     leptos::on_cleanup(cx, || {
-        ICONVEC.write().clear();
+        MEDIACACHE.write().clear();
         STREAMVEC.write().clear();
         SINKVEC::send_clear();
     });
@@ -47,7 +48,37 @@ fn UserList(cx: Scope) -> impl IntoView {
 
 #[component]
 fn UserBox(cx: Scope) -> impl IntoView {
-    let users_arr = create_local_resource(cx, || (), move |_| async move { get_users(cx).await });
+    // Search text and offset, typing starts over from the first page
+    let query = create_rw_signal(cx, (String::new(), 0_u64));
+    let loaded = create_rw_signal(cx, Vec::<UserModel>::new());
+    let next_offset = create_rw_signal(cx, None::<u64>);
+
+    let page = create_local_resource(
+        cx,
+        move || query.get(),
+        move |(search, offset)| async move {
+            let page = get_users(
+                cx,
+                UserQuery {
+                    search: (!search.trim().is_empty()).then_some(search),
+                    offset: Some(offset),
+                    limit: None,
+                },
+            )
+            .await;
+            (offset, page)
+        },
+    );
+    create_effect(cx, move |_| {
+        if let Some((offset, Ok(page))) = page.read(cx) {
+            next_offset.set(page.next_offset);
+            match offset {
+                0 => loaded.set(page.users),
+                _ => loaded.update(|users| users.extend(page.users)),
+            }
+        }
+    });
+
     let on_click = move |id: i32, cx: Scope| {
         spawn_local(async move {
             conversation_action(cx, vec![id], false, None)
@@ -65,36 +96,47 @@ fn UserBox(cx: Scope) -> impl IntoView {
     };
 
     view! {cx,
-        <Suspense fallback=loading_fallback(cx)>
-            {move || users_arr.read(cx).map(|items| {
-                let items = items.unwrap();
-                view!{cx,
-                        <For
-                          each=move || items.clone()
-                          key=|items| items.id
-                          view=move |cx, item: UserModel| {
-                                        view!{cx,
-                                             <div class="w-full relative flex
-                                                 items-center space-x-3 bg-white
-                                                 p-3 hover:bg-neutral-100 rounded-lg
-                                                 transition cursor-pointer"
-                                                 on:click=move |_| on_click(item.id, cx)>
-                                                     <Avatar id=item.id/>
-                                                     <div class="min-w-0 flex-1">
-                                                         <div class="focus:outline-none">
-                                                             <div class="flex justify-between items-center mb-1">
-                                                                 <p class="text-sm font-medium text-gray-900">
-                                                                     {format!("{} {}", item.first_name, item.last_name)}
-                                                                 </p>
-                                                             </div>
-                                                         </div>
-                                                     </div>
+      <div>
+        <input type="search" placeholder="Search by name or email"
+            class="w-full mb-2 py-2 px-4 border border-gray-300 rounded-md text-sm
+                focus:outline-none focus:ring-2 focus:ring-indigo-500 focus:border-indigo-500"
+            on:input=move |ev| query.set((event_target_value(&ev), 0))/>
+        <For
+          each=move || loaded.get()
+          key=|items| items.id
+          view=move |cx, item: UserModel| {
+                        view!{cx,
+                             <div class="w-full relative flex
+                                 items-center space-x-3 bg-white
+                                 p-3 hover:bg-neutral-100 rounded-lg
+                                 transition cursor-pointer"
+                                 on:click=move |_| on_click(item.id, cx)>
+                                     <Avatar id=item.id/>
+                                     <div class="min-w-0 flex-1">
+                                         <div class="focus:outline-none">
+                                             <div class="flex justify-between items-center mb-1">
+                                                 <p class="text-sm font-medium text-gray-900">
+                                                     {format!("{} {}", item.first_name, item.last_name)}
+                                                 </p>
                                              </div>
-                                  }
-                          }
-                        />
-                    }
-            })}
-        </Suspense>
+                                         </div>
+                                     </div>
+                             </div>
+                  }
+          }
+        />
+        {move || page.loading().get().then(|| view! {cx,
+            <div class="flex justify-center py-3">{loading_fallback(cx)()}</div>
+        })}
+        {move || (!page.loading().get() && loaded.with(Vec::is_empty)).then(|| view! {cx,
+            <p class="text-sm text-gray-500 py-3">"No users found"</p>
+        })}
+        {move || next_offset.get().filter(|_| !page.loading().get()).map(|offset| view! {cx,
+            <button class="w-full py-2 text-sm font-medium text-indigo-600 hover:bg-neutral-100 rounded-lg"
+                on:click=move |_| query.update(|(_, current)| *current = offset)>
+                "Load more"
+            </button>
+        })}
+      </div>
     }
 }
@@ -13,8 +13,11 @@ use std::any::Any;
 use super::components::avatar::{self, IconData, SINKVEC, STREAMVEC};
 use super::conversation::Message;
 use super::UserContext;
-use crate::app::{pages::components::avatar::ToStreamData, SeenContext};
-use crate::server_function::routes::handle_message_input;
+use crate::app::pages::components::avatar::ToStreamData;
+use crate::server_function::{
+    routes::{handle_message_input, handle_voice_note},
+    MentionEvent, MessagesDeleted, PinUpdate, ReadReceipt, UnreadCount,
+};
 
 #[derive(Debug, Clone)]
 pub enum SyncChannel {
@@ -51,9 +54,11 @@ impl SyncChannel {
         E: for<'de> Deserialize<'de> + std::any::Any + std::fmt::Debug, // Add this line
     {
         while let Some(data) = self.next().await {
-            let mut value: E;
-            value = serde_json::from_value(data.into_inner()).unwrap();
-            value = *Box::<dyn Any>::downcast::<E>(Box::new(value)).unwrap();
+            // Channels are shared by every payload type sent on a socket, skip the others
+            let Ok(value) = serde_json::from_value::<E>(data.into_inner()) else {
+                continue;
+            };
+            let value = *Box::<dyn Any>::downcast::<E>(Box::new(value)).unwrap();
             match messages() {
                 Some(messages) => messages.update(|signal_inner| {
                     function(Some(signal_inner), value);
@@ -68,6 +73,11 @@ impl SyncChannel {
 pub enum StreamData {
     Message(Message),
     IconData(IconData),
+    Mention(MentionEvent),
+    Receipt(ReadReceipt),
+    Unread(UnreadCount),
+    Pin(PinUpdate),
+    Deleted(MessagesDeleted),
     Close,
 }
 
@@ -75,6 +85,9 @@ pub enum StreamData {
 pub enum WsData {
     IconData,
     MessageData,
+    /// Events addressed to the logged in user, mentions, read receipts, unread counts, pins and
+    /// swept messages
+    UserData,
 }
 
 impl ToStreamData for String {
@@ -83,8 +96,18 @@ impl ToStreamData for String {
         if let Ok(message) = serde_json::from_value::<Message>(value.clone()) {
             return Ok(StreamData::Message(message));
         }
-        if let Ok(icon_data) = serde_json::from_value::<IconData>(value) {
+        if let Ok(icon_data) = serde_json::from_value::<IconData>(value.clone()) {
             Ok(StreamData::IconData(icon_data))
+        } else if let Ok(mention) = serde_json::from_value::<MentionEvent>(value.clone()) {
+            Ok(StreamData::Mention(mention))
+        } else if let Ok(receipt) = serde_json::from_value::<ReadReceipt>(value.clone()) {
+            Ok(StreamData::Receipt(receipt))
+        } else if let Ok(unread) = serde_json::from_value::<UnreadCount>(value.clone()) {
+            Ok(StreamData::Unread(unread))
+        } else if let Ok(pin) = serde_json::from_value::<PinUpdate>(value.clone()) {
+            Ok(StreamData::Pin(pin))
+        } else if let Ok(deleted) = serde_json::from_value::<MessagesDeleted>(value) {
+            Ok(StreamData::Deleted(deleted))
         } else {
             log!("Error with stream text: {}", inner);
             Err(std::io::Error::new(
@@ -100,6 +123,11 @@ impl StreamData {
         match self {
             Self::Message(message) => serde_json::to_value(message).unwrap(),
             Self::IconData(icon_data) => serde_json::to_value(icon_data).unwrap(),
+            Self::Mention(mention) => serde_json::to_value(mention).unwrap(),
+            Self::Receipt(receipt) => serde_json::to_value(receipt).unwrap(),
+            Self::Unread(unread) => serde_json::to_value(unread).unwrap(),
+            Self::Pin(pin) => serde_json::to_value(pin).unwrap(),
+            Self::Deleted(deleted) => serde_json::to_value(deleted).unwrap(),
             Self::Close => serde_json::to_value("command: close").unwrap(),
         }
     }
@@ -149,6 +177,14 @@ impl HandleWebSocket {
     {
         let data = match std::any::TypeId::of::<E>() {
             t if t == std::any::TypeId::of::<avatar::IconData>() => WsData::IconData,
+            t if t == std::any::TypeId::of::<MentionEvent>()
+                || t == std::any::TypeId::of::<ReadReceipt>()
+                || t == std::any::TypeId::of::<UnreadCount>()
+                || t == std::any::TypeId::of::<PinUpdate>()
+                || t == std::any::TypeId::of::<MessagesDeleted>() =>
+            {
+                WsData::UserData
+            }
             _ => WsData::MessageData,
         };
 
@@ -194,16 +230,17 @@ impl HandleWebSocket {
                                 if let Some(value) = value {
                                     match value {
                                     Ok(gloo_net::websocket::Message::Text(text)) => {
-                                        let _ = sync_channel
-                                            .send(std::string::String::from_inner(text.trim()).unwrap())
-                                            .await;
-                                        let value: E = serde_json::from_str(&text).unwrap();
-                                        match messages() {
-                                            Some(messages) => messages.update(|signal_inner| {
-                                                function(Some(signal_inner), value);
-                                            }),
-                                            None => function(None, value),
-                                        };
+                                        if let Ok(stream_data) = std::string::String::from_inner(text.trim()) {
+                                            sync_channel.send(stream_data).await;
+                                        }
+                                        if let Ok(value) = serde_json::from_str::<E>(&text) {
+                                            match messages() {
+                                                Some(messages) => messages.update(|signal_inner| {
+                                                    function(Some(signal_inner), value);
+                                                }),
+                                                None => function(None, value),
+                                            };
+                                        }
                                     },
                                     Ok(gloo_net::websocket::Message::Bytes(_)) => {
                                         log!("BYTES?");
@@ -230,62 +267,73 @@ impl UserInputHandler {
     ) {
         let body = input_ref.get_untracked().unwrap().value();
         let user_context = use_context::<UserContext>(cx).unwrap();
-        let seen_context = move || use_context::<SeenContext>(cx).unwrap().status.get();
 
         if let Some(files) = image_ref.get_untracked().unwrap().files() {
             let list = gloo_file::FileList::from(files);
             if let Some(file) = list.first() {
                 let file = Some(gloo_file::futures::read_as_bytes(file).await.unwrap());
-                if let Ok(path) = handle_message_input(cx, id, None, file.clone()).await {
+                if let Ok(sent) = handle_message_input(cx, id, None, file.clone()).await {
                     HandleWebSocket::handle_sink_stream(
                         Message {
                             message: None,
-                            image: path,
+                            image: sent.image,
+                            audio: None,
+                            audio_duration: None,
                             conversation_id: id,
                             first_name: user_context.first_name.get_untracked(),
                             last_name: user_context.last_name.get_untracked(),
                             user_id: user_context.id.get_untracked(),
-                            seen: None,
-                            message_id: {
-                                if let Some(message) = seen_context()
-                                    .iter()
-                                    .find(|conversation| conversation.conversation_id == id)
-                                {
-                                    message.last_message_id + 1
-                                } else {
-                                    0
-                                }
-                            },
+                            message_id: sent.message_id,
+                            system: false,
                         },
                         id,
                     )
                     .await;
                 }
-            } else if (handle_message_input(cx, id, Some(body.clone()), None).await).is_ok() {
+            } else if let Ok(sent) = handle_message_input(cx, id, Some(body.clone()), None).await {
                 HandleWebSocket::handle_sink_stream(
                     Message {
                         message: Some(body),
                         image: None,
+                        audio: None,
+                        audio_duration: None,
                         conversation_id: id,
                         first_name: user_context.first_name.get_untracked(),
                         last_name: user_context.last_name.get_untracked(),
                         user_id: user_context.id.get_untracked(),
-                        seen: None,
-                        message_id: {
-                            if let Some(message) = seen_context()
-                                .iter()
-                                .find(|conversation| conversation.conversation_id == id)
-                            {
-                                message.last_message_id + 1
-                            } else {
-                                0
-                            }
-                        },
+                        message_id: sent.message_id,
+                        system: false,
+                    },
+                    id,
+                )
+                .await
+            }
+        }
+    }
+
+    pub async fn handle_voice_note(cx: Scope, audio: Vec<u8>, id: i32) {
+        let user_context = use_context::<UserContext>(cx).unwrap();
+
+        match handle_voice_note(cx, id, audio).await {
+            Ok(voice_note) => {
+                HandleWebSocket::handle_sink_stream(
+                    Message {
+                        message: None,
+                        image: None,
+                        audio: Some(voice_note.path),
+                        audio_duration: Some(voice_note.duration_ms),
+                        conversation_id: id,
+                        first_name: user_context.first_name.get_untracked(),
+                        last_name: user_context.last_name.get_untracked(),
+                        user_id: user_context.id.get_untracked(),
+                        message_id: voice_note.message_id,
+                        system: false,
                     },
                     id,
                 )
                 .await
             }
+            Err(e) => log!("Voice note rejected: {}", e),
         }
     }
 }
@@ -0,0 +1,448 @@
+//! Session cookie and identity settings from the environment. The cookie key comes from
+//! `SESSION_KEY` or a key file, so sessions survive restarts and validate on every replica. A
+//! previous key keeps working until its grace period ends, see `rotation`.
+
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use actix_identity::IdentityMiddleware;
+use actix_session::{
+    config::{BrowserSession, PersistentSession},
+    storage::RedisSessionStore,
+    SessionMiddleware,
+};
+use actix_web::cookie::{Cookie, Key, SameSite};
+use base64::{engine::general_purpose, Engine};
+use chrono::{DateTime, Utc};
+
+use super::rotation::KeyRotation;
+
+pub const DEFAULT_KEY_FILE: &str = "./session.key";
+pub const DEFAULT_COOKIE_NAME: &str = "id";
+
+#[derive(Debug)]
+pub enum SessionConfigError {
+    Config(String),
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for SessionConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionConfigError::Config(e) => write!(f, "Session configuration error: {e}"),
+            SessionConfigError::Io(e) => write!(f, "Could not read or write the session key: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for SessionConfigError {}
+
+impl From<std::io::Error> for SessionConfigError {
+    fn from(value: std::io::Error) -> Self {
+        SessionConfigError::Io(value)
+    }
+}
+
+/// Attributes of the session cookie.
+#[derive(Debug, Clone)]
+pub struct CookiePolicy {
+    pub name: String,
+    pub secure: bool,
+    pub same_site: SameSite,
+    pub domain: Option<String>,
+    /// `None` ends the session when the browser closes
+    pub lifetime: Option<Duration>,
+}
+
+impl Default for CookiePolicy {
+    fn default() -> Self {
+        Self {
+            name: String::from(DEFAULT_COOKIE_NAME),
+            secure: true,
+            same_site: SameSite::Lax,
+            domain: None,
+            lifetime: None,
+        }
+    }
+}
+
+impl CookiePolicy {
+    /// A session cookie carrying `value` with these attributes, as `SessionMiddleware` would
+    /// set it.
+    pub fn cookie(&self, value: String) -> Cookie<'static> {
+        let mut cookie = Cookie::build(self.name.clone(), value)
+            .path("/")
+            .secure(self.secure)
+            .http_only(true)
+            .same_site(self.same_site)
+            .finish();
+        if let Some(domain) = &self.domain {
+            cookie.set_domain(domain.clone());
+        }
+        if let Some(lifetime) = self.lifetime {
+            cookie.set_max_age(to_cookie_duration(lifetime));
+        }
+        cookie
+    }
+}
+
+/// How long an identity lasts. Both are off unless configured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IdentityTimeouts {
+    /// Logged out after this long without a request
+    pub idle: Option<Duration>,
+    /// Logged out this long after logging in, however active
+    pub absolute: Option<Duration>,
+}
+
+/// A key replaced by `SessionConfig::key`, still accepted until `until`.
+#[derive(Clone)]
+pub struct PreviousKey {
+    pub key: Key,
+    pub until: DateTime<Utc>,
+}
+
+#[derive(Clone)]
+pub struct SessionConfig {
+    pub key: Key,
+    pub previous_key: Option<PreviousKey>,
+    pub cookie: CookiePolicy,
+    pub identity: IdentityTimeouts,
+}
+
+fn to_cookie_duration(duration: Duration) -> actix_web::cookie::time::Duration {
+    actix_web::cookie::time::Duration::seconds(duration.as_secs() as i64)
+}
+
+fn invalid(name: &str, value: &str) -> SessionConfigError {
+    SessionConfigError::Config(format!("Invalid {name} {value}"))
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool, SessionConfigError> {
+    match value.to_lowercase().as_str() {
+        "true" | "1" | "yes" => Ok(true),
+        "false" | "0" | "no" => Ok(false),
+        _ => Err(invalid(name, value)),
+    }
+}
+
+fn parse_same_site(value: &str) -> Result<SameSite, SessionConfigError> {
+    match value.to_lowercase().as_str() {
+        "strict" => Ok(SameSite::Strict),
+        "lax" => Ok(SameSite::Lax),
+        "none" => Ok(SameSite::None),
+        _ => Err(invalid("SESSION_COOKIE_SAME_SITE", value)),
+    }
+}
+
+fn parse_duration(
+    name: &str,
+    value: Option<String>,
+    unit_seconds: u64,
+) -> Result<Option<Duration>, SessionConfigError> {
+    value
+        .map(|value| {
+            value
+                .parse::<u64>()
+                .ok()
+                .filter(|amount| *amount > 0)
+                .map(|amount| Duration::from_secs(amount * unit_seconds))
+                .ok_or_else(|| invalid(name, &value))
+        })
+        .transpose()
+}
+
+/// A key as stored in configuration, base64 of at least 64 bytes.
+pub fn decode_key(source: &str, encoded: &str) -> Result<Key, SessionConfigError> {
+    let bytes = general_purpose::STANDARD
+        .decode(encoded.trim())
+        .map_err(|_| SessionConfigError::Config(format!("{source} is not valid base64")))?;
+    Key::try_from(bytes.as_slice()).map_err(|_| {
+        SessionConfigError::Config(format!("{source} must hold at least 64 bytes"))
+    })
+}
+
+pub fn encode_key(key: &Key) -> String {
+    general_purpose::STANDARD.encode(key.master())
+}
+
+/// Reads the key file at `path`, generating it first when `create` is set and it is missing.
+pub fn read_key_file(path: &Path, create: bool) -> Result<Key, SessionConfigError> {
+    match std::fs::read_to_string(path) {
+        Ok(encoded) => decode_key(&path.display().to_string(), &encoded),
+        Err(e) if create && e.kind() == std::io::ErrorKind::NotFound => {
+            let key = Key::generate();
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            options.open(path)?.write_all(encode_key(&key).as_bytes())?;
+            println!("Generated a new session key in {}", path.display());
+            Ok(key)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+impl SessionConfig {
+    /// Reads the `SESSION_` and `IDENTITY_` settings through `var`, so they can come from
+    /// anywhere.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, SessionConfigError> {
+        let key = match (var("SESSION_KEY"), var("SESSION_KEY_FILE")) {
+            (Some(key), _) => decode_key("SESSION_KEY", &key)?,
+            (None, Some(path)) => read_key_file(Path::new(&path), true)?,
+            (None, None) => read_key_file(Path::new(DEFAULT_KEY_FILE), true)?,
+        };
+
+        let previous = match (var("SESSION_PREVIOUS_KEY"), var("SESSION_PREVIOUS_KEY_FILE")) {
+            (Some(key), _) => Some(decode_key("SESSION_PREVIOUS_KEY", &key)?),
+            (None, Some(path)) => Some(read_key_file(Path::new(&path), false)?),
+            (None, None) => None,
+        };
+        let previous_key = match (previous, var("SESSION_PREVIOUS_KEY_UNTIL")) {
+            (Some(key), Some(until)) => Some(PreviousKey {
+                key,
+                until: DateTime::parse_from_rfc3339(&until)
+                    .map_err(|_| invalid("SESSION_PREVIOUS_KEY_UNTIL", &until))?
+                    .with_timezone(&Utc),
+            }),
+            (Some(_), None) => {
+                return Err(SessionConfigError::Config(String::from(
+                    "SESSION_PREVIOUS_KEY_UNTIL must be set with a previous key",
+                )))
+            }
+            (None, _) => None,
+        };
+
+        let cookie = CookiePolicy {
+            name: var("SESSION_COOKIE_NAME").unwrap_or_else(|| String::from(DEFAULT_COOKIE_NAME)),
+            secure: var("SESSION_COOKIE_SECURE")
+                .map(|secure| parse_bool("SESSION_COOKIE_SECURE", &secure))
+                .transpose()?
+                .unwrap_or(true),
+            same_site: var("SESSION_COOKIE_SAME_SITE")
+                .map(|same_site| parse_same_site(&same_site))
+                .transpose()?
+                .unwrap_or(SameSite::Lax),
+            domain: var("SESSION_COOKIE_DOMAIN"),
+            lifetime: parse_duration(
+                "SESSION_LIFETIME_HOURS",
+                var("SESSION_LIFETIME_HOURS"),
+                3600,
+            )?,
+        };
+        // Browsers drop SameSite=None cookies that are not Secure
+        if cookie.same_site == SameSite::None && !cookie.secure {
+            return Err(SessionConfigError::Config(String::from(
+                "SESSION_COOKIE_SAME_SITE=none needs SESSION_COOKIE_SECURE",
+            )));
+        }
+
+        let identity = IdentityTimeouts {
+            idle: parse_duration(
+                "IDENTITY_IDLE_TIMEOUT_MINUTES",
+                var("IDENTITY_IDLE_TIMEOUT_MINUTES"),
+                60,
+            )?,
+            absolute: parse_duration(
+                "IDENTITY_ABSOLUTE_TIMEOUT_HOURS",
+                var("IDENTITY_ABSOLUTE_TIMEOUT_HOURS"),
+                3600,
+            )?,
+        };
+
+        Ok(Self {
+            key,
+            previous_key,
+            cookie,
+            identity,
+        })
+    }
+
+    pub fn from_env() -> Result<Self, SessionConfigError> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    pub fn session_middleware(
+        &self,
+        store: RedisSessionStore,
+    ) -> SessionMiddleware<RedisSessionStore> {
+        let builder = SessionMiddleware::builder(store, self.key.clone())
+            .cookie_name(self.cookie.name.clone())
+            .cookie_secure(self.cookie.secure)
+            .cookie_same_site(self.cookie.same_site)
+            .cookie_domain(self.cookie.domain.clone())
+            .cookie_http_only(true);
+        let builder = match self.cookie.lifetime {
+            Some(lifetime) => builder.session_lifecycle(
+                PersistentSession::default().session_ttl(to_cookie_duration(lifetime)),
+            ),
+            None => builder.session_lifecycle(BrowserSession::default()),
+        };
+        builder.build()
+    }
+
+    pub fn identity_middleware(&self) -> IdentityMiddleware {
+        IdentityMiddleware::builder()
+            .visit_deadline(self.identity.idle)
+            .login_deadline(self.identity.absolute)
+            .build()
+    }
+
+    /// Re-encrypts session cookies made with the previous key, a no-op without one.
+    pub fn key_rotation(&self) -> KeyRotation {
+        KeyRotation::new(
+            self.key.clone(),
+            self.previous_key.clone(),
+            self.cookie.clone(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn encoded_key(len: usize) -> String {
+        general_purpose::STANDARD.encode(vec![7u8; len])
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    /// Settings with `SESSION_KEY` set, so no key file is touched.
+    fn settings(pairs: &[(&str, &str)]) -> Result<SessionConfig, SessionConfigError> {
+        let key = encoded_key(64);
+        let mut pairs = pairs.to_vec();
+        pairs.push(("SESSION_KEY", &key));
+        SessionConfig::from_vars(vars(&pairs))
+    }
+
+    fn error(pairs: &[(&str, &str)]) -> String {
+        match settings(pairs) {
+            Err(SessionConfigError::Config(message)) => message,
+            Err(e) => panic!("{e}"),
+            Ok(_) => panic!("{pairs:?} was accepted"),
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let config = settings(&[]).unwrap();
+        assert_eq!(config.key.master(), [7u8; 64]);
+        assert!(config.previous_key.is_none());
+        assert_eq!(config.cookie.name, DEFAULT_COOKIE_NAME);
+        assert!(config.cookie.secure);
+        assert_eq!(config.cookie.same_site, SameSite::Lax);
+        assert_eq!(config.cookie.lifetime, None);
+        assert_eq!(config.identity, IdentityTimeouts::default());
+    }
+
+    #[test]
+    fn keys_need_64_bytes_of_base64() {
+        assert!(decode_key("SESSION_KEY", &format!(" {}\n", encoded_key(64))).is_ok());
+        assert!(decode_key("SESSION_KEY", &encoded_key(96)).is_ok());
+
+        let short = SessionConfig::from_vars(vars(&[("SESSION_KEY", &encoded_key(32))]));
+        assert!(matches!(
+            short,
+            Err(SessionConfigError::Config(message)) if message.contains("at least 64 bytes")
+        ));
+        let garbled = SessionConfig::from_vars(vars(&[("SESSION_KEY", "not base64!")]));
+        assert!(matches!(
+            garbled,
+            Err(SessionConfigError::Config(message)) if message.contains("not valid base64")
+        ));
+        assert!(error(&[("SESSION_PREVIOUS_KEY", "not base64!")]).contains("not valid base64"));
+    }
+
+    #[test]
+    fn previous_key_needs_an_end() {
+        let previous = encoded_key(64);
+        assert!(error(&[("SESSION_PREVIOUS_KEY", &previous)])
+            .contains("SESSION_PREVIOUS_KEY_UNTIL must be set"));
+        assert!(error(&[
+            ("SESSION_PREVIOUS_KEY", &previous),
+            ("SESSION_PREVIOUS_KEY_UNTIL", "next week"),
+        ])
+        .contains("SESSION_PREVIOUS_KEY_UNTIL"));
+
+        let config = settings(&[
+            ("SESSION_PREVIOUS_KEY", &previous),
+            ("SESSION_PREVIOUS_KEY_UNTIL", "2023-06-01T12:00:00+02:00"),
+        ])
+        .unwrap();
+        let previous_key = config.previous_key.unwrap();
+        assert_eq!(previous_key.until.to_rfc3339(), "2023-06-01T10:00:00+00:00");
+
+        // An end without a previous key is ignored
+        let config = settings(&[("SESSION_PREVIOUS_KEY_UNTIL", "2023-06-01T12:00:00Z")]).unwrap();
+        assert!(config.previous_key.is_none());
+    }
+
+    #[test]
+    fn same_site_none_needs_secure() {
+        assert!(error(&[
+            ("SESSION_COOKIE_SAME_SITE", "None"),
+            ("SESSION_COOKIE_SECURE", "false"),
+        ])
+        .contains("needs SESSION_COOKIE_SECURE"));
+
+        let config = settings(&[("SESSION_COOKIE_SAME_SITE", "none")]).unwrap();
+        assert_eq!(config.cookie.same_site, SameSite::None);
+        assert!(config.cookie.secure);
+
+        assert!(error(&[("SESSION_COOKIE_SAME_SITE", "sometimes")])
+            .contains("SESSION_COOKIE_SAME_SITE"));
+        assert!(error(&[("SESSION_COOKIE_SECURE", "maybe")]).contains("SESSION_COOKIE_SECURE"));
+    }
+
+    #[test]
+    fn lifetimes() {
+        let config = settings(&[
+            ("SESSION_LIFETIME_HOURS", "2"),
+            ("IDENTITY_IDLE_TIMEOUT_MINUTES", "30"),
+            ("IDENTITY_ABSOLUTE_TIMEOUT_HOURS", "24"),
+        ])
+        .unwrap();
+        assert_eq!(config.cookie.lifetime, Some(Duration::from_secs(2 * 3600)));
+        assert_eq!(config.identity.idle, Some(Duration::from_secs(30 * 60)));
+        assert_eq!(
+            config.identity.absolute,
+            Some(Duration::from_secs(24 * 3600))
+        );
+
+        assert!(error(&[("SESSION_LIFETIME_HOURS", "0")]).contains("SESSION_LIFETIME_HOURS"));
+        assert!(error(&[("IDENTITY_IDLE_TIMEOUT_MINUTES", "soon")])
+            .contains("IDENTITY_IDLE_TIMEOUT_MINUTES"));
+    }
+
+    #[test]
+    fn key_file_is_generated_once() {
+        let path = std::env::temp_dir().join(format!("session-{}.key", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert!(matches!(
+            read_key_file(&path, false),
+            Err(SessionConfigError::Io(_))
+        ));
+
+        let generated = read_key_file(&path, true).unwrap();
+        let read = read_key_file(&path, false).unwrap();
+        assert_eq!(generated.master(), read.master());
+        #[cfg(unix)]
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(
+                &std::fs::metadata(&path).unwrap().permissions()
+            ) & 0o777,
+            0o600
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,209 @@
+//! What the identity cookie holds and how it becomes a user. Only the user id and the user's
+//! session version are stored, the user itself is loaded on every request by `CurrentUser`, so
+//! profile changes show up right away and a deleted user is logged out. Bumping the version
+//! invalidates every identity issued before, see `bump_session_version`.
+
+use actix_identity::IdentityExt;
+use actix_web::{dev::Payload, error, web, Error, FromRequest, HttpMessage, HttpRequest};
+use futures_util::future::LocalBoxFuture;
+use sea_orm::{
+    sea_query::Expr, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::database::DbConnection;
+use crate::entities::{prelude::Users, users};
+
+/// The identity string of a logged in session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityClaims {
+    pub user_id: i32,
+    pub session_version: i32,
+}
+
+impl IdentityClaims {
+    pub fn of(user: &users::server::Model) -> Self {
+        Self {
+            user_id: user.id,
+            session_version: user.session_version,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        serde_json::to_string(self).expect("claims serialize")
+    }
+
+    /// `None` for anything else, including identities from before the claims.
+    pub fn decode(id: &str) -> Option<Self> {
+        serde_json::from_str(id).ok()
+    }
+
+    /// Whether these claims still identify `user`.
+    pub fn matches(&self, user: &users::server::Model) -> bool {
+        self.user_id == user.id && self.session_version == user.session_version
+    }
+
+    /// The user of these claims, `None` when it was deleted or its version moved on.
+    pub async fn resolve(
+        self,
+        data: &DatabaseConnection,
+    ) -> Result<Option<users::server::Model>, DbErr> {
+        Ok(Users::find_by_id(self.user_id)
+            .one(data)
+            .await?
+            .filter(|user| self.matches(user)))
+    }
+}
+
+/// Logs out every session of `user_id` and forgets them, returns their tokens so their
+/// sockets can be closed.
+pub async fn bump_session_version(
+    data: &DatabaseConnection,
+    user_id: i32,
+) -> Result<Vec<String>, DbErr> {
+    Users::update_many()
+        .col_expr(
+            users::server::Column::SessionVersion,
+            Expr::col(users::server::Column::SessionVersion).add(1),
+        )
+        .filter(users::server::Column::Id.eq(user_id))
+        .exec(data)
+        .await?;
+    super::sessions::revoke_all(data, user_id).await
+}
+
+/// The logged in user, loaded from the database. `SessionGuard` stores it on the request, so
+/// extracting it usually costs no query. Fails with 401 without a current identity, take an
+/// `Option<CurrentUser>` to handle that.
+#[derive(Debug, Clone)]
+pub struct CurrentUser(pub users::server::Model);
+
+impl FromRequest for CurrentUser {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let resolved = req.extensions().get::<CurrentUser>().cloned();
+        let claims = req
+            .get_identity()
+            .ok()
+            .and_then(|identity| identity.id().ok())
+            .and_then(|id| IdentityClaims::decode(&id));
+        let data = req
+            .app_data::<web::Data<tokio::sync::Mutex<DbConnection>>>()
+            .cloned();
+
+        Box::pin(async move {
+            let claims = claims.ok_or_else(|| error::ErrorUnauthorized("Login required"))?;
+            if let Some(user) = resolved.filter(|user| claims.matches(&user.0)) {
+                return Ok(user);
+            }
+
+            let data = data.ok_or_else(|| error::ErrorInternalServerError("No database"))?;
+            let db = &data.lock().await.connection;
+            claims
+                .resolve(db)
+                .await
+                .map_err(error::ErrorInternalServerError)?
+                .map(CurrentUser)
+                .ok_or_else(|| error::ErrorUnauthorized("Invalid session"))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+    use crate::entities::{prelude::UserSessions, user_sessions};
+    use chrono::{TimeZone, Utc};
+    use sea_orm::{ActiveModelTrait, IntoActiveModel, PaginatorTrait};
+
+    fn user(id: i32) -> users::server::Model {
+        users::server::Model {
+            id,
+            first_name: String::from("Tester"),
+            last_name: String::from("Tester"),
+            email: format!("user{id}@example.com"),
+            phone_number: 0,
+            password: String::new(),
+            image: None,
+            send_read_receipts: 1,
+            email_missed_messages: 0,
+            email_digest: 0,
+            notified_until: None,
+            digest_sent_at: None,
+            totp_secret: None,
+            totp_enabled_at: None,
+            totp_last_step: None,
+            session_version: 0,
+        }
+    }
+
+    async fn find(db: &DatabaseConnection, id: i32) -> users::server::Model {
+        Users::find_by_id(id).one(db).await.unwrap().unwrap()
+    }
+
+    #[test]
+    fn claims_round_trip() {
+        let claims = IdentityClaims::of(&user(7));
+        assert_eq!(IdentityClaims::decode(&claims.encode()), Some(claims));
+        // Identities from before the claims held only the user id
+        assert_eq!(IdentityClaims::decode("7"), None);
+    }
+
+    #[tokio::test]
+    async fn bumping_the_version_rejects_earlier_claims() {
+        let db = testing::sqlite().await;
+        testing::create_table(&db, Users).await;
+        testing::create_table(&db, UserSessions).await;
+        for id in [1, 2] {
+            user(id).into_active_model().insert(&db).await.unwrap();
+        }
+        let at = Utc.with_ymd_and_hms(2023, 5, 1, 12, 0, 0).unwrap();
+        for (id, user_id, token) in [(1, 1, "laptop"), (2, 1, "phone"), (3, 2, "other")] {
+            user_sessions::server::Model {
+                id,
+                user_id,
+                token: token.to_string(),
+                user_agent: None,
+                ip: None,
+                created_at: at,
+                last_seen_at: at,
+            }
+            .into_active_model()
+            .insert(&db)
+            .await
+            .unwrap();
+        }
+
+        let issued = IdentityClaims::of(&find(&db, 1).await);
+        assert_eq!(issued.resolve(&db).await.unwrap(), Some(find(&db, 1).await));
+
+        let mut revoked = bump_session_version(&db, 1).await.unwrap();
+        revoked.sort();
+        assert_eq!(revoked, ["laptop", "phone"]);
+        assert_eq!(issued.resolve(&db).await.unwrap(), None);
+
+        let bumped = find(&db, 1).await;
+        assert_eq!(bumped.session_version, 1);
+        assert_eq!(
+            IdentityClaims::of(&bumped).resolve(&db).await.unwrap(),
+            Some(bumped)
+        );
+
+        // Other users keep their identities and sessions
+        let other = IdentityClaims {
+            user_id: 2,
+            session_version: 0,
+        };
+        assert!(other.resolve(&db).await.unwrap().is_some());
+        assert_eq!(UserSessions::find().count(&db).await.unwrap(), 1);
+
+        let deleted = IdentityClaims {
+            user_id: 3,
+            session_version: 0,
+        };
+        assert_eq!(deleted.resolve(&db).await.unwrap(), None);
+    }
+}
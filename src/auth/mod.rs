@@ -0,0 +1,300 @@
+//! Server side session checks. `SessionGuard` logs out identities whose user was deleted or
+//! whose session version moved on, e.g. after a password reset, and sessions ended from the
+//! session list. It runs for server functions, sockets and media, pages and static files are
+//! served without a query and resolve the user through `CurrentUser` when they need it.
+
+pub mod config;
+pub mod identity;
+pub mod rotation;
+pub mod sessions;
+pub mod totp;
+
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use actix_identity::{Identity, IdentityExt};
+use actix_session::{Session, SessionExt};
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    web, Error, HttpMessage, HttpRequest,
+};
+use chrono::{DateTime, Utc};
+use futures_util::future::LocalBoxFuture;
+use sea_orm::DatabaseConnection;
+
+use crate::database::DbConnection;
+use crate::entities::users;
+use crate::server_function::UserLogin;
+use identity::{CurrentUser, IdentityClaims};
+
+/// Session key holding the user id and unix time of a correct password whose account still
+/// needs its second factor.
+pub const PENDING_TWO_FACTOR: &str = "pending_two_factor";
+
+/// How long the second factor can be entered after the password.
+pub const TWO_FACTOR_TTL_SECONDS: i64 = 5 * 60;
+
+/// Issues the identity of `user` on this session and adds it to the user's sessions.
+pub async fn log_in(
+    data: &DatabaseConnection,
+    request: &HttpRequest,
+    session: &Session,
+    user: &users::server::Model,
+    now: DateTime<Utc>,
+) -> Result<UserLogin, leptos::ServerFnError> {
+    Identity::login(&request.extensions(), IdentityClaims::of(user).encode())
+        .map_err(|e| leptos::ServerFnError::ServerError(e.to_string()))?;
+    session.remove(PENDING_TWO_FACTOR);
+    if let Some(previous) = sessions::current_token(session) {
+        sessions::end(data, &previous).await?;
+    }
+    sessions::register(
+        data,
+        session,
+        user.id,
+        sessions::Client::from_request(request),
+        now,
+    )
+    .await?;
+    Ok(user.clone().into())
+}
+
+/// The user whose password was entered on this session within the last
+/// `TWO_FACTOR_TTL_SECONDS`.
+pub fn pending_two_factor(session: &Session, now: DateTime<Utc>) -> Option<i32> {
+    session
+        .get::<(i32, i64)>(PENDING_TWO_FACTOR)
+        .ok()
+        .flatten()
+        .filter(|(_, started_at)| now.timestamp() - started_at < TWO_FACTOR_TTL_SECONDS)
+        .map(|(user_id, _)| user_id)
+}
+
+/// Path prefixes `SessionGuard` checks the identity on, everything that serves user data.
+const GUARDED_PATHS: [&str; 5] = ["/api/", "/ws/", "/upload/", "/images/", "/icons/"];
+
+fn is_guarded(path: &str) -> bool {
+    GUARDED_PATHS.iter().any(|prefix| path.starts_with(prefix))
+}
+
+pub struct SessionGuard;
+
+impl<S, B> Transform<S, ServiceRequest> for SessionGuard
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = SessionGuardMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SessionGuardMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct SessionGuardMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for SessionGuardMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            if !is_guarded(req.path()) {
+                return service.call(req).await;
+            }
+
+            if let Ok(identity) = req.get_identity() {
+                let claims = identity
+                    .id()
+                    .ok()
+                    .and_then(|id| IdentityClaims::decode(&id));
+                let data = req
+                    .app_data::<web::Data<tokio::sync::Mutex<DbConnection>>>()
+                    .cloned();
+
+                if let Some(data) = data {
+                    let db = &data.lock().await.connection;
+                    let session = req.get_session();
+                    let user = match claims {
+                        Some(claims) => claims.resolve(db).await,
+                        None => Ok(None),
+                    };
+
+                    match user {
+                        Ok(Some(user)) => {
+                            let client =
+                                sessions::Client::from_parts(req.headers(), req.peer_addr());
+                            let tracked =
+                                sessions::check(db, &session, user.id, client, Utc::now())
+                                    .await
+                                    .unwrap_or(true);
+                            match tracked {
+                                true => {
+                                    req.extensions_mut().insert(CurrentUser(user));
+                                }
+                                false => identity.logout(),
+                            }
+                        }
+                        Ok(None) => {
+                            if let Some(token) = sessions::current_token(&session) {
+                                let _ = sessions::end(db, &token).await;
+                            }
+                            identity.logout();
+                        }
+                        // A database error should not log everyone out
+                        Err(_) => {}
+                    }
+                }
+            }
+
+            service.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+    use crate::entities::prelude::{UserSessions, Users};
+    use actix_identity::IdentityMiddleware;
+    use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+    use actix_web::{
+        cookie::Key,
+        http::StatusCode,
+        test::{call_service, init_service, TestRequest},
+        App, HttpResponse,
+    };
+    use chrono::TimeZone;
+    use sea_orm::{ActiveModelTrait, EntityTrait, IntoActiveModel};
+
+    #[test]
+    fn guards_server_functions_sockets_and_media() {
+        assert!(is_guarded("/api/GetUser"));
+        assert!(is_guarded("/ws/icons/3"));
+        assert!(is_guarded("/ws/12"));
+        assert!(is_guarded("/upload/1690000000000.png"));
+        assert!(is_guarded("/images/avatar.png"));
+        assert!(is_guarded("/icons/3"));
+        assert!(!is_guarded("/"));
+        assert!(!is_guarded("/pkg/zing.wasm"));
+        assert!(!is_guarded("/apiary"));
+    }
+
+    #[test]
+    fn pending_two_factor_expires() {
+        let request = TestRequest::default().to_http_request();
+        let session = request.get_session();
+        let started_at = Utc.with_ymd_and_hms(2023, 5, 1, 12, 0, 0).unwrap();
+        assert_eq!(pending_two_factor(&session, started_at), None);
+
+        session
+            .insert(PENDING_TWO_FACTOR, (7, started_at.timestamp()))
+            .unwrap();
+        let after = |seconds| started_at + chrono::Duration::seconds(seconds);
+        assert_eq!(pending_two_factor(&session, after(0)), Some(7));
+        assert_eq!(
+            pending_two_factor(&session, after(TWO_FACTOR_TTL_SECONDS - 1)),
+            Some(7)
+        );
+        assert_eq!(
+            pending_two_factor(&session, after(TWO_FACTOR_TTL_SECONDS)),
+            None
+        );
+    }
+
+    async fn log_in_first_user(
+        request: HttpRequest,
+        session: Session,
+        data: web::Data<tokio::sync::Mutex<DbConnection>>,
+    ) -> HttpResponse {
+        let db = &data.lock().await.connection;
+        let user = Users::find_by_id(1).one(db).await.unwrap().unwrap();
+        log_in(db, &request, &session, &user, Utc::now())
+            .await
+            .unwrap();
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn revoked_sessions_can_not_load_media() {
+        let db = testing::sqlite().await;
+        testing::create_table(&db, Users).await;
+        testing::create_table(&db, UserSessions).await;
+        users::server::Model {
+            id: 1,
+            first_name: String::from("Alice"),
+            last_name: String::from("Tester"),
+            email: String::from("alice@example.com"),
+            phone_number: 0,
+            password: String::new(),
+            image: None,
+            send_read_receipts: 1,
+            email_missed_messages: 0,
+            email_digest: 0,
+            notified_until: None,
+            digest_sent_at: None,
+            totp_secret: None,
+            totp_enabled_at: None,
+            totp_last_step: None,
+            session_version: 0,
+        }
+        .into_active_model()
+        .insert(&db)
+        .await
+        .unwrap();
+
+        let data = web::Data::new(tokio::sync::Mutex::new(DbConnection {
+            connection: db.clone(),
+        }));
+        let app = init_service(
+            App::new()
+                .app_data(data)
+                .wrap(SessionGuard)
+                .wrap(IdentityMiddleware::default())
+                .wrap(
+                    SessionMiddleware::builder(CookieSessionStore::default(), Key::generate())
+                        .cookie_secure(false)
+                        .build(),
+                )
+                .route("/login", web::post().to(log_in_first_user))
+                .service(crate::media::upload_path),
+        )
+        .await;
+
+        let res = call_service(&app, TestRequest::post().uri("/login").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        // The cookie store keeps everything in the cookie, like a copied session cookie
+        let cookie = res.response().cookies().next().unwrap().into_owned();
+
+        let load = || {
+            TestRequest::get()
+                .uri("/images/missing.png")
+                .cookie(cookie.clone())
+                .to_request()
+        };
+        let res = call_service(&app, load()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        assert_eq!(sessions::revoke_all(&db, 1).await.unwrap().len(), 1);
+        let res = call_service(&app, load()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,277 @@
+//! Session key rotation. Until the previous key's grace period ends, `KeyRotation` decrypts
+//! session cookies made with it and hands `SessionMiddleware` the same cookie encrypted with
+//! the current key, which is also sent back so the browser stops using the old one.
+
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use actix_web::{
+    cookie::{Cookie, CookieJar, Key},
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderValue, COOKIE, SET_COOKIE},
+    Error,
+};
+use chrono::Utc;
+use futures_util::future::LocalBoxFuture;
+
+use super::config::{CookiePolicy, PreviousKey};
+
+/// `value` of the cookie `name` encrypted with `current`, when it currently only decrypts
+/// with `previous`.
+pub fn reencrypt(name: &str, value: &str, current: &Key, previous: &Key) -> Option<String> {
+    let mut jar = CookieJar::new();
+    jar.add_original(Cookie::new(name.to_string(), value.to_string()));
+    if jar.private(current).get(name).is_some() {
+        return None;
+    }
+    let plain = jar.private(previous).get(name)?;
+
+    let mut reencrypted = CookieJar::new();
+    reencrypted.private_mut(current).add(plain);
+    reencrypted
+        .get(name)
+        .map(|cookie| cookie.value().to_string())
+}
+
+/// The cookies of a `Cookie` header, skipping malformed ones.
+fn parse_cookies(header: &str) -> impl Iterator<Item = Cookie<'_>> {
+    header
+        .split(';')
+        .filter_map(|cookie| Cookie::parse(cookie.trim()).ok())
+}
+
+/// The `Cookie` header with the value of `name` replaced.
+fn replace_cookie(header: &str, name: &str, value: &str) -> String {
+    parse_cookies(header)
+        .map(|cookie| match cookie.name() == name {
+            true => format!("{name}={value}"),
+            false => format!("{}={}", cookie.name(), cookie.value()),
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+#[derive(Clone)]
+pub struct KeyRotation {
+    current: Key,
+    previous: Option<PreviousKey>,
+    cookie: CookiePolicy,
+}
+
+impl KeyRotation {
+    pub fn new(current: Key, previous: Option<PreviousKey>, cookie: CookiePolicy) -> Self {
+        Self {
+            current,
+            previous,
+            cookie,
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for KeyRotation
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = KeyRotationMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(KeyRotationMiddleware {
+            service: Rc::new(service),
+            rotation: self.clone(),
+        }))
+    }
+}
+
+pub struct KeyRotationMiddleware<S> {
+    service: Rc<S>,
+    rotation: KeyRotation,
+}
+
+impl<S, B> Service<ServiceRequest> for KeyRotationMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let KeyRotation {
+            current,
+            previous,
+            cookie,
+        } = self.rotation.clone();
+
+        Box::pin(async move {
+            let previous = previous.filter(|previous| Utc::now() < previous.until);
+            // The header is parsed by hand, `req.cookies()` would cache the old value
+            let header = req
+                .headers()
+                .get(COOKIE)
+                .and_then(|header| header.to_str().ok())
+                .map(String::from);
+            let reencrypted = previous.zip(header).and_then(|(previous, header)| {
+                let value = parse_cookies(&header)
+                    .find(|session| session.name() == cookie.name)
+                    .and_then(|session| {
+                        reencrypt(&cookie.name, session.value(), &current, &previous.key)
+                    })?;
+                let header = replace_cookie(&header, &cookie.name, &value);
+                Some((value, HeaderValue::from_str(&header).ok()?))
+            });
+
+            if let Some((_, header)) = &reencrypted {
+                req.headers_mut().insert(COOKIE, header.clone());
+            }
+            let mut res = service.call(req).await?;
+
+            if let Some((value, _)) = reencrypted {
+                let already_set = res.headers().get_all(SET_COOKIE).any(|set_cookie| {
+                    set_cookie
+                        .to_str()
+                        .map_or(false, |set_cookie| {
+                            set_cookie.starts_with(&format!("{}=", cookie.name))
+                        })
+                });
+                if !already_set {
+                    res.response_mut().add_cookie(&cookie.cookie(value))?;
+                }
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test::TestRequest, web, App, HttpRequest};
+
+    fn seal(key: &Key, value: &str) -> String {
+        let mut jar = CookieJar::new();
+        jar.private_mut(key)
+            .add(Cookie::new("id", value.to_string()));
+        jar.get("id").unwrap().value().to_string()
+    }
+
+    fn open(key: &Key, sealed: &str) -> Option<String> {
+        let mut jar = CookieJar::new();
+        jar.add_original(Cookie::new("id", sealed.to_string()));
+        jar.private(key)
+            .get("id")
+            .map(|cookie| cookie.value().to_string())
+    }
+
+    #[test]
+    fn reencrypts_only_previous_key_cookies() {
+        let (current, previous) = (Key::generate(), Key::generate());
+
+        let reencrypted =
+            reencrypt("id", &seal(&previous, "session"), &current, &previous).unwrap();
+        assert_eq!(open(&current, &reencrypted).as_deref(), Some("session"));
+        assert_eq!(open(&previous, &reencrypted), None);
+
+        assert_eq!(
+            reencrypt("id", &seal(&current, "session"), &current, &previous),
+            None
+        );
+        assert_eq!(
+            reencrypt(
+                "id",
+                &seal(&Key::generate(), "session"),
+                &current,
+                &previous
+            ),
+            None
+        );
+        assert_eq!(reencrypt("id", "garbage", &current, &previous), None);
+    }
+
+    #[test]
+    fn replaces_one_cookie() {
+        assert_eq!(
+            replace_cookie("theme=dark; id=old; lang=en", "id", "new"),
+            "theme=dark; id=new; lang=en"
+        );
+    }
+
+    /// Sends `cookie` through the rotation, returns the cookie header the app saw and the
+    /// session cookie set on the response.
+    async fn call(rotation: KeyRotation, cookie: &str) -> (String, Option<String>) {
+        let app = actix_web::test::init_service(App::new().wrap(rotation).route(
+            "/",
+            web::get().to(|req: HttpRequest| async move {
+                req.headers()
+                    .get(COOKIE)
+                    .map(|header| header.to_str().unwrap().to_string())
+                    .unwrap_or_default()
+            }),
+        ))
+        .await;
+        let req = TestRequest::get()
+            .uri("/")
+            .insert_header((COOKIE, cookie))
+            .to_request();
+        let res = actix_web::test::call_service(&app, req).await;
+        let set = res
+            .response()
+            .cookies()
+            .find(|cookie| cookie.name() == "id")
+            .map(|cookie| cookie.value().to_string());
+        let seen = String::from_utf8(actix_web::test::read_body(res).await.to_vec()).unwrap();
+        (seen, set)
+    }
+
+    fn session_of(header: &str) -> String {
+        parse_cookies(header)
+            .find(|cookie| cookie.name() == "id")
+            .unwrap()
+            .value()
+            .to_string()
+    }
+
+    #[actix_web::test]
+    async fn previous_key_cookies_are_reencrypted_until_the_end() {
+        let (current, previous) = (Key::generate(), Key::generate());
+        let rotation = |until| {
+            KeyRotation::new(
+                current.clone(),
+                Some(PreviousKey {
+                    key: previous.clone(),
+                    until,
+                }),
+                CookiePolicy::default(),
+            )
+        };
+        let old = format!("theme=dark; id={}", seal(&previous, "session"));
+
+        let (seen, set) = call(rotation(Utc::now() + chrono::Duration::hours(1)), &old).await;
+        assert!(seen.starts_with("theme=dark; "));
+        assert_eq!(
+            open(&current, &session_of(&seen)).as_deref(),
+            Some("session")
+        );
+        assert_eq!(set, Some(session_of(&seen)));
+
+        // Cookies of the current key pass untouched
+        let fresh = format!("id={}", seal(&current, "session"));
+        let (seen, set) = call(rotation(Utc::now() + chrono::Duration::hours(1)), &fresh).await;
+        assert_eq!(seen, fresh);
+        assert_eq!(set, None);
+
+        // After the end the previous key is dropped, its cookies no longer open
+        let (seen, set) = call(rotation(Utc::now() - chrono::Duration::seconds(1)), &old).await;
+        assert_eq!(seen, old);
+        assert_eq!(set, None);
+        assert_eq!(open(&current, &session_of(&seen)), None);
+    }
+}
@@ -0,0 +1,249 @@
+//! Sessions of logged in users, so they can be listed and ended from elsewhere. The session
+//! state in Redis holds a random token naming a row of `user_sessions`, `SessionGuard` logs
+//! out sessions whose row is gone. Sessions from before this was tracked get a row on their
+//! next request.
+
+use actix_session::Session;
+use base64::{engine::general_purpose, Engine};
+use chrono::{DateTime, Utc};
+use rand::{rngs::OsRng, RngCore};
+use sea_orm::{
+    sea_query::Expr, ActiveValue, ColumnTrait, DatabaseConnection, DbErr, EntityTrait,
+    QueryFilter, QueryOrder,
+};
+
+use crate::entities::{prelude::*, user_sessions};
+use crate::server_function::SessionInfo;
+use crate::web_socket::server::{ChatServer, CloseSessions, IconWs};
+
+/// Session key holding the token of the session's `user_sessions` row.
+pub const SESSION_TOKEN: &str = "session_token";
+
+/// Last activity is written at most this often per session.
+pub const TOUCH_INTERVAL_SECONDS: i64 = 60;
+
+/// Where a request came from, as recorded on the session.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Client {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl Client {
+    pub fn from_parts(
+        headers: &actix_web::http::header::HeaderMap,
+        peer_addr: Option<std::net::SocketAddr>,
+    ) -> Self {
+        Self {
+            user_agent: headers
+                .get(actix_web::http::header::USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.chars().take(255).collect()),
+            ip: peer_addr.map(|address| address.ip().to_string()),
+        }
+    }
+
+    pub fn from_request(request: &actix_web::HttpRequest) -> Self {
+        Self::from_parts(request.headers(), request.peer_addr())
+    }
+}
+
+/// A short "Browser on OS" description of a user agent.
+pub fn device_name(user_agent: Option<&str>) -> String {
+    let Some(user_agent) = user_agent else {
+        return String::from("Unknown device");
+    };
+
+    // Order matters, e.g. every Chromium based browser also claims to be Chrome and Safari
+    let browser = [
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("Firefox/", "Firefox"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+    ]
+    .into_iter()
+    .find(|(marker, _)| user_agent.contains(marker))
+    .map_or("Unknown browser", |(_, name)| name);
+    let os = [
+        ("Android", "Android"),
+        ("iPhone", "iOS"),
+        ("iPad", "iPadOS"),
+        ("Windows", "Windows"),
+        ("Mac OS X", "macOS"),
+        ("Linux", "Linux"),
+    ]
+    .into_iter()
+    .find(|(marker, _)| user_agent.contains(marker))
+    .map_or("unknown OS", |(_, name)| name);
+
+    format!("{browser} on {os}")
+}
+
+/// Whether a session last seen at `last_seen_at` should have its activity written again.
+pub fn needs_touch(last_seen_at: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+    (now - last_seen_at).num_seconds() >= TOUCH_INTERVAL_SECONDS
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub fn current_token(session: &Session) -> Option<String> {
+    session.get::<String>(SESSION_TOKEN).ok().flatten()
+}
+
+/// Records a new session of `user_id` and names it in the session state.
+pub async fn register(
+    data: &DatabaseConnection,
+    session: &Session,
+    user_id: i32,
+    client: Client,
+    now: DateTime<Utc>,
+) -> Result<(), DbErr> {
+    let token = generate_token();
+    UserSessions::insert(user_sessions::server::ActiveModel {
+        user_id: ActiveValue::Set(user_id),
+        token: ActiveValue::Set(token.clone()),
+        user_agent: ActiveValue::Set(client.user_agent),
+        ip: ActiveValue::Set(client.ip),
+        created_at: ActiveValue::Set(now),
+        last_seen_at: ActiveValue::Set(now),
+        ..Default::default()
+    })
+    .exec_without_returning(data)
+    .await?;
+
+    session
+        .insert(SESSION_TOKEN, token)
+        .map_err(|e| DbErr::Custom(e.to_string()))
+}
+
+/// Checks the session of `user_id` on a request. Returns false when it was revoked, registers
+/// sessions that have no row yet and records activity otherwise.
+pub async fn check(
+    data: &DatabaseConnection,
+    session: &Session,
+    user_id: i32,
+    client: Client,
+    now: DateTime<Utc>,
+) -> Result<bool, DbErr> {
+    let Some(token) = current_token(session) else {
+        register(data, session, user_id, client, now).await?;
+        return Ok(true);
+    };
+
+    match UserSessions::find()
+        .filter(user_sessions::server::Column::Token.eq(token))
+        .one(data)
+        .await?
+    {
+        Some(row) if row.user_id == user_id => {
+            if needs_touch(row.last_seen_at, now) {
+                UserSessions::update_many()
+                    .col_expr(user_sessions::server::Column::LastSeenAt, Expr::value(now))
+                    .col_expr(user_sessions::server::Column::Ip, Expr::value(client.ip))
+                    .filter(user_sessions::server::Column::Id.eq(row.id))
+                    .exec(data)
+                    .await?;
+            }
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// The sessions of `user_id`, most recently active first.
+pub async fn list(
+    data: &DatabaseConnection,
+    user_id: i32,
+    current: Option<&str>,
+) -> Result<Vec<SessionInfo>, DbErr> {
+    Ok(UserSessions::find()
+        .filter(user_sessions::server::Column::UserId.eq(user_id))
+        .order_by_desc(user_sessions::server::Column::LastSeenAt)
+        .all(data)
+        .await?
+        .into_iter()
+        .map(|row| SessionInfo {
+            id: row.id,
+            device: device_name(row.user_agent.as_deref()),
+            ip: row.ip,
+            created_at: row.created_at.timestamp(),
+            last_seen_at: row.last_seen_at.timestamp(),
+            current: Some(row.token.as_str()) == current,
+        })
+        .collect())
+}
+
+/// Deletes the matching sessions of `user_id`, returns their tokens so their sockets can be
+/// closed.
+async fn revoke_where(
+    data: &DatabaseConnection,
+    user_id: i32,
+    condition: sea_orm::Condition,
+) -> Result<Vec<String>, DbErr> {
+    let rows = UserSessions::find()
+        .filter(user_sessions::server::Column::UserId.eq(user_id))
+        .filter(condition)
+        .all(data)
+        .await?;
+    UserSessions::delete_many()
+        .filter(user_sessions::server::Column::Id.is_in(rows.iter().map(|row| row.id)))
+        .exec(data)
+        .await?;
+    Ok(rows.into_iter().map(|row| row.token).collect())
+}
+
+pub async fn revoke(
+    data: &DatabaseConnection,
+    user_id: i32,
+    session_id: i32,
+) -> Result<Vec<String>, DbErr> {
+    revoke_where(
+        data,
+        user_id,
+        sea_orm::Condition::all().add(user_sessions::server::Column::Id.eq(session_id)),
+    )
+    .await
+}
+
+/// Revokes every session of `user_id` except the one holding `current`.
+pub async fn revoke_others(
+    data: &DatabaseConnection,
+    user_id: i32,
+    current: &str,
+) -> Result<Vec<String>, DbErr> {
+    revoke_where(
+        data,
+        user_id,
+        sea_orm::Condition::all().add(user_sessions::server::Column::Token.ne(current)),
+    )
+    .await
+}
+
+pub async fn revoke_all(data: &DatabaseConnection, user_id: i32) -> Result<Vec<String>, DbErr> {
+    revoke_where(data, user_id, sea_orm::Condition::all()).await
+}
+
+/// Forgets the session holding `token`, on logout.
+pub async fn end(data: &DatabaseConnection, token: &str) -> Result<(), DbErr> {
+    UserSessions::delete_many()
+        .filter(user_sessions::server::Column::Token.eq(token))
+        .exec(data)
+        .await?;
+    Ok(())
+}
+
+/// Closes the sockets opened by revoked sessions.
+pub fn close_sockets(
+    server: &actix::Addr<ChatServer>,
+    icon_server: &actix::Addr<IconWs>,
+    session_tokens: Vec<String>,
+) {
+    let message = CloseSessions { session_tokens };
+    server.do_send(message.clone());
+    icon_server.do_send(message);
+}
@@ -0,0 +1,290 @@
+//! Time based one time passwords (RFC 6238) as shown by authenticator apps: HMAC-SHA1, six
+//! digits, 30 second steps. Every function takes the current time so codes can be checked
+//! against a fixed clock. Secrets are stored encrypted with AES-256-GCM under
+//! `TOTP_ENCRYPTION_KEY`.
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::{engine::general_purpose, Engine};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use rand::{Rng, RngCore};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+pub const STEP_SECONDS: i64 = 30;
+pub const DIGITS: u32 = 6;
+/// Steps accepted either side of the current one, for clocks that are a little off
+pub const SKEW_STEPS: i64 = 1;
+pub const SECRET_BYTES: usize = 20;
+pub const ISSUER: &str = "Zing";
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const NONCE_BYTES: usize = 12;
+
+lazy_static! {
+    /// Key encrypting the stored secrets, derived from `TOTP_ENCRYPTION_KEY`. Falls back to a
+    /// per-process key, which leaves enrolled users with only their recovery codes after a
+    /// restart, so the variable has to be set wherever 2FA is used.
+    static ref ENCRYPTION_KEY: [u8; 32] = match std::env::var("TOTP_ENCRYPTION_KEY") {
+        Ok(key) => Sha256::digest(key.as_bytes()).into(),
+        Err(_) => {
+            println!("TOTP_ENCRYPTION_KEY is not set, 2FA secrets will not survive a restart");
+            let mut key = [0u8; 32];
+            OsRng.fill_bytes(&mut key);
+            key
+        }
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CryptoError;
+
+impl std::fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Two factor secret could not be decrypted")
+    }
+}
+
+impl std::error::Error for CryptoError {}
+
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; SECRET_BYTES];
+    OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+/// Unpadded RFC 4648 base32, the form authenticator apps take secrets in.
+pub fn base32(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+        buffer &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// The `otpauth://` URI encoded in the enrollment QR code.
+pub fn otpauth_uri(account: &str, secret: &[u8]) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECONDS}",
+        issuer = percent_encode(ISSUER),
+        account = percent_encode(account),
+        secret = base32(secret),
+    )
+}
+
+pub fn step(now: DateTime<Utc>) -> i64 {
+    now.timestamp().div_euclid(STEP_SECONDS)
+}
+
+pub fn code_at(secret: &[u8], step: i64) -> String {
+    let mut mac = <Hmac<Sha1> as Mac>::new_from_slice(secret).expect("HMAC accepts any key size");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let truncated = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        truncated % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// The step `code` belongs to, if it is valid at `now`. Steps up to `last_step` were already
+/// used and are rejected, so a code can not be replayed.
+pub fn verify(
+    secret: &[u8],
+    code: &str,
+    now: DateTime<Utc>,
+    last_step: Option<i64>,
+) -> Option<i64> {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    let current = step(now);
+    (current - SKEW_STEPS..=current + SKEW_STEPS)
+        .filter(|step| last_step.map_or(true, |last_step| *step > last_step))
+        .find(|step| constant_time_eq(code_at(secret, *step).as_bytes(), code.as_bytes()))
+}
+
+/// Whether `code` is shaped like a TOTP code rather than a recovery code.
+pub fn is_totp_code(code: &str) -> bool {
+    let code = code.trim();
+    code.len() == DIGITS as usize && code.chars().all(|c| c.is_ascii_digit())
+}
+
+/// A recovery code like `k3v9q-x2m7p`, lowercase letters and digits.
+pub fn recovery_code() -> String {
+    const CHARSET: &[u8] = b"abcdefghijkmnpqrstuvwxyz23456789";
+    let mut rng = OsRng;
+    let mut part = || {
+        (0..5)
+            .map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char)
+            .collect::<String>()
+    };
+    format!("{}-{}", part(), part())
+}
+
+/// Recovery codes are compared case insensitively and without surrounding whitespace.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.trim().to_lowercase()
+}
+
+pub fn encrypt_secret(secret: &[u8]) -> String {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&*ENCRYPTION_KEY));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, secret)
+        .expect("encrypting a short secret can not fail");
+
+    let mut stored = nonce.to_vec();
+    stored.extend(ciphertext);
+    general_purpose::STANDARD.encode(stored)
+}
+
+pub fn decrypt_secret(stored: &str) -> Result<Vec<u8>, CryptoError> {
+    let stored = general_purpose::STANDARD
+        .decode(stored)
+        .map_err(|_| CryptoError)?;
+    if stored.len() <= NONCE_BYTES {
+        return Err(CryptoError);
+    }
+    let (nonce, ciphertext) = stored.split_at(NONCE_BYTES);
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&*ENCRYPTION_KEY))
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| CryptoError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// The SHA-1 seed of RFC 6238 appendix B.
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    fn at(timestamp: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(timestamp, 0).unwrap()
+    }
+
+    #[test]
+    fn matches_rfc_6238_vectors() {
+        // The RFC lists eight digits, six digit codes are their last six
+        for (timestamp, code) in [
+            (59, "94287082"),
+            (1111111109, "07081804"),
+            (1111111111, "14050471"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+            (20000000000, "65353130"),
+        ] {
+            assert_eq!(code_at(RFC_SECRET, step(at(timestamp))), code[2..]);
+        }
+    }
+
+    #[test]
+    fn base32_matches_rfc_4648() {
+        for (bytes, encoded) in [
+            ("", ""),
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ] {
+            assert_eq!(base32(bytes.as_bytes()), encoded);
+        }
+    }
+
+    #[test]
+    fn verify_accepts_neighbouring_steps() {
+        let now = at(1111111111);
+        let current = step(now);
+        for offset in -SKEW_STEPS..=SKEW_STEPS {
+            let code = code_at(RFC_SECRET, current + offset);
+            assert_eq!(verify(RFC_SECRET, &code, now, None), Some(current + offset));
+        }
+
+        let too_old = code_at(RFC_SECRET, current - SKEW_STEPS - 1);
+        assert_eq!(verify(RFC_SECRET, &too_old, now, None), None);
+        assert_eq!(verify(RFC_SECRET, "000000", now, None), None);
+        assert_eq!(verify(RFC_SECRET, "", now, None), None);
+        assert_eq!(
+            verify(RFC_SECRET, "050 471", now, None),
+            Some(current),
+            "spaces are ignored"
+        );
+    }
+
+    #[test]
+    fn verify_rejects_used_steps() {
+        let now = at(1111111111);
+        let current = step(now);
+        let code = code_at(RFC_SECRET, current);
+        assert_eq!(verify(RFC_SECRET, &code, now, Some(current)), None);
+        assert_eq!(verify(RFC_SECRET, &code, now, Some(current + 1)), None);
+        assert_eq!(
+            verify(RFC_SECRET, &code, now, Some(current - 1)),
+            Some(current)
+        );
+    }
+
+    #[test]
+    fn recovery_codes_are_not_totp_codes() {
+        let code = recovery_code();
+        assert_eq!(code.len(), 11);
+        assert!(!is_totp_code(&code));
+        assert!(is_totp_code(" 123456 "));
+        assert!(!is_totp_code("12345"));
+        assert_eq!(normalize_recovery_code(" K3V9Q-X2M7P\n"), "k3v9q-x2m7p");
+    }
+
+    #[test]
+    fn secrets_survive_encryption() {
+        let secret = generate_secret();
+        let stored = encrypt_secret(&secret);
+        assert_ne!(encrypt_secret(&secret), stored, "nonces are random");
+        assert_eq!(decrypt_secret(&stored), Ok(secret));
+
+        let mut tampered = general_purpose::STANDARD.decode(&stored).unwrap();
+        *tampered.last_mut().unwrap() ^= 1;
+        let tampered = general_purpose::STANDARD.encode(tampered);
+        assert_eq!(decrypt_secret(&tampered), Err(CryptoError));
+        assert_eq!(decrypt_secret("not base64"), Err(CryptoError));
+    }
+}
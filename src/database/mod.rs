@@ -121,3 +121,24 @@ pub async fn database_run() -> Result<(), DbErr> {
     assert!(schema_manager.has_table("users").await?);
     Ok(())
 }
+
+/// In-memory SQLite databases for tests, the migrations only run on MySQL.
+#[cfg(all(test, feature = "ssr"))]
+pub(crate) mod testing {
+    use sea_orm::{ConnectionTrait, Database, DatabaseConnection, EntityTrait, Schema};
+
+    pub async fn sqlite() -> DatabaseConnection {
+        Database::connect("sqlite::memory:")
+            .await
+            .expect("in-memory sqlite")
+    }
+
+    /// Creates the table of `entity` from its definition, foreign keys included.
+    pub async fn create_table<E: EntityTrait>(db: &DatabaseConnection, entity: E) {
+        let backend = db.get_database_backend();
+        let statement = Schema::new(backend).create_table_from_entity(entity);
+        db.execute(backend.build(&statement))
+            .await
+            .expect("create table");
+    }
+}
@@ -0,0 +1,200 @@
+//! The emails the app sends, rendered from the Askama templates and handed to a `Mailer`.
+
+use askama::Template;
+use base64::engine::{general_purpose, Engine as _};
+use lazy_static::lazy_static;
+use lettre::message::{header::ContentType, Mailbox, MultiPart};
+use lettre::Message;
+use serde::Serialize;
+
+use super::mailer::{self, MailError, Mailer};
+
+lazy_static! {
+    /// The logo font, embedded into every email. Read once, an email without it still renders
+    /// with a fallback font.
+    static ref FONT: String = match std::fs::read("./assets/MagicSchoolTwo.ttf") {
+        Ok(font) => general_purpose::STANDARD_NO_PAD.encode(font),
+        Err(e) => {
+            println!("Could not read the email font: {e}");
+            String::new()
+        }
+    };
+}
+
+#[derive(Template)]
+#[template(path = "template.html")]
+#[derive(Serialize)]
+struct Context<'a> {
+    first_name: &'a str,
+    verification_code: &'a str,
+    base_64: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "password_reset.html")]
+#[derive(Serialize)]
+struct PasswordResetContext<'a> {
+    first_name: &'a str,
+    link: &'a str,
+    expires_in_minutes: i64,
+    base_64: &'a str,
+}
+
+/// One conversation listed in a notification email.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConversationSummary {
+    pub name: String,
+    pub unread: u64,
+}
+
+/// A missed message email or daily digest, sent with an HTML and a plain text part.
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub subject: String,
+    pub first_name: String,
+    pub intro: String,
+    pub conversations: Vec<ConversationSummary>,
+    pub unsubscribe_link: String,
+}
+
+#[derive(Template)]
+#[template(path = "notification.html")]
+struct NotificationHtml<'a> {
+    first_name: &'a str,
+    intro: &'a str,
+    conversations: &'a [ConversationSummary],
+    app_url: &'a str,
+    unsubscribe_link: &'a str,
+    base_64: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "notification.txt")]
+struct NotificationText<'a> {
+    first_name: &'a str,
+    intro: &'a str,
+    conversations: &'a [ConversationSummary],
+    app_url: &'a str,
+    unsubscribe_link: &'a str,
+}
+
+/// Where links in emails point, `APP_URL` overrides the local address.
+pub fn app_url() -> String {
+    std::env::var("APP_URL")
+        .unwrap_or_else(|_| String::from("http://localhost:8000"))
+        .trim_end_matches('/')
+        .to_string()
+}
+
+pub struct EmailClient {
+    mailer: Box<dyn Mailer>,
+    from: Mailbox,
+}
+
+impl EmailClient {
+    pub fn new(mailer: impl Mailer + 'static, from: Mailbox) -> Self {
+        Self {
+            mailer: Box::new(mailer),
+            from,
+        }
+    }
+
+    /// Uses the transport of `mailer::from_env`, sending as `MAIL_FROM`.
+    pub fn from_env() -> Result<Self, MailError> {
+        let from = std::env::var("MAIL_FROM")
+            .unwrap_or_else(|_| String::from("ZING <no-reply@localhost>"))
+            .parse()?;
+        Ok(Self {
+            mailer: mailer::from_env()?,
+            from,
+        })
+    }
+
+    async fn deliver(&self, recipient: &str, subject: &str, body: String) -> Result<(), MailError> {
+        let email = Message::builder()
+            .from(self.from.clone())
+            .to(recipient.parse()?)
+            .subject(subject)
+            .header(ContentType::TEXT_HTML)
+            .body(body)?;
+        self.mailer.send(&email).await
+    }
+
+    async fn deliver_alternative(
+        &self,
+        recipient: &str,
+        subject: &str,
+        text: String,
+        html: String,
+    ) -> Result<(), MailError> {
+        let email = Message::builder()
+            .from(self.from.clone())
+            .to(recipient.parse()?)
+            .subject(subject)
+            .multipart(MultiPart::alternative_plain_html(text, html))?;
+        self.mailer.send(&email).await
+    }
+
+    pub async fn send_verification(
+        &self,
+        recipient: &str,
+        first_name: &str,
+        verification_code: &str,
+    ) -> Result<(), MailError> {
+        let template = Context {
+            first_name,
+            verification_code,
+            base_64: &FONT,
+        };
+        self.deliver(recipient, "Email Verification", template.render()?)
+            .await
+    }
+
+    /// Emails the one-time `link` of a password reset.
+    pub async fn send_password_reset(
+        &self,
+        recipient: &str,
+        first_name: &str,
+        link: &str,
+        expires_in_minutes: i64,
+    ) -> Result<(), MailError> {
+        let template = PasswordResetContext {
+            first_name,
+            link,
+            expires_in_minutes,
+            base_64: &FONT,
+        };
+        self.deliver(recipient, "Password Reset", template.render()?)
+            .await
+    }
+
+    pub async fn send_notification(
+        &self,
+        recipient: &str,
+        notification: &Notification,
+    ) -> Result<(), MailError> {
+        let app_url = app_url();
+        let html = NotificationHtml {
+            first_name: &notification.first_name,
+            intro: &notification.intro,
+            conversations: &notification.conversations,
+            app_url: &app_url,
+            unsubscribe_link: &notification.unsubscribe_link,
+            base_64: &FONT,
+        };
+        let text = NotificationText {
+            first_name: &notification.first_name,
+            intro: &notification.intro,
+            conversations: &notification.conversations,
+            app_url: &app_url,
+            unsubscribe_link: &notification.unsubscribe_link,
+        };
+        self.deliver_alternative(
+            recipient,
+            &notification.subject,
+            text.render()?,
+            html.render()?,
+        )
+        .await
+    }
+}
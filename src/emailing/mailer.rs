@@ -0,0 +1,405 @@
+//! Where outgoing email goes. `from_env` picks the transport from `MAIL_TRANSPORT`:
+//!
+//! * `smtp` relays through `MAIL_HOST`, with `MAIL_PORT`, `MAIL_TLS` (`tls`, `starttls` or
+//!   `none`) and optionally `MAIL_USERNAME` and `MAIL_PASSWORD`
+//! * `mailhog` sends to a local SMTP stand-in on `localhost:1025`, e.g. MailHog in tests
+//! * `file` writes every email to `MAIL_DIR`, `./mail` by default
+//! * `stdout`, the default, prints every email. Nothing reaches an inbox then, so an unset
+//!   `MAIL_TRANSPORT` is logged at startup
+//!
+//! Sending is async, SMTP goes through the Tokio transport so a slow relay does not hold up a
+//! worker thread.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+#[derive(Debug)]
+pub enum MailError {
+    Config(String),
+    Address(lettre::address::AddressError),
+    Build(lettre::error::Error),
+    Render(askama::Error),
+    Smtp(lettre::transport::smtp::Error),
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for MailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MailError::Config(e) => write!(f, "Mail configuration error: {e}"),
+            MailError::Address(e) => write!(f, "Invalid email address: {e}"),
+            MailError::Build(e) => write!(f, "Could not build email: {e}"),
+            MailError::Render(e) => write!(f, "Could not render email: {e}"),
+            MailError::Smtp(e) => write!(f, "Could not send email: {e}"),
+            MailError::Io(e) => write!(f, "Could not write email: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for MailError {}
+
+impl From<lettre::address::AddressError> for MailError {
+    fn from(value: lettre::address::AddressError) -> Self {
+        MailError::Address(value)
+    }
+}
+
+impl From<lettre::error::Error> for MailError {
+    fn from(value: lettre::error::Error) -> Self {
+        MailError::Build(value)
+    }
+}
+
+impl From<askama::Error> for MailError {
+    fn from(value: askama::Error) -> Self {
+        MailError::Render(value)
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for MailError {
+    fn from(value: lettre::transport::smtp::Error) -> Self {
+        MailError::Smtp(value)
+    }
+}
+
+impl From<std::io::Error> for MailError {
+    fn from(value: std::io::Error) -> Self {
+        MailError::Io(value)
+    }
+}
+
+/// Delivers a finished email.
+#[async_trait::async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, message: &Message) -> Result<(), MailError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsMode {
+    /// Plain text, only meant for local stand-ins
+    None,
+    StartTls,
+    /// Implicit TLS from the first byte, usually port 465
+    Tls,
+}
+
+impl std::str::FromStr for TlsMode {
+    type Err = MailError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(TlsMode::None),
+            "starttls" => Ok(TlsMode::StartTls),
+            "tls" => Ok(TlsMode::Tls),
+            other => Err(MailError::Config(format!("Unknown MAIL_TLS mode {other}"))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmtpConfig {
+    pub host: String,
+    /// The default port of the TLS mode when missing
+    pub port: Option<u16>,
+    pub tls: TlsMode,
+    pub credentials: Option<(String, String)>,
+}
+
+impl SmtpConfig {
+    /// Reads the `MAIL_` settings through `var`, so they can come from anywhere.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, MailError> {
+        let host = var("MAIL_HOST")
+            .ok_or_else(|| MailError::Config(String::from("MAIL_HOST is not set")))?;
+        let port = var("MAIL_PORT")
+            .map(|port| {
+                port.parse::<u16>()
+                    .map_err(|_| MailError::Config(format!("Invalid MAIL_PORT {port}")))
+            })
+            .transpose()?;
+        let tls = var("MAIL_TLS")
+            .map(|tls| tls.parse())
+            .transpose()?
+            .unwrap_or(TlsMode::StartTls);
+        let credentials = match (var("MAIL_USERNAME"), var("MAIL_PASSWORD")) {
+            (Some(username), Some(password)) => Some((username, password)),
+            (None, None) => None,
+            _ => {
+                return Err(MailError::Config(String::from(
+                    "MAIL_USERNAME and MAIL_PASSWORD must be set together",
+                )))
+            }
+        };
+
+        Ok(Self {
+            host,
+            port,
+            tls,
+            credentials,
+        })
+    }
+
+    /// MailHog, or any SMTP stand-in, listening on its default local port.
+    pub fn mailhog() -> Self {
+        Self {
+            host: String::from("localhost"),
+            port: Some(1025),
+            tls: TlsMode::None,
+            credentials: None,
+        }
+    }
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpMailer {
+    pub fn new(config: SmtpConfig) -> Result<Self, MailError> {
+        type Transport = AsyncSmtpTransport<Tokio1Executor>;
+        let mut builder = match config.tls {
+            TlsMode::None => Transport::builder_dangerous(&config.host),
+            TlsMode::StartTls => Transport::starttls_relay(&config.host)?,
+            TlsMode::Tls => Transport::relay(&config.host)?,
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let Some((username, password)) = config.credentials {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, message: &Message) -> Result<(), MailError> {
+        self.transport.send(message.clone()).await?;
+        Ok(())
+    }
+}
+
+/// Writes every email as an `.eml` file, for development.
+pub struct FileMailer {
+    dir: PathBuf,
+    sent: AtomicUsize,
+}
+
+impl FileMailer {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            sent: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, message: &Message) -> Result<(), MailError> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let file_name = format!(
+            "{}-{}.eml",
+            chrono::Utc::now().timestamp_millis(),
+            self.sent.fetch_add(1, Ordering::Relaxed)
+        );
+        tokio::fs::write(self.dir.join(file_name), message.formatted()).await?;
+        Ok(())
+    }
+}
+
+/// Prints every email, for development.
+pub struct StdoutMailer;
+
+#[async_trait::async_trait]
+impl Mailer for StdoutMailer {
+    async fn send(&self, message: &Message) -> Result<(), MailError> {
+        println!("{}", String::from_utf8_lossy(&message.formatted()));
+        Ok(())
+    }
+}
+
+/// Called once at startup, which is where an unset `MAIL_TRANSPORT` gets logged.
+pub fn from_env() -> Result<Box<dyn Mailer>, MailError> {
+    let var = |name: &str| std::env::var(name).ok();
+    let transport = var("MAIL_TRANSPORT").unwrap_or_else(|| {
+        println!("MAIL_TRANSPORT is not set, emails are printed instead of sent");
+        String::from("stdout")
+    });
+    match transport.as_str() {
+        "smtp" => Ok(Box::new(SmtpMailer::new(SmtpConfig::from_vars(var)?)?)),
+        "mailhog" => Ok(Box::new(SmtpMailer::new(SmtpConfig::mailhog())?)),
+        "file" => Ok(Box::new(FileMailer::new(
+            var("MAIL_DIR").unwrap_or_else(|| String::from("./mail")),
+        ))),
+        "stdout" => Ok(Box::new(StdoutMailer)),
+        other => Err(MailError::Config(format!("Unknown MAIL_TRANSPORT {other}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    fn message() -> Message {
+        Message::builder()
+            .from("ZING <no-reply@localhost>".parse().unwrap())
+            .to("someone@example.com".parse().unwrap())
+            .subject("Hello")
+            .body(String::from("See you on ZING!"))
+            .unwrap()
+    }
+
+    /// Takes a single email the way MailHog does, returns the commands and the data it got.
+    async fn accept_one(listener: TcpListener) -> (Vec<String>, String) {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        let mut commands = Vec::new();
+        let mut data = String::new();
+
+        write.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            let command = line.to_ascii_uppercase();
+            commands.push(line);
+            if command.starts_with("EHLO") {
+                write
+                    .write_all(b"250-localhost\r\n250 8BITMIME\r\n")
+                    .await
+                    .unwrap();
+            } else if command.starts_with("DATA") {
+                write.write_all(b"354 Go ahead\r\n").await.unwrap();
+                while let Some(line) = lines.next_line().await.unwrap() {
+                    if line == "." {
+                        break;
+                    }
+                    data.push_str(&line);
+                    data.push('\n');
+                }
+                write.write_all(b"250 Queued\r\n").await.unwrap();
+                // The transport keeps the connection for the next email
+                break;
+            } else {
+                write.write_all(b"250 OK\r\n").await.unwrap();
+            }
+        }
+        (commands, data)
+    }
+
+    #[tokio::test]
+    async fn smtp_mailer_delivers_to_a_local_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(accept_one(listener));
+
+        let mailer = SmtpMailer::new(SmtpConfig {
+            host: String::from("127.0.0.1"),
+            port: Some(port),
+            ..SmtpConfig::mailhog()
+        })
+        .unwrap();
+        mailer.send(&message()).await.unwrap();
+
+        let (commands, data) = server.await.unwrap();
+        assert!(commands[0].starts_with("EHLO"));
+        assert!(commands[1].starts_with("MAIL FROM:<no-reply@localhost>"));
+        assert!(commands[2].starts_with("RCPT TO:<someone@example.com>"));
+        assert!(data.contains("Subject: Hello"));
+        assert!(data.contains("See you on ZING!"));
+    }
+
+    #[tokio::test]
+    async fn smtp_mailer_reports_an_unreachable_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let mailer = SmtpMailer::new(SmtpConfig {
+            host: String::from("127.0.0.1"),
+            port: Some(port),
+            ..SmtpConfig::mailhog()
+        })
+        .unwrap();
+        assert!(matches!(
+            mailer.send(&message()).await,
+            Err(MailError::Smtp(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn file_mailer_writes_eml_files() {
+        let dir = std::env::temp_dir().join(format!("zing-mail-{}", std::process::id()));
+        let mailer = FileMailer::new(&dir);
+        mailer.send(&message()).await.unwrap();
+        mailer.send(&message()).await.unwrap();
+
+        let mut files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.sort();
+        assert_eq!(files.len(), 2);
+        assert!(files.iter().all(|file| file.extension().unwrap() == "eml"));
+        let email = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(email.contains("Subject: Hello"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn smtp_config_from_vars() {
+        let vars = |pairs: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                pairs
+                    .iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+
+        assert_eq!(
+            SmtpConfig::from_vars(vars(&[("MAIL_HOST", "smtp.example.com")])).unwrap(),
+            SmtpConfig {
+                host: String::from("smtp.example.com"),
+                port: None,
+                tls: TlsMode::StartTls,
+                credentials: None,
+            }
+        );
+        assert_eq!(
+            SmtpConfig::from_vars(vars(&[
+                ("MAIL_HOST", "smtp.example.com"),
+                ("MAIL_PORT", "465"),
+                ("MAIL_TLS", "TLS"),
+                ("MAIL_USERNAME", "zing"),
+                ("MAIL_PASSWORD", "secret"),
+            ]))
+            .unwrap(),
+            SmtpConfig {
+                host: String::from("smtp.example.com"),
+                port: Some(465),
+                tls: TlsMode::Tls,
+                credentials: Some((String::from("zing"), String::from("secret"))),
+            }
+        );
+
+        for invalid in [
+            vars(&[]),
+            vars(&[("MAIL_HOST", "smtp.example.com"), ("MAIL_PORT", "smtp")]),
+            vars(&[("MAIL_HOST", "smtp.example.com"), ("MAIL_TLS", "ssl")]),
+            vars(&[("MAIL_HOST", "smtp.example.com"), ("MAIL_USERNAME", "zing")]),
+        ] {
+            assert!(matches!(
+                SmtpConfig::from_vars(invalid),
+                Err(MailError::Config(_))
+            ));
+        }
+    }
+}
@@ -13,6 +13,7 @@ pub mod server {
         pub created_at: DateTimeUtc,
         pub name: Option<String>,
         pub is_group: i8,
+        pub retention_seconds: Option<i64>,
     }
 
     #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
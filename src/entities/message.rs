@@ -11,9 +11,12 @@ pub mod server {
         pub message_id: i32,
         pub message_body: Option<String>,
         pub message_image: Option<String>,
+        pub message_audio: Option<String>,
+        pub message_audio_duration: Option<i32>,
         pub message_created_at: DateTimeUtc,
         pub message_conversation_id: i32,
         pub message_sender_id: i32,
+        pub message_system: i8,
     }
 
     #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -5,12 +5,13 @@ pub mod server {
     use sea_orm::entity::prelude::*;
 
     #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
-    #[sea_orm(table_name = "seen_messages")]
+    #[sea_orm(table_name = "message_mentions")]
     pub struct Model {
         #[sea_orm(primary_key, auto_increment = false)]
         pub message_id: i32,
         #[sea_orm(primary_key, auto_increment = false)]
-        pub seen_id: i32,
+        pub user_id: i32,
+        pub read_at: Option<DateTimeUtc>,
     }
 
     #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -25,7 +26,7 @@ pub mod server {
         Message,
         #[sea_orm(
             belongs_to = "crate::entities::users::server::Entity",
-            from = "Column::SeenId",
+            from = "Column::UserId",
             to = "crate::entities::users::server::Column::Id",
             on_update = "Restrict",
             on_delete = "Cascade"
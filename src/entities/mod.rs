@@ -2,9 +2,17 @@
 
 pub mod prelude;
 
+pub mod contacts;
 pub mod conversation;
+pub mod email_changes;
+pub mod link_previews;
 pub mod message;
-pub mod seen_messages;
+pub mod message_mentions;
+pub mod password_reset_tokens;
+pub mod pinned_messages;
+pub mod recovery_codes;
+pub mod scheduled_messages;
 pub mod temp_users;
 pub mod user_conversation;
+pub mod user_sessions;
 pub mod users;
@@ -2,11 +2,19 @@
 
 cfg_if::cfg_if! {
 if #[cfg(feature = "ssr")] {
+    pub use super::contacts::server::Entity as Contacts;
     pub use super::conversation::server::Entity as Conversation;
+    pub use super::email_changes::server::Entity as EmailChanges;
+    pub use super::link_previews::server::Entity as LinkPreviews;
     pub use super::message::server::Entity as Message;
-    pub use super::seen_messages::server::Entity as SeenMessages;
+    pub use super::message_mentions::server::Entity as MessageMentions;
+    pub use super::password_reset_tokens::server::Entity as PasswordResetTokens;
+    pub use super::pinned_messages::server::Entity as PinnedMessages;
+    pub use super::recovery_codes::server::Entity as RecoveryCodes;
+    pub use super::scheduled_messages::server::Entity as ScheduledMessages;
     pub use super::temp_users::server::Entity as TempUsers;
     pub use super::user_conversation::server::Entity as UserConversation;
+    pub use super::user_sessions::server::Entity as UserSessions;
     pub use super::users::server::Entity as Users;
 }
 }
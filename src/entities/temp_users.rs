@@ -16,6 +16,8 @@ pub mod server {
         pub password: String,
         pub verification: String,
         pub time: DateTimeUtc,
+        pub verification_sent_at: DateTimeUtc,
+        pub verification_sends: i32,
     }
 
     #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -3,7 +3,6 @@
 #[cfg(feature = "ssr")]
 pub mod server {
     use sea_orm::entity::prelude::*;
-    use crate::entities::prelude::UserConversation;
 
     #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
     #[sea_orm(table_name = "user_conversation")]
@@ -12,6 +11,10 @@ pub mod server {
         pub user_ids: i32,
         #[sea_orm(primary_key, auto_increment = false)]
         pub conversation_id: i32,
+        pub last_read_message_id: Option<i32>,
+        pub pinned: i8,
+        pub archived: i8,
+        pub muted: i8,
     }
 
     #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -46,19 +49,5 @@ pub mod server {
         }
     }
 
-    impl Linked for UserConversation {
-        type FromEntity = Entity;
-
-        type ToEntity = crate::entities::message::server::Entity;
-
-        fn link(&self) -> Vec<RelationDef> {
-            vec![
-                crate::entities::conversation::server::Relation::Message.def(),
-                crate::entities::message::server::Relation::Conversation.def(),
-                crate::entities::seen_messages::server::Relation::Message.def(),
-            ]
-        }
-    }
-
     impl ActiveModelBehavior for ActiveModel {}
 }
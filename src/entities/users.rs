@@ -14,7 +14,16 @@ pub struct Model {
     pub email: String,
     pub phone_number: i64,
     pub password: String,
-    pub image: Option<String>
+    pub image: Option<String>,
+    pub send_read_receipts: i8,
+    pub email_missed_messages: i8,
+    pub email_digest: i8,
+    pub notified_until: Option<DateTimeUtc>,
+    pub digest_sent_at: Option<DateTimeUtc>,
+    pub totp_secret: Option<String>,
+    pub totp_enabled_at: Option<DateTimeUtc>,
+    pub totp_last_step: Option<i64>,
+    pub session_version: i32,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
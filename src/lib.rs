@@ -5,11 +5,19 @@
 
 use cfg_if::cfg_if;
 mod app;
+#[cfg(feature = "ssr")]
+pub mod auth;
 pub mod server_function;
 pub mod entities;
 pub mod database;
 pub mod migrator;
 pub mod emailing;
+#[cfg(feature = "ssr")]
+pub mod media;
+#[cfg(feature = "ssr")]
+pub mod link_preview;
+#[cfg(feature = "ssr")]
+pub mod web_socket;
 
 cfg_if! {
 if #[cfg(feature = "hydrate")] {
@@ -0,0 +1,409 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use reqwest::{header, redirect, Url};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FetchError {
+    InvalidUrl,
+    /// The host resolved to an address the server must not reach, e.g. `127.0.0.1`
+    Blocked(IpAddr),
+    Timeout,
+    Status(u16),
+    NotHtml,
+    TooManyRedirects,
+    Request(String),
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FetchError::InvalidUrl => write!(f, "Invalid url"),
+            FetchError::Blocked(ip) => write!(f, "Refusing to fetch from {ip}"),
+            FetchError::Timeout => write!(f, "Timed out"),
+            FetchError::Status(status) => write!(f, "Unexpected status {status}"),
+            FetchError::NotHtml => write!(f, "Not an html document"),
+            FetchError::TooManyRedirects => write!(f, "Too many redirects"),
+            FetchError::Request(e) => write!(f, "Request failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(value: reqwest::Error) -> Self {
+        if value.is_timeout() {
+            FetchError::Timeout
+        } else {
+            FetchError::Request(value.to_string())
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FetchedPage {
+    /// Where the document was found after following redirects
+    pub url: Url,
+    pub html: String,
+}
+
+/// Retrieves the html behind a link. Swapped out for a local stand-in server in tests.
+#[async_trait::async_trait]
+pub trait PreviewFetcher: Send + Sync {
+    async fn fetch(&self, url: &Url) -> Result<FetchedPage, FetchError>;
+}
+
+pub struct HttpFetcher {
+    timeout: Duration,
+    max_bytes: usize,
+    max_redirects: usize,
+    allow_private: bool,
+}
+
+impl Default for HttpFetcher {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+            max_bytes: 512 * 1024,
+            max_redirects: 3,
+            allow_private: false,
+        }
+    }
+}
+
+impl HttpFetcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Lifts the private address check, only meant for pointing the fetcher at a local
+    /// stand-in server.
+    pub fn allow_private_networks(mut self) -> Self {
+        self.allow_private = true;
+        self
+    }
+
+    /// Resolves the host once and pins the connection to the checked address, so a second
+    /// DNS answer cannot swap in an internal address after validation.
+    async fn resolve(&self, url: &Url) -> Result<(String, SocketAddr), FetchError> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(FetchError::InvalidUrl);
+        }
+        let host = url.host_str().ok_or(FetchError::InvalidUrl)?.to_string();
+        let port = url.port_or_known_default().ok_or(FetchError::InvalidUrl)?;
+
+        let addresses: Vec<SocketAddr> =
+            tokio::time::timeout(self.timeout, tokio::net::lookup_host((host.as_str(), port)))
+                .await
+                .map_err(|_| FetchError::Timeout)?
+                .map_err(|e| FetchError::Request(e.to_string()))?
+                .collect();
+
+        if !self.allow_private {
+            if let Some(blocked) = addresses.iter().find(|address| !is_public(address.ip())) {
+                return Err(FetchError::Blocked(blocked.ip()));
+            }
+        }
+
+        let address = addresses.first().copied().ok_or(FetchError::InvalidUrl)?;
+        Ok((host, address))
+    }
+}
+
+#[async_trait::async_trait]
+impl PreviewFetcher for HttpFetcher {
+    async fn fetch(&self, url: &Url) -> Result<FetchedPage, FetchError> {
+        let mut url = url.clone();
+
+        // Redirects are followed by hand so every hop goes through the address check
+        for _ in 0..=self.max_redirects {
+            let (host, address) = self.resolve(&url).await?;
+            let client = reqwest::Client::builder()
+                .redirect(redirect::Policy::none())
+                .timeout(self.timeout)
+                .resolve(&host, address)
+                .user_agent("ZingLinkPreview/1.0")
+                .build()?;
+
+            let mut response = client
+                .get(url.clone())
+                .header(header::ACCEPT, "text/html,application/xhtml+xml")
+                .send()
+                .await?;
+
+            if response.status().is_redirection() {
+                url = response
+                    .headers()
+                    .get(header::LOCATION)
+                    .and_then(|location| location.to_str().ok())
+                    .and_then(|location| url.join(location).ok())
+                    .ok_or(FetchError::InvalidUrl)?;
+                continue;
+            }
+
+            if !response.status().is_success() {
+                return Err(FetchError::Status(response.status().as_u16()));
+            }
+
+            let is_html = response
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .and_then(|content_type| content_type.to_str().ok())
+                .map(|content_type| {
+                    content_type.starts_with("text/html")
+                        || content_type.starts_with("application/xhtml+xml")
+                })
+                .unwrap_or(false);
+            if !is_html {
+                return Err(FetchError::NotHtml);
+            }
+
+            // The metadata lives in <head>, there is no need to download the whole page
+            let mut body = Vec::new();
+            while let Some(chunk) = response.chunk().await? {
+                body.extend_from_slice(&chunk);
+                if body.len() >= self.max_bytes {
+                    body.truncate(self.max_bytes);
+                    break;
+                }
+            }
+
+            return Ok(FetchedPage {
+                url,
+                html: String::from_utf8_lossy(&body).into_owned(),
+            });
+        }
+
+        Err(FetchError::TooManyRedirects)
+    }
+}
+
+/// Whether an address is reachable on the public internet. Loopback, private, link-local,
+/// shared (CGNAT), documentation and multicast ranges are all rejected.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || (a == 198 && (b == 18 || b == 19))
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // Link local fe80::/10
+        || (first & 0xffc0) == 0xfe80
+        // Documentation 2001:db8::/32
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::link_preview::opengraph;
+
+    const ARTICLE: &str = r#"<html><head>
+        <title>Fallback title</title>
+        <meta property="og:title" content="Zing &amp; friends">
+        <meta property="og:description" content="Chatting, but faster">
+        <meta property="og:image" content="/cover.png">
+        <meta property="og:site_name" content="Zing">
+        </head><body>Hello</body></html>"#;
+
+    fn response(status: &str, headers: &[(&str, &str)], body: &str) -> String {
+        let headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{name}: {value}\r\n"))
+            .collect();
+        format!(
+            "HTTP/1.1 {status}\r\n{headers}Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    async fn respond(path: &str) -> String {
+        let html = [("Content-Type", "text/html; charset=utf-8")];
+        match path {
+            "/article" => response("200 OK", &html, ARTICLE),
+            "/moved" => response("301 Moved Permanently", &[("Location", "/article")], ""),
+            "/slow" => {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                response("200 OK", &html, ARTICLE)
+            }
+            "/image.png" => response("200 OK", &[("Content-Type", "image/png")], "\u{89}PNG"),
+            path if path.starts_with("/hop/") => {
+                let hop: usize = path["/hop/".len()..].parse().unwrap();
+                let location = format!("/hop/{}", hop + 1);
+                response("302 Found", &[("Location", &location)], "")
+            }
+            _ => response("404 Not Found", &html, "missing"),
+        }
+    }
+
+    /// A local stand-in for the sites being unfurled.
+    async fn serve() -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buffer = [0; 1024];
+                    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                        match socket.read(&mut buffer).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(read) => request.extend_from_slice(&buffer[..read]),
+                        }
+                    }
+                    let request = String::from_utf8_lossy(&request).into_owned();
+                    let path = request.split_whitespace().nth(1).unwrap_or("/");
+                    let _ = socket.write_all(respond(path).await.as_bytes()).await;
+                });
+            }
+        });
+        address
+    }
+
+    fn url(address: SocketAddr, path: &str) -> Url {
+        Url::parse(&format!("http://{address}{path}")).unwrap()
+    }
+
+    fn local() -> HttpFetcher {
+        HttpFetcher::new().allow_private_networks()
+    }
+
+    #[tokio::test]
+    async fn parses_opengraph_after_a_redirect() {
+        let address = serve().await;
+        let page = local().fetch(&url(address, "/moved")).await.unwrap();
+        assert_eq!(page.url, url(address, "/article"));
+
+        let preview = opengraph::parse(&page.url, &page.html);
+        assert_eq!(preview.title.as_deref(), Some("Zing & friends"));
+        assert_eq!(preview.description.as_deref(), Some("Chatting, but faster"));
+        assert_eq!(preview.image, Some(url(address, "/cover.png").to_string()));
+        assert_eq!(preview.site_name.as_deref(), Some("Zing"));
+    }
+
+    #[tokio::test]
+    async fn times_out() {
+        let address = serve().await;
+        let fetcher = local().timeout(Duration::from_millis(200));
+        assert_eq!(
+            fetcher.fetch(&url(address, "/slow")).await.unwrap_err(),
+            FetchError::Timeout
+        );
+    }
+
+    #[tokio::test]
+    async fn stops_after_the_redirect_limit() {
+        let address = serve().await;
+        assert_eq!(
+            local().fetch(&url(address, "/hop/0")).await.unwrap_err(),
+            FetchError::TooManyRedirects
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_other_content_and_errors() {
+        let address = serve().await;
+        assert_eq!(
+            local()
+                .fetch(&url(address, "/image.png"))
+                .await
+                .unwrap_err(),
+            FetchError::NotHtml
+        );
+        assert_eq!(
+            local().fetch(&url(address, "/gone")).await.unwrap_err(),
+            FetchError::Status(404)
+        );
+        assert_eq!(
+            local()
+                .fetch(&Url::parse("ftp://127.0.0.1/article").unwrap())
+                .await
+                .unwrap_err(),
+            FetchError::InvalidUrl
+        );
+    }
+
+    #[tokio::test]
+    async fn blocks_private_addresses() {
+        let address = serve().await;
+        assert_eq!(
+            HttpFetcher::new()
+                .fetch(&url(address, "/article"))
+                .await
+                .unwrap_err(),
+            FetchError::Blocked(address.ip())
+        );
+        let localhost =
+            Url::parse(&format!("http://localhost:{}/article", address.port())).unwrap();
+        assert!(matches!(
+            HttpFetcher::new().fetch(&localhost).await,
+            Err(FetchError::Blocked(ip)) if ip.is_loopback()
+        ));
+    }
+
+    #[test]
+    fn public_addresses() {
+        for blocked in [
+            "0.0.0.0",
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "198.18.0.1",
+            "224.0.0.1",
+            "240.0.0.1",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "2001:db8::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(blocked.parse().unwrap()), "{blocked}");
+        }
+        for public in ["1.1.1.1", "93.184.216.34", "2606:4700:4700::1111"] {
+            assert!(is_public(public.parse().unwrap()), "{public}");
+        }
+    }
+}
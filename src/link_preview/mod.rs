@@ -0,0 +1,227 @@
+//! Unfurls links posted in messages into OpenGraph previews. Fetched metadata is cached in
+//! `link_previews`, including pages without any metadata so they are not fetched again.
+
+use reqwest::Url;
+use sea_orm::{
+    sea_query::OnConflict, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, Set,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    entities::{
+        link_previews,
+        prelude::{LinkPreviews, Message, UserConversation},
+        user_conversation,
+    },
+    server_function::LinkPreview,
+};
+
+pub mod fetcher;
+pub mod opengraph;
+
+pub use fetcher::{FetchError, HttpFetcher, PreviewFetcher};
+
+/// How long a cached preview is served before the page is fetched again, in days
+pub const PREVIEW_TTL_DAYS: i64 = 7;
+
+#[derive(Debug)]
+pub enum PreviewError {
+    UnknownMessage,
+    NotMember,
+    Database(DbErr),
+}
+
+impl std::fmt::Display for PreviewError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PreviewError::UnknownMessage => write!(f, "Message does not exist"),
+            PreviewError::NotMember => write!(f, "Not a member of this conversation"),
+            PreviewError::Database(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for PreviewError {}
+
+impl PreviewError {
+    pub fn into_server_fn_error(self) -> leptos::ServerFnError {
+        match self {
+            PreviewError::Database(e) => leptos::ServerFnError::ServerError(e.to_string()),
+            e => leptos::ServerFnError::Request(e.to_string()),
+        }
+    }
+}
+
+impl From<DbErr> for PreviewError {
+    fn from(value: DbErr) -> Self {
+        PreviewError::Database(value)
+    }
+}
+
+pub struct LinkPreviewService {
+    fetcher: Box<dyn PreviewFetcher>,
+}
+
+impl LinkPreviewService {
+    pub fn new(fetcher: impl PreviewFetcher + 'static) -> Self {
+        Self {
+            fetcher: Box::new(fetcher),
+        }
+    }
+
+    fn url_hash(url: &str) -> String {
+        format!("{:x}", Sha256::digest(url.as_bytes()))
+    }
+
+    /// Looks up previews that have already been fetched, without touching the network.
+    pub async fn cached(urls: &[String], db: &DatabaseConnection) -> Vec<LinkPreview> {
+        if urls.is_empty() {
+            return Vec::new();
+        }
+
+        let rows = LinkPreviews::find()
+            .filter(
+                link_previews::server::Column::UrlHash
+                    .is_in(urls.iter().map(|url| Self::url_hash(url)).collect::<Vec<_>>()),
+            )
+            .all(db)
+            .await
+            .unwrap_or_default();
+
+        urls.iter()
+            .filter_map(|url| rows.iter().find(|row| &row.url == url))
+            .map(|row| LinkPreview {
+                url: row.url.clone(),
+                title: row.title.clone(),
+                description: row.description.clone(),
+                image: row.image.clone(),
+                site_name: row.site_name.clone(),
+            })
+            .filter(|preview| !preview.is_empty())
+            .collect()
+    }
+
+    /// Returns the previews for the given links, fetching and caching the ones that are
+    /// missing or stale.
+    pub async fn resolve(&self, urls: &[String], db: &DatabaseConnection) -> Vec<LinkPreview> {
+        let mut previews = Vec::new();
+
+        for url in urls.iter().take(LinkPreview::MAX_PER_MESSAGE) {
+            let cached = LinkPreviews::find()
+                .filter(link_previews::server::Column::UrlHash.eq(Self::url_hash(url)))
+                .one(db)
+                .await
+                .ok()
+                .flatten()
+                .filter(|row| {
+                    row.fetched_at > chrono::Utc::now() - chrono::Duration::days(PREVIEW_TTL_DAYS)
+                });
+
+            let preview = match cached {
+                Some(row) => LinkPreview {
+                    url: row.url,
+                    title: row.title,
+                    description: row.description,
+                    image: row.image,
+                    site_name: row.site_name,
+                },
+                None => {
+                    let preview = self.fetch(url).await;
+                    Self::store(&preview, db).await;
+                    preview
+                }
+            };
+
+            if !preview.is_empty() {
+                previews.push(preview);
+            }
+        }
+
+        previews
+    }
+
+    /// Previews for the links of a stored message, for a member of its conversation. Only
+    /// links from the message body are fetched, callers never choose what the server fetches.
+    pub async fn for_message(
+        &self,
+        user_id: i32,
+        message_id: i32,
+        db: &DatabaseConnection,
+    ) -> Result<Vec<LinkPreview>, PreviewError> {
+        let message = Message::find_by_id(message_id)
+            .one(db)
+            .await?
+            .ok_or(PreviewError::UnknownMessage)?;
+        UserConversation::find()
+            .filter(
+                user_conversation::server::Column::ConversationId
+                    .eq(message.message_conversation_id),
+            )
+            .filter(user_conversation::server::Column::UserIds.eq(user_id))
+            .one(db)
+            .await?
+            .ok_or(PreviewError::NotMember)?;
+
+        let urls = message
+            .message_body
+            .as_deref()
+            .map(LinkPreview::extract_urls)
+            .unwrap_or_default();
+        Ok(self.resolve(&urls, db).await)
+    }
+
+    async fn fetch(&self, url: &str) -> LinkPreview {
+        let empty = LinkPreview {
+            url: url.to_string(),
+            title: None,
+            description: None,
+            image: None,
+            site_name: None,
+        };
+
+        let Ok(parsed) = Url::parse(url) else {
+            return empty;
+        };
+
+        match self.fetcher.fetch(&parsed).await {
+            Ok(page) => LinkPreview {
+                // Keyed by what the user posted, not where the redirects ended up
+                url: url.to_string(),
+                ..opengraph::parse(&page.url, &page.html)
+            },
+            Err(e) => {
+                leptos::log!("Link preview for {url} failed: {e}");
+                empty
+            }
+        }
+    }
+
+    async fn store(preview: &LinkPreview, db: &DatabaseConnection) {
+        let model = link_previews::server::ActiveModel {
+            url_hash: Set(Self::url_hash(&preview.url)),
+            url: Set(preview.url.clone()),
+            title: Set(preview.title.clone()),
+            description: Set(preview.description.clone()),
+            image: Set(preview.image.clone()),
+            site_name: Set(preview.site_name.clone()),
+            fetched_at: Set(chrono::Utc::now()),
+            ..Default::default()
+        };
+
+        // Two senders can post the same link at once, the later fetch wins
+        let _ = LinkPreviews::insert(model)
+            .on_conflict(
+                OnConflict::column(link_previews::server::Column::UrlHash)
+                    .update_columns([
+                        link_previews::server::Column::Title,
+                        link_previews::server::Column::Description,
+                        link_previews::server::Column::Image,
+                        link_previews::server::Column::SiteName,
+                        link_previews::server::Column::FetchedAt,
+                    ])
+                    .to_owned(),
+            )
+            .exec(db)
+            .await;
+    }
+}
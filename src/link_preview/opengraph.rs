@@ -0,0 +1,118 @@
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
+use reqwest::Url;
+
+use crate::server_function::LinkPreview;
+
+const MAX_TITLE: usize = 200;
+const MAX_DESCRIPTION: usize = 300;
+
+lazy_static! {
+    static ref META_TAG: Regex = Regex::new(r"(?is)<meta\s[^>]*>").unwrap();
+    static ref ATTRIBUTE: Regex =
+        Regex::new(r#"(?is)([a-z:_-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+    static ref TITLE_TAG: Regex = Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap();
+}
+
+/// Reads the OpenGraph metadata of a page, falling back to the Twitter card, the plain
+/// `description` meta tag and `<title>`.
+pub fn parse(url: &Url, html: &str) -> LinkPreview {
+    let mut tags: Vec<(String, String)> = Vec::new();
+    for tag in META_TAG.find_iter(html).flatten() {
+        let mut key = None;
+        let mut content = None;
+        for attribute in ATTRIBUTE.captures_iter(tag.as_str()).flatten() {
+            let value = attribute
+                .get(2)
+                .or_else(|| attribute.get(3))
+                .map(|value| value.as_str())
+                .unwrap_or_default();
+            match attribute[1].to_ascii_lowercase().as_str() {
+                "property" | "name" => key = Some(value.to_ascii_lowercase()),
+                "content" => content = Some(decode_entities(value)),
+                _ => (),
+            }
+        }
+        if let (Some(key), Some(content)) = (key, content) {
+            tags.push((key, content));
+        }
+    }
+
+    let find = |keys: &[&str]| {
+        keys.iter().find_map(|key| {
+            tags.iter()
+                .find(|(tag, content)| tag == key && !content.trim().is_empty())
+                .map(|(_, content)| content.trim().to_string())
+        })
+    };
+
+    let title = find(&["og:title", "twitter:title"]).or_else(|| {
+        TITLE_TAG
+            .captures(html)
+            .ok()
+            .flatten()
+            .and_then(|captures| captures.get(1))
+            .map(|title| decode_entities(title.as_str()).trim().to_string())
+            .filter(|title| !title.is_empty())
+    });
+
+    LinkPreview {
+        url: url.to_string(),
+        title: title.map(|title| truncate(title, MAX_TITLE)),
+        description: find(&["og:description", "twitter:description", "description"])
+            .map(|description| truncate(description, MAX_DESCRIPTION)),
+        image: find(&["og:image", "og:image:url", "twitter:image"])
+            .and_then(|image| url.join(&image).ok())
+            .filter(|image| matches!(image.scheme(), "http" | "https"))
+            .map(|image| image.to_string()),
+        site_name: find(&["og:site_name"]).or_else(|| url.host_str().map(str::to_string)),
+    }
+}
+
+fn truncate(value: String, max: usize) -> String {
+    match value.char_indices().nth(max) {
+        Some((index, _)) => format!("{}…", &value[..index]),
+        None => value,
+    }
+}
+
+fn decode_entities(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';').filter(|end| *end <= 10) else {
+            decoded.push('&');
+            rest = &rest[1..];
+            continue;
+        };
+        let entity = &rest[1..end];
+        let character = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        match character {
+            Some(character) => {
+                decoded.push(character);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
@@ -5,13 +5,16 @@
 use actix::Addr;
 use actix::*;
 use actix_web::web;
-use actix_web::{cookie::Key, get, App, Error, HttpRequest, HttpResponse, HttpServer, Responder};
+use actix_web::{get, App, Error, HttpRequest, HttpResponse, HttpServer};
 use actix_web_actors::ws;
 pub use sea_orm::{Database, DbErr, *};
 pub mod app;
+pub mod auth;
 pub mod database;
 pub mod emailing;
 pub mod entities;
+pub mod link_preview;
+pub mod media;
 pub mod migrator;
 pub mod server_function;
 pub mod web_socket;
@@ -44,31 +47,6 @@ async fn clear_temp_db() {
     }
 }
 
-fn render_images(folder: &str, path: actix_web::web::Path<String>) -> Vec<u8> {
-    use std::io::Read;
-
-    let path = std::env::current_dir()
-        .unwrap()
-        .join(folder.to_string() + "/" + &path);
-
-    let mut file = std::fs::File::open(path).unwrap();
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer).unwrap();
-    buffer
-}
-
-#[get("/upload/{image_path}")]
-async fn image_path(path: actix_web::web::Path<String>, req: HttpRequest) -> HttpResponse {
-    let buffer = render_images("upload", path);
-    HttpResponse::Ok().body(buffer).respond_to(&req)
-}
-
-#[get("/images/{image_path}")]
-async fn upload_path(path: actix_web::web::Path<String>, req: HttpRequest) -> HttpResponse {
-    let buffer = render_images("images", path);
-    HttpResponse::Ok().body(buffer).respond_to(&req)
-}
-
 // Entry point for our websocket route
 #[get("/ws/icons/{id}")]
 async fn chat_route_icon(
@@ -76,11 +54,17 @@ async fn chat_route_icon(
     stream: web::Payload,
     path: web::Path<usize>,
     srv: web::Data<Addr<web_socket::server::IconWs>>,
+    user: Option<auth::identity::CurrentUser>,
+    session: actix_session::Session,
 ) -> Result<HttpResponse, Error> {
+    let user_id = user.map(|user| user.0.id);
+
     ws::start(
         web_socket::session::WsChatSessionIcon {
             id: *path,
             hb: std::time::Instant::now(),
+            user_id,
+            session_token: auth::sessions::current_token(&session),
             addr: srv.get_ref().clone(),
         },
         &req,
@@ -95,6 +79,7 @@ async fn chat_route(
     stream: web::Payload,
     path: web::Path<usize>,
     srv: web::Data<Addr<web_socket::server::ChatServer>>,
+    session: actix_session::Session,
 ) -> Result<HttpResponse, Error> {
     ws::start(
         web_socket::session::WsChatSession {
@@ -102,6 +87,7 @@ async fn chat_route(
             hb: std::time::Instant::now(),
             room: *path,
             name: None,
+            session_token: auth::sessions::current_token(&session),
             addr: srv.get_ref().clone(),
         },
         &req,
@@ -120,8 +106,7 @@ async fn main() -> std::io::Result<()> {
     use actix_web::middleware::{Compress, Logger, NormalizePath};
 
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("debug"));
-    use actix_identity::IdentityMiddleware;
-    use actix_session::{storage::RedisSessionStore, SessionMiddleware};
+    use actix_session::storage::RedisSessionStore;
     use leptos::*;
     use leptos_actix::{generate_route_list, LeptosRoutes};
 
@@ -143,10 +128,42 @@ async fn main() -> std::io::Result<()> {
         connection: DbConnection::connect().await,
     }));
     let redis_address = "redis://127.0.0.1:6379";
-    let secret_key = Key::generate();
+    let session_config = auth::config::SessionConfig::from_env()
+        .unwrap_or_else(|e| panic!("Sessions could not be configured: {e}"));
     let redis_store = RedisSessionStore::new(redis_address).await.unwrap();
     let server = web_socket::server::ChatServer::new().start();
     let icon_server = web_socket::server::IconWs::new().start();
+    server_function::scheduler::Scheduler::new(
+        DbConnection::connect().await,
+        server_function::scheduler::SystemClock,
+        server.clone(),
+        icon_server.clone(),
+    )
+    .start();
+    server_function::retention::Sweeper::new(
+        DbConnection::connect().await,
+        server_function::scheduler::SystemClock,
+        icon_server.clone(),
+    )
+    .start();
+    let email_client = web::Data::new(
+        emailing::email_client::EmailClient::from_env()
+            .unwrap_or_else(|e| panic!("Email transport could not be configured: {e}")),
+    );
+    server_function::notifications::Notifier::new(
+        DbConnection::connect().await,
+        server_function::scheduler::SystemClock,
+        email_client.clone(),
+        icon_server.clone(),
+    )
+    .with_offline_period(server_function::notifications::offline_period_from_env())
+    .start();
+    let rate_limiter = web::Data::new(server_function::rate_limit::RateLimiter::redis(
+        redis_address,
+    ));
+    let link_previews = web::Data::new(link_preview::LinkPreviewService::new(
+        link_preview::HttpFetcher::new(),
+    ));
 
     HttpServer::new(move || {
         let leptos_options = &conf.leptos_options;
@@ -158,13 +175,19 @@ async fn main() -> std::io::Result<()> {
             .app_data(actix_web::web::PayloadConfig::new(10_485_760))
             .app_data(web::Data::new(server.clone()))
             .app_data(web::Data::new(icon_server.clone()))
-            .wrap(IdentityMiddleware::default())
-            .wrap(SessionMiddleware::new(
-                redis_store.clone(),
-                secret_key.clone(),
-            ))
+            .app_data(link_previews.clone())
+            .app_data(email_client.clone())
+            .app_data(rate_limiter.clone())
+            .wrap(auth::SessionGuard)
+            .wrap(session_config.identity_middleware())
+            .wrap(session_config.session_middleware(redis_store.clone()))
+            .wrap(session_config.key_rotation())
             .service(chat_route_icon)
             .service(chat_route)
+            .service(media::image_path)
+            .service(media::upload_path)
+            .service(media::icon_path)
+            .service(server_function::notifications::unsubscribe)
             .route("/api/{tail:.*}", leptos_actix::handle_server_fns())
             .leptos_routes(leptos_options.to_owned(), routes, |cx| view! { cx, <App/> })
             .wrap(Logger::new("%r %U").log_target("actix"))
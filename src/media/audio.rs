@@ -0,0 +1,420 @@
+//! Validation and duration extraction for recorded voice notes.
+//!
+//! Everything in here works on the raw upload bytes so it can be exercised with fixture
+//! files without a database or a running server.
+
+/// Largest voice note accepted, in milliseconds
+pub const MAX_DURATION_MS: i32 = 5 * 60 * 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioKind {
+    Ogg,
+    WebM,
+    Wav,
+    Mp4,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AudioError {
+    UnsupportedType(Option<String>),
+    UnknownDuration,
+    TooLong(i32),
+}
+
+impl std::fmt::Display for AudioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AudioError::UnsupportedType(Some(mime)) => write!(f, "Unsupported audio type {mime}"),
+            AudioError::UnsupportedType(None) => write!(f, "Unrecognised audio type"),
+            AudioError::UnknownDuration => write!(f, "Could not determine the audio duration"),
+            AudioError::TooLong(ms) => write!(
+                f,
+                "Voice notes are limited to {} seconds, received {} seconds",
+                MAX_DURATION_MS / 1000,
+                ms / 1000
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AudioError {}
+
+impl AudioKind {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AudioKind::Ogg => "ogg",
+            AudioKind::WebM => "webm",
+            AudioKind::Wav => "wav",
+            AudioKind::Mp4 => "m4a",
+        }
+    }
+
+    /// MediaRecorder produces webm/mp4 containers, which `infer` reports as video types.
+    pub fn detect(bytes: &[u8]) -> Result<AudioKind, AudioError> {
+        match infer::get(bytes).map(|kind| kind.mime_type()) {
+            Some("audio/ogg") | Some("audio/opus") => Ok(AudioKind::Ogg),
+            Some("video/webm") | Some("audio/webm") => Ok(AudioKind::WebM),
+            Some("audio/x-wav") | Some("audio/wav") => Ok(AudioKind::Wav),
+            Some("audio/m4a") | Some("video/mp4") | Some("audio/mp4") => Ok(AudioKind::Mp4),
+            other => Err(AudioError::UnsupportedType(other.map(str::to_string))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoiceNote {
+    pub kind: AudioKind,
+    pub duration_ms: i32,
+}
+
+/// Checks the container type and duration of an uploaded voice note.
+pub fn validate(bytes: &[u8]) -> Result<VoiceNote, AudioError> {
+    let kind = AudioKind::detect(bytes)?;
+    let duration_ms = duration_ms(kind, bytes).ok_or(AudioError::UnknownDuration)?;
+
+    if duration_ms > MAX_DURATION_MS {
+        return Err(AudioError::TooLong(duration_ms));
+    }
+
+    Ok(VoiceNote { kind, duration_ms })
+}
+
+pub fn duration_ms(kind: AudioKind, bytes: &[u8]) -> Option<i32> {
+    let seconds = match kind {
+        AudioKind::Ogg => ogg_duration(bytes),
+        AudioKind::WebM => webm_duration(bytes),
+        AudioKind::Wav => wav_duration(bytes),
+        AudioKind::Mp4 => mp4_duration(bytes),
+    }?;
+
+    (seconds.is_finite() && seconds >= 0.0).then(|| (seconds * 1000.0).round() as i32)
+}
+
+fn read_u16_le(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_u32_le(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn read_u32_be(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn read_u64_be(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(bytes.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Ogg pages carry the absolute granule position, so the last page gives the sample count.
+fn ogg_duration(bytes: &[u8]) -> Option<f64> {
+    let (sample_rate, pre_skip) = if let Some(head) = find(bytes, b"OpusHead") {
+        // Opus always runs its granule clock at 48kHz
+        (48_000, read_u16_le(bytes, head + 10)? as u64)
+    } else {
+        let head = find(bytes, b"\x01vorbis")?;
+        (read_u32_le(bytes, head + 12)? as u64, 0)
+    };
+
+    let last_page = bytes
+        .windows(4)
+        .rposition(|window| window == b"OggS")?;
+    let granule = i64::from_le_bytes(bytes.get(last_page + 6..last_page + 14)?.try_into().ok()?);
+
+    if granule < 0 || sample_rate == 0 {
+        return None;
+    }
+
+    Some((granule as u64).saturating_sub(pre_skip) as f64 / sample_rate as f64)
+}
+
+fn wav_duration(bytes: &[u8]) -> Option<f64> {
+    if bytes.get(0..4)? != b"RIFF" || bytes.get(8..12)? != b"WAVE" {
+        return None;
+    }
+
+    let mut offset = 12;
+    let mut byte_rate = None;
+    while offset + 8 <= bytes.len() {
+        let id = &bytes[offset..offset + 4];
+        let size = read_u32_le(bytes, offset + 4)? as usize;
+        match id {
+            b"fmt " => byte_rate = read_u32_le(bytes, offset + 16),
+            b"data" => {
+                let byte_rate = byte_rate.filter(|rate| *rate > 0)?;
+                // Streaming encoders leave the size unset, fall back to what was received
+                let size = size.min(bytes.len() - offset - 8);
+                return Some(size as f64 / byte_rate as f64);
+            }
+            _ => (),
+        }
+        // Chunks are word aligned
+        offset += 8 + size + (size & 1);
+    }
+    None
+}
+
+/// The `mvhd` box of the movie header holds the overall timescale and duration.
+fn mp4_duration(bytes: &[u8]) -> Option<f64> {
+    let mvhd = find(bytes, b"mvhd")?;
+    let version = *bytes.get(mvhd + 4)?;
+    let (timescale, duration) = match version {
+        0 => (
+            read_u32_be(bytes, mvhd + 16)? as u64,
+            read_u32_be(bytes, mvhd + 20)? as u64,
+        ),
+        1 => (
+            read_u32_be(bytes, mvhd + 24)? as u64,
+            read_u64_be(bytes, mvhd + 28)?,
+        ),
+        _ => return None,
+    };
+
+    (timescale > 0).then(|| duration as f64 / timescale as f64)
+}
+
+const EBML_SEGMENT: u64 = 0x1853_8067;
+const EBML_INFO: u64 = 0x1549_A966;
+const EBML_TIMECODE_SCALE: u64 = 0x2A_D7B1;
+const EBML_DURATION: u64 = 0x4489;
+const EBML_CLUSTER: u64 = 0x1F43_B675;
+const EBML_TIMECODE: u64 = 0xE7;
+const EBML_BLOCK_GROUP: u64 = 0xA0;
+const EBML_BLOCK: u64 = 0xA1;
+const EBML_SIMPLE_BLOCK: u64 = 0xA3;
+/// Deepest nesting walked, blocks sit at Segment > Cluster > BlockGroup
+const EBML_MAX_DEPTH: usize = 8;
+
+/// Reads an EBML variable length integer, returning the value and its encoded length.
+/// Element IDs keep their marker bit, sizes have it stripped.
+fn read_vint(bytes: &[u8], offset: usize, keep_marker: bool) -> Option<(u64, usize, bool)> {
+    let first = *bytes.get(offset)?;
+    let length = first.leading_zeros() as usize + 1;
+    if length > 8 {
+        return None;
+    }
+
+    let mut value = if keep_marker {
+        first as u64
+    } else {
+        // Widened first, an 8 byte length would shift a u8 by 8
+        first as u64 & (0xFF >> length)
+    };
+    for byte in bytes.get(offset + 1..offset + length)? {
+        value = (value << 8) | *byte as u64;
+    }
+
+    let unknown = !keep_marker && value == (1u64 << (7 * length)) - 1;
+    Some((value, length, unknown))
+}
+
+fn read_uint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |value, byte| (value << 8) | *byte as u64)
+}
+
+#[derive(Default)]
+struct WebMTimings {
+    timecode_scale: Option<u64>,
+    duration: Option<f64>,
+    cluster_timecode: u64,
+    last_block: u64,
+}
+
+/// Chrome's MediaRecorder never writes the `Duration` element, so the end of the last
+/// block is used when it is missing.
+fn webm_duration(bytes: &[u8]) -> Option<f64> {
+    let mut timings = WebMTimings::default();
+    walk_ebml(bytes, 0, bytes.len(), 0, &mut timings)?;
+
+    let scale = timings.timecode_scale.unwrap_or(1_000_000) as f64;
+    let ticks = timings
+        .duration
+        .unwrap_or(timings.last_block as f64);
+
+    (ticks > 0.0).then(|| ticks * scale / 1_000_000_000.0)
+}
+
+/// Gives up past `EBML_MAX_DEPTH`, a crafted file could otherwise nest deep enough to
+/// overflow the stack.
+fn walk_ebml(
+    bytes: &[u8],
+    mut offset: usize,
+    end: usize,
+    depth: usize,
+    timings: &mut WebMTimings,
+) -> Option<()> {
+    if depth > EBML_MAX_DEPTH {
+        return None;
+    }
+    while offset < end {
+        let Some((id, id_length, _)) = read_vint(bytes, offset, true) else {
+            break;
+        };
+        let Some((size, size_length, unknown)) = read_vint(bytes, offset + id_length, false)
+        else {
+            break;
+        };
+
+        let data = offset + id_length + size_length;
+        // Live recordings use unknown sizes for the segment and clusters
+        let data_end = if unknown {
+            end
+        } else {
+            data.saturating_add(size as usize).min(end)
+        };
+
+        match id {
+            EBML_SEGMENT | EBML_INFO | EBML_BLOCK_GROUP => {
+                walk_ebml(bytes, data, data_end, depth + 1, timings)?;
+            }
+            EBML_CLUSTER => {
+                timings.cluster_timecode = 0;
+                walk_ebml(bytes, data, data_end, depth + 1, timings)?;
+            }
+            EBML_TIMECODE_SCALE => timings.timecode_scale = Some(read_uint(bytes.get(data..data_end)?)),
+            EBML_DURATION => {
+                let raw = bytes.get(data..data_end)?;
+                timings.duration = match raw.len() {
+                    4 => Some(f32::from_be_bytes(raw.try_into().ok()?) as f64),
+                    8 => Some(f64::from_be_bytes(raw.try_into().ok()?)),
+                    _ => None,
+                };
+            }
+            EBML_TIMECODE => timings.cluster_timecode = read_uint(bytes.get(data..data_end)?),
+            EBML_SIMPLE_BLOCK | EBML_BLOCK => {
+                let (_, track_length, _) = read_vint(bytes, data, false)?;
+                let relative = i16::from_be_bytes(
+                    bytes
+                        .get(data + track_length..data + track_length + 2)?
+                        .try_into()
+                        .ok()?,
+                );
+                let timestamp = (timings.cluster_timecode as i64 + relative as i64).max(0) as u64;
+                timings.last_block = timings.last_block.max(timestamp);
+            }
+            _ => (),
+        }
+
+        if unknown {
+            break;
+        }
+        offset = data_end;
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OGG: &[u8] = include_bytes!("../../tests/fixtures/audio/note.ogg");
+    const WEBM: &[u8] = include_bytes!("../../tests/fixtures/audio/note.webm");
+    const WAV: &[u8] = include_bytes!("../../tests/fixtures/audio/note.wav");
+    const M4A: &[u8] = include_bytes!("../../tests/fixtures/audio/note.m4a");
+
+    /// The m4a fixture with its `mvhd` duration replaced, in milliseconds.
+    fn m4a_lasting(ms: u32) -> Vec<u8> {
+        let mut bytes = M4A.to_vec();
+        let mvhd = find(&bytes, b"mvhd").unwrap();
+        bytes[mvhd + 20..mvhd + 24].copy_from_slice(&ms.to_be_bytes());
+        bytes
+    }
+
+    #[test]
+    fn valid_fixtures() {
+        for (bytes, kind, duration_ms) in [
+            (OGG, AudioKind::Ogg, 1200),
+            (WEBM, AudioKind::WebM, 1500),
+            (WAV, AudioKind::Wav, 500),
+            (M4A, AudioKind::Mp4, 2500),
+        ] {
+            assert_eq!(validate(bytes), Ok(VoiceNote { kind, duration_ms }));
+        }
+    }
+
+    #[test]
+    fn webm_duration_element_wins_over_blocks() {
+        // Info with a 4 byte float Duration of 2000 ticks and a single block at 0
+        let bytes = [
+            &[0x15, 0x49, 0xA9, 0x66, 0x87, 0x44, 0x89, 0x84][..],
+            &2000f32.to_be_bytes(),
+            &[
+                0x1F, 0x43, 0xB6, 0x75, 0x88, 0xE7, 0x81, 0x00, 0xA3, 0x83, 0x81, 0x00, 0x00,
+            ],
+        ]
+        .concat();
+        assert_eq!(duration_ms(AudioKind::WebM, &bytes), Some(2000));
+    }
+
+    #[test]
+    fn deeply_nested_webm() {
+        // The fixture's EBML header, then a segment of nested block groups of unknown size
+        let segment = find(WEBM, &[0x18, 0x53, 0x80, 0x67]).unwrap();
+        let mut bytes = WEBM[..segment].to_vec();
+        bytes.extend([0x18, 0x53, 0x80, 0x67, 0xFF]);
+        for _ in 0..1_000_000 {
+            bytes.extend([0xA0, 0xFF]);
+        }
+        assert_eq!(validate(&bytes), Err(AudioError::UnknownDuration));
+
+        // Nesting within the limit still counts its blocks
+        let mut nested = WEBM[..segment].to_vec();
+        nested.extend([0x18, 0x53, 0x80, 0x67, 0xFF, 0x1F, 0x43, 0xB6, 0x75, 0xFF]);
+        nested.extend([0xA0, 0xFF].repeat(EBML_MAX_DEPTH - 2));
+        nested.extend([0xA3, 0x84, 0x81, 0x03, 0xE8, 0x00]);
+        assert_eq!(duration_ms(AudioKind::WebM, &nested), Some(1000));
+    }
+
+    #[test]
+    fn truncated_headers() {
+        for (bytes, kind) in [
+            (&OGG[..32], AudioKind::Ogg),
+            (&WEBM[..40], AudioKind::WebM),
+            (&WAV[..20], AudioKind::Wav),
+            (&M4A[..28], AudioKind::Mp4),
+        ] {
+            assert_eq!(AudioKind::detect(bytes), Ok(kind));
+            assert_eq!(duration_ms(kind, bytes), None);
+            assert_eq!(validate(bytes), Err(AudioError::UnknownDuration));
+        }
+        assert_eq!(validate(&[]), Err(AudioError::UnsupportedType(None)));
+    }
+
+    #[test]
+    fn unknown_containers() {
+        assert_eq!(
+            validate(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"),
+            Err(AudioError::UnsupportedType(Some(String::from("image/png"))))
+        );
+        assert_eq!(
+            validate(b"just some text, not audio"),
+            Err(AudioError::UnsupportedType(None))
+        );
+    }
+
+    #[test]
+    fn over_length_notes() {
+        let longest = m4a_lasting(MAX_DURATION_MS as u32);
+        assert_eq!(
+            validate(&longest).map(|note| note.duration_ms),
+            Ok(MAX_DURATION_MS)
+        );
+
+        let too_long = m4a_lasting(MAX_DURATION_MS as u32 + 1000);
+        assert_eq!(
+            validate(&too_long),
+            Err(AudioError::TooLong(MAX_DURATION_MS + 1000))
+        );
+
+        // A wav claiming one byte per second lasts as many seconds as it has samples
+        let mut slow = WAV.to_vec();
+        slow[28..32].copy_from_slice(&1u32.to_le_bytes());
+        assert_eq!(validate(&slow), Err(AudioError::TooLong(4000 * 1000)));
+    }
+}
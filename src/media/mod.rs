@@ -0,0 +1,179 @@
+use actix_files::NamedFile;
+use actix_web::{error, get, web, Error};
+use base64::{engine::general_purpose, Engine};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use sea_orm::{ColumnTrait, Condition, DatabaseConnection, EntityTrait, QueryFilter};
+use sha2::Sha256;
+
+use crate::{auth::identity::CurrentUser, database::DbConnection, server_function::UserLogin};
+
+pub mod audio;
+
+type DbData = web::Data<tokio::sync::Mutex<DbConnection>>;
+
+/// How long a signed embed URL stays valid, in minutes
+pub const SIGNED_URL_TTL_MINUTES: i64 = 60;
+
+lazy_static! {
+    /// Key used to sign embed URLs. Falls back to a per-process key, which means signed
+    /// URLs stop validating after a restart unless `MEDIA_SIGNING_KEY` is set.
+    static ref SIGNING_KEY: Vec<u8> = match std::env::var("MEDIA_SIGNING_KEY") {
+        Ok(key) => key.into_bytes(),
+        Err(_) => {
+            use rand::RngCore;
+            let mut key = vec![0u8; 32];
+            rand::thread_rng().fill_bytes(&mut key);
+            key
+        }
+    };
+}
+
+#[derive(serde::Deserialize)]
+pub struct SignedQuery {
+    expires: Option<i64>,
+    signature: Option<String>,
+}
+
+/// Only plain file names written by the upload handlers are served, anything that could
+/// walk out of the media folder is treated as missing.
+fn sanitize(file_name: &str) -> Result<&str, Error> {
+    if !file_name.is_empty()
+        && !file_name.starts_with('.')
+        && file_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+    {
+        Ok(file_name)
+    } else {
+        Err(error::ErrorNotFound("Not found"))
+    }
+}
+
+/// Streams a file from one of the media folders. `NamedFile` takes care of range requests
+/// and conditional GETs (`ETag`/`Last-Modified`), the content type is sniffed from the file
+/// itself since uploads are stored with a `.png` extension regardless of the encoding.
+async fn stream_media(folder: &str, file_name: &str) -> Result<NamedFile, Error> {
+    let path = std::env::current_dir()?
+        .join(folder)
+        .join(sanitize(file_name)?);
+    let file = NamedFile::open_async(&path)
+        .await
+        .map_err(|_| error::ErrorNotFound("Not found"))?;
+
+    let file = match infer::get_from_path(&path).ok().flatten() {
+        Some(kind) => file.set_content_type(actix_files::file_extension_to_mime(kind.extension())),
+        None => file,
+    };
+
+    Ok(file.use_etag(true).use_last_modified(true))
+}
+
+fn authenticate(user: Option<CurrentUser>) -> Result<UserLogin, Error> {
+    user.map(|user| user.0.into())
+        .ok_or_else(|| error::ErrorUnauthorized("Login required"))
+}
+
+fn signature(path: &str, expires: i64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&SIGNING_KEY).expect("HMAC accepts any key size");
+    mac.update(format!("{path}:{expires}").as_bytes());
+    mac
+}
+
+/// Appends an expiring signature to a media path, e.g. `/upload/123.png`, so it can be
+/// embedded where the session cookie is not sent.
+pub fn sign_url(path: &str, ttl: chrono::Duration) -> String {
+    let expires = (chrono::Utc::now() + ttl).timestamp();
+    let signature =
+        general_purpose::URL_SAFE_NO_PAD.encode(signature(path, expires).finalize().into_bytes());
+    format!("{path}?expires={expires}&signature={signature}")
+}
+
+fn verify_signature(path: &str, query: &SignedQuery) -> bool {
+    match (query.expires, &query.signature) {
+        (Some(expires), Some(provided)) if expires > chrono::Utc::now().timestamp() => {
+            match general_purpose::URL_SAFE_NO_PAD.decode(provided) {
+                Ok(provided) => signature(path, expires).verify_slice(&provided).is_ok(),
+                Err(_) => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+/// A chat attachment is only visible to members of the conversation it was posted in.
+pub async fn authorize_attachment(
+    user_id: i32,
+    file_name: &str,
+    db: &DatabaseConnection,
+) -> Result<(), Error> {
+    use crate::entities::{message, prelude::*};
+
+    let path = format!("/upload/{}", sanitize(file_name)?);
+    let message = Message::find()
+        .filter(
+            Condition::any()
+                .add(message::server::Column::MessageImage.eq(path.clone()))
+                .add(message::server::Column::MessageAudio.eq(path)),
+        )
+        .one(db)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorNotFound("Not found"))?;
+
+    match UserConversation::find_by_id((user_id, message.message_conversation_id))
+        .one(db)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+    {
+        Some(_) => Ok(()),
+        None => Err(error::ErrorForbidden("Not a member of this conversation")),
+    }
+}
+
+#[get("/upload/{image_path}")]
+pub async fn image_path(
+    path: web::Path<String>,
+    query: web::Query<SignedQuery>,
+    user: Option<CurrentUser>,
+    data: DbData,
+) -> Result<NamedFile, Error> {
+    if !verify_signature(&format!("/upload/{path}"), &query) {
+        let user = authenticate(user)?;
+        authorize_attachment(user.id, &path, &data.lock().await.connection).await?;
+    }
+    stream_media("upload", &path).await
+}
+
+#[get("/images/{image_path}")]
+pub async fn upload_path(
+    path: web::Path<String>,
+    query: web::Query<SignedQuery>,
+    user: Option<CurrentUser>,
+) -> Result<NamedFile, Error> {
+    if !verify_signature(&format!("/images/{path}"), &query) {
+        authenticate(user)?;
+    }
+    stream_media("images", &path).await
+}
+
+#[get("/icons/{id}")]
+pub async fn icon_path(
+    path: web::Path<i32>,
+    user: Option<CurrentUser>,
+    data: DbData,
+) -> Result<NamedFile, Error> {
+    use crate::entities::prelude::*;
+
+    authenticate(user)?;
+
+    let image = Users::find_by_id(*path)
+        .one(&data.lock().await.connection)
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .and_then(|user| user.image)
+        .ok_or_else(|| error::ErrorNotFound("No icon"))?;
+
+    let file_name = image.trim_start_matches("images/");
+    stream_media("images", file_name).await
+}
@@ -0,0 +1,46 @@
+use super::m20230606_000004_create_message_table::Message;
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m_20230716_000007_add_message_audio.rs"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Define how to apply this migration: Add the voice note columns to Message.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Message::Table)
+                    .add_column(ColumnDef::new(MessageAudio::MessageAudio).string())
+                    .add_column(ColumnDef::new(MessageAudio::MessageAudioDuration).integer())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    // Define how to rollback this migration: Drop the voice note columns.
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Message::Table)
+                    .drop_column(MessageAudio::MessageAudio)
+                    .drop_column(MessageAudio::MessageAudioDuration)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum MessageAudio {
+    MessageAudio,
+    /// Length of the voice note in milliseconds
+    MessageAudioDuration,
+}
@@ -0,0 +1,68 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m_20230716_000008_create_link_previews_table.rs"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Define how to apply this migration: Create the LinkPreviews table.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(LinkPreviews::Table)
+                    .col(
+                        ColumnDef::new(LinkPreviews::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(LinkPreviews::UrlHash)
+                            .char_len(64)
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(LinkPreviews::Url).text().not_null())
+                    .col(ColumnDef::new(LinkPreviews::Title).string())
+                    .col(ColumnDef::new(LinkPreviews::Description).text())
+                    .col(ColumnDef::new(LinkPreviews::Image).text())
+                    .col(ColumnDef::new(LinkPreviews::SiteName).string())
+                    .col(
+                        ColumnDef::new(LinkPreviews::FetchedAt)
+                            .timestamp()
+                            .not_null()
+                            .extra("DEFAULT CURRENT_TIMESTAMP".to_string()),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    // Define how to rollback this migration: Drop the LinkPreviews table.
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(LinkPreviews::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum LinkPreviews {
+    Table,
+    Id,
+    /// SHA-256 of the URL, URLs themselves are too long to index
+    UrlHash,
+    Url,
+    Title,
+    Description,
+    Image,
+    SiteName,
+    FetchedAt,
+}
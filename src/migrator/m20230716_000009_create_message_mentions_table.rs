@@ -0,0 +1,74 @@
+use super::{
+    m20230521_000001_create_user_table::Users, m20230606_000004_create_message_table::Message,
+};
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m_20230716_000009_create_message_mentions_table.rs"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Define how to apply this migration: Create the MessageMentions table.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(MessageMentions::Table)
+                    .col(ColumnDef::new(MessageMentions::MessageId).integer().not_null())
+                    .col(ColumnDef::new(MessageMentions::UserId).integer().not_null())
+                    .col(ColumnDef::new(MessageMentions::ReadAt).timestamp())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_mention_message_id")
+                            .from(MessageMentions::Table, MessageMentions::MessageId)
+                            .to(Message::Table, Message::MessageId)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_mention_user_id")
+                            .from(MessageMentions::Table, MessageMentions::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .primary_key(
+                        Index::create()
+                            .col(MessageMentions::MessageId)
+                            .col(MessageMentions::UserId),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_mention_user_unread")
+                    .table(MessageMentions::Table)
+                    .col(MessageMentions::UserId)
+                    .col(MessageMentions::ReadAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    // Define how to rollback this migration: Drop the MessageMentions table.
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(MessageMentions::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum MessageMentions {
+    Table,
+    MessageId,
+    UserId,
+    ReadAt,
+}
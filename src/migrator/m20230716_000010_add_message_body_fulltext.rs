@@ -0,0 +1,48 @@
+use super::m20230606_000004_create_message_table::Message;
+use sea_orm_migration::{prelude::*, sea_orm::DatabaseBackend};
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m_20230716_000010_add_message_body_fulltext.rs"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Define how to apply this migration: Add a FULLTEXT index for message search.
+    // Other backends have no FULLTEXT support, search falls back to LIKE there.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() != DatabaseBackend::MySql {
+            return Ok(());
+        }
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("ft_message_body")
+                    .table(Message::Table)
+                    .col(Message::MessageBody)
+                    .full_text()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    // Define how to rollback this migration: Drop the FULLTEXT index.
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() != DatabaseBackend::MySql {
+            return Ok(());
+        }
+
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("ft_message_body")
+                    .table(Message::Table)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,80 @@
+use super::m20230521_000001_create_user_table::Users;
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m_20230716_000011_create_contacts_table.rs"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Define how to apply this migration: Create the Contacts table.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Contacts::Table)
+                    .col(ColumnDef::new(Contacts::OwnerId).integer().not_null())
+                    .col(ColumnDef::new(Contacts::ContactId).integer().not_null())
+                    .col(ColumnDef::new(Contacts::Blocked).boolean().not_null())
+                    .col(
+                        ColumnDef::new(Contacts::CreatedAt)
+                            .timestamp()
+                            .not_null()
+                            .extra("DEFAULT CURRENT_TIMESTAMP".to_string()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_contacts_owner_id")
+                            .from(Contacts::Table, Contacts::OwnerId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_contacts_contact_id")
+                            .from(Contacts::Table, Contacts::ContactId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .primary_key(
+                        Index::create()
+                            .col(Contacts::OwnerId)
+                            .col(Contacts::ContactId),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Block checks look up who blocked the sender
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_contacts_contact_blocked")
+                    .table(Contacts::Table)
+                    .col(Contacts::ContactId)
+                    .col(Contacts::Blocked)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    // Define how to rollback this migration: Drop the Contacts table.
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Contacts::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum Contacts {
+    Table,
+    OwnerId,
+    ContactId,
+    Blocked,
+    CreatedAt,
+}
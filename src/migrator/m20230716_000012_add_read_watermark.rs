@@ -0,0 +1,95 @@
+use super::{
+    m20230521_000001_create_user_table::Users,
+    m20230606_000005_create_user_conversation_table::UserConversation,
+    m20230606_000006_create_seen_messages_table::{self, SeenMessages},
+};
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m_20230716_000012_add_read_watermark.rs"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Define how to apply this migration: Replace the per message SeenMessages rows with a
+    // read watermark per member, and add the read receipt setting to Users.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UserConversation::Table)
+                    .add_column(ColumnDef::new(ReadWatermark::LastReadMessageId).integer())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .add_column(
+                        ColumnDef::new(ReadWatermark::SendReadReceipts)
+                            .boolean()
+                            .not_null()
+                            .default(true),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Every member keeps the newest message they had seen
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "UPDATE user_conversation SET last_read_message_id = (
+                    SELECT MAX(seen_messages.message_id) FROM seen_messages
+                    INNER JOIN message ON message.message_id = seen_messages.message_id
+                    WHERE seen_messages.seen_id = user_conversation.user_ids
+                    AND message.message_conversation_id = user_conversation.conversation_id
+                )",
+            )
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(SeenMessages::Table).to_owned())
+            .await
+    }
+
+    // Define how to rollback this migration: Bring back an empty SeenMessages table and drop
+    // the watermark columns.
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        m20230606_000006_create_seen_messages_table::Migration
+            .up(manager)
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .drop_column(ReadWatermark::SendReadReceipts)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UserConversation::Table)
+                    .drop_column(ReadWatermark::LastReadMessageId)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum ReadWatermark {
+    /// Newest message of the conversation the member has read
+    LastReadMessageId,
+    /// Whether the user shares their read state with the other members
+    SendReadReceipts,
+}
@@ -0,0 +1,67 @@
+use super::m20230606_000005_create_user_conversation_table::UserConversation;
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m_20230716_000013_add_conversation_flags.rs"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Define how to apply this migration: Add the per member Pinned, Archived and Muted flags
+    // to UserConversation.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UserConversation::Table)
+                    .add_column(
+                        ColumnDef::new(ConversationFlags::Pinned)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .add_column(
+                        ColumnDef::new(ConversationFlags::Archived)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .add_column(
+                        ColumnDef::new(ConversationFlags::Muted)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    // Define how to rollback this migration: Drop the flags.
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UserConversation::Table)
+                    .drop_column(ConversationFlags::Pinned)
+                    .drop_column(ConversationFlags::Archived)
+                    .drop_column(ConversationFlags::Muted)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum ConversationFlags {
+    /// Listed above the other conversations
+    Pinned,
+    /// Hidden from the conversation list until the next message
+    Archived,
+    /// Messages arrive without mention pushes or a share of the unread total
+    Muted,
+}
@@ -0,0 +1,95 @@
+use super::{
+    m20230521_000001_create_user_table::Users,
+    m20230606_000003_create_conversation_table::Conversation,
+    m20230606_000004_create_message_table::Message,
+};
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m_20230716_000014_create_pinned_messages_table.rs"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Define how to apply this migration: Create the PinnedMessages table.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PinnedMessages::Table)
+                    .col(
+                        ColumnDef::new(PinnedMessages::MessageId)
+                            .integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(PinnedMessages::ConversationId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(PinnedMessages::PinnedBy).integer().not_null())
+                    .col(
+                        ColumnDef::new(PinnedMessages::PinnedAt)
+                            .timestamp()
+                            .not_null()
+                            .extra("DEFAULT CURRENT_TIMESTAMP".to_string()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_pinned_messages_message_id")
+                            .from(PinnedMessages::Table, PinnedMessages::MessageId)
+                            .to(Message::Table, Message::MessageId)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_pinned_messages_conversation_id")
+                            .from(PinnedMessages::Table, PinnedMessages::ConversationId)
+                            .to(Conversation::Table, Conversation::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_pinned_messages_pinned_by")
+                            .from(PinnedMessages::Table, PinnedMessages::PinnedBy)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Pins are listed per conversation, newest first
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_pinned_messages_conversation")
+                    .table(PinnedMessages::Table)
+                    .col(PinnedMessages::ConversationId)
+                    .col(PinnedMessages::PinnedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    // Define how to rollback this migration: Drop the PinnedMessages table.
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PinnedMessages::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum PinnedMessages {
+    Table,
+    MessageId,
+    ConversationId,
+    PinnedBy,
+    PinnedAt,
+}
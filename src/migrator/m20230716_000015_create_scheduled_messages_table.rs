@@ -0,0 +1,99 @@
+use super::{
+    m20230521_000001_create_user_table::Users,
+    m20230606_000003_create_conversation_table::Conversation,
+};
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m_20230716_000015_create_scheduled_messages_table.rs"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Define how to apply this migration: Create the ScheduledMessages table.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ScheduledMessages::Table)
+                    .col(
+                        ColumnDef::new(ScheduledMessages::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ScheduledMessages::ConversationId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ScheduledMessages::SenderId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(ScheduledMessages::Body).text().not_null())
+                    .col(
+                        ColumnDef::new(ScheduledMessages::DeliverAt)
+                            .timestamp()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ScheduledMessages::CreatedAt)
+                            .timestamp()
+                            .not_null()
+                            .extra("DEFAULT CURRENT_TIMESTAMP".to_string()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_scheduled_messages_conversation_id")
+                            .from(ScheduledMessages::Table, ScheduledMessages::ConversationId)
+                            .to(Conversation::Table, Conversation::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_scheduled_messages_sender_id")
+                            .from(ScheduledMessages::Table, ScheduledMessages::SenderId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // The scheduler only ever asks for the rows that are due
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_scheduled_messages_deliver_at")
+                    .table(ScheduledMessages::Table)
+                    .col(ScheduledMessages::DeliverAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    // Define how to rollback this migration: Drop the ScheduledMessages table.
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ScheduledMessages::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum ScheduledMessages {
+    Table,
+    Id,
+    ConversationId,
+    SenderId,
+    Body,
+    DeliverAt,
+    CreatedAt,
+}
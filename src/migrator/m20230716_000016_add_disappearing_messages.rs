@@ -0,0 +1,74 @@
+use super::{
+    m20230606_000003_create_conversation_table::Conversation,
+    m20230606_000004_create_message_table::Message,
+};
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m_20230716_000016_add_disappearing_messages.rs"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Define how to apply this migration: Add the retention to Conversation and mark the
+    // messages the server posts itself.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Conversation::Table)
+                    .add_column(
+                        ColumnDef::new(DisappearingMessages::RetentionSeconds).big_integer(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Message::Table)
+                    .add_column(
+                        ColumnDef::new(DisappearingMessages::MessageSystem)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    // Define how to rollback this migration: Drop both columns.
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Message::Table)
+                    .drop_column(DisappearingMessages::MessageSystem)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Conversation::Table)
+                    .drop_column(DisappearingMessages::RetentionSeconds)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum DisappearingMessages {
+    /// Seconds a message is kept before the sweeper deletes it, kept forever when unset
+    RetentionSeconds,
+    /// Posted by the server itself, e.g. when the retention changes
+    MessageSystem,
+}
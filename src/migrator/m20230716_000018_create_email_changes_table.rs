@@ -0,0 +1,63 @@
+use super::m20230521_000001_create_user_table::Users;
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m_20230716_000018_create_email_changes_table.rs"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Define how to apply this migration: Create the EmailChanges table.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(EmailChanges::Table)
+                    .col(
+                        ColumnDef::new(EmailChanges::UserId)
+                            .integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(EmailChanges::NewEmail).string().not_null())
+                    .col(ColumnDef::new(EmailChanges::Verification).string().not_null())
+                    .col(
+                        ColumnDef::new(EmailChanges::CreatedAt)
+                            .timestamp()
+                            .not_null()
+                            .extra("DEFAULT CURRENT_TIMESTAMP".to_string()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_email_changes_user_id")
+                            .from(EmailChanges::Table, EmailChanges::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    // Define how to rollback this migration: Drop the EmailChanges table.
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(EmailChanges::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum EmailChanges {
+    Table,
+    /// A user has at most one pending change, a new request replaces it
+    UserId,
+    NewEmail,
+    /// Code emailed to `NewEmail`, the address is switched once it is entered
+    Verification,
+    CreatedAt,
+}
@@ -0,0 +1,58 @@
+use super::m20230527_000002_create_temp_user_table::TempUsers;
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m_20230716_000019_add_verification_resends.rs"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Define how to apply this migration: Track when and how often the verification code of a
+    // pending signup was emailed.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TempUsers::Table)
+                    .add_column(
+                        ColumnDef::new(VerificationResends::VerificationSentAt)
+                            .timestamp()
+                            .not_null()
+                            .extra("DEFAULT CURRENT_TIMESTAMP".to_string()),
+                    )
+                    .add_column(
+                        ColumnDef::new(VerificationResends::VerificationSends)
+                            .integer()
+                            .not_null()
+                            .default(1),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    // Define how to rollback this migration: Drop the resend tracking.
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TempUsers::Table)
+                    .drop_column(VerificationResends::VerificationSentAt)
+                    .drop_column(VerificationResends::VerificationSends)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum VerificationResends {
+    /// Last time the code was emailed
+    VerificationSentAt,
+    /// How often the code was emailed, the signup included
+    VerificationSends,
+}
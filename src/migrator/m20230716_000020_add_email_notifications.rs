@@ -0,0 +1,74 @@
+use super::m20230521_000001_create_user_table::Users;
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m_20230716_000020_add_email_notifications.rs"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Define how to apply this migration: Add the email notification settings of Users, both
+    // off until a user opts in.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .add_column(
+                        ColumnDef::new(EmailNotifications::EmailMissedMessages)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .add_column(
+                        ColumnDef::new(EmailNotifications::EmailDigest)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .add_column(
+                        ColumnDef::new(EmailNotifications::NotifiedUntil)
+                            .timestamp()
+                            .null(),
+                    )
+                    .add_column(
+                        ColumnDef::new(EmailNotifications::DigestSentAt)
+                            .timestamp()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    // Define how to rollback this migration: Drop the email notification settings.
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .drop_column(EmailNotifications::EmailMissedMessages)
+                    .drop_column(EmailNotifications::EmailDigest)
+                    .drop_column(EmailNotifications::NotifiedUntil)
+                    .drop_column(EmailNotifications::DigestSentAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum EmailNotifications {
+    /// Email about messages left unread past the offline period
+    EmailMissedMessages,
+    /// Email a daily digest of unread conversations
+    EmailDigest,
+    /// Messages sent up to this time were already considered for a missed message email
+    NotifiedUntil,
+    /// Last time the daily digest was due, whether or not anything was unread
+    DigestSentAt,
+}
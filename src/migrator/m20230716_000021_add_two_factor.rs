@@ -0,0 +1,96 @@
+use super::m20230521_000001_create_user_table::Users;
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m_20230716_000021_add_two_factor.rs"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Define how to apply this migration: Add the TOTP secret of Users and create the
+    // RecoveryCodes table.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .add_column(ColumnDef::new(TwoFactor::TotpSecret).string().null())
+                    .add_column(ColumnDef::new(TwoFactor::TotpEnabledAt).timestamp().null())
+                    .add_column(ColumnDef::new(TwoFactor::TotpLastStep).big_integer().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(RecoveryCodes::Table)
+                    .col(
+                        ColumnDef::new(RecoveryCodes::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(RecoveryCodes::UserId).integer().not_null())
+                    .col(
+                        ColumnDef::new(RecoveryCodes::CodeHash)
+                            .string_len(64)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(RecoveryCodes::UsedAt).timestamp().null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_recovery_codes_user_id")
+                            .from(RecoveryCodes::Table, RecoveryCodes::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    // Define how to rollback this migration: Drop the RecoveryCodes table and the TOTP secret.
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(RecoveryCodes::Table).to_owned())
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .drop_column(TwoFactor::TotpSecret)
+                    .drop_column(TwoFactor::TotpEnabledAt)
+                    .drop_column(TwoFactor::TotpLastStep)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum TwoFactor {
+    /// The TOTP secret encrypted with AES-256-GCM, set from the start of an enrollment
+    TotpSecret,
+    /// Set once the first code was confirmed, logins ask for a code from then on
+    TotpEnabledAt,
+    /// Time step of the last accepted code, a code is only accepted once
+    TotpLastStep,
+}
+
+#[derive(Iden)]
+pub enum RecoveryCodes {
+    Table,
+    Id,
+    UserId,
+    /// SHA-256 of the code, the code itself is only shown once
+    CodeHash,
+    /// Set once the code was used, every code works only once
+    UsedAt,
+}
@@ -0,0 +1,81 @@
+use super::m20230521_000001_create_user_table::Users;
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m_20230716_000022_create_user_sessions_table.rs"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Define how to apply this migration: Create the UserSessions table.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserSessions::Table)
+                    .col(
+                        ColumnDef::new(UserSessions::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(UserSessions::UserId).integer().not_null())
+                    .col(
+                        ColumnDef::new(UserSessions::Token)
+                            .string_len(64)
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(UserSessions::UserAgent).string_len(255).null())
+                    .col(ColumnDef::new(UserSessions::Ip).string_len(45).null())
+                    .col(
+                        ColumnDef::new(UserSessions::CreatedAt)
+                            .timestamp()
+                            .not_null()
+                            .extra("DEFAULT CURRENT_TIMESTAMP".to_string()),
+                    )
+                    .col(
+                        ColumnDef::new(UserSessions::LastSeenAt)
+                            .timestamp()
+                            .not_null()
+                            .extra("DEFAULT CURRENT_TIMESTAMP".to_string()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_user_sessions_user_id")
+                            .from(UserSessions::Table, UserSessions::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    // Define how to rollback this migration: Drop the UserSessions table.
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(UserSessions::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum UserSessions {
+    Table,
+    Id,
+    UserId,
+    /// Random token kept in the session state, a session whose row is gone is logged out
+    Token,
+    UserAgent,
+    /// Address of the latest request
+    Ip,
+    CreatedAt,
+    /// Updated at most once a minute
+    LastSeenAt,
+}
@@ -0,0 +1,57 @@
+use super::m20230521_000001_create_user_table::Users;
+use super::m20230716_000017_create_password_reset_tokens_table::SessionRevocation;
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m_20230716_000023_add_session_version.rs"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    // Define how to apply this migration: Add the session version of Users, which replaces the
+    // revocation time.
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .add_column(
+                        ColumnDef::new(SessionVersion::SessionVersion)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .drop_column(SessionRevocation::SessionsRevokedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    // Define how to rollback this migration: Drop the session version and restore the
+    // revocation time.
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .drop_column(SessionVersion::SessionVersion)
+                    .add_column(
+                        ColumnDef::new(SessionRevocation::SessionsRevokedAt)
+                            .timestamp()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum SessionVersion {
+    /// Part of every identity, bumping it logs out all of the user's sessions
+    SessionVersion,
+}
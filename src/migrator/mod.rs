@@ -7,6 +7,23 @@ mod m20230606_000003_create_conversation_table;
 mod m20230606_000004_create_message_table;
 mod m20230606_000005_create_user_conversation_table;
 mod m20230606_000006_create_seen_messages_table;
+mod m20230716_000007_add_message_audio;
+mod m20230716_000008_create_link_previews_table;
+mod m20230716_000009_create_message_mentions_table;
+mod m20230716_000010_add_message_body_fulltext;
+mod m20230716_000011_create_contacts_table;
+mod m20230716_000012_add_read_watermark;
+mod m20230716_000013_add_conversation_flags;
+mod m20230716_000014_create_pinned_messages_table;
+mod m20230716_000015_create_scheduled_messages_table;
+mod m20230716_000016_add_disappearing_messages;
+mod m20230716_000017_create_password_reset_tokens_table;
+mod m20230716_000018_create_email_changes_table;
+mod m20230716_000019_add_verification_resends;
+mod m20230716_000020_add_email_notifications;
+mod m20230716_000021_add_two_factor;
+mod m20230716_000022_create_user_sessions_table;
+mod m20230716_000023_add_session_version;
 
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
@@ -17,7 +34,24 @@ impl MigratorTrait for Migrator {
             Box::new(m20230606_000003_create_conversation_table::Migration),
             Box::new(m20230606_000004_create_message_table::Migration),
             Box::new(m20230606_000005_create_user_conversation_table::Migration),
-            Box::new(m20230606_000006_create_seen_messages_table::Migration)
+            Box::new(m20230606_000006_create_seen_messages_table::Migration),
+            Box::new(m20230716_000007_add_message_audio::Migration),
+            Box::new(m20230716_000008_create_link_previews_table::Migration),
+            Box::new(m20230716_000009_create_message_mentions_table::Migration),
+            Box::new(m20230716_000010_add_message_body_fulltext::Migration),
+            Box::new(m20230716_000011_create_contacts_table::Migration),
+            Box::new(m20230716_000012_add_read_watermark::Migration),
+            Box::new(m20230716_000013_add_conversation_flags::Migration),
+            Box::new(m20230716_000014_create_pinned_messages_table::Migration),
+            Box::new(m20230716_000015_create_scheduled_messages_table::Migration),
+            Box::new(m20230716_000016_add_disappearing_messages::Migration),
+            Box::new(m20230716_000017_create_password_reset_tokens_table::Migration),
+            Box::new(m20230716_000018_create_email_changes_table::Migration),
+            Box::new(m20230716_000019_add_verification_resends::Migration),
+            Box::new(m20230716_000020_add_email_notifications::Migration),
+            Box::new(m20230716_000021_add_two_factor::Migration),
+            Box::new(m20230716_000022_create_user_sessions_table::Migration),
+            Box::new(m20230716_000023_add_session_version::Migration)
         ]
     }
 }
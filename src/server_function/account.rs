@@ -0,0 +1,379 @@
+//! Credential changes of a logged in user. Both need the current password. A new email is only
+//! written to `users` once the code sent to it comes back, until then it waits in
+//! `email_changes`.
+
+use argon2::{
+    password_hash::{PasswordHash, PasswordVerifier},
+    Argon2,
+};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use sea_orm::{
+    sea_query::OnConflict, ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection,
+    DbErr, EntityTrait, QueryFilter,
+};
+use validator::Validate;
+
+use crate::app::{EmailSchema, PasswordSchema};
+use crate::entities::{email_changes, prelude::*, temp_users, users};
+
+/// How long the code of an email change can be entered.
+pub const EMAIL_CHANGE_TTL_MINUTES: i64 = 15;
+
+#[derive(Debug)]
+pub enum AccountError {
+    IncorrectPassword,
+    WeakPassword,
+    InvalidEmail,
+    EmailPresent,
+    NoPendingChange,
+    IncorrectValidationCode,
+    Database(DbErr),
+}
+
+impl std::fmt::Display for AccountError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AccountError::IncorrectPassword => write!(f, "Current password is incorrect"),
+            AccountError::WeakPassword => write!(f, "Password does not meet the requirements"),
+            AccountError::InvalidEmail => write!(f, "Email address is invalid"),
+            AccountError::EmailPresent => write!(f, "Email address is already in use"),
+            AccountError::NoPendingChange => write!(f, "No email change is pending"),
+            AccountError::IncorrectValidationCode => write!(f, "Verification code is incorrect"),
+            AccountError::Database(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for AccountError {}
+
+impl AccountError {
+    pub fn into_server_fn_error(self) -> leptos::ServerFnError {
+        match self {
+            AccountError::Database(e) => leptos::ServerFnError::ServerError(e.to_string()),
+            e => leptos::ServerFnError::Request(e.to_string()),
+        }
+    }
+}
+
+impl From<DbErr> for AccountError {
+    fn from(value: DbErr) -> Self {
+        AccountError::Database(value)
+    }
+}
+
+/// A random 15 character code of lowercase, uppercase and special characters, as emailed on
+/// signup and on an email change.
+pub fn verification_code() -> String {
+    let special_characters = "!@#$%^&*";
+
+    let mut rng = rand::thread_rng();
+    (0..15)
+        .map(|_| {
+            let charset: Vec<u8> = match rng.gen_range(0..3) {
+                0 => (b'a'..=b'z').collect(),
+                1 => (b'A'..=b'Z').collect(),
+                _ => special_characters.bytes().collect(),
+            };
+            char::from(charset[rng.gen_range(0..charset.len())])
+        })
+        .collect()
+}
+
+pub fn verify_password(hash: &str, password: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|parsed_hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &parsed_hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+async fn authenticate(
+    data: &DatabaseConnection,
+    user_id: i32,
+    password: &str,
+) -> Result<users::server::Model, AccountError> {
+    Users::find_by_id(user_id)
+        .one(data)
+        .await?
+        .filter(|user| verify_password(&user.password, password))
+        .ok_or(AccountError::IncorrectPassword)
+}
+
+/// Whether anyone, registered or still verifying their signup, uses `email`.
+async fn email_present(data: &DatabaseConnection, email: &str) -> Result<bool, DbErr> {
+    Ok(Users::find()
+        .filter(users::server::Column::Email.eq(email))
+        .one(data)
+        .await?
+        .is_some()
+        || TempUsers::find()
+            .filter(temp_users::server::Column::Email.eq(email))
+            .one(data)
+            .await?
+            .is_some())
+}
+
+/// Sets a new password and logs out every session of the user, like a password reset does.
+/// Returns the updated user, to log the caller in again, and the revoked session tokens.
+pub async fn change_password(
+    data: &DatabaseConnection,
+    user_id: i32,
+    current_password: &str,
+    new_password: &PasswordSchema,
+) -> Result<(users::server::Model, Vec<String>), AccountError> {
+    if new_password.validate().is_err() {
+        return Err(AccountError::WeakPassword);
+    }
+
+    let mut user: users::server::ActiveModel =
+        authenticate(data, user_id, current_password).await?.into();
+    user.password = ActiveValue::Set(super::password_reset::hash_password(&new_password.entry));
+    user.update(data).await?;
+
+    let revoked = crate::auth::identity::bump_session_version(data, user_id).await?;
+    let user = Users::find_by_id(user_id)
+        .one(data)
+        .await?
+        .ok_or(AccountError::IncorrectPassword)?;
+    Ok((user, revoked))
+}
+
+/// Stores a pending change to `new_email`, replacing an earlier one. Returns the user's first
+/// name and the code to email to the new address.
+pub async fn request_email_change(
+    data: &DatabaseConnection,
+    now: DateTime<Utc>,
+    user_id: i32,
+    current_password: &str,
+    new_email: &EmailSchema,
+) -> Result<(String, String), AccountError> {
+    if new_email.validate().is_err() {
+        return Err(AccountError::InvalidEmail);
+    }
+    let user = authenticate(data, user_id, current_password).await?;
+    if email_present(data, &new_email.entry).await? {
+        return Err(AccountError::EmailPresent);
+    }
+
+    let code = verification_code();
+    EmailChanges::insert(email_changes::server::ActiveModel {
+        user_id: ActiveValue::Set(user_id),
+        new_email: ActiveValue::Set(new_email.entry.clone()),
+        verification: ActiveValue::Set(code.clone()),
+        created_at: ActiveValue::Set(now),
+    })
+    .on_conflict(
+        OnConflict::column(email_changes::server::Column::UserId)
+            .update_columns([
+                email_changes::server::Column::NewEmail,
+                email_changes::server::Column::Verification,
+                email_changes::server::Column::CreatedAt,
+            ])
+            .to_owned(),
+    )
+    .exec_without_returning(data)
+    .await?;
+    Ok((user.first_name, code))
+}
+
+/// Switches `users.email` to the pending address when `code` matches, returns the updated
+/// user.
+pub async fn confirm_email_change(
+    data: &DatabaseConnection,
+    now: DateTime<Utc>,
+    user_id: i32,
+    code: &str,
+) -> Result<users::server::Model, AccountError> {
+    let pending = EmailChanges::find_by_id(user_id)
+        .one(data)
+        .await?
+        .filter(|pending| {
+            pending.created_at + chrono::Duration::minutes(EMAIL_CHANGE_TTL_MINUTES) > now
+        })
+        .ok_or(AccountError::NoPendingChange)?;
+    if pending.verification.trim() != code.trim() {
+        return Err(AccountError::IncorrectValidationCode);
+    }
+
+    EmailChanges::delete_by_id(user_id).exec(data).await?;
+    // Somebody may have signed up with the address since the code went out
+    if email_present(data, &pending.new_email).await? {
+        return Err(AccountError::EmailPresent);
+    }
+
+    let user = Users::find_by_id(user_id)
+        .one(data)
+        .await?
+        .ok_or(AccountError::NoPendingChange)?;
+    let mut user: users::server::ActiveModel = user.into();
+    user.email = ActiveValue::Set(pending.new_email);
+    Ok(user.update(data).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+    use chrono::TimeZone;
+    use sea_orm::IntoActiveModel;
+
+    const PASSWORD: &str = "Current1!";
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 5, 1, 12, 0, 0).unwrap()
+    }
+
+    fn password(entry: &str) -> PasswordSchema {
+        PasswordSchema {
+            entry: String::from(entry),
+        }
+    }
+
+    fn email(entry: &str) -> EmailSchema {
+        EmailSchema {
+            entry: String::from(entry),
+        }
+    }
+
+    fn user(id: i32, email: &str) -> users::server::Model {
+        users::server::Model {
+            id,
+            first_name: String::from("Alice"),
+            last_name: String::from("Tester"),
+            email: String::from(email),
+            phone_number: 0,
+            password: super::super::password_reset::hash_password(PASSWORD),
+            image: None,
+            send_read_receipts: 1,
+            email_missed_messages: 0,
+            email_digest: 0,
+            notified_until: None,
+            digest_sent_at: None,
+            totp_secret: None,
+            totp_enabled_at: None,
+            totp_last_step: None,
+            session_version: 0,
+        }
+    }
+
+    async fn database() -> DatabaseConnection {
+        let db = testing::sqlite().await;
+        testing::create_table(&db, Users).await;
+        testing::create_table(&db, TempUsers).await;
+        testing::create_table(&db, EmailChanges).await;
+        testing::create_table(&db, UserSessions).await;
+        user(1, "alice@example.com")
+            .into_active_model()
+            .insert(&db)
+            .await
+            .unwrap();
+        db
+    }
+
+    async fn stored(db: &DatabaseConnection) -> users::server::Model {
+        Users::find_by_id(1).one(db).await.unwrap().unwrap()
+    }
+
+    async fn pending(db: &DatabaseConnection) -> Option<email_changes::server::Model> {
+        EmailChanges::find_by_id(1).one(db).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn changing_the_password_logs_out_every_session() {
+        let db = database().await;
+
+        let (user, _) = change_password(&db, 1, PASSWORD, &password("Changed1!"))
+            .await
+            .unwrap();
+        assert_eq!(user.session_version, 1);
+        assert!(verify_password(&user.password, "Changed1!"));
+        assert!(!verify_password(&user.password, PASSWORD));
+    }
+
+    #[tokio::test]
+    async fn wrong_current_password_changes_nothing() {
+        let db = database().await;
+
+        assert!(matches!(
+            change_password(&db, 1, "Wrong1!", &password("Changed1!")).await,
+            Err(AccountError::IncorrectPassword)
+        ));
+        let user = stored(&db).await;
+        assert!(verify_password(&user.password, PASSWORD));
+        assert_eq!(user.session_version, 0);
+
+        assert!(matches!(
+            request_email_change(&db, now(), 1, "Wrong1!", &email("new@example.com")).await,
+            Err(AccountError::IncorrectPassword)
+        ));
+        assert!(pending(&db).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn weak_new_password_is_refused() {
+        let db = database().await;
+
+        for weak in ["short1!", "nouppercase1!", "NoNumber!", "NoSpecial1"] {
+            assert!(matches!(
+                change_password(&db, 1, PASSWORD, &password(weak)).await,
+                Err(AccountError::WeakPassword)
+            ));
+        }
+        assert!(verify_password(&stored(&db).await.password, PASSWORD));
+    }
+
+    #[tokio::test]
+    async fn email_change_expires() {
+        let db = database().await;
+        let (_, code) = request_email_change(&db, now(), 1, PASSWORD, &email("new@example.com"))
+            .await
+            .unwrap();
+
+        let expired = now() + chrono::Duration::minutes(EMAIL_CHANGE_TTL_MINUTES);
+        assert!(matches!(
+            confirm_email_change(&db, expired, 1, &code).await,
+            Err(AccountError::NoPendingChange)
+        ));
+        assert!(matches!(
+            confirm_email_change(&db, now(), 1, "wrong").await,
+            Err(AccountError::IncorrectValidationCode)
+        ));
+        let user = confirm_email_change(&db, now() + chrono::Duration::minutes(1), 1, &code)
+            .await
+            .unwrap();
+        assert_eq!(user.email, "new@example.com");
+        // The code only works once
+        assert!(matches!(
+            confirm_email_change(&db, now(), 1, &code).await,
+            Err(AccountError::NoPendingChange)
+        ));
+    }
+
+    #[tokio::test]
+    async fn address_taken_after_the_request() {
+        let db = database().await;
+        assert!(matches!(
+            request_email_change(&db, now(), 1, PASSWORD, &email("alice@example.com")).await,
+            Err(AccountError::EmailPresent)
+        ));
+
+        let (_, code) = request_email_change(&db, now(), 1, PASSWORD, &email("new@example.com"))
+            .await
+            .unwrap();
+        user(2, "new@example.com")
+            .into_active_model()
+            .insert(&db)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            confirm_email_change(&db, now(), 1, &code).await,
+            Err(AccountError::EmailPresent)
+        ));
+        assert_eq!(stored(&db).await.email, "alice@example.com");
+        assert!(pending(&db).await.is_none());
+    }
+}
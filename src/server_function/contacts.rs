@@ -0,0 +1,349 @@
+//! Contacts and the blocklist. A row in `contacts` belongs to its owner, `blocked` turns a
+//! contact into a block. Blocks are checked in both directions: neither side can open a new
+//! conversation with the other or send into their direct conversation, while the history
+//! already exchanged stays where it is.
+
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    sea_query::{Expr, OnConflict},
+    ActiveValue, ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, QueryFilter,
+};
+
+use super::{ContactAction, ContactModel};
+use crate::entities::{contacts, prelude::*, user_conversation, users};
+
+#[derive(Debug)]
+pub enum ContactError {
+    SelfContact,
+    UnknownUser,
+    Blocked,
+    NotMember,
+    Database(DbErr),
+}
+
+impl std::fmt::Display for ContactError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContactError::SelfContact => write!(f, "You cannot add yourself"),
+            ContactError::UnknownUser => write!(f, "User does not exist"),
+            ContactError::Blocked => write!(f, "This user is not accepting your messages"),
+            ContactError::NotMember => write!(f, "Not a member of this conversation"),
+            ContactError::Database(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ContactError {}
+
+impl ContactError {
+    pub fn into_server_fn_error(self) -> leptos::ServerFnError {
+        match self {
+            ContactError::Database(e) => leptos::ServerFnError::ServerError(e.to_string()),
+            e => leptos::ServerFnError::Request(e.to_string()),
+        }
+    }
+}
+
+impl From<DbErr> for ContactError {
+    fn from(value: DbErr) -> Self {
+        ContactError::Database(value)
+    }
+}
+
+/// Whether either user has blocked any of the others.
+async fn any_blocked(data: &DatabaseConnection, user_id: i32, others: &[i32]) -> Result<bool, DbErr> {
+    if others.is_empty() {
+        return Ok(false);
+    }
+
+    Ok(Contacts::find()
+        .filter(contacts::server::Column::Blocked.eq(1))
+        .filter(
+            Condition::any()
+                .add(
+                    Condition::all()
+                        .add(contacts::server::Column::OwnerId.eq(user_id))
+                        .add(contacts::server::Column::ContactId.is_in(others.to_vec())),
+                )
+                .add(
+                    Condition::all()
+                        .add(contacts::server::Column::OwnerId.is_in(others.to_vec()))
+                        .add(contacts::server::Column::ContactId.eq(user_id)),
+                ),
+        )
+        .one(data)
+        .await?
+        .is_some())
+}
+
+/// Ids of the users that have blocked `user_id`.
+pub async fn blocked_by(data: &DatabaseConnection, user_id: i32) -> Result<Vec<i32>, DbErr> {
+    Ok(Contacts::find()
+        .filter(contacts::server::Column::ContactId.eq(user_id))
+        .filter(contacts::server::Column::Blocked.eq(1))
+        .all(data)
+        .await?
+        .into_iter()
+        .map(|row| row.owner_id)
+        .collect())
+}
+
+/// Checked before a conversation with `others` is created.
+pub async fn ensure_can_start(
+    data: &DatabaseConnection,
+    user_id: i32,
+    others: &[i32],
+) -> Result<(), ContactError> {
+    match any_blocked(data, user_id, others).await? {
+        true => Err(ContactError::Blocked),
+        false => Ok(()),
+    }
+}
+
+/// Checked before a message is stored. Group conversations stay open, a block only closes the
+/// direct conversation between the two users.
+pub async fn ensure_can_send(
+    data: &DatabaseConnection,
+    user_id: i32,
+    conversation_id: i32,
+) -> Result<(), ContactError> {
+    let members: Vec<i32> = UserConversation::find()
+        .filter(user_conversation::server::Column::ConversationId.eq(conversation_id))
+        .all(data)
+        .await?
+        .into_iter()
+        .map(|membership| membership.user_ids)
+        .collect();
+
+    if !members.contains(&user_id) {
+        return Err(ContactError::NotMember);
+    }
+
+    let is_group = Conversation::find_by_id(conversation_id)
+        .one(data)
+        .await?
+        .map_or(false, |conversation| conversation.is_group != 0);
+    if is_group {
+        return Ok(());
+    }
+
+    let others: Vec<i32> = members.into_iter().filter(|id| *id != user_id).collect();
+    ensure_can_start(data, user_id, &others).await
+}
+
+pub async fn apply(
+    data: &DatabaseConnection,
+    now: DateTime<Utc>,
+    owner_id: i32,
+    contact_id: i32,
+    action: ContactAction,
+) -> Result<(), ContactError> {
+    if owner_id == contact_id {
+        return Err(ContactError::SelfContact);
+    }
+    if Users::find_by_id(contact_id).one(data).await?.is_none() {
+        return Err(ContactError::UnknownUser);
+    }
+
+    let model = |blocked: i8| contacts::server::ActiveModel {
+        owner_id: ActiveValue::Set(owner_id),
+        contact_id: ActiveValue::Set(contact_id),
+        blocked: ActiveValue::Set(blocked),
+        created_at: ActiveValue::Set(now),
+    };
+
+    match action {
+        // Adding someone who is blocked leaves the block in place. MySQL has no `DO NOTHING`,
+        // so the conflict sets the owner to itself instead.
+        ContactAction::Add => {
+            Contacts::insert(model(0))
+                .on_conflict(
+                    OnConflict::columns([
+                        contacts::server::Column::OwnerId,
+                        contacts::server::Column::ContactId,
+                    ])
+                    .update_column(contacts::server::Column::OwnerId)
+                    .to_owned(),
+                )
+                .exec_without_returning(data)
+                .await?;
+        }
+        ContactAction::Remove => {
+            Contacts::delete_many()
+                .filter(contacts::server::Column::OwnerId.eq(owner_id))
+                .filter(contacts::server::Column::ContactId.eq(contact_id))
+                .filter(contacts::server::Column::Blocked.eq(0))
+                .exec(data)
+                .await?;
+        }
+        ContactAction::Block => {
+            Contacts::insert(model(1))
+                .on_conflict(
+                    OnConflict::columns([
+                        contacts::server::Column::OwnerId,
+                        contacts::server::Column::ContactId,
+                    ])
+                    .update_column(contacts::server::Column::Blocked)
+                    .to_owned(),
+                )
+                .exec_without_returning(data)
+                .await?;
+        }
+        // The user stays in the contact list after being unblocked
+        ContactAction::Unblock => {
+            Contacts::update_many()
+                .col_expr(contacts::server::Column::Blocked, Expr::value(0))
+                .filter(contacts::server::Column::OwnerId.eq(owner_id))
+                .filter(contacts::server::Column::ContactId.eq(contact_id))
+                .exec(data)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn list(data: &DatabaseConnection, owner_id: i32) -> Result<Vec<ContactModel>, DbErr> {
+    let rows = Contacts::find()
+        .filter(contacts::server::Column::OwnerId.eq(owner_id))
+        .all(data)
+        .await?;
+    if rows.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut users = Users::find()
+        .filter(users::server::Column::Id.is_in(rows.iter().map(|row| row.contact_id)))
+        .all(data)
+        .await?;
+    users.sort_by(|a, b| (&a.first_name, &a.last_name).cmp(&(&b.first_name, &b.last_name)));
+
+    Ok(users
+        .into_iter()
+        .filter_map(|user| {
+            let blocked = rows.iter().find(|row| row.contact_id == user.id)?.blocked != 0;
+            Some(ContactModel {
+                user: user.into(),
+                blocked,
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+    use chrono::TimeZone;
+    use sea_orm::{ActiveModelTrait, IntoActiveModel};
+
+    const ALICE: i32 = 1;
+    const BOB: i32 = 2;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 5, 1, 12, 0, 0).unwrap()
+    }
+
+    async fn seeded() -> DatabaseConnection {
+        let data = testing::sqlite().await;
+        testing::create_table(&data, Users).await;
+        testing::create_table(&data, Contacts).await;
+        for (id, first_name) in [(ALICE, "Alice"), (BOB, "Bob")] {
+            users::server::Model {
+                id,
+                first_name: first_name.to_string(),
+                last_name: "Tester".to_string(),
+                email: format!("{first_name}@example.com"),
+                phone_number: 0,
+                password: String::new(),
+                image: None,
+                send_read_receipts: 1,
+                email_missed_messages: 0,
+                email_digest: 0,
+                notified_until: None,
+                digest_sent_at: None,
+                totp_secret: None,
+                totp_enabled_at: None,
+                totp_last_step: None,
+                session_version: 0,
+            }
+            .into_active_model()
+            .insert(&data)
+            .await
+            .unwrap();
+        }
+        data
+    }
+
+    /// Bob as Alice sees him, `None` when he is not in her list.
+    async fn bob_blocked(data: &DatabaseConnection) -> Option<bool> {
+        list(data, ALICE)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|contact| contact.user.id == BOB)
+            .map(|contact| contact.blocked)
+    }
+
+    #[tokio::test]
+    async fn adding_twice_keeps_one_contact() {
+        let data = seeded().await;
+        apply(&data, now(), ALICE, BOB, ContactAction::Add)
+            .await
+            .unwrap();
+        apply(&data, now(), ALICE, BOB, ContactAction::Add)
+            .await
+            .unwrap();
+        assert_eq!(list(&data, ALICE).await.unwrap().len(), 1);
+        assert_eq!(bob_blocked(&data).await, Some(false));
+
+        apply(&data, now(), ALICE, BOB, ContactAction::Remove)
+            .await
+            .unwrap();
+        assert_eq!(bob_blocked(&data).await, None);
+    }
+
+    #[tokio::test]
+    async fn adding_keeps_a_block() {
+        let data = seeded().await;
+        apply(&data, now(), ALICE, BOB, ContactAction::Block)
+            .await
+            .unwrap();
+        apply(&data, now(), ALICE, BOB, ContactAction::Add)
+            .await
+            .unwrap();
+        assert_eq!(bob_blocked(&data).await, Some(true));
+
+        // Removing only removes contacts, blocks stay
+        apply(&data, now(), ALICE, BOB, ContactAction::Remove)
+            .await
+            .unwrap();
+        assert_eq!(bob_blocked(&data).await, Some(true));
+        assert_eq!(blocked_by(&data, BOB).await.unwrap(), [ALICE]);
+        for (user_id, other) in [(ALICE, BOB), (BOB, ALICE)] {
+            assert!(matches!(
+                ensure_can_start(&data, user_id, &[other]).await,
+                Err(ContactError::Blocked)
+            ));
+        }
+
+        apply(&data, now(), ALICE, BOB, ContactAction::Unblock)
+            .await
+            .unwrap();
+        assert_eq!(bob_blocked(&data).await, Some(false));
+        ensure_can_start(&data, BOB, &[ALICE]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_self_and_unknown_users() {
+        let data = seeded().await;
+        assert!(matches!(
+            apply(&data, now(), ALICE, ALICE, ContactAction::Add).await,
+            Err(ContactError::SelfContact)
+        ));
+        assert!(matches!(
+            apply(&data, now(), ALICE, 9, ContactAction::Block).await,
+            Err(ContactError::UnknownUser)
+        ));
+    }
+}
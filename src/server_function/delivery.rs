@@ -0,0 +1,69 @@
+//! What happens to a message once it is stored: unread counts and mentions go out over
+//! `IconWs`. Shared by the message routes and the scheduler so both deliver the same way.
+
+use sea_orm::{DatabaseConnection, DbErr};
+
+use super::{AppendDatabase, MentionEvent, RetrieveConversations, UserLogin};
+use crate::web_socket::server::{ChatServer, ClientMessage, IconWs, NotifyUsers};
+
+/// Pushes the new unread counts, then records the mentions in `body` and notifies the
+/// mentioned members that have not muted the conversation.
+pub async fn announce(
+    data: &DatabaseConnection,
+    icon_server: &actix::Addr<IconWs>,
+    sender: &UserLogin,
+    conversation_id: i32,
+    message_id: i32,
+    body: Option<&str>,
+) -> Result<(), DbErr> {
+    super::unread::notify_members(data, icon_server, conversation_id, sender.id).await?;
+
+    let Some(body) = body else {
+        return Ok(());
+    };
+
+    let mentioned =
+        AppendDatabase::insert_mentions(data, message_id, conversation_id, sender.id, body).await;
+
+    // Muted members still find the mention in their unread mentions
+    let muted = RetrieveConversations::retrieve_muted_members(conversation_id, data).await;
+    let mentioned: Vec<i32> = mentioned
+        .into_iter()
+        .filter(|user_id| !muted.contains(user_id))
+        .collect();
+
+    if mentioned.is_empty() {
+        return Ok(());
+    }
+
+    if let Ok(message) = serde_json::to_string(&MentionEvent {
+        mention_message_id: message_id,
+        mention_conversation_id: conversation_id,
+        sender_id: sender.id,
+        sender_first_name: sender.first_name.clone(),
+        sender_last_name: sender.last_name.clone(),
+        body: body.to_string(),
+        created_at: chrono::Utc::now().to_string(),
+    }) {
+        icon_server.do_send(NotifyUsers {
+            user_ids: mentioned,
+            message,
+        });
+    }
+    Ok(())
+}
+
+/// Sends the message to everyone that has the conversation open, the way a client does right
+/// after its own send. Only needed for messages the server sends on a user's behalf.
+pub fn broadcast(
+    chat_server: &actix::Addr<ChatServer>,
+    message: &crate::app::pages::conversation::Message,
+) {
+    if let Ok(msg) = serde_json::to_string(message) {
+        chat_server.do_send(ClientMessage {
+            id: 0,
+            msg,
+            room: message.conversation_id as usize,
+        });
+    }
+}
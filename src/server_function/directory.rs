@@ -0,0 +1,147 @@
+//! The user directory behind `get_users`. Contacts, anyone the caller shares a conversation
+//! with, are ranked by the conversation's last activity and served before everyone else, so
+//! a page is cut from the ranked contacts first and the remainder comes from `users`.
+
+use std::collections::HashMap;
+
+use sea_orm::{
+    sea_query::{Expr, LikeExpr},
+    ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder,
+    QuerySelect,
+};
+
+use super::{search::escape_like, UserLogin, UserModel, UserPage, UserQuery};
+use crate::entities::{conversation, prelude::*, user_conversation, users};
+
+const MAX_WORDS: usize = 4;
+
+/// Every word has to be the prefix of the first name, the last name or the email.
+fn prefix_condition(search: &str) -> Condition {
+    search
+        .split_whitespace()
+        .take(MAX_WORDS)
+        .fold(Condition::all(), |condition, word| {
+            let pattern = format!("{}%", escape_like(word));
+            let column = |column: users::server::Column| {
+                Expr::col((users::server::Entity, column))
+                    .like(LikeExpr::new(pattern.clone()).escape('\\'))
+            };
+            condition.add(
+                Condition::any()
+                    .add(column(users::server::Column::FirstName))
+                    .add(column(users::server::Column::LastName))
+                    .add(column(users::server::Column::Email)),
+            )
+        })
+}
+
+/// Ids of the caller's contacts, most recent conversation activity first.
+async fn recent_contacts(user: &UserLogin, data: &DatabaseConnection) -> Result<Vec<i32>, DbErr> {
+    let conversation_ids: Vec<i32> = UserConversation::find()
+        .filter(user_conversation::server::Column::UserIds.eq(user.id))
+        .all(data)
+        .await?
+        .into_iter()
+        .map(|membership| membership.conversation_id)
+        .collect();
+
+    if conversation_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let last_activity: HashMap<i32, _> = Conversation::find()
+        .filter(conversation::server::Column::Id.is_in(conversation_ids.clone()))
+        .all(data)
+        .await?
+        .into_iter()
+        .map(|conversation| (conversation.id, conversation.last_message_at))
+        .collect();
+
+    let mut contacts = HashMap::new();
+    for membership in UserConversation::find()
+        .filter(user_conversation::server::Column::ConversationId.is_in(conversation_ids))
+        .filter(user_conversation::server::Column::UserIds.ne(user.id))
+        .all(data)
+        .await?
+    {
+        let Some(activity) = last_activity.get(&membership.conversation_id).copied() else {
+            continue;
+        };
+        contacts
+            .entry(membership.user_ids)
+            .and_modify(|latest| {
+                if activity > *latest {
+                    *latest = activity
+                }
+            })
+            .or_insert(activity);
+    }
+
+    let mut contacts: Vec<_> = contacts.into_iter().collect();
+    contacts.sort_by(|(a_id, a), (b_id, b)| b.cmp(a).then(a_id.cmp(b_id)));
+    Ok(contacts.into_iter().map(|(id, _)| id).collect())
+}
+
+pub async fn directory(
+    user: &UserLogin,
+    query: &UserQuery,
+    data: &DatabaseConnection,
+) -> Result<UserPage, DbErr> {
+    let limit = query
+        .limit
+        .unwrap_or(UserQuery::DEFAULT_LIMIT)
+        .clamp(1, UserQuery::MAX_LIMIT);
+    let offset = query.offset.unwrap_or_default();
+    let search = prefix_condition(query.search.as_deref().unwrap_or_default());
+
+    let contact_ids = recent_contacts(user, data).await?;
+
+    let matching_contacts: Vec<users::server::Model> = match contact_ids.is_empty() {
+        true => Vec::new(),
+        false => {
+            let mut found: HashMap<i32, users::server::Model> = Users::find()
+                .filter(users::server::Column::Id.is_in(contact_ids.clone()))
+                .filter(search.clone())
+                .all(data)
+                .await?
+                .into_iter()
+                .map(|model| (model.id, model))
+                .collect();
+            contact_ids.iter().filter_map(|id| found.remove(id)).collect()
+        }
+    };
+
+    // One extra row tells whether there is another page
+    let wanted = limit as usize + 1;
+    let mut page: Vec<users::server::Model> = matching_contacts
+        .iter()
+        .skip(offset as usize)
+        .take(wanted)
+        .cloned()
+        .collect();
+
+    if page.len() < wanted {
+        let mut others = Users::find()
+            .filter(users::server::Column::Id.ne(user.id))
+            .filter(search)
+            .order_by_asc(users::server::Column::FirstName)
+            .order_by_asc(users::server::Column::LastName)
+            .order_by_asc(users::server::Column::Id)
+            .offset(offset.saturating_sub(matching_contacts.len() as u64))
+            .limit((wanted - page.len()) as u64);
+        if !contact_ids.is_empty() {
+            others = others.filter(users::server::Column::Id.is_not_in(contact_ids));
+        }
+        page.extend(others.all(data).await?);
+    }
+
+    let next_offset = (page.len() > limit as usize).then(|| {
+        page.truncate(limit as usize);
+        offset + limit
+    });
+
+    Ok(UserPage {
+        users: page.into_iter().map(Into::into).collect(),
+        next_offset,
+    })
+}
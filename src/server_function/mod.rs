@@ -4,6 +4,34 @@ use leptos::*;
 use serde::{Deserialize, Serialize};
 
 pub mod routes;
+#[cfg(feature = "ssr")]
+mod account;
+#[cfg(feature = "ssr")]
+mod contacts;
+#[cfg(feature = "ssr")]
+mod delivery;
+#[cfg(feature = "ssr")]
+mod directory;
+#[cfg(feature = "ssr")]
+pub mod notifications;
+#[cfg(feature = "ssr")]
+mod password_reset;
+#[cfg(feature = "ssr")]
+mod pins;
+#[cfg(feature = "ssr")]
+pub mod rate_limit;
+#[cfg(feature = "ssr")]
+pub mod retention;
+#[cfg(feature = "ssr")]
+pub mod scheduler;
+#[cfg(feature = "ssr")]
+mod search;
+#[cfg(feature = "ssr")]
+mod two_factor;
+#[cfg(feature = "ssr")]
+mod unread;
+#[cfg(feature = "ssr")]
+mod verification;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct UserModel {
@@ -13,6 +41,43 @@ pub struct UserModel {
     pub email: String,
     pub phone_number: i64,
     pub image: Option<String>,
+    pub send_read_receipts: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct UserQuery {
+    /// Matched as a prefix of the first name, last name or email, every word must match
+    pub search: Option<String>,
+    /// `next_offset` of the previous page
+    pub offset: Option<u64>,
+    pub limit: Option<u64>,
+}
+
+impl UserQuery {
+    pub const DEFAULT_LIMIT: u64 = 30;
+    pub const MAX_LIMIT: u64 = 100;
+}
+
+/// A page of the user directory, people the caller shares a conversation with come first,
+/// most recently active conversation first, everyone else follows alphabetically.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserPage {
+    pub users: Vec<UserModel>,
+    pub next_offset: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ContactAction {
+    Add,
+    Remove,
+    Block,
+    Unblock,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ContactModel {
+    pub user: UserModel,
+    pub blocked: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -21,6 +86,19 @@ pub enum ImageAvailability {
     Missing,
 }
 
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct VoiceNoteUpload {
+    pub message_id: i32,
+    pub path: String,
+    pub duration_ms: i32,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct SentMessage {
+    pub message_id: i32,
+    pub image: Option<String>,
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct ConversationMeta {
     pub id: i32,
@@ -30,6 +108,44 @@ pub struct ConversationMeta {
     pub is_group: i8,
     pub count: usize,
     pub other_users: Vec<(String, String, i32)>,
+    #[serde(default)]
+    pub retention: Option<Retention>,
+}
+
+/// How long the messages of a conversation are kept before the sweeper deletes them, shared
+/// by every member.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Retention {
+    Day,
+    Week,
+    Quarter,
+}
+
+impl Retention {
+    pub const ALL: [Retention; 3] = [Retention::Day, Retention::Week, Retention::Quarter];
+
+    pub fn seconds(self) -> i64 {
+        match self {
+            Retention::Day => 24 * 60 * 60,
+            Retention::Week => 7 * 24 * 60 * 60,
+            Retention::Quarter => 90 * 24 * 60 * 60,
+        }
+    }
+
+    /// Stored values that no longer match a choice are treated as no retention.
+    pub fn from_seconds(seconds: i64) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|retention| retention.seconds() == seconds)
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Retention::Day => "24 hours",
+            Retention::Week => "7 days",
+            Retention::Quarter => "90 days",
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
@@ -50,24 +166,30 @@ impl From<users::server::Model> for UserModel {
             last_name: value.last_name,
             phone_number: value.phone_number,
             image: value.image,
+            send_read_receipts: value.send_read_receipts != 0,
+        }
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl From<users::server::Model> for UserLogin {
+    fn from(value: users::server::Model) -> Self {
+        Self {
+            id: value.id,
+            email: value.email,
+            first_name: value.first_name,
+            last_name: value.last_name,
         }
     }
 }
 
 #[cfg(feature = "ssr")]
 impl UserLogin {
-    fn evaluate_user(user: Option<actix_identity::Identity>) -> Result<UserLogin, ServerFnError> {
-        let returned_user: UserLogin;
-        Self::server(match &user.unwrap().id() {
-            Ok(val) => match serde_json::from_str(val) {
-                Ok(val) => {
-                    returned_user = val;
-                    Ok(returned_user)
-                }
-                Err(_) => Err(UserValidation::SerializationError),
-            },
-            Err(_) => Err(UserValidation::NoUser),
-        })
+    /// The caller as resolved by the `CurrentUser` extractor, an error when logged out.
+    pub(crate) fn evaluate_user(
+        user: Option<crate::auth::identity::CurrentUser>,
+    ) -> Result<UserLogin, ServerFnError> {
+        Self::server(user.map(|user| user.0.into()).ok_or(UserValidation::NoUser))
     }
 
     fn server(
@@ -116,6 +238,23 @@ pub struct ConversationInner {
     pub is_group: bool,
     pub name: Option<String>,
     pub messages: Vec<MergedMessages>,
+    /// Newest message the caller has read
+    pub last_read_message_id: Option<i32>,
+    pub pinned: bool,
+    pub archived: bool,
+    pub muted: bool,
+}
+
+/// Per member flags of a conversation, nobody else sees them.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ConversationFlag {
+    /// Listed above the other conversations
+    Pinned,
+    /// Hidden from the list until the next message arrives
+    Archived,
+    /// Messages still arrive, mentions are not pushed and the unread count stays out of the
+    /// total
+    Muted,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -124,19 +263,55 @@ pub struct MergedMessages {
     pub message_id: i32,
     pub message_body: Option<String>,
     pub message_image: Option<String>,
+    pub message_audio: Option<String>,
+    pub message_audio_duration: Option<i32>,
     pub message_sender_id: i32,
-    pub seen_status: Vec<SeenMessageFacing>,
+    #[serde(default)]
+    pub message_system: bool,
+    #[serde(default)]
+    pub link_previews: Vec<LinkPreview>,
     pub created_at: String,
     pub first_name: String,
     pub last_name: String,
 }
 
 #[derive(Debug, Serialize, Clone, PartialEq, Deserialize)]
-pub struct SeenMessageFacing {
-    pub seen_id: Option<i32>,
-    pub message_id: Option<i32>,
-    pub first_name: Option<String>,
-    pub last_name: Option<String>,
+pub struct LinkPreview {
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image: Option<String>,
+    pub site_name: Option<String>,
+}
+
+impl LinkPreview {
+    /// Only the first few links of a message are unfurled
+    pub const MAX_PER_MESSAGE: usize = 3;
+
+    /// Finds the http(s) links in a message body, dropping trailing punctuation that is
+    /// more likely to belong to the sentence than to the URL.
+    pub fn extract_urls(body: &str) -> Vec<String> {
+        let mut urls: Vec<String> = Vec::new();
+        for word in body
+            .split_whitespace()
+            .filter(|word| word.starts_with("http://") || word.starts_with("https://"))
+        {
+            let url = word.trim_end_matches(|c| {
+                matches!(c, '.' | ',' | '!' | '?' | ';' | ':' | ')' | ']' | '}' | '"' | '\'')
+            });
+            if url.len() > "https://".len() && !urls.iter().any(|existing| existing == url) {
+                urls.push(url.to_string());
+            }
+            if urls.len() == Self::MAX_PER_MESSAGE {
+                break;
+            }
+        }
+        urls
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none() && self.description.is_none() && self.image.is_none()
+    }
 }
 
 #[derive(Debug, Serialize, Clone, PartialEq, Deserialize)]
@@ -144,20 +319,184 @@ pub struct MessageStructFacing {
     pub message_id: i32,
     pub message_body: Option<String>,
     pub message_image: Option<String>,
+    pub message_audio: Option<String>,
+    pub message_audio_duration: Option<i32>,
     pub message_created_at: String,
     pub message_conversation_id: i32,
     pub message_sender_id: i32,
+    #[serde(default)]
+    pub message_system: bool,
     pub first_name: String,
     pub last_name: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct SearchQuery {
+    pub text: String,
+    pub sender_id: Option<i32>,
+    pub conversation_id: Option<i32>,
+    /// Inclusive `YYYY-MM-DD` bounds, in UTC
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub has_image: Option<bool>,
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SnippetPart {
+    pub text: String,
+    pub highlighted: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SearchHit {
+    pub message_id: i32,
+    pub conversation_id: i32,
+    pub sender_id: i32,
+    pub first_name: String,
+    pub last_name: String,
+    pub created_at: String,
+    pub has_image: bool,
+    pub snippet: Vec<SnippetPart>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SearchResults {
+    pub hits: Vec<SearchHit>,
+    pub next_cursor: Option<String>,
+}
+
+/// Pushed over `IconWs` to the mentioned user, and returned by the unread mentions API.
+#[derive(Debug, Serialize, Clone, PartialEq, Deserialize)]
+pub struct MentionEvent {
+    pub mention_message_id: i32,
+    pub mention_conversation_id: i32,
+    pub sender_id: i32,
+    pub sender_first_name: String,
+    pub sender_last_name: String,
+    pub body: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq, Deserialize)]
+pub struct PinnedMessage {
+    pub message: MessageStructFacing,
+    pub pinned_by: i32,
+    pub pinned_by_first_name: String,
+    pub pinned_by_last_name: String,
+    pub pinned_at: String,
+}
+
+/// Pushed over `IconWs` to every member when a message is pinned or unpinned, `pin` is
+/// `None` after an unpin.
+#[derive(Debug, Serialize, Clone, PartialEq, Deserialize)]
+pub struct PinUpdate {
+    pub pin_conversation_id: i32,
+    pub pin_message_id: i32,
+    pub pin: Option<PinnedMessage>,
+}
+
+impl PinUpdate {
+    /// Newest pin first, replaying the same update is harmless.
+    pub fn apply(self, pins: &mut Vec<PinnedMessage>) {
+        pins.retain(|pinned| pinned.message.message_id != self.pin_message_id);
+        if let Some(pin) = self.pin {
+            pins.insert(0, pin);
+        }
+    }
+}
+
+/// A message waiting to be sent, only its sender ever sees it. `deliver_at` is in
+/// milliseconds since the epoch so the browser can show it in local time.
+#[derive(Debug, Serialize, Clone, PartialEq, Deserialize)]
+pub struct ScheduledMessage {
+    pub id: i32,
+    pub conversation_id: i32,
+    pub body: String,
+    pub deliver_at: i64,
+}
+
+/// Pushed over `IconWs` to every member when expired messages were swept.
+#[derive(Debug, Serialize, Clone, PartialEq, Deserialize)]
+pub struct MessagesDeleted {
+    pub deleted_conversation_id: i32,
+    pub deleted_message_ids: Vec<i32>,
+}
+
+/// Messages from other members after the caller's read watermark. Pushed over `IconWs`
+/// whenever the count of a conversation changes.
+#[derive(Debug, Serialize, Clone, PartialEq, Deserialize)]
+pub struct UnreadCount {
+    pub unread_conversation_id: i32,
+    pub unread: u64,
+    #[serde(default)]
+    pub muted: bool,
+}
+
+impl UnreadCount {
+    /// Counts are absolute, replaying the same update is harmless.
+    pub fn apply(counts: &mut Vec<UnreadCount>, update: UnreadCount) {
+        match counts
+            .iter_mut()
+            .find(|count| count.unread_conversation_id == update.unread_conversation_id)
+        {
+            Some(count) => *count = update,
+            None => counts.push(update),
+        }
+    }
+}
+
+/// Conversations without unread messages are left out, `total` skips muted ones.
+#[derive(Debug, Serialize, Clone, PartialEq, Deserialize, Default)]
+pub struct UnreadCounts {
+    pub conversations: Vec<UnreadCount>,
+    pub total: u64,
+}
+
+/// Email notifications a user opted into, both are off by default.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+pub struct NotificationSettings {
+    /// Email about messages still unread after the offline period
+    pub missed_messages: bool,
+    /// Email a daily digest of unread conversations
+    pub daily_digest: bool,
+}
+
+/// How far a member has read a conversation. Pushed over `IconWs` to the other members
+/// whenever it moves, users that turned read receipts off never show up.
+#[derive(Debug, Serialize, Clone, PartialEq, Deserialize)]
+pub struct ReadReceipt {
+    pub receipt_conversation_id: i32,
+    pub reader_id: i32,
+    pub reader_first_name: String,
+    pub reader_last_name: String,
+    pub read_up_to: i32,
+}
+
+impl ReadReceipt {
+    /// The members other than the sender that have read up to the message.
+    pub fn seen_by<'a>(
+        receipts: &'a [ReadReceipt],
+        conversation_id: i32,
+        message_id: i32,
+        sender_id: i32,
+    ) -> impl Iterator<Item = &'a ReadReceipt> {
+        receipts.iter().filter(move |receipt| {
+            receipt.receipt_conversation_id == conversation_id
+                && receipt.reader_id != sender_id
+                && receipt.read_up_to >= message_id
+        })
+    }
+}
+
 #[cfg(feature = "ssr")]
 use crate::entities::{conversation, user_conversation};
 
 #[derive(Debug, Serialize, Deserialize)]
 enum UserValidation {
     NoUser,
-    SerializationError,
 }
 
 cfg_if::cfg_if! {
@@ -173,7 +512,12 @@ cfg_if::cfg_if! {
         struct MessageInfo {
             conversation_id: i32,
             name: Option<String>,
-            is_group: bool
+            is_group: bool,
+            last_message_at: sea_orm::prelude::DateTimeUtc,
+            last_read_message_id: Option<i32>,
+            pinned: bool,
+            archived: bool,
+            muted: bool
         }
     
         #[derive(Debug, sea_orm::FromQueryResult)]
@@ -185,21 +529,23 @@ cfg_if::cfg_if! {
             email: String
         }
     
-        #[derive(Debug, sea_orm::FromQueryResult, Serialize, Clone, PartialEq, Deserialize)]
-        pub struct SeenMessageStruct {
-           seen_id: Option<i32>,
-           message_id: Option<i32>,
-           first_name: Option<String>,
-           last_name: Option<String>,
+        #[derive(Debug, sea_orm::FromQueryResult)]
+        struct ReceiptStruct {
+            conversation_id: i32,
+            user_ids: i32,
+            last_read_message_id: i32,
+            first_name: String,
+            last_name: String,
         }
-    
-        impl From<SeenMessageStruct> for SeenMessageFacing {
-                fn from(value: SeenMessageStruct) -> Self {
+
+        impl From<ReceiptStruct> for ReadReceipt {
+                fn from(value: ReceiptStruct) -> Self {
                     Self {
-                        seen_id: value.seen_id,
-                        message_id: value.message_id,
-                        first_name: value.first_name,
-                        last_name: value.last_name
+                        receipt_conversation_id: value.conversation_id,
+                        reader_id: value.user_ids,
+                        reader_first_name: value.first_name,
+                        reader_last_name: value.last_name,
+                        read_up_to: value.last_read_message_id,
                     }
                 }
         }
@@ -209,9 +555,12 @@ cfg_if::cfg_if! {
             pub message_id: i32,
             pub message_body: Option<String>,
             pub message_image: Option<String>,
+            pub message_audio: Option<String>,
+            pub message_audio_duration: Option<i32>,
             pub message_created_at: sea_orm::prelude::DateTimeUtc,
             pub message_conversation_id: i32,
             pub message_sender_id: i32,
+            pub message_system: i8,
             pub first_name: String,
             pub last_name: String
         }
@@ -223,14 +572,31 @@ cfg_if::cfg_if! {
                         message_body: value.message_body,
                         message_sender_id: value.message_sender_id,
                         message_image: value.message_image,
+                        message_audio: value.message_audio,
+                        message_audio_duration: value.message_audio_duration,
                         message_created_at: value.message_created_at.to_string(),
                         message_conversation_id: value.message_conversation_id,
+                        message_system: value.message_system != 0,
                         first_name: value.first_name,
                         last_name: value.last_name
                     }
                 }
             }
     
+        impl From<MessageStruct> for MentionEvent {
+                fn from(value: MessageStruct) -> Self {
+                    Self {
+                        mention_message_id: value.message_id,
+                        mention_conversation_id: value.message_conversation_id,
+                        sender_id: value.message_sender_id,
+                        sender_first_name: value.first_name,
+                        sender_last_name: value.last_name,
+                        body: value.message_body.unwrap_or_default(),
+                        created_at: value.message_created_at.to_string(),
+                    }
+                }
+            }
+    
         impl From<ConversationInfo> for FacingMessageInfo {
                 fn from(value: ConversationInfo) -> Self {
                     Self {
@@ -253,6 +619,7 @@ cfg_if::cfg_if! {
                             crate::entities::conversation::server::Column::Id,
                             crate::entities::conversation::server::Column::Name,
                             crate::entities::conversation::server::Column::IsGroup,
+                            crate::entities::conversation::server::Column::LastMessageAt,
                         ])
                         .inner_join(Conversation)
                         .into_model::<MessageInfo>()
@@ -305,27 +672,63 @@ cfg_if::cfg_if! {
                         .await.unwrap().into_iter().map_into().collect()
                 }
     
-                async fn retrieve_seen(messages: &Vec<MessageStructFacing>, data: &sea_orm::DatabaseConnection) -> Vec<SeenMessageFacing> {
-                    use crate::entities::seen_messages;
-    
-                    let mut condition: Condition = Condition::any();
-                    for message in messages {
-                        condition = condition.add(seen_messages::server::Column::MessageId.eq(message.message_id));
+                async fn retrieve_unread_mentions(user: &UserLogin, data: &sea_orm::DatabaseConnection) -> Vec<MentionEvent> {
+                    let mentioned: Vec<i32> = MessageMentions::find()
+                        .filter(message_mentions::server::Column::UserId.eq(user.id))
+                        .filter(message_mentions::server::Column::ReadAt.is_null())
+                        .all(data)
+                        .await
+                        .unwrap()
+                        .into_iter()
+                        .map(|mention| mention.message_id)
+                        .collect();
+
+                    if mentioned.is_empty() {
+                        return Vec::new();
                     }
-    
-                    SeenMessages::find().filter(condition)
-                        .columns::<crate::entities::users::server::Column, Vec<_>>(vec![
-                            crate::entities::users::server::Column::Id,
+
+                    Message::find().filter(message::server::Column::MessageId.is_in(mentioned)).inner_join(Users).columns::<users::server::Column, Vec<_>>(vec![
                             crate::entities::users::server::Column::FirstName,
                             crate::entities::users::server::Column::LastName,
-                        ])
-                        .join(JoinType::LeftJoin, seen_messages::server::Relation::Users.def()).into_model::<SeenMessageStruct>()
-                        .all(data).await.unwrap().into_iter().map_into().collect()
+                    ])
+                        .order_by_desc(message::server::Column::MessageCreatedAt).into_model::<MessageStruct>().all(data)
+                        .await.unwrap().into_iter().map_into().collect()
+                }
     
+                async fn retrieve_muted_members(conversation_id: i32, data: &sea_orm::DatabaseConnection) -> Vec<i32> {
+                    UserConversation::find()
+                        .filter(user_conversation::server::Column::ConversationId.eq(conversation_id))
+                        .filter(user_conversation::server::Column::Muted.ne(0))
+                        .all(data)
+                        .await
+                        .unwrap()
+                        .into_iter()
+                        .map(|membership| membership.user_ids)
+                        .collect()
                 }
     
-                async fn retrieve_images(user_id: i32, data: &sea_orm::DatabaseConnection) -> Option<String> {
-                    Users::find().filter(users::server::Column::Id.eq(user_id)).one(data).await.unwrap().unwrap().image
+                /// Read watermarks of the members of the conversations that share them.
+                async fn retrieve_receipts(conversations: &Vec<i32>, data: &sea_orm::DatabaseConnection) -> Vec<ReadReceipt> {
+                    if conversations.is_empty() {
+                        return Vec::new();
+                    }
+
+                    UserConversation::find()
+                        .filter(user_conversation::server::Column::ConversationId.is_in(conversations.clone()))
+                        .filter(user_conversation::server::Column::LastReadMessageId.is_not_null())
+                        .filter(users::server::Column::SendReadReceipts.ne(0))
+                        .inner_join(Users)
+                        .columns::<users::server::Column, Vec<_>>(vec![
+                            users::server::Column::FirstName,
+                            users::server::Column::LastName,
+                        ])
+                        .into_model::<ReceiptStruct>()
+                        .all(data)
+                        .await
+                        .unwrap()
+                        .into_iter()
+                        .map_into()
+                        .collect()
                 }
     
     }
@@ -334,45 +737,139 @@ cfg_if::cfg_if! {
     
     impl AppendDatabase {
     
-                async fn insert_messages(data: &sea_orm::DatabaseConnection, message_model: crate::entities::message::server::ActiveModel) {
-                    let inserted_message = Message::insert(message_model.clone()).exec(data).await.unwrap();
-    
-                    SeenMessages::insert(seen_messages::server::ActiveModel {
-                        message_id: ActiveValue::Set(inserted_message.last_insert_id),
-                        seen_id: message_model.message_sender_id
-                    }).exec(data).await.unwrap();
+                async fn insert_messages(data: &sea_orm::DatabaseConnection, message_model: crate::entities::message::server::ActiveModel) -> Result<i32, DbErr> {
+                    let inserted_message = Message::insert(message_model.clone()).exec(data).await?;
+
+                    // Keeps the list order and brings the conversation back for whoever archived it
+                    if let ActiveValue::Set(conversation_id) = message_model.message_conversation_id {
+                        Conversation::update_many()
+                            .col_expr(conversation::server::Column::LastMessageAt, sea_orm::sea_query::Expr::value(chrono::Utc::now()))
+                            .filter(conversation::server::Column::Id.eq(conversation_id))
+                            .exec(data)
+                            .await?;
+
+                        UserConversation::update_many()
+                            .col_expr(user_conversation::server::Column::Archived, sea_orm::sea_query::Expr::value(0))
+                            .filter(user_conversation::server::Column::ConversationId.eq(conversation_id))
+                            .filter(user_conversation::server::Column::Archived.ne(0))
+                            .exec(data)
+                            .await?;
+                    }
+
+                    // The sender has read their own message
+                    if let (ActiveValue::Set(conversation_id), ActiveValue::Set(sender_id)) =
+                        (message_model.message_conversation_id, message_model.message_sender_id)
+                    {
+                        Self::mark_read(data, conversation_id, sender_id, Some(inserted_message.last_insert_id)).await?;
+                    }
     
+                    Ok(inserted_message.last_insert_id)
                 }
     
-                async fn insert_seen(data: &DatabaseConnection, message_model: Vec<i32>, user_id: i32) {
-                    let existing_ids = SeenMessages::find()
-                        .filter(seen_messages::server::Column::MessageId.is_in(message_model.clone()))
-                        .filter(seen_messages::server::Column::SeenId.eq(user_id))
+                /// Resolves `@First Last` and `@<user id>` mentions against the members of the
+                /// conversation and records them, returning the mentioned user ids.
+                async fn insert_mentions(data: &sea_orm::DatabaseConnection, message_id: i32, conversation_id: i32, sender_id: i32, body: &str) -> Vec<i32> {
+                    let member_ids: Vec<i32> = UserConversation::find()
+                        .filter(user_conversation::server::Column::ConversationId.eq(conversation_id))
                         .all(data)
                         .await
-                        .unwrap();
+                        .unwrap()
+                        .into_iter()
+                        .map(|member| member.user_ids)
+                        .filter(|id| *id != sender_id)
+                        .collect();
     
-                    let existing_ids: Vec<i32> = existing_ids.iter().map(|row| row.message_id).collect();
+                    if member_ids.is_empty() || !body.contains('@') {
+                        return Vec::new();
+                    }
     
-                    let new_ids: Vec<i32> = message_model
+                    let members = Users::find()
+                        .filter(users::server::Column::Id.is_in(member_ids))
+                        .all(data)
+                        .await
+                        .unwrap();
+                    let names: Vec<String> = members
                         .iter()
-                        .filter(|&message_id| !existing_ids.contains(message_id))
-                        .copied()
+                        .map(|member| format!("{} {}", member.first_name, member.last_name))
                         .collect();
     
-                    if !new_ids.is_empty() {
-                        let insert_data: Vec<seen_messages::server::ActiveModel> = new_ids
-                            .iter()
-                            .map(|&message_id| seen_messages::server::ActiveModel {
-                                message_id: ActiveValue::Set(message_id),
-                                seen_id: ActiveValue::Set(user_id),
+                    let mentioned: Vec<i32> = crate::app::markup::mentions(&crate::app::markup::parse(body, &names))
+                        .iter()
+                        .filter_map(|mention| {
+                            members.iter().find(|member| {
+                                mention.parse::<i32>().map_or(false, |id| id == member.id)
+                                    || mention.eq_ignore_ascii_case(&format!("{} {}", member.first_name, member.last_name))
                             })
-                            .collect();
-                        SeenMessages::insert_many(insert_data)
-                            .exec(data)
-                            .await
-                            .unwrap();
+                        })
+                        .map(|member| member.id)
+                        .unique()
+                        .collect();
+    
+                    if !mentioned.is_empty() {
+                        MessageMentions::insert_many(mentioned.iter().map(|user_id| message_mentions::server::ActiveModel {
+                            message_id: ActiveValue::Set(message_id),
+                            user_id: ActiveValue::Set(*user_id),
+                            read_at: ActiveValue::Set(None),
+                        }))
+                        .exec(data)
+                        .await
+                        .unwrap();
                     }
+    
+                    mentioned
+                }
+    
+                /// Marks the user's mentions in `conversation_id` read, the conversation's
+                /// messages are matched in the database rather than loaded.
+                async fn read_mentions(data: &sea_orm::DatabaseConnection, conversation_id: i32, user_id: i32) -> Result<(), DbErr> {
+                    MessageMentions::update_many()
+                        .col_expr(message_mentions::server::Column::ReadAt, sea_orm::sea_query::Expr::value(chrono::Utc::now()))
+                        .filter(message_mentions::server::Column::UserId.eq(user_id))
+                        .filter(message_mentions::server::Column::ReadAt.is_null())
+                        .filter(
+                            message_mentions::server::Column::MessageId.in_subquery(
+                                sea_orm::sea_query::Query::select()
+                                    .column(message::server::Column::MessageId)
+                                    .from(Message)
+                                    .and_where(message::server::Column::MessageConversationId.eq(conversation_id))
+                                    .to_owned(),
+                            ),
+                        )
+                        .exec(data)
+                        .await?;
+                    Ok(())
+                }
+    
+                /// Moves the member's read watermark forward to `up_to`, or to the newest message
+                /// when `None`. Returns the new watermark if it moved.
+                async fn mark_read(data: &sea_orm::DatabaseConnection, conversation_id: i32, user_id: i32, up_to: Option<i32>) -> Result<Option<i32>, DbErr> {
+                    let up_to = match up_to {
+                        Some(up_to) => up_to,
+                        None => match Message::find()
+                            .filter(message::server::Column::MessageConversationId.eq(conversation_id))
+                            .order_by_desc(message::server::Column::MessageId)
+                            .one(data)
+                            .await?
+                        {
+                            Some(latest) => latest.message_id,
+                            None => return Ok(None),
+                        },
+                    };
+
+                    // Only ever forward, a stale tab cannot mark messages unread again
+                    let updated = UserConversation::update_many()
+                        .col_expr(user_conversation::server::Column::LastReadMessageId, sea_orm::sea_query::Expr::value(up_to))
+                        .filter(user_conversation::server::Column::UserIds.eq(user_id))
+                        .filter(user_conversation::server::Column::ConversationId.eq(conversation_id))
+                        .filter(
+                            Condition::any()
+                                .add(user_conversation::server::Column::LastReadMessageId.is_null())
+                                .add(user_conversation::server::Column::LastReadMessageId.lt(up_to)),
+                        )
+                        .exec(data)
+                        .await?;
+
+                    Ok((updated.rows_affected > 0).then_some(up_to))
                 }
     
                 async fn delete_conversation(conversation_id: i32, data: &sea_orm::DatabaseConnection, user: UserLogin) {
@@ -406,3 +903,32 @@ cfg_if::cfg_if! {
         }
     }
 }
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+pub struct TwoFactorStatus {
+    pub enabled: bool,
+    pub recovery_codes_left: u64,
+}
+
+/// A started 2FA enrollment, enabled once a code from the authenticator app is confirmed.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Deserialize)]
+pub struct TwoFactorEnrollment {
+    pub otpauth_uri: String,
+    /// The URI as an SVG QR code
+    pub qr_svg: String,
+    /// The base32 secret, for apps that can not scan
+    pub secret: String,
+}
+
+/// A logged in session of the user, listed in the settings.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Deserialize)]
+pub struct SessionInfo {
+    pub id: i32,
+    /// Browser and operating system, from the user agent
+    pub device: String,
+    pub ip: Option<String>,
+    pub created_at: i64,
+    pub last_seen_at: i64,
+    /// The session making the request
+    pub current: bool,
+}
@@ -0,0 +1,708 @@
+//! Notification emails. Users opt into emails about messages that stayed unread past an
+//! offline period, and into a daily digest of their unread conversations. Muted
+//! conversations never show up, and users with the app open get no missed message emails. Every email carries an unsubscribe link signed for its user
+//! and kind, so it works without logging in.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use actix::{Actor, Addr, AsyncContext, Context, WrapFuture};
+use actix_web::{error, get, web, Error, HttpResponse};
+use base64::engine::{general_purpose, Engine as _};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, Condition, DatabaseConnection, DbErr,
+    EntityTrait, QueryFilter,
+};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use super::{scheduler::Clock, NotificationSettings, UnreadCount};
+use crate::database::DbConnection;
+use crate::emailing::email_client::{self, ConversationSummary, EmailClient, Notification};
+use crate::entities::{conversation, prelude::*, user_conversation, users};
+use crate::web_socket::server::{IconWs, OnlineUsers};
+
+lazy_static! {
+    /// Key used to sign unsubscribe links. Falls back to a per-process key, which means links
+    /// in earlier emails stop working after a restart unless `NOTIFICATION_SIGNING_KEY` is set.
+    static ref SIGNING_KEY: Vec<u8> = match std::env::var("NOTIFICATION_SIGNING_KEY") {
+        Ok(key) => key.into_bytes(),
+        Err(_) => {
+            use rand::RngCore;
+            let mut key = vec![0u8; 32];
+            rand::thread_rng().fill_bytes(&mut key);
+            key
+        }
+    };
+}
+
+/// How long a digest waits for the previous one.
+pub const DIGEST_INTERVAL_HOURS: i64 = 24;
+/// How long a message stays unread before it is emailed about, unless configured.
+pub const DEFAULT_OFFLINE_MINUTES: i64 = 15;
+
+/// The offline period from `NOTIFY_OFFLINE_MINUTES`.
+pub fn offline_period_from_env() -> chrono::Duration {
+    chrono::Duration::minutes(
+        std::env::var("NOTIFY_OFFLINE_MINUTES")
+            .ok()
+            .and_then(|minutes| minutes.parse().ok())
+            .unwrap_or(DEFAULT_OFFLINE_MINUTES),
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailKind {
+    MissedMessages,
+    DailyDigest,
+}
+
+impl EmailKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            EmailKind::MissedMessages => "missed_messages",
+            EmailKind::DailyDigest => "daily_digest",
+        }
+    }
+}
+
+fn signature(user_id: i32, kind: EmailKind) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&SIGNING_KEY).expect("HMAC accepts any key size");
+    mac.update(format!("unsubscribe:{user_id}:{}", kind.as_str()).as_bytes());
+    mac
+}
+
+pub fn unsubscribe_link(user_id: i32, kind: EmailKind) -> String {
+    let signature = general_purpose::URL_SAFE_NO_PAD
+        .encode(signature(user_id, kind).finalize().into_bytes());
+    format!(
+        "{}/unsubscribe?user={user_id}&kind={}&signature={signature}",
+        email_client::app_url(),
+        kind.as_str()
+    )
+}
+
+pub fn verify_unsubscribe(user_id: i32, kind: EmailKind, provided: &str) -> bool {
+    match general_purpose::URL_SAFE_NO_PAD.decode(provided) {
+        Ok(provided) => signature(user_id, kind).verify_slice(&provided).is_ok(),
+        Err(_) => false,
+    }
+}
+
+pub async fn settings(
+    data: &DatabaseConnection,
+    user_id: i32,
+) -> Result<NotificationSettings, DbErr> {
+    Ok(Users::find_by_id(user_id)
+        .one(data)
+        .await?
+        .map(|user| NotificationSettings {
+            missed_messages: user.email_missed_messages != 0,
+            daily_digest: user.email_digest != 0,
+        })
+        .unwrap_or_default())
+}
+
+/// Opting into missed message emails starts at `now`, messages left unread before never
+/// trigger one.
+pub async fn set_settings(
+    data: &DatabaseConnection,
+    now: DateTime<Utc>,
+    user_id: i32,
+    settings: NotificationSettings,
+) -> Result<(), DbErr> {
+    let Some(user) = Users::find_by_id(user_id).one(data).await? else {
+        return Ok(());
+    };
+    let opted_in = user.email_missed_messages == 0 && settings.missed_messages;
+
+    let mut user: users::server::ActiveModel = user.into();
+    user.email_missed_messages = ActiveValue::Set(settings.missed_messages as i8);
+    user.email_digest = ActiveValue::Set(settings.daily_digest as i8);
+    if opted_in {
+        user.notified_until = ActiveValue::Set(Some(now));
+    }
+    user.update(data).await?;
+    Ok(())
+}
+
+/// Group names, or the other members' names for direct conversations.
+async fn conversation_names(
+    data: &DatabaseConnection,
+    user_id: i32,
+    conversation_ids: Vec<i32>,
+) -> Result<HashMap<i32, String>, DbErr> {
+    let mut names: HashMap<i32, String> = Conversation::find()
+        .filter(conversation::server::Column::Id.is_in(conversation_ids.clone()))
+        .all(data)
+        .await?
+        .into_iter()
+        .filter_map(|conversation| Some((conversation.id, conversation.name?)))
+        .collect();
+
+    let members = UserConversation::find()
+        .filter(user_conversation::server::Column::ConversationId.is_in(conversation_ids))
+        .filter(user_conversation::server::Column::UserIds.ne(user_id))
+        .find_also_related(Users)
+        .all(data)
+        .await?;
+    let mut member_names: HashMap<i32, Vec<String>> = HashMap::new();
+    for (membership, member) in members {
+        if let Some(member) = member {
+            member_names
+                .entry(membership.conversation_id)
+                .or_default()
+                .push(format!("{} {}", member.first_name, member.last_name));
+        }
+    }
+    for (conversation_id, members) in member_names {
+        names
+            .entry(conversation_id)
+            .or_insert_with(|| members.join(", "));
+    }
+    Ok(names)
+}
+
+async fn summaries(
+    data: &DatabaseConnection,
+    user_id: i32,
+    counts: Vec<UnreadCount>,
+) -> Result<Vec<ConversationSummary>, DbErr> {
+    let mut names = conversation_names(
+        data,
+        user_id,
+        counts.iter().map(|count| count.unread_conversation_id).collect(),
+    )
+    .await?;
+
+    Ok(counts
+        .into_iter()
+        .map(|count| ConversationSummary {
+            name: names
+                .remove(&count.unread_conversation_id)
+                .unwrap_or_else(|| String::from("A conversation")),
+            unread: count.unread,
+        })
+        .collect())
+}
+
+/// Whether the email went out.
+async fn send(
+    email_client: &EmailClient,
+    user: &users::server::Model,
+    notification: &Notification,
+) -> bool {
+    match email_client
+        .send_notification(&user.email, notification)
+        .await
+    {
+        Ok(()) => true,
+        Err(e) => {
+            println!(
+                "Error at sending notification email to user {}: {e}",
+                user.id
+            );
+            false
+        }
+    }
+}
+
+async fn missed_message_recipients(
+    data: &DatabaseConnection,
+) -> Result<Vec<users::server::Model>, DbErr> {
+    Users::find()
+        .filter(users::server::Column::EmailMissedMessages.eq(1))
+        .all(data)
+        .await
+}
+
+/// Emails every opted in user about messages that stayed unread for `offline_period`, each
+/// message at most once. Users in `online` saw the messages come in, they are not emailed
+/// about them later either. A failed email is retried on the next run. Returns how many
+/// emails went out.
+pub async fn send_missed_messages(
+    data: &DatabaseConnection,
+    email_client: &EmailClient,
+    now: DateTime<Utc>,
+    offline_period: chrono::Duration,
+    online: &[i32],
+) -> Result<usize, DbErr> {
+    let cutoff = now - offline_period;
+
+    let mut sent = 0;
+    for user in missed_message_recipients(data).await? {
+        let counts =
+            super::unread::unmuted_for_user(data, user.id, user.notified_until, cutoff).await?;
+
+        if !counts.is_empty() && !online.contains(&user.id) {
+            let total: u64 = counts.iter().map(|count| count.unread).sum();
+            let notification = Notification {
+                subject: match total {
+                    1 => String::from("You have an unread message"),
+                    total => format!("You have {total} unread messages"),
+                },
+                first_name: user.first_name.clone(),
+                intro: String::from("You missed some messages while you were away."),
+                conversations: summaries(data, user.id, counts).await?,
+                unsubscribe_link: unsubscribe_link(user.id, EmailKind::MissedMessages),
+            };
+            if !send(email_client, &user, &notification).await {
+                continue;
+            }
+            sent += 1;
+        }
+
+        // Never moves back, so opting in does not reach into the offline period before
+        let notified_until = user
+            .notified_until
+            .map_or(cutoff, |until| until.max(cutoff));
+        let mut notified: users::server::ActiveModel = user.into();
+        notified.notified_until = ActiveValue::Set(Some(notified_until));
+        notified.update(data).await?;
+    }
+    Ok(sent)
+}
+
+/// Emails the digest to every opted in user whose last one is a day old. Users with nothing
+/// unread get no email, their next digest is still a day away. A failed email is retried on
+/// the next run. Returns how many went out.
+pub async fn send_digests(
+    data: &DatabaseConnection,
+    email_client: &EmailClient,
+    now: DateTime<Utc>,
+) -> Result<usize, DbErr> {
+    let users = Users::find()
+        .filter(users::server::Column::EmailDigest.eq(1))
+        .filter(
+            Condition::any()
+                .add(users::server::Column::DigestSentAt.is_null())
+                .add(
+                    users::server::Column::DigestSentAt
+                        .lte(now - chrono::Duration::hours(DIGEST_INTERVAL_HOURS)),
+                ),
+        )
+        .all(data)
+        .await?;
+
+    let mut sent = 0;
+    for user in users {
+        let counts = super::unread::unmuted_for_user(data, user.id, None, now).await?;
+
+        if !counts.is_empty() {
+            let notification = Notification {
+                subject: String::from("Your daily ZING! digest"),
+                first_name: user.first_name.clone(),
+                intro: String::from("These conversations are waiting for you."),
+                conversations: summaries(data, user.id, counts).await?,
+                unsubscribe_link: unsubscribe_link(user.id, EmailKind::DailyDigest),
+            };
+            if !send(email_client, &user, &notification).await {
+                continue;
+            }
+            sent += 1;
+        }
+
+        let mut digested: users::server::ActiveModel = user.into();
+        digested.digest_sent_at = ActiveValue::Set(Some(now));
+        digested.update(data).await?;
+    }
+    Ok(sent)
+}
+
+#[derive(Deserialize)]
+pub struct UnsubscribeQuery {
+    user: i32,
+    kind: EmailKind,
+    signature: String,
+}
+
+/// Target of the link in every notification email.
+#[get("/unsubscribe")]
+pub async fn unsubscribe(
+    query: web::Query<UnsubscribeQuery>,
+    data: web::Data<tokio::sync::Mutex<DbConnection>>,
+) -> Result<HttpResponse, Error> {
+    if !verify_unsubscribe(query.user, query.kind, &query.signature) {
+        return Err(error::ErrorForbidden("Invalid unsubscribe link"));
+    }
+
+    let column = match query.kind {
+        EmailKind::MissedMessages => users::server::Column::EmailMissedMessages,
+        EmailKind::DailyDigest => users::server::Column::EmailDigest,
+    };
+    Users::update_many()
+        .col_expr(column, sea_orm::sea_query::Expr::value(0))
+        .filter(users::server::Column::Id.eq(query.user))
+        .exec(&data.lock().await.connection)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(format!(
+            "<p>You will no longer receive these emails. Turn them back on in the settings of \
+             <a href=\"{}/conversations\">ZING!</a></p>",
+            email_client::app_url()
+        )))
+}
+
+/// The users opted into missed message emails that have the app open right now.
+async fn online_recipients(
+    data: &DatabaseConnection,
+    icon_server: &Addr<IconWs>,
+) -> Result<Vec<i32>, String> {
+    let user_ids = missed_message_recipients(data)
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|user| user.id)
+        .collect();
+    icon_server
+        .send(OnlineUsers { user_ids })
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Background actor that sends the notification emails every `interval`.
+pub struct Notifier<C: Clock> {
+    data: DatabaseConnection,
+    clock: C,
+    email_client: web::Data<EmailClient>,
+    icon_server: Addr<IconWs>,
+    offline_period: chrono::Duration,
+    interval: Duration,
+}
+
+impl<C: Clock> Notifier<C> {
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+
+    pub fn new(
+        data: DatabaseConnection,
+        clock: C,
+        email_client: web::Data<EmailClient>,
+        icon_server: Addr<IconWs>,
+    ) -> Self {
+        Self {
+            data,
+            clock,
+            email_client,
+            icon_server,
+            offline_period: chrono::Duration::minutes(DEFAULT_OFFLINE_MINUTES),
+            interval: Self::DEFAULT_INTERVAL,
+        }
+    }
+
+    /// How long a message has to stay unread before it is emailed about.
+    pub fn with_offline_period(mut self, offline_period: chrono::Duration) -> Self {
+        self.offline_period = offline_period;
+        self
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+}
+
+impl<C: Clock> Actor for Notifier<C> {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.interval, |act, ctx| {
+            let data = act.data.clone();
+            let now = act.clock.now();
+            let email_client = act.email_client.clone();
+            let icon_server = act.icon_server.clone();
+            let offline_period = act.offline_period;
+
+            ctx.spawn(
+                async move {
+                    match online_recipients(&data, &icon_server).await {
+                        Ok(online) => match send_missed_messages(
+                            &data,
+                            &email_client,
+                            now,
+                            offline_period,
+                            &online,
+                        )
+                        .await
+                        {
+                            Ok(0) => (),
+                            Ok(sent) => println!("Sent {sent} missed message emails"),
+                            Err(e) => println!("Missed message emails failed: {e}"),
+                        },
+                        Err(e) => println!("Missed message emails skipped: {e}"),
+                    }
+                    match send_digests(&data, &email_client, now).await {
+                        Ok(0) => (),
+                        Ok(sent) => println!("Sent {sent} digest emails"),
+                        Err(e) => println!("Digest emails failed: {e}"),
+                    }
+                }
+                .into_actor(act),
+            );
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+    use crate::emailing::mailer::{MailError, Mailer};
+    use crate::entities::message;
+    use chrono::TimeZone;
+    use sea_orm::IntoActiveModel;
+    use std::sync::{Arc, Mutex};
+
+    const ALICE: i32 = 1;
+    const BOB: i32 = 2;
+    const DIRECT: i32 = 1;
+
+    fn at(minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 5, 1, 12, minute, 0).unwrap()
+    }
+
+    fn offline_period() -> chrono::Duration {
+        chrono::Duration::minutes(DEFAULT_OFFLINE_MINUTES)
+    }
+
+    /// Keeps the recipients of every email, or fails them all.
+    #[derive(Clone, Default)]
+    struct Outbox {
+        recipients: Arc<Mutex<Vec<String>>>,
+        unreachable: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl Mailer for Outbox {
+        async fn send(&self, message: &lettre::Message) -> Result<(), MailError> {
+            if self.unreachable {
+                return Err(MailError::Config(String::from("unreachable")));
+            }
+            self.recipients.lock().unwrap().extend(
+                message
+                    .envelope()
+                    .to()
+                    .iter()
+                    .map(|address| address.to_string()),
+            );
+            Ok(())
+        }
+    }
+
+    fn client(outbox: &Outbox) -> EmailClient {
+        EmailClient::new(outbox.clone(), "ZING <no-reply@localhost>".parse().unwrap())
+    }
+
+    /// Alice, opted into missed message emails at minute 0, and Bob, who messaged her at
+    /// minute 1.
+    async fn seeded() -> DatabaseConnection {
+        let data = testing::sqlite().await;
+        testing::create_table(&data, Users).await;
+        testing::create_table(&data, Conversation).await;
+        testing::create_table(&data, UserConversation).await;
+        testing::create_table(&data, Message).await;
+
+        for (id, first_name) in [(ALICE, "Alice"), (BOB, "Bob")] {
+            users::server::Model {
+                id,
+                first_name: first_name.to_string(),
+                last_name: "Tester".to_string(),
+                email: format!("{}@example.com", first_name.to_lowercase()),
+                phone_number: 0,
+                password: String::new(),
+                image: None,
+                send_read_receipts: 1,
+                email_missed_messages: (id == ALICE) as i8,
+                email_digest: 0,
+                notified_until: (id == ALICE).then(|| at(0)),
+                digest_sent_at: None,
+                totp_secret: None,
+                totp_enabled_at: None,
+                totp_last_step: None,
+                session_version: 0,
+            }
+            .into_active_model()
+            .insert(&data)
+            .await
+            .unwrap();
+        }
+        conversation::server::Model {
+            id: DIRECT,
+            last_message_at: at(1),
+            created_at: at(0),
+            name: None,
+            is_group: 0,
+            retention_seconds: None,
+        }
+        .into_active_model()
+        .insert(&data)
+        .await
+        .unwrap();
+        for user_ids in [ALICE, BOB] {
+            user_conversation::server::Model {
+                user_ids,
+                conversation_id: DIRECT,
+                last_read_message_id: None,
+                pinned: 0,
+                archived: 0,
+                muted: 0,
+            }
+            .into_active_model()
+            .insert(&data)
+            .await
+            .unwrap();
+        }
+        message::server::Model {
+            message_id: 1,
+            message_body: Some(String::from("Are you there?")),
+            message_image: None,
+            message_audio: None,
+            message_audio_duration: None,
+            message_created_at: at(1),
+            message_conversation_id: DIRECT,
+            message_sender_id: BOB,
+            message_system: 0,
+        }
+        .into_active_model()
+        .insert(&data)
+        .await
+        .unwrap();
+        data
+    }
+
+    async fn notified_until(data: &DatabaseConnection) -> Option<DateTime<Utc>> {
+        Users::find_by_id(ALICE)
+            .one(data)
+            .await
+            .unwrap()
+            .unwrap()
+            .notified_until
+    }
+
+    fn signature_of(link: &str) -> &str {
+        link.split("signature=").nth(1).unwrap()
+    }
+
+    #[test]
+    fn unsubscribe_links_are_signed_per_user_and_kind() {
+        let link = unsubscribe_link(ALICE, EmailKind::MissedMessages);
+        assert!(link.contains("user=1&kind=missed_messages&"));
+        let signature = signature_of(&link);
+
+        assert!(verify_unsubscribe(
+            ALICE,
+            EmailKind::MissedMessages,
+            signature
+        ));
+        assert!(!verify_unsubscribe(
+            BOB,
+            EmailKind::MissedMessages,
+            signature
+        ));
+        assert!(!verify_unsubscribe(
+            ALICE,
+            EmailKind::DailyDigest,
+            signature
+        ));
+        assert!(!verify_unsubscribe(
+            ALICE,
+            EmailKind::MissedMessages,
+            &signature[1..]
+        ));
+        assert!(!verify_unsubscribe(
+            ALICE,
+            EmailKind::MissedMessages,
+            "not base64!"
+        ));
+        assert!(!verify_unsubscribe(ALICE, EmailKind::MissedMessages, ""));
+    }
+
+    #[tokio::test]
+    async fn missed_messages_wait_for_the_offline_period() {
+        let data = seeded().await;
+        let outbox = Outbox::default();
+
+        // Sent 14 minutes ago, still within the offline period
+        assert_eq!(
+            send_missed_messages(&data, &client(&outbox), at(15), offline_period(), &[])
+                .await
+                .unwrap(),
+            0
+        );
+        // The cutoff is before opting in, which stays the start
+        assert_eq!(notified_until(&data).await, Some(at(0)));
+
+        assert_eq!(
+            send_missed_messages(&data, &client(&outbox), at(16), offline_period(), &[])
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(*outbox.recipients.lock().unwrap(), ["alice@example.com"]);
+        assert_eq!(notified_until(&data).await, Some(at(1)));
+
+        // Each message is emailed about once
+        assert_eq!(
+            send_missed_messages(&data, &client(&outbox), at(30), offline_period(), &[])
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(outbox.recipients.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn online_users_are_not_emailed() {
+        let data = seeded().await;
+        let outbox = Outbox::default();
+
+        assert_eq!(
+            send_missed_messages(&data, &client(&outbox), at(16), offline_period(), &[ALICE])
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(notified_until(&data).await, Some(at(1)));
+
+        // Messages that came in while online are not emailed once offline either
+        assert_eq!(
+            send_missed_messages(&data, &client(&outbox), at(30), offline_period(), &[])
+                .await
+                .unwrap(),
+            0
+        );
+        assert!(outbox.recipients.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_emails_are_not_counted_and_retried() {
+        let data = seeded().await;
+        let unreachable = Outbox {
+            unreachable: true,
+            ..Outbox::default()
+        };
+
+        assert_eq!(
+            send_missed_messages(&data, &client(&unreachable), at(16), offline_period(), &[])
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(notified_until(&data).await, Some(at(0)));
+
+        let outbox = Outbox::default();
+        assert_eq!(
+            send_missed_messages(&data, &client(&outbox), at(17), offline_period(), &[])
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(notified_until(&data).await, Some(at(2)));
+    }
+}
@@ -0,0 +1,151 @@
+//! Password resets. A reset emails a random token, only its SHA-256 is stored, so a leaked
+//! table can not be used to take over accounts. A token works once and only until it expires,
+//! using it replaces the password and revokes every session of the user.
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
+    Argon2,
+};
+use base64::engine::{general_purpose, Engine as _};
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, DbErr, EntityTrait,
+    QueryFilter,
+};
+use sha2::{Digest, Sha256};
+use validator::Validate;
+
+use crate::app::PasswordSchema;
+use crate::entities::{password_reset_tokens, prelude::*, users};
+
+/// How long an emailed link stays valid.
+pub const TOKEN_TTL_MINUTES: i64 = 30;
+
+#[derive(Debug)]
+pub enum ResetError {
+    InvalidToken,
+    WeakPassword,
+    Database(DbErr),
+}
+
+impl std::fmt::Display for ResetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResetError::InvalidToken => write!(f, "Reset link is invalid or has expired"),
+            ResetError::WeakPassword => write!(f, "Password does not meet the requirements"),
+            ResetError::Database(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ResetError {}
+
+impl ResetError {
+    pub fn into_server_fn_error(self) -> leptos::ServerFnError {
+        match self {
+            ResetError::Database(e) => leptos::ServerFnError::ServerError(e.to_string()),
+            e => leptos::ServerFnError::Request(e.to_string()),
+        }
+    }
+}
+
+impl From<DbErr> for ResetError {
+    fn from(value: DbErr) -> Self {
+        ResetError::Database(value)
+    }
+}
+
+/// 32 random bytes, URL safe so the token can go into a query string as is.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// The link sent in the email.
+pub fn reset_link(token: &str) -> String {
+    format!("{}/reset-password?token={token}", crate::emailing::email_client::app_url())
+}
+
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .unwrap()
+        .to_string()
+}
+
+/// Issues a token for `user_id`, replacing any the user still had.
+pub async fn issue(
+    data: &DatabaseConnection,
+    now: DateTime<Utc>,
+    user_id: i32,
+) -> Result<String, DbErr> {
+    PasswordResetTokens::delete_many()
+        .filter(password_reset_tokens::server::Column::UserId.eq(user_id))
+        .exec(data)
+        .await?;
+
+    let token = generate_token();
+    password_reset_tokens::server::ActiveModel {
+        user_id: ActiveValue::Set(user_id),
+        token_hash: ActiveValue::Set(hash_token(&token)),
+        expires_at: ActiveValue::Set(now + chrono::Duration::minutes(TOKEN_TTL_MINUTES)),
+        created_at: ActiveValue::Set(now),
+        ..Default::default()
+    }
+    .insert(data)
+    .await?;
+    Ok(token)
+}
+
+/// Sets the new password of the token's owner and bumps their session version, which logs out
+/// every session they had. Returns the tokens of those sessions. The token is marked used
+/// before the password changes, so it can never be replayed.
+pub async fn consume(
+    data: &DatabaseConnection,
+    now: DateTime<Utc>,
+    token: &str,
+    password: &PasswordSchema,
+) -> Result<Vec<String>, ResetError> {
+    if password.validate().is_err() {
+        return Err(ResetError::WeakPassword);
+    }
+
+    let stored = PasswordResetTokens::find()
+        .filter(password_reset_tokens::server::Column::TokenHash.eq(hash_token(token)))
+        .one(data)
+        .await?
+        .filter(|stored| stored.used_at.is_none() && stored.expires_at > now)
+        .ok_or(ResetError::InvalidToken)?;
+
+    let claimed = PasswordResetTokens::update_many()
+        .col_expr(
+            password_reset_tokens::server::Column::UsedAt,
+            sea_orm::sea_query::Expr::value(now),
+        )
+        .filter(password_reset_tokens::server::Column::Id.eq(stored.id))
+        .filter(password_reset_tokens::server::Column::UsedAt.is_null())
+        .exec(data)
+        .await?;
+    if claimed.rows_affected == 0 {
+        return Err(ResetError::InvalidToken);
+    }
+
+    let user = Users::find_by_id(stored.user_id)
+        .one(data)
+        .await?
+        .ok_or(ResetError::InvalidToken)?;
+    let mut user: users::server::ActiveModel = user.into();
+    user.password = ActiveValue::Set(hash_password(&password.entry));
+    let user = user.update(data).await?;
+    Ok(crate::auth::identity::bump_session_version(data, user.id).await?)
+}
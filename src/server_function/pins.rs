@@ -0,0 +1,368 @@
+//! Pinned messages. A message is pinned at most once per conversation, every member sees the
+//! same pins and gets a `PinUpdate` over `IconWs` whenever one changes.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    sea_query::OnConflict, ActiveValue, ColumnTrait, DatabaseConnection, DbErr, EntityTrait,
+    Insert, QueryFilter, QueryOrder, QuerySelect,
+};
+
+use super::{MessageStruct, MessageStructFacing, PinUpdate, PinnedMessage};
+use crate::entities::{message, pinned_messages, prelude::*, user_conversation, users};
+
+#[derive(Debug)]
+pub enum PinError {
+    UnknownMessage,
+    NotMember,
+    Database(DbErr),
+}
+
+impl std::fmt::Display for PinError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PinError::UnknownMessage => write!(f, "Message does not exist"),
+            PinError::NotMember => write!(f, "Not a member of this conversation"),
+            PinError::Database(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for PinError {}
+
+impl PinError {
+    pub fn into_server_fn_error(self) -> leptos::ServerFnError {
+        match self {
+            PinError::Database(e) => leptos::ServerFnError::ServerError(e.to_string()),
+            e => leptos::ServerFnError::Request(e.to_string()),
+        }
+    }
+}
+
+impl From<DbErr> for PinError {
+    fn from(value: DbErr) -> Self {
+        PinError::Database(value)
+    }
+}
+
+async fn members(data: &DatabaseConnection, conversation_id: i32) -> Result<Vec<i32>, DbErr> {
+    Ok(UserConversation::find()
+        .filter(user_conversation::server::Column::ConversationId.eq(conversation_id))
+        .all(data)
+        .await?
+        .into_iter()
+        .map(|membership| membership.user_ids)
+        .collect())
+}
+
+/// Any member can pin in a direct conversation. Groups have no roles yet, so every member
+/// counts as an admin there until they do, and the admin check belongs here.
+async fn ensure_can_pin(
+    data: &DatabaseConnection,
+    user_id: i32,
+    conversation_id: i32,
+) -> Result<Vec<i32>, PinError> {
+    let members = members(data, conversation_id).await?;
+    match members.contains(&user_id) {
+        true => Ok(members),
+        false => Err(PinError::NotMember),
+    }
+}
+
+async fn messages(
+    data: &DatabaseConnection,
+    message_ids: Vec<i32>,
+) -> Result<HashMap<i32, MessageStructFacing>, DbErr> {
+    Ok(Message::find()
+        .filter(message::server::Column::MessageId.is_in(message_ids))
+        .inner_join(Users)
+        .columns::<users::server::Column, Vec<_>>(vec![
+            users::server::Column::FirstName,
+            users::server::Column::LastName,
+        ])
+        .into_model::<MessageStruct>()
+        .all(data)
+        .await?
+        .into_iter()
+        .map(|message| (message.message_id, message.into()))
+        .collect())
+}
+
+/// Joins the pins with their message and pinner, pins whose message is gone are dropped.
+async fn resolve(
+    data: &DatabaseConnection,
+    pins: Vec<pinned_messages::server::Model>,
+) -> Result<Vec<PinnedMessage>, DbErr> {
+    if pins.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut messages = messages(data, pins.iter().map(|pin| pin.message_id).collect()).await?;
+    let pinners: HashMap<i32, users::server::Model> = Users::find()
+        .filter(users::server::Column::Id.is_in(pins.iter().map(|pin| pin.pinned_by)))
+        .all(data)
+        .await?
+        .into_iter()
+        .map(|user| (user.id, user))
+        .collect();
+
+    Ok(pins
+        .into_iter()
+        .filter_map(|pin| {
+            let pinner = pinners.get(&pin.pinned_by)?;
+            Some(PinnedMessage {
+                message: messages.remove(&pin.message_id)?,
+                pinned_by: pin.pinned_by,
+                pinned_by_first_name: pinner.first_name.clone(),
+                pinned_by_last_name: pinner.last_name.clone(),
+                pinned_at: pin.pinned_at.to_string(),
+            })
+        })
+        .collect())
+}
+
+/// Inserts a pin, leaving an existing one as it is. MySQL has no `DO NOTHING`, so a conflict
+/// sets the key to itself instead.
+fn insert_pin(
+    pin: pinned_messages::server::ActiveModel,
+) -> Insert<pinned_messages::server::ActiveModel> {
+    PinnedMessages::insert(pin).on_conflict(
+        OnConflict::column(pinned_messages::server::Column::MessageId)
+            .update_column(pinned_messages::server::Column::MessageId)
+            .to_owned(),
+    )
+}
+
+/// Pins the message, pinning it again keeps the original pinner. Returns the update and the
+/// members it goes to.
+pub async fn pin(
+    data: &DatabaseConnection,
+    now: DateTime<Utc>,
+    user_id: i32,
+    message_id: i32,
+) -> Result<(PinUpdate, Vec<i32>), PinError> {
+    let message = Message::find_by_id(message_id)
+        .one(data)
+        .await?
+        .ok_or(PinError::UnknownMessage)?;
+    let conversation_id = message.message_conversation_id;
+    let members = ensure_can_pin(data, user_id, conversation_id).await?;
+
+    insert_pin(pinned_messages::server::ActiveModel {
+        message_id: ActiveValue::Set(message_id),
+        conversation_id: ActiveValue::Set(conversation_id),
+        pinned_by: ActiveValue::Set(user_id),
+        pinned_at: ActiveValue::Set(now),
+    })
+    .exec_without_returning(data)
+    .await?;
+
+    let pin = PinnedMessages::find_by_id(message_id).one(data).await?;
+    Ok((
+        PinUpdate {
+            pin_conversation_id: conversation_id,
+            pin_message_id: message_id,
+            pin: resolve(data, pin.into_iter().collect()).await?.pop(),
+        },
+        members,
+    ))
+}
+
+pub async fn unpin(
+    data: &DatabaseConnection,
+    user_id: i32,
+    message_id: i32,
+) -> Result<(PinUpdate, Vec<i32>), PinError> {
+    let message = Message::find_by_id(message_id)
+        .one(data)
+        .await?
+        .ok_or(PinError::UnknownMessage)?;
+    let conversation_id = message.message_conversation_id;
+    let members = ensure_can_pin(data, user_id, conversation_id).await?;
+
+    PinnedMessages::delete_by_id(message_id).exec(data).await?;
+
+    Ok((
+        PinUpdate {
+            pin_conversation_id: conversation_id,
+            pin_message_id: message_id,
+            pin: None,
+        },
+        members,
+    ))
+}
+
+pub async fn list(
+    data: &DatabaseConnection,
+    user_id: i32,
+    conversation_id: i32,
+) -> Result<Vec<PinnedMessage>, PinError> {
+    if !members(data, conversation_id).await?.contains(&user_id) {
+        return Err(PinError::NotMember);
+    }
+
+    let pins = PinnedMessages::find()
+        .filter(pinned_messages::server::Column::ConversationId.eq(conversation_id))
+        .order_by_desc(pinned_messages::server::Column::PinnedAt)
+        .order_by_desc(pinned_messages::server::Column::MessageId)
+        .all(data)
+        .await?;
+    Ok(resolve(data, pins).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+    use crate::entities::conversation;
+    use chrono::TimeZone;
+    use sea_orm::{ActiveModelTrait, DbBackend, IntoActiveModel, PaginatorTrait, QueryTrait};
+
+    const ALICE: i32 = 1;
+    const BOB: i32 = 2;
+    const CAROL: i32 = 3;
+    const DIRECT: i32 = 1;
+
+    fn at(minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 5, 1, 12, minute, 0).unwrap()
+    }
+
+    /// Alice and Bob talking directly, Carol outside.
+    async fn seeded() -> DatabaseConnection {
+        let data = testing::sqlite().await;
+        testing::create_table(&data, Users).await;
+        testing::create_table(&data, Conversation).await;
+        testing::create_table(&data, UserConversation).await;
+        testing::create_table(&data, Message).await;
+        testing::create_table(&data, PinnedMessages).await;
+
+        for (id, first_name) in [(ALICE, "Alice"), (BOB, "Bob"), (CAROL, "Carol")] {
+            users::server::Model {
+                id,
+                first_name: first_name.to_string(),
+                last_name: "Tester".to_string(),
+                email: format!("{first_name}@example.com"),
+                phone_number: 0,
+                password: String::new(),
+                image: None,
+                send_read_receipts: 1,
+                email_missed_messages: 0,
+                email_digest: 0,
+                notified_until: None,
+                digest_sent_at: None,
+                totp_secret: None,
+                totp_enabled_at: None,
+                totp_last_step: None,
+                session_version: 0,
+            }
+            .into_active_model()
+            .insert(&data)
+            .await
+            .unwrap();
+        }
+        conversation::server::Model {
+            id: DIRECT,
+            last_message_at: at(0),
+            created_at: at(0),
+            name: None,
+            is_group: 0,
+            retention_seconds: None,
+        }
+        .into_active_model()
+        .insert(&data)
+        .await
+        .unwrap();
+        for user_ids in [ALICE, BOB] {
+            user_conversation::server::Model {
+                user_ids,
+                conversation_id: DIRECT,
+                last_read_message_id: None,
+                pinned: 0,
+                archived: 0,
+                muted: 0,
+            }
+            .into_active_model()
+            .insert(&data)
+            .await
+            .unwrap();
+        }
+        for message_id in [1, 2] {
+            message::server::Model {
+                message_id,
+                message_body: Some(format!("message {message_id}")),
+                message_image: None,
+                message_audio: None,
+                message_audio_duration: None,
+                message_created_at: at(0),
+                message_conversation_id: DIRECT,
+                message_sender_id: ALICE,
+                message_system: 0,
+            }
+            .into_active_model()
+            .insert(&data)
+            .await
+            .unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn mysql_upsert_is_valid() {
+        let sql = insert_pin(pinned_messages::server::ActiveModel {
+            message_id: ActiveValue::Set(1),
+            conversation_id: ActiveValue::Set(DIRECT),
+            pinned_by: ActiveValue::Set(ALICE),
+            pinned_at: ActiveValue::Set(at(0)),
+        })
+        .build(DbBackend::MySql)
+        .to_string();
+        assert!(sql.ends_with("ON DUPLICATE KEY UPDATE `message_id` = VALUES(`message_id`)"));
+        assert!(!sql.contains("DO NOTHING"));
+    }
+
+    #[tokio::test]
+    async fn pinning_twice_keeps_the_first_pin() {
+        let data = seeded().await;
+        let (update, members) = pin(&data, at(1), ALICE, 1).await.unwrap();
+        assert_eq!(members, [ALICE, BOB]);
+        assert_eq!(update.pin.unwrap().pinned_by, ALICE);
+
+        let (update, _) = pin(&data, at(2), BOB, 1).await.unwrap();
+        let pinned = update.pin.unwrap();
+        assert_eq!(pinned.pinned_by, ALICE);
+        assert_eq!(pinned.pinned_at, at(1).to_string());
+        assert_eq!(PinnedMessages::find().count(&data).await.unwrap(), 1);
+
+        pin(&data, at(3), BOB, 2).await.unwrap();
+        let pins = list(&data, BOB, DIRECT).await.unwrap();
+        assert_eq!(
+            pins.iter()
+                .map(|pin| pin.message.message_id)
+                .collect::<Vec<_>>(),
+            [2, 1]
+        );
+    }
+
+    #[tokio::test]
+    async fn only_members_pin() {
+        let data = seeded().await;
+        assert!(matches!(
+            pin(&data, at(1), CAROL, 1).await,
+            Err(PinError::NotMember)
+        ));
+        assert!(matches!(
+            pin(&data, at(1), ALICE, 9).await,
+            Err(PinError::UnknownMessage)
+        ));
+        assert!(matches!(
+            list(&data, CAROL, DIRECT).await,
+            Err(PinError::NotMember)
+        ));
+
+        pin(&data, at(1), ALICE, 1).await.unwrap();
+        let (update, _) = unpin(&data, BOB, 1).await.unwrap();
+        assert!(update.pin.is_none());
+        assert!(list(&data, ALICE, DIRECT).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,700 @@
+//! Throttling of the login, signup, verification and password reset endpoints and of the
+//! current password account changes ask for. Every attempt counts against the client's address
+//! and, where there is one, the account it targets, each in a fixed window. Failed logins and
+//! verification codes additionally lock the account, for a period that doubles with every
+//! further failure. Counters live in Redis so they are shared between workers, when Redis can
+//! not be reached they fall back to this process' memory.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::app::RateLimit;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Policy {
+    /// Attempts allowed per window
+    pub limit: u32,
+    pub window_seconds: i64,
+    /// Failures after which the subject is locked, `None` never locks
+    pub lockout_after: Option<u32>,
+    pub lockout_base_seconds: i64,
+    pub lockout_max_seconds: i64,
+}
+
+impl Policy {
+    const fn window(limit: u32, window_seconds: i64) -> Self {
+        Self {
+            limit,
+            window_seconds,
+            lockout_after: None,
+            lockout_base_seconds: 0,
+            lockout_max_seconds: 0,
+        }
+    }
+
+    const fn lockout(self, after: u32, base_seconds: i64, max_seconds: i64) -> Self {
+        Self {
+            lockout_after: Some(after),
+            lockout_base_seconds: base_seconds,
+            lockout_max_seconds: max_seconds,
+            ..self
+        }
+    }
+
+    /// How long the subject is locked after its `failures`th failure, doubling from the base
+    /// up to the maximum.
+    pub fn backoff_seconds(&self, failures: u32) -> Option<i64> {
+        let after = self.lockout_after?;
+        let excess = failures.checked_sub(after)?;
+        Some(
+            self.lockout_base_seconds
+                .saturating_mul(1i64 << excess.min(32))
+                .min(self.lockout_max_seconds),
+        )
+    }
+
+    /// How long an entry is worth keeping.
+    fn ttl_seconds(&self) -> i64 {
+        self.window_seconds.max(self.lockout_max_seconds)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Login,
+    SignUp,
+    CredValidation,
+    ConfirmSubscription,
+    TwoFactor,
+    PasswordReset,
+    ResendVerification,
+    CurrentPassword,
+}
+
+impl Action {
+    fn name(self) -> &'static str {
+        match self {
+            Action::Login => "login",
+            Action::SignUp => "sign_up",
+            Action::CredValidation => "cred_validation",
+            Action::ConfirmSubscription => "confirm_subscription",
+            Action::TwoFactor => "two_factor",
+            Action::PasswordReset => "password_reset",
+            Action::ResendVerification => "resend_verification",
+            Action::CurrentPassword => "current_password",
+        }
+    }
+
+    pub fn ip_policy(self) -> Policy {
+        match self {
+            Action::Login => Policy::window(30, 5 * 60),
+            Action::SignUp => Policy::window(5, 60 * 60),
+            // Called while the signup form is typed in
+            Action::CredValidation => Policy::window(120, 5 * 60),
+            Action::ConfirmSubscription => Policy::window(30, 5 * 60),
+            Action::TwoFactor => Policy::window(30, 5 * 60),
+            Action::PasswordReset => Policy::window(10, 60 * 60),
+            Action::ResendVerification => Policy::window(10, 60 * 60),
+            Action::CurrentPassword => Policy::window(30, 5 * 60),
+        }
+    }
+
+    pub fn account_policy(self) -> Policy {
+        match self {
+            Action::Login => Policy::window(20, 15 * 60).lockout(5, 30, 30 * 60),
+            Action::SignUp => Policy::window(3, 60 * 60),
+            Action::CredValidation => Policy::window(120, 5 * 60),
+            Action::ConfirmSubscription => Policy::window(20, 15 * 60).lockout(5, 60, 60 * 60),
+            // Six digits are guessed quickly, so the lockout starts early
+            Action::TwoFactor => Policy::window(10, 15 * 60).lockout(3, 60, 60 * 60),
+            // Every request sends an email to the address
+            Action::PasswordReset => Policy::window(3, 60 * 60),
+            Action::ResendVerification => Policy::window(5, 60 * 60),
+            // A stolen session must not be able to guess its way to the password
+            Action::CurrentPassword => Policy::window(20, 15 * 60).lockout(5, 30, 30 * 60),
+        }
+    }
+}
+
+/// Who an attempt counts against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subject<'a> {
+    Ip(&'a str),
+    /// An email address or user id, compared case insensitively
+    Account(&'a str),
+}
+
+impl Subject<'_> {
+    fn key(&self, action: Action) -> String {
+        match self {
+            Subject::Ip(ip) => format!("rate_limit:{}:ip:{ip}", action.name()),
+            Subject::Account(email) => format!(
+                "rate_limit:{}:account:{}",
+                action.name(),
+                email.trim().to_lowercase()
+            ),
+        }
+    }
+
+    fn policy(&self, action: Action) -> Policy {
+        match self {
+            Subject::Ip(_) => action.ip_policy(),
+            Subject::Account(_) => action.account_policy(),
+        }
+    }
+}
+
+/// The address of the client, `None` when actix does not know it.
+pub fn client_ip(request: &actix_web::HttpRequest) -> Option<String> {
+    request.peer_addr().map(|address| address.ip().to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Entry {
+    pub window_start: i64,
+    pub attempts: u32,
+    pub failures: u32,
+    pub locked_until: Option<i64>,
+}
+
+impl Entry {
+    /// Starts a new window once the current one is over.
+    pub fn roll(&mut self, policy: &Policy, now: i64) {
+        if now >= self.window_start + policy.window_seconds {
+            self.window_start = now;
+            self.attempts = 0;
+        }
+    }
+
+    pub fn check(&self, policy: &Policy, now: i64) -> Result<(), RateLimit> {
+        if let Some(locked_until) = self.locked_until.filter(|locked_until| *locked_until > now) {
+            return Err(RateLimit::LockedOut {
+                retry_after_seconds: locked_until - now,
+            });
+        }
+        match self.attempts >= policy.limit {
+            true => Err(RateLimit::TooManyAttempts {
+                retry_after_seconds: self.window_start + policy.window_seconds - now,
+            }),
+            false => Ok(()),
+        }
+    }
+
+    pub fn fail(&mut self, policy: &Policy, now: i64) {
+        self.failures += 1;
+        if let Some(backoff) = policy.backoff_seconds(self.failures) {
+            self.locked_until = Some(now + backoff);
+        }
+    }
+
+    pub fn succeed(&mut self) {
+        self.failures = 0;
+        self.locked_until = None;
+    }
+}
+
+#[derive(Debug)]
+pub struct StoreError(pub String);
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Rate limit store error: {}", self.0)
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+/// Changes the entries of one operation, in the order of its keys. Returning an error leaves
+/// them as they were.
+pub type Update<'a> = &'a (dyn Fn(&mut [Entry]) -> Result<(), RateLimit> + Send + Sync);
+
+#[async_trait::async_trait]
+pub trait LimitStore: Send + Sync {
+    /// Runs `update` on the entries of `keys`, each kept for its ttl in seconds, and stores the
+    /// result in one atomic step, so concurrent requests never overwrite each other's counts.
+    async fn update(
+        &self,
+        keys: &[(String, i64)],
+        update: Update<'_>,
+    ) -> Result<Result<(), RateLimit>, StoreError>;
+}
+
+/// Entries of this process only, expired ones are dropped as they are written.
+#[derive(Default)]
+pub struct MemoryStore {
+    entries: parking_lot::Mutex<HashMap<String, (Entry, DateTime<Utc>)>>,
+}
+
+#[async_trait::async_trait]
+impl LimitStore for MemoryStore {
+    async fn update(
+        &self,
+        keys: &[(String, i64)],
+        update: Update<'_>,
+    ) -> Result<Result<(), RateLimit>, StoreError> {
+        let now = Utc::now();
+        let mut entries = self.entries.lock();
+        entries.retain(|_, (_, expires_at)| *expires_at > now);
+
+        let mut current: Vec<Entry> = keys
+            .iter()
+            .map(|(key, _)| {
+                entries
+                    .get(key)
+                    .map(|(entry, _)| *entry)
+                    .unwrap_or_default()
+            })
+            .collect();
+        if let Err(limit) = update(&mut current) {
+            return Ok(Err(limit));
+        }
+        for ((key, ttl_seconds), entry) in keys.iter().zip(current) {
+            entries.insert(
+                key.clone(),
+                (entry, now + chrono::Duration::seconds(*ttl_seconds)),
+            );
+        }
+        Ok(Ok(()))
+    }
+}
+
+/// Writes the new entries only when none changed since they were read, returns whether it did.
+/// `ARGV` holds the entries as read, empty when missing, then the new entries, then their ttls.
+const COMPARE_AND_SET: &str = r"
+local count = #KEYS
+for i = 1, count do
+    if (redis.call('GET', KEYS[i]) or '') ~= ARGV[i] then
+        return 0
+    end
+end
+for i = 1, count do
+    redis.call('SET', KEYS[i], ARGV[count + i], 'EX', ARGV[2 * count + i])
+end
+return 1
+";
+
+/// Entries as JSON in Redis, expiring with their policy. An update reads the entries, applies
+/// the change here and writes them back with a compare and set script, starting over when
+/// another worker got in between. One connection is reused and opened again after an error.
+pub struct RedisStore {
+    client: redis::Client,
+    connection: Arc<parking_lot::Mutex<Option<redis::Connection>>>,
+    compare_and_set: redis::Script,
+}
+
+impl RedisStore {
+    const TIMEOUT: Duration = Duration::from_millis(500);
+    /// Rounds an update gets before it gives up on a contended key
+    const ROUNDS: usize = 8;
+
+    pub fn new(url: &str) -> Result<Self, StoreError> {
+        Ok(Self {
+            client: redis::Client::open(url).map_err(|e| StoreError(e.to_string()))?,
+            connection: Arc::new(parking_lot::Mutex::new(None)),
+            compare_and_set: redis::Script::new(COMPARE_AND_SET),
+        })
+    }
+
+    async fn run<T: Send + 'static>(
+        &self,
+        command: impl FnOnce(&mut redis::Connection) -> redis::RedisResult<T> + Send + 'static,
+    ) -> Result<T, StoreError> {
+        let client = self.client.clone();
+        let connection = self.connection.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock();
+            if connection.is_none() {
+                let opened = client.get_connection_with_timeout(Self::TIMEOUT)?;
+                opened.set_read_timeout(Some(Self::TIMEOUT))?;
+                opened.set_write_timeout(Some(Self::TIMEOUT))?;
+                *connection = Some(opened);
+            }
+            let result = command(connection.as_mut().expect("connection was just opened"));
+            if result.is_err() {
+                *connection = None;
+            }
+            result
+        })
+        .await
+        .map_err(|e| StoreError(e.to_string()))?
+        .map_err(|e| StoreError(e.to_string()))
+    }
+}
+
+#[async_trait::async_trait]
+impl LimitStore for RedisStore {
+    async fn update(
+        &self,
+        keys: &[(String, i64)],
+        update: Update<'_>,
+    ) -> Result<Result<(), RateLimit>, StoreError> {
+        let names: Vec<String> = keys.iter().map(|(key, _)| key.clone()).collect();
+        let ttls: Vec<i64> = keys
+            .iter()
+            .map(|(_, ttl_seconds)| (*ttl_seconds).max(1))
+            .collect();
+
+        for _ in 0..Self::ROUNDS {
+            let read = names.clone();
+            let stored: Vec<Option<String>> = self
+                .run(move |connection| redis::cmd("MGET").arg(read).query(connection))
+                .await?;
+
+            let mut entries: Vec<Entry> = stored
+                .iter()
+                .map(|value| {
+                    value
+                        .as_deref()
+                        .and_then(|value| serde_json::from_str(value).ok())
+                        .unwrap_or_default()
+                })
+                .collect();
+            if let Err(limit) = update(&mut entries) {
+                return Ok(Err(limit));
+            }
+
+            let expected: Vec<String> = stored.into_iter().map(Option::unwrap_or_default).collect();
+            let written = entries
+                .iter()
+                .map(serde_json::to_string)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| StoreError(e.to_string()))?;
+            let script = self.compare_and_set.clone();
+            let (names, ttls) = (names.clone(), ttls.clone());
+            let swapped: bool = self
+                .run(move |connection| {
+                    let mut invocation = script.prepare_invoke();
+                    for name in &names {
+                        invocation.key(name);
+                    }
+                    for value in expected.iter().chain(&written) {
+                        invocation.arg(value);
+                    }
+                    for ttl_seconds in &ttls {
+                        invocation.arg(*ttl_seconds);
+                    }
+                    invocation.invoke(connection)
+                })
+                .await?;
+            if swapped {
+                return Ok(Ok(()));
+            }
+        }
+        Err(StoreError(String::from("entries kept changing")))
+    }
+}
+
+pub struct RateLimiter {
+    store: Option<Box<dyn LimitStore>>,
+    fallback: MemoryStore,
+}
+
+impl RateLimiter {
+    pub fn new(store: impl LimitStore + 'static) -> Self {
+        Self {
+            store: Some(Box::new(store)),
+            fallback: MemoryStore::default(),
+        }
+    }
+
+    pub fn in_memory() -> Self {
+        Self {
+            store: None,
+            fallback: MemoryStore::default(),
+        }
+    }
+
+    /// Uses Redis at `url`, or memory only when the url is invalid.
+    pub fn redis(url: &str) -> Self {
+        match RedisStore::new(url) {
+            Ok(store) => Self::new(store),
+            Err(e) => {
+                println!("{e}, rate limits are kept in memory");
+                Self::in_memory()
+            }
+        }
+    }
+
+    async fn update(
+        &self,
+        action: Action,
+        subjects: &[Subject<'_>],
+        update: Update<'_>,
+    ) -> Result<(), RateLimit> {
+        let keys: Vec<(String, i64)> = subjects
+            .iter()
+            .map(|subject| (subject.key(action), subject.policy(action).ttl_seconds()))
+            .collect();
+
+        if let Some(store) = &self.store {
+            match store.update(&keys, update).await {
+                Ok(result) => return result,
+                Err(e) => println!("{e}, falling back to memory"),
+            }
+        }
+        self.fallback.update(&keys, update).await.unwrap_or(Ok(()))
+    }
+
+    /// Counts an attempt against every subject, or rejects it when one of them is locked or
+    /// out of attempts. Rejected attempts do not count.
+    pub async fn attempt(
+        &self,
+        action: Action,
+        subjects: &[Subject<'_>],
+        now: DateTime<Utc>,
+    ) -> Result<(), RateLimit> {
+        let now = now.timestamp();
+        let policies: Vec<Policy> = subjects
+            .iter()
+            .map(|subject| subject.policy(action))
+            .collect();
+        self.update(action, subjects, &|entries| {
+            for (entry, policy) in entries.iter_mut().zip(&policies) {
+                entry.roll(policy, now);
+                entry.check(policy, now)?;
+            }
+            for entry in entries.iter_mut() {
+                entry.attempts += 1;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Records a wrong password or code, locking subjects past their policy's threshold.
+    pub async fn failed(&self, action: Action, subjects: &[Subject<'_>], now: DateTime<Utc>) {
+        let now = now.timestamp();
+        let policies: Vec<Policy> = subjects
+            .iter()
+            .map(|subject| subject.policy(action))
+            .collect();
+        let _ = self
+            .update(action, subjects, &|entries| {
+                for (entry, policy) in entries.iter_mut().zip(&policies) {
+                    entry.fail(policy, now);
+                }
+                Ok(())
+            })
+            .await;
+    }
+
+    /// Forgets earlier failures after a correct password or code.
+    pub async fn succeeded(&self, action: Action, subjects: &[Subject<'_>]) {
+        let _ = self
+            .update(action, subjects, &|entries| {
+                entries.iter_mut().for_each(Entry::succeed);
+                Ok(())
+            })
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_700_000_000 + seconds, 0).unwrap()
+    }
+
+    /// Redis while it is down.
+    struct Unreachable;
+
+    #[async_trait::async_trait]
+    impl LimitStore for Unreachable {
+        async fn update(
+            &self,
+            _: &[(String, i64)],
+            _: Update<'_>,
+        ) -> Result<Result<(), RateLimit>, StoreError> {
+            Err(StoreError(String::from("Connection refused")))
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let policy = Action::Login.account_policy();
+        assert_eq!(policy.backoff_seconds(4), None);
+        assert_eq!(policy.backoff_seconds(5), Some(30));
+        assert_eq!(policy.backoff_seconds(6), Some(60));
+        assert_eq!(policy.backoff_seconds(9), Some(480));
+        assert_eq!(policy.backoff_seconds(11), Some(30 * 60));
+        assert_eq!(policy.backoff_seconds(u32::MAX), Some(30 * 60));
+        assert_eq!(Action::SignUp.account_policy().backoff_seconds(100), None);
+    }
+
+    #[tokio::test]
+    async fn window_rolls_over() {
+        let limiter = RateLimiter::in_memory();
+        let account = [Subject::Account("someone@example.com")];
+        for second in 0..3 {
+            limiter
+                .attempt(Action::SignUp, &account, at(second))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            limiter.attempt(Action::SignUp, &account, at(600)).await,
+            Err(RateLimit::TooManyAttempts {
+                retry_after_seconds: 3000
+            })
+        );
+        // Accounts are the same however they are written
+        assert_eq!(
+            limiter
+                .attempt(
+                    Action::SignUp,
+                    &[Subject::Account(" SomeOne@Example.com ")],
+                    at(3599)
+                )
+                .await,
+            Err(RateLimit::TooManyAttempts {
+                retry_after_seconds: 1
+            })
+        );
+
+        limiter
+            .attempt(Action::SignUp, &account, at(3600))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejected_attempts_do_not_count() {
+        let limiter = RateLimiter::in_memory();
+        let ip = Subject::Ip("192.0.2.1");
+        let taken = Subject::Account("taken@example.com");
+        for _ in 0..3 {
+            limiter
+                .attempt(Action::SignUp, &[ip, taken], at(0))
+                .await
+                .unwrap();
+        }
+        for _ in 0..3 {
+            assert!(limiter
+                .attempt(Action::SignUp, &[ip, taken], at(0))
+                .await
+                .is_err());
+        }
+
+        // The address still has two of its five attempts left
+        let other = Subject::Account("other@example.com");
+        for _ in 0..2 {
+            limiter
+                .attempt(Action::SignUp, &[ip, other], at(0))
+                .await
+                .unwrap();
+        }
+        assert!(limiter
+            .attempt(Action::SignUp, &[ip, other], at(0))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn failures_lock_with_backoff() {
+        let limiter = RateLimiter::in_memory();
+        let account = [Subject::Account("someone@example.com")];
+        for _ in 0..4 {
+            limiter.failed(Action::Login, &account, at(0)).await;
+        }
+        limiter
+            .attempt(Action::Login, &account, at(0))
+            .await
+            .unwrap();
+
+        limiter.failed(Action::Login, &account, at(0)).await;
+        assert_eq!(
+            limiter.attempt(Action::Login, &account, at(10)).await,
+            Err(RateLimit::LockedOut {
+                retry_after_seconds: 20
+            })
+        );
+        limiter
+            .attempt(Action::Login, &account, at(30))
+            .await
+            .unwrap();
+
+        limiter.failed(Action::Login, &account, at(30)).await;
+        assert_eq!(
+            limiter.attempt(Action::Login, &account, at(30)).await,
+            Err(RateLimit::LockedOut {
+                retry_after_seconds: 60
+            })
+        );
+
+        // A success forgets the failures, the next one does not lock
+        limiter.succeeded(Action::Login, &account).await;
+        limiter
+            .attempt(Action::Login, &account, at(31))
+            .await
+            .unwrap();
+        limiter.failed(Action::Login, &account, at(31)).await;
+        limiter
+            .attempt(Action::Login, &account, at(31))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn unreachable_store_falls_back_to_memory() {
+        let limiter = RateLimiter::new(Unreachable);
+        let subjects = [
+            Subject::Ip("192.0.2.1"),
+            Subject::Account("someone@example.com"),
+        ];
+        for _ in 0..3 {
+            limiter
+                .attempt(Action::SignUp, &subjects, at(0))
+                .await
+                .unwrap();
+        }
+        assert!(matches!(
+            limiter.attempt(Action::SignUp, &subjects, at(1)).await,
+            Err(RateLimit::TooManyAttempts { .. })
+        ));
+
+        for _ in 0..3 {
+            limiter.failed(Action::TwoFactor, &subjects, at(0)).await;
+        }
+        assert_eq!(
+            limiter.attempt(Action::TwoFactor, &subjects, at(1)).await,
+            Err(RateLimit::LockedOut {
+                retry_after_seconds: 59
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn memory_store_keeps_entries_for_their_ttl() {
+        let store = MemoryStore::default();
+        let keys = [(String::from("kept"), 60), (String::from("expired"), 0)];
+        store
+            .update(&keys, &|entries| {
+                entries.iter_mut().for_each(|entry| entry.attempts = 1);
+                Ok(())
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        store
+            .update(&keys, &|entries| {
+                assert_eq!(entries[0].attempts, 1);
+                assert_eq!(entries[1], Entry::default());
+                Err(RateLimit::TooManyAttempts {
+                    retry_after_seconds: 1,
+                })
+            })
+            .await
+            .unwrap()
+            .unwrap_err();
+    }
+}
@@ -0,0 +1,261 @@
+//! Disappearing messages. A conversation with a retention keeps its messages that long,
+//! `Sweeper` deletes the older ones together with their uploads and tells the members which
+//! are gone. Changing the retention posts a system message to the room.
+
+use std::time::Duration;
+
+use actix::{Actor, Addr, AsyncContext, Context, WrapFuture};
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, DbErr, EntityTrait,
+    QueryFilter,
+};
+
+use super::{scheduler::Clock, AppendDatabase, MessagesDeleted, Retention, UserLogin};
+use crate::entities::{conversation, message, prelude::*, user_conversation};
+use crate::web_socket::server::{IconWs, NotifyUsers};
+
+#[derive(Debug)]
+pub enum RetentionError {
+    NotMember,
+    Database(DbErr),
+}
+
+impl std::fmt::Display for RetentionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RetentionError::NotMember => write!(f, "Not a member of this conversation"),
+            RetentionError::Database(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for RetentionError {}
+
+impl RetentionError {
+    pub fn into_server_fn_error(self) -> leptos::ServerFnError {
+        match self {
+            RetentionError::Database(e) => leptos::ServerFnError::ServerError(e.to_string()),
+            e => leptos::ServerFnError::Request(e.to_string()),
+        }
+    }
+}
+
+impl From<DbErr> for RetentionError {
+    fn from(value: DbErr) -> Self {
+        RetentionError::Database(value)
+    }
+}
+
+/// Body of the system message posted when `name` changes the retention.
+pub fn notice(name: &str, retention: Option<Retention>) -> String {
+    match retention {
+        Some(retention) => format!("{name} set messages to disappear after {}", retention.label()),
+        None => format!("{name} turned off disappearing messages"),
+    }
+}
+
+/// Messages created before this are expired.
+pub fn cutoff(now: DateTime<Utc>, retention_seconds: i64) -> DateTime<Utc> {
+    now - chrono::Duration::seconds(retention_seconds)
+}
+
+/// Any member can change the retention. Returns the system message for the room, or `None`
+/// when nothing changed.
+pub async fn set(
+    data: &DatabaseConnection,
+    user: &UserLogin,
+    conversation_id: i32,
+    retention: Option<Retention>,
+) -> Result<Option<crate::app::pages::conversation::Message>, RetentionError> {
+    if UserConversation::find_by_id((user.id, conversation_id))
+        .one(data)
+        .await?
+        .is_none()
+    {
+        return Err(RetentionError::NotMember);
+    }
+    let conversation = Conversation::find_by_id(conversation_id)
+        .one(data)
+        .await?
+        .ok_or(RetentionError::NotMember)?;
+
+    let retention_seconds = retention.map(Retention::seconds);
+    if conversation.retention_seconds == retention_seconds {
+        return Ok(None);
+    }
+
+    let mut conversation: conversation::server::ActiveModel = conversation.into();
+    conversation.retention_seconds = ActiveValue::Set(retention_seconds);
+    conversation.update(data).await?;
+
+    let body = notice(&format!("{} {}", user.first_name, user.last_name), retention);
+    let message_id = AppendDatabase::insert_messages(
+        data,
+        message::server::ActiveModel {
+            message_body: ActiveValue::Set(Some(body.clone())),
+            message_sender_id: ActiveValue::Set(user.id),
+            message_conversation_id: ActiveValue::Set(conversation_id),
+            message_system: ActiveValue::Set(1),
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    Ok(Some(crate::app::pages::conversation::Message {
+        message: Some(body),
+        image: None,
+        audio: None,
+        audio_duration: None,
+        conversation_id,
+        user_id: user.id,
+        first_name: user.first_name.clone(),
+        last_name: user.last_name.clone(),
+        message_id,
+        system: true,
+    }))
+}
+
+/// Removes an upload written by the message routes, paths that could point anywhere else
+/// are left alone.
+async fn remove_upload(path: &str) {
+    let Some(file_name) = path.strip_prefix("/upload/") else {
+        return;
+    };
+    if file_name.is_empty() || file_name.starts_with('.') || file_name.contains('/') {
+        return;
+    }
+
+    match tokio::fs::remove_file(format!("./upload/{file_name}")).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            println!("Could not remove expired upload {file_name}: {e}")
+        }
+        _ => (),
+    }
+}
+
+/// Deletes every message past its conversation's retention at `now`, returns how many.
+pub async fn sweep(
+    data: &DatabaseConnection,
+    now: DateTime<Utc>,
+    icon_server: &Addr<IconWs>,
+) -> Result<usize, DbErr> {
+    let conversations = Conversation::find()
+        .filter(conversation::server::Column::RetentionSeconds.is_not_null())
+        .all(data)
+        .await?;
+
+    let mut deleted = 0;
+    for conversation in conversations {
+        let Some(retention_seconds) = conversation.retention_seconds else {
+            continue;
+        };
+
+        let expired = Message::find()
+            .filter(message::server::Column::MessageConversationId.eq(conversation.id))
+            .filter(message::server::Column::MessageCreatedAt.lt(cutoff(now, retention_seconds)))
+            .all(data)
+            .await?;
+        if expired.is_empty() {
+            continue;
+        }
+
+        let message_ids: Vec<i32> = expired.iter().map(|message| message.message_id).collect();
+        Message::delete_many()
+            .filter(message::server::Column::MessageId.is_in(message_ids.clone()))
+            .exec(data)
+            .await?;
+
+        for path in expired
+            .iter()
+            .flat_map(|message| [&message.message_image, &message.message_audio])
+            .flatten()
+        {
+            remove_upload(path).await;
+        }
+
+        deleted += message_ids.len();
+        notify(data, icon_server, conversation.id, message_ids).await?;
+    }
+    Ok(deleted)
+}
+
+/// Tells every member which messages are gone, along with their new unread count.
+async fn notify(
+    data: &DatabaseConnection,
+    icon_server: &Addr<IconWs>,
+    conversation_id: i32,
+    message_ids: Vec<i32>,
+) -> Result<(), DbErr> {
+    let members: Vec<i32> = UserConversation::find()
+        .filter(user_conversation::server::Column::ConversationId.eq(conversation_id))
+        .all(data)
+        .await?
+        .into_iter()
+        .map(|membership| membership.user_ids)
+        .collect();
+
+    if let Ok(message) = serde_json::to_string(&MessagesDeleted {
+        deleted_conversation_id: conversation_id,
+        deleted_message_ids: message_ids,
+    }) {
+        icon_server.do_send(NotifyUsers {
+            user_ids: members.clone(),
+            message,
+        });
+    }
+
+    for user_id in members {
+        super::unread::notify_member(data, icon_server, conversation_id, user_id).await?;
+    }
+    Ok(())
+}
+
+/// Background actor that sweeps expired messages every `interval`.
+pub struct Sweeper<C: Clock> {
+    data: DatabaseConnection,
+    clock: C,
+    icon_server: Addr<IconWs>,
+    interval: Duration,
+}
+
+impl<C: Clock> Sweeper<C> {
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+
+    pub fn new(data: DatabaseConnection, clock: C, icon_server: Addr<IconWs>) -> Self {
+        Self {
+            data,
+            clock,
+            icon_server,
+            interval: Self::DEFAULT_INTERVAL,
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+}
+
+impl<C: Clock> Actor for Sweeper<C> {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.interval, |act, ctx| {
+            let data = act.data.clone();
+            let now = act.clock.now();
+            let icon_server = act.icon_server.clone();
+
+            ctx.spawn(
+                async move {
+                    match sweep(&data, now, &icon_server).await {
+                        Ok(0) => (),
+                        Ok(deleted) => println!("Swept {deleted} expired messages"),
+                        Err(e) => println!("Sweeping expired messages failed: {e}"),
+                    }
+                }
+                .into_actor(act),
+            );
+        });
+    }
+}
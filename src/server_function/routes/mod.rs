@@ -2,7 +2,10 @@ use iter_tools::Itertools;
 use leptos::{log, server, server_fn, Scope, ServerFnError};
 
 use crate::{
-    app::{EmailSchema, FormValidation, PhoneSchema, VerificationValidation, VerifyPassword},
+    app::{
+        EmailSchema, FormValidation, PhoneSchema, ResendValidation, ResetValidation,
+        VerificationValidation, VerifyPassword,
+    },
     entities::{conversation, user_conversation},
     server_function::{
         AppendDatabase, ConversationInner, ConversationMeta, MergedConversation,
@@ -10,7 +13,10 @@ use crate::{
     },
 };
 
-use super::{ImageAvailability, MergedMessages, UserModel};
+use super::{
+    ContactAction, ContactModel, ConversationFlag, ImageAvailability, LinkPreview, MentionEvent, MergedMessages, NotificationSettings, PinnedMessage, ReadReceipt, Retention, ScheduledMessage, SearchQuery,
+    SearchResults, SentMessage, SessionInfo, TwoFactorEnrollment, TwoFactorStatus, UnreadCounts, UserModel, UserPage, UserQuery, VoiceNoteUpload,
+};
 
 #[server(SignUp, "/api", "Url")]
 pub async fn sign_up(
@@ -22,7 +28,6 @@ pub async fn sign_up(
         password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
         Argon2,
     };
-    use rand::Rng;
     use sea_orm::*;
 
     let struct_vector: Vec<Box<dyn validator::Validate>> = vec![
@@ -40,11 +45,28 @@ pub async fn sign_up(
 
         leptos_actix::extract(
             cx,
-            move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>| {
+            move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+                  email_client: actix_web::web::Data<crate::emailing::email_client::EmailClient>,
+                  limiter: actix_web::web::Data<crate::server_function::rate_limit::RateLimiter>,
+                  request: actix_web::HttpRequest| {
                 let form = form.clone();
                 let entry = form.email.entry.clone();
 
                 async move {
+                    use super::rate_limit::{client_ip, Action, Subject};
+
+                    let ip = client_ip(&request).unwrap_or_default();
+                    if let Err(limit) = limiter
+                        .attempt(
+                            Action::SignUp,
+                            &[Subject::Ip(&ip), Subject::Account(&entry)],
+                            chrono::Utc::now(),
+                        )
+                        .await
+                    {
+                        return Ok(FormValidation::Limited(limit));
+                    }
+
                     if Users::find()
                         .filter(users::server::Column::Email.eq(entry.clone()))
                         .one(&data.lock().await.connection)
@@ -66,20 +88,7 @@ pub async fn sign_up(
                     {
                         Ok(super::super::app::FormValidation::PhonePresent)
                     } else {
-                        let special_characters = "!@#$%^&*";
-
-                        // Generate a random 15-letter string with lowercase, uppercase, and special characters
-                        let mut rng = rand::thread_rng();
-                        let random_string: String = (0..15)
-                            .map(|_| {
-                                let charset: Vec<u8> = match rng.gen_range(0..3) {
-                                    0 => (b'a'..=b'z').collect(),
-                                    1 => (b'A'..=b'Z').collect(),
-                                    _ => special_characters.bytes().collect(),
-                                };
-                                char::from(charset[rng.gen_range(0..charset.len())])
-                            })
-                            .collect();
+                        let random_string = super::account::verification_code();
 
                         let new_user = temp_users::server::ActiveModel {
                             first_name: ActiveValue::Set(form.first_name.entry.clone()),
@@ -107,16 +116,17 @@ pub async fn sign_up(
                                 ActiveValue::Set(random_string.clone())
                             },
                             time: ActiveValue::Set(chrono::Utc::now()),
+                            verification_sent_at: ActiveValue::Set(chrono::Utc::now()),
+                            verification_sends: ActiveValue::Set(1),
                             ..Default::default()
                         };
-                        if TempUsers::insert(new_user)
-                            .exec(&data.lock().await.connection)
-                            .await
-                            .is_ok()
-                        {
-                            Ok(super::super::app::FormValidation::Success {
-                                random_string: Some(random_string),
-                            })
+                        // Bound first so the database is released before the email goes out
+                        let inserted = new_user.insert(&data.lock().await.connection).await;
+                        if let Ok(pending) = inserted {
+                            // The code only goes to the address itself, it never reaches the
+                            // browser
+                            super::verification::send(&email_client, &pending).await;
+                            Ok(super::super::app::FormValidation::Success)
                         } else {
                             Ok(super::super::app::FormValidation::Error)
                         }
@@ -139,10 +149,23 @@ pub async fn cred_validation(
 
     leptos_actix::extract(
         cx,
-        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>| {
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              limiter: actix_web::web::Data<crate::server_function::rate_limit::RateLimiter>,
+              request: actix_web::HttpRequest| {
             let email = email.clone();
             let phone_number = phone_number.clone();
             async move {
+                use super::rate_limit::{client_ip, Action, Subject};
+
+                // Keyed by address only, this is how accounts would be enumerated
+                let ip = client_ip(&request).unwrap_or_default();
+                if let Err(limit) = limiter
+                    .attempt(Action::CredValidation, &[Subject::Ip(&ip)], chrono::Utc::now())
+                    .await
+                {
+                    return Ok(FormValidation::Limited(limit));
+                }
+
                 let db = &data.lock().await.connection;
                 if let Some(email) = email {
                     if TempUsers::find()
@@ -158,9 +181,7 @@ pub async fn cred_validation(
                     {
                         Ok(FormValidation::EmailPresent)
                     } else {
-                        Ok(FormValidation::Success {
-                            random_string: None,
-                        })
+                        Ok(FormValidation::Success)
                     }
                 } else if TempUsers::find()
                     .filter(
@@ -190,9 +211,7 @@ pub async fn cred_validation(
                 {
                     Ok(FormValidation::PhonePresent)
                 } else {
-                    Ok(FormValidation::Success {
-                        random_string: None,
-                    })
+                    Ok(FormValidation::Success)
                 }
             }
         },
@@ -200,16 +219,60 @@ pub async fn cred_validation(
     .await?
 }
 
-#[server(VerifyEmail, "/api", "Url")]
-pub async fn send_verification_email(
+/// Emails the verification code of the pending signup for `email` again.
+#[server(ResendVerification, "/api", "Url")]
+pub async fn resend_verification_email(
+    cx: Scope,
     email: String,
-    first_name: String,
-    random_string: String,
-) -> Result<String, ServerFnError> {
-    match crate::emailing::email_client::send_email(email, first_name, random_string) {
-        Ok(_) => Ok(String::from("Successful Signup")),
-        Err(e) => Ok(format!("Error at sending verification email: {e}")),
-    }
+) -> Result<ResendValidation, ServerFnError> {
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              email_client: actix_web::web::Data<crate::emailing::email_client::EmailClient>,
+              limiter: actix_web::web::Data<crate::server_function::rate_limit::RateLimiter>,
+              request: actix_web::HttpRequest| {
+            let email = email.clone();
+            async move {
+                use super::rate_limit::{client_ip, Action, Subject};
+
+                // The per signup cap starts over with every new signup, this one does not
+                let ip = client_ip(&request).unwrap_or_default();
+                let now = chrono::Utc::now();
+                if let Err(
+                    crate::app::RateLimit::TooManyAttempts {
+                        retry_after_seconds,
+                    }
+                    | crate::app::RateLimit::LockedOut {
+                        retry_after_seconds,
+                    },
+                ) = limiter
+                    .attempt(
+                        Action::ResendVerification,
+                        &[Subject::Ip(&ip), Subject::Account(&email)],
+                        now,
+                    )
+                    .await
+                {
+                    return Ok(ResendValidation::TooSoon {
+                        retry_after_seconds,
+                    });
+                }
+
+                let pending = {
+                    let data = &data.lock().await.connection;
+                    match super::verification::claim_resend(data, now, &email).await? {
+                        Ok(pending) => pending,
+                        Err(rejected) => return Ok(rejected),
+                    }
+                };
+                if let Some(pending) = pending {
+                    super::verification::send(&email_client, &pending).await;
+                }
+                Ok(ResendValidation::Sent)
+            }
+        },
+    )
+    .await?
 }
 
 #[server(ConfirmSubscription, "/api", "Url")]
@@ -223,19 +286,36 @@ pub async fn confirm_subscription(
 
     leptos_actix::extract(
         cx,
-        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>| {
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              limiter: actix_web::web::Data<crate::server_function::rate_limit::RateLimiter>,
+              request: actix_web::HttpRequest| {
             let email = email.clone();
             let input = input.clone();
             async move {
+                use super::rate_limit::{client_ip, Action, Subject};
+
+                let ip = client_ip(&request).unwrap_or_default();
+                let subjects = [Subject::Ip(&ip), Subject::Account(&email)];
+                let now = chrono::Utc::now();
+                if let Err(limit) = limiter
+                    .attempt(Action::ConfirmSubscription, &subjects, now)
+                    .await
+                {
+                    return Ok(VerificationValidation::Limited(limit));
+                }
+
                 let db = &data.lock().await.connection;
                 if let Ok(user) = TempUsers::find()
-                    .filter(temp_users::server::Column::Email.eq(email))
+                    .filter(temp_users::server::Column::Email.eq(email.clone()))
                     .one(&db.clone())
                     .await
                     .map_err(|_| VerificationValidation::EmailNotPresent)
                 {
                     let user = user.unwrap();
                     if user.verification.trim().replace('"', "") == input.trim().replace('"', "") {
+                        limiter
+                            .succeeded(Action::ConfirmSubscription, &subjects)
+                            .await;
                         let registered_user = users::server::ActiveModel {
                             first_name: ActiveValue::Set(user.first_name.clone()),
                             last_name: ActiveValue::Set(user.last_name.clone()),
@@ -256,6 +336,9 @@ pub async fn confirm_subscription(
                             Ok(super::super::app::VerificationValidation::ServerError)
                         }
                     } else {
+                        limiter
+                            .failed(Action::ConfirmSubscription, &subjects, now)
+                            .await;
                         Ok(VerificationValidation::IncorrectValidationCode)
                     }
                 } else {
@@ -274,8 +357,6 @@ pub async fn login(
     password: String,
 ) -> Result<VerifyPassword, ServerFnError> {
     use super::super::entities::{prelude::*, *};
-    use actix_identity::Identity;
-    use actix_web::HttpMessage;
     use actix_web::HttpRequest;
     use argon2::{
         password_hash::{PasswordHash, PasswordVerifier},
@@ -287,11 +368,22 @@ pub async fn login(
     leptos_actix::extract(
         cx,
         move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
-              request: HttpRequest| {
+              request: HttpRequest,
+              session: actix_session::Session,
+              limiter: actix_web::web::Data<crate::server_function::rate_limit::RateLimiter>| {
             log!("retrieved request");
             let email = email.clone();
             let password = password.clone();
             async move {
+                use super::rate_limit::{client_ip, Action, Subject};
+
+                let ip = client_ip(&request).unwrap_or_default();
+                let subjects = [Subject::Ip(&ip), Subject::Account(&email)];
+                let now = chrono::Utc::now();
+                if let Err(limit) = limiter.attempt(Action::Login, &subjects, now).await {
+                    return Ok(VerifyPassword::Limited(limit));
+                }
+
                 let db = &data.lock().await.connection;
                 if let Some(user) = Users::find()
                     .filter(users::server::Column::Email.eq(email.clone()))
@@ -304,27 +396,29 @@ pub async fn login(
                         .is_ok()
                     {
                         true => {
-                            Identity::login(
-                                &request.extensions(),
-                                serde_json::to_string_pretty(&UserLogin {
-                                    id: user.id,
-                                    email: user.email.clone(),
-                                    first_name: user.first_name.clone(),
-                                    last_name: user.last_name.clone(),
-                                })?,
-                            )
-                            .unwrap();
+                            limiter.succeeded(Action::Login, &subjects).await;
+                            if user.totp_enabled_at.is_some() {
+                                // The identity waits for the code, see `verify_two_factor`
+                                session.insert(
+                                    crate::auth::PENDING_TWO_FACTOR,
+                                    (user.id, now.timestamp()),
+                                )?;
+                                return Ok(VerifyPassword::TwoFactorRequired);
+                            }
 
-                            Ok(VerifyPassword::Success(UserLogin {
-                                id: user.id,
-                                email: user.email,
-                                first_name: user.first_name,
-                                last_name: user.last_name,
-                            }))
+                            Ok(VerifyPassword::Success(
+                                crate::auth::log_in(db, &request, &session, &user, now).await?,
+                            ))
+                        }
+                        false => {
+                            limiter.failed(Action::Login, &subjects, now).await;
+                            Ok(VerifyPassword::IncorrectCredentials)
                         }
-                        false => Ok(VerifyPassword::IncorrectCredentials),
                     }
                 } else {
+                    // Unknown addresses lock the same way, so the lockout does not tell which
+                    // accounts exist
+                    limiter.failed(Action::Login, &subjects, now).await;
                     Ok(VerifyPassword::IncorrectCredentials)
                 }
             }
@@ -333,11 +427,160 @@ pub async fn login(
     .await?
 }
 
+/// The second login step of accounts with 2FA, takes a code from the authenticator app or a
+/// recovery code. Only issues the identity once the code is accepted.
+#[server(VerifyTwoFactor, "/api", "Url")]
+pub async fn verify_two_factor(cx: Scope, code: String) -> Result<VerifyPassword, ServerFnError> {
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              request: actix_web::HttpRequest,
+              session: actix_session::Session,
+              limiter: actix_web::web::Data<crate::server_function::rate_limit::RateLimiter>| {
+            let code = code.clone();
+            async move {
+                use super::rate_limit::{client_ip, Action, Subject};
+                use super::two_factor::TwoFactorError;
+
+                let now = chrono::Utc::now();
+                let user_id = crate::auth::pending_two_factor(&session, now).ok_or_else(|| {
+                    ServerFnError::Request(String::from(
+                        "The login expired, enter your password again",
+                    ))
+                })?;
+
+                let ip = client_ip(&request).unwrap_or_default();
+                let account = user_id.to_string();
+                let subjects = [Subject::Ip(&ip), Subject::Account(&account)];
+                if let Err(limit) = limiter.attempt(Action::TwoFactor, &subjects, now).await {
+                    return Ok(VerifyPassword::Limited(limit));
+                }
+
+                let data = &data.lock().await.connection;
+                match super::two_factor::verify_login(data, now, user_id, &code).await {
+                    Ok(user) => {
+                        limiter.succeeded(Action::TwoFactor, &subjects).await;
+                        Ok(VerifyPassword::Success(
+                            crate::auth::log_in(data, &request, &session, &user, now).await?,
+                        ))
+                    }
+                    Err(TwoFactorError::IncorrectCode) => {
+                        limiter.failed(Action::TwoFactor, &subjects, now).await;
+                        Ok(VerifyPassword::IncorrectCredentials)
+                    }
+                    Err(e) => Err(e.into_server_fn_error()),
+                }
+            }
+        },
+    )
+    .await?
+}
+
+/// Emails a reset link when `email` belongs to an account. Succeeds either way, so the form
+/// can not be used to find out who has an account, `Some` when the rate limit refused it.
+#[server(RequestPasswordReset, "/api", "Url")]
+pub async fn request_password_reset(
+    cx: Scope,
+    email: String,
+) -> Result<Option<crate::app::RateLimit>, ServerFnError> {
+    use super::super::entities::{prelude::*, *};
+    use sea_orm::*;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              email_client: actix_web::web::Data<crate::emailing::email_client::EmailClient>,
+              limiter: actix_web::web::Data<crate::server_function::rate_limit::RateLimiter>,
+              request: actix_web::HttpRequest| {
+            let email = email.clone();
+            async move {
+                use super::rate_limit::{client_ip, Action, Subject};
+
+                // Counted whether or not the address has an account, so the limit gives
+                // nothing away either
+                let ip = client_ip(&request).unwrap_or_default();
+                if let Err(limit) = limiter
+                    .attempt(
+                        Action::PasswordReset,
+                        &[Subject::Ip(&ip), Subject::Account(&email)],
+                        chrono::Utc::now(),
+                    )
+                    .await
+                {
+                    return Ok(Some(limit));
+                }
+
+                let (user, token) = {
+                    let data = &data.lock().await.connection;
+                    let Some(user) = Users::find()
+                        .filter(users::server::Column::Email.eq(email.trim()))
+                        .one(data)
+                        .await?
+                    else {
+                        return Ok(None);
+                    };
+                    let token =
+                        super::password_reset::issue(data, chrono::Utc::now(), user.id).await?;
+                    (user, token)
+                };
+
+                if let Err(e) = email_client
+                    .send_password_reset(
+                        &user.email,
+                        &user.first_name,
+                        &super::password_reset::reset_link(&token),
+                        super::password_reset::TOKEN_TTL_MINUTES,
+                    )
+                    .await
+                {
+                    println!("Error at sending password reset email: {e}");
+                }
+                Ok(None)
+            }
+        },
+    )
+    .await?
+}
+
+#[server(ResetPassword, "/api", "Url")]
+pub async fn reset_password(
+    cx: Scope,
+    token: String,
+    password: crate::app::PasswordSchema,
+) -> Result<ResetValidation, ServerFnError> {
+    use super::password_reset::ResetError;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              server: actix_web::web::Data<actix::Addr<crate::web_socket::server::ChatServer>>,
+              icon_server: actix_web::web::Data<actix::Addr<crate::web_socket::server::IconWs>>| {
+            let token = token.clone();
+            let password = password.clone();
+            async move {
+                let data = &data.lock().await.connection;
+                match super::password_reset::consume(data, chrono::Utc::now(), &token, &password)
+                    .await
+                {
+                    Ok(revoked) => {
+                        crate::auth::sessions::close_sockets(&server, &icon_server, revoked);
+                        Ok(ResetValidation::Success)
+                    }
+                    Err(ResetError::InvalidToken) => Ok(ResetValidation::InvalidToken),
+                    Err(ResetError::WeakPassword) => Ok(ResetValidation::WeakPassword),
+                    Err(e) => Err(e.into_server_fn_error()),
+                }
+            }
+        },
+    )
+    .await?
+}
+
 #[server(LoginStatus, "/api", "Url")]
 pub async fn login_status(cx: Scope) -> Result<UserLogin, ServerFnError> {
-    use actix_identity::Identity;
+    use crate::auth::identity::CurrentUser;
 
-    leptos_actix::extract(cx, move |user: Option<Identity>| async {
+    leptos_actix::extract(cx, move |user: Option<CurrentUser>| async {
         let user = match UserLogin::evaluate_user(user) {
             Ok(val) => val,
             Err(e) => return Err(e),
@@ -349,25 +592,22 @@ pub async fn login_status(cx: Scope) -> Result<UserLogin, ServerFnError> {
 
 #[server(Redirect, "/api", "Url")]
 pub async fn redirect(cx: Scope) -> Result<bool, ServerFnError> {
-    use actix_identity::Identity;
+    use crate::auth::identity::CurrentUser;
     leptos_actix::extract(
         cx,
-        move |user: Option<Identity>| async move { user.is_none() },
+        move |user: Option<CurrentUser>| async move { user.is_none() },
     )
     .await
 }
 
 // #[cfg(feature = "ssr")]
 #[server(GetUsers, "/api", "Url")]
-pub async fn get_users(cx: Scope) -> Result<Vec<UserModel>, ServerFnError> {
-    use super::super::entities::prelude::*;
-    use super::super::entities::users;
-    use sea_orm::*;
-
-    Ok(leptos_actix::extract(
+pub async fn get_users(cx: Scope, query: UserQuery) -> Result<UserPage, ServerFnError> {
+    leptos_actix::extract(
         cx,
         move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
-              user: Option<actix_identity::Identity>| {
+              user: Option<crate::auth::identity::CurrentUser>| {
+            let query = query.clone();
             async move {
                 let user = match UserLogin::evaluate_user(user) {
                     Ok(val) => val,
@@ -375,30 +615,27 @@ pub async fn get_users(cx: Scope) -> Result<Vec<UserModel>, ServerFnError> {
                 };
 
                 let data = &data.lock().await.connection;
-                Ok(Users::find()
-                    .order_by_asc(users::server::Column::Id)
-                    .filter(users::server::Column::Id.ne(user.id))
-                    .all(data)
-                    .await?)
+                Ok(super::directory::directory(&user, &query, data).await?)
             }
         },
     )
-    .await??
-    .into_iter()
-    .map_into()
-    .rev()
-    .collect())
+    .await?
 }
 
+/// The caller's conversations, pinned ones first and then by latest activity. `archived`
+/// switches between the regular list and the archive.
 #[server(GetConversations, "/api", "Url")]
-pub async fn get_conversations(cx: Scope) -> Result<Vec<MergedConversation>, ServerFnError> {
-    use actix_identity::Identity;
+pub async fn get_conversations(
+    cx: Scope,
+    archived: bool,
+) -> Result<Vec<MergedConversation>, ServerFnError> {
+    use crate::auth::identity::CurrentUser;
     use sea_orm::*;
 
     leptos_actix::extract(
         cx,
         move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
-              user: Option<Identity>| {
+              user: Option<CurrentUser>| {
             async move {
                 let user = match UserLogin::evaluate_user(user) {
                     Ok(val) => val,
@@ -406,8 +643,14 @@ pub async fn get_conversations(cx: Scope) -> Result<Vec<MergedConversation>, Ser
                 };
 
                 let data = &data.lock().await.connection;
-                let conversations =
+                let mut conversations =
                     RetrieveConversations::retrieve_user_conversations(&user, data).await;
+                conversations.retain(|conversation| conversation.archived == archived);
+                conversations.sort_by(|a, b| {
+                    b.pinned
+                        .cmp(&a.pinned)
+                        .then(b.last_message_at.cmp(&a.last_message_at))
+                });
 
                 let mut condition = Condition::any();
                 for conversation in &conversations {
@@ -430,8 +673,6 @@ pub async fn get_conversations(cx: Scope) -> Result<Vec<MergedConversation>, Ser
                 )
                 .await;
 
-                let seen_messages = RetrieveConversations::retrieve_seen(&messages, data).await;
-
                 let vec_merged_conversation = conversations
                     .iter()
                     .map(|conversation| {
@@ -445,21 +686,17 @@ pub async fn get_conversations(cx: Scope) -> Result<Vec<MergedConversation>, Ser
                             .iter()
                             .filter(|message| message.message_conversation_id == conversation_id)
                             .map(|messages| {
-                                let seen_status = seen_messages
-                                    .iter()
-                                    .filter(|seen_messages| {
-                                        seen_messages.message_id.unwrap() == messages.message_id
-                                    })
-                                    .cloned()
-                                    .collect_vec();
-
                                 MergedMessages {
                                     message_conversation_id: messages.message_conversation_id,
                                     message_id: messages.message_id,
                                     message_body: messages.message_body.clone(),
                                     message_image: messages.message_image.clone(),
+                                    message_audio: messages.message_audio.clone(),
+                                    message_audio_duration: messages.message_audio_duration,
+                                    // Only the conversation view unfurls links
+                                    link_previews: Vec::new(),
                                     message_sender_id: messages.message_sender_id,
-                                    seen_status,
+                                    message_system: messages.message_system,
                                     created_at: messages.message_created_at.to_string(),
                                     first_name: messages.first_name.clone(),
                                     last_name: messages.last_name.clone(),
@@ -491,6 +728,10 @@ pub async fn get_conversations(cx: Scope) -> Result<Vec<MergedConversation>, Ser
                                 name: conversation.name.clone(),
                                 is_group: conversation.is_group,
                                 messages: conversation_messages,
+                                last_read_message_id: conversation.last_read_message_id,
+                                pinned: conversation.pinned,
+                                archived: conversation.archived,
+                                muted: conversation.muted,
                             },
                         }
                     })
@@ -507,10 +748,90 @@ pub async fn get_conversations(cx: Scope) -> Result<Vec<MergedConversation>, Ser
 pub async fn logout(cx: Scope) -> Result<(), ServerFnError> {
     use actix_identity::Identity;
 
-    leptos_actix::extract(cx, move |user: Option<Identity>| async {
-        user.unwrap().logout()
-    })
-    .await
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              server: actix_web::web::Data<actix::Addr<crate::web_socket::server::ChatServer>>,
+              icon_server: actix_web::web::Data<actix::Addr<crate::web_socket::server::IconWs>>,
+              session: actix_session::Session,
+              user: Option<Identity>| async move {
+            if let Some(token) = crate::auth::sessions::current_token(&session) {
+                crate::auth::sessions::end(&data.lock().await.connection, &token).await?;
+                crate::auth::sessions::close_sockets(&server, &icon_server, vec![token]);
+            }
+            user.unwrap().logout();
+            Ok(())
+        },
+    )
+    .await?
+}
+
+/// The user's logged in sessions, most recently active first.
+#[server(ListSessions, "/api", "Url")]
+pub async fn list_sessions(cx: Scope) -> Result<Vec<SessionInfo>, ServerFnError> {
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              session: actix_session::Session,
+              user: Option<CurrentUser>| async move {
+            let data = &data.lock().await.connection;
+            let user = UserLogin::evaluate_user(user)?;
+            let current = crate::auth::sessions::current_token(&session);
+
+            Ok(crate::auth::sessions::list(data, user.id, current.as_deref()).await?)
+        },
+    )
+    .await?
+}
+
+/// Logs out one of the user's sessions, which may be the current one.
+#[server(RevokeSession, "/api", "Url")]
+pub async fn revoke_session(cx: Scope, session_id: i32) -> Result<(), ServerFnError> {
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              server: actix_web::web::Data<actix::Addr<crate::web_socket::server::ChatServer>>,
+              icon_server: actix_web::web::Data<actix::Addr<crate::web_socket::server::IconWs>>,
+              user: Option<CurrentUser>| async move {
+            let data = &data.lock().await.connection;
+            let user = UserLogin::evaluate_user(user)?;
+
+            let revoked = crate::auth::sessions::revoke(data, user.id, session_id).await?;
+            crate::auth::sessions::close_sockets(&server, &icon_server, revoked);
+            Ok(())
+        },
+    )
+    .await?
+}
+
+/// Logs out every session of the user except the current one.
+#[server(RevokeOtherSessions, "/api", "Url")]
+pub async fn revoke_other_sessions(cx: Scope) -> Result<(), ServerFnError> {
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              server: actix_web::web::Data<actix::Addr<crate::web_socket::server::ChatServer>>,
+              icon_server: actix_web::web::Data<actix::Addr<crate::web_socket::server::IconWs>>,
+              session: actix_session::Session,
+              user: Option<CurrentUser>| async move {
+            let data = &data.lock().await.connection;
+            let user = UserLogin::evaluate_user(user)?;
+            let current = crate::auth::sessions::current_token(&session).ok_or_else(|| {
+                ServerFnError::ServerError(String::from("Session is not tracked"))
+            })?;
+
+            let revoked = crate::auth::sessions::revoke_others(data, user.id, &current).await?;
+            crate::auth::sessions::close_sockets(&server, &icon_server, revoked);
+            Ok(())
+        },
+    )
+    .await?
 }
 
 #[server(ConversationAction, "/api", "Url")]
@@ -521,7 +842,7 @@ pub async fn conversation_action(
     name: Option<String>,
 ) -> Result<(), ServerFnError> {
     use crate::entities::prelude::*;
-    use actix_identity::Identity;
+    use crate::auth::identity::CurrentUser;
     use iter_tools::prelude::Itertools;
     use sea_orm::prelude::*;
     use sea_orm::*;
@@ -538,7 +859,7 @@ pub async fn conversation_action(
     leptos_actix::extract(
         cx,
         move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
-              user: Option<Identity>| {
+              user: Option<CurrentUser>| {
             let other_users = other_users.clone();
             let name = name.clone();
             async move {
@@ -549,6 +870,14 @@ pub async fn conversation_action(
 
                 let data = &data.lock().await.connection;
 
+                let invited = match is_group {
+                    true => other_users.clone(),
+                    false => other_users.iter().take(1).copied().collect(),
+                };
+                super::contacts::ensure_can_start(data, user.id, &invited)
+                    .await
+                    .map_err(super::contacts::ContactError::into_server_fn_error)?;
+
                 let mut existing_conversation = UserConversation::find()
                     .select_only()
                     .column(user_conversation::server::Column::ConversationId)
@@ -598,6 +927,7 @@ pub async fn conversation_action(
                                 UserConversation::insert(user_conversation::server::ActiveModel {
                                     user_ids: ActiveValue::Set(*user),
                                     conversation_id: ActiveValue::Set(conversation.last_insert_id),
+                                    ..Default::default()
                                 })
                                 .exec(data)
                                 .await?;
@@ -623,6 +953,7 @@ pub async fn conversation_action(
                                         conversation_id: ActiveValue::Set(
                                             conversation.last_insert_id,
                                         ),
+                                        ..Default::default()
                                     })
                                 });
 
@@ -647,7 +978,7 @@ pub async fn validate_conversation(
     desired_conversation_id: i32,
 ) -> Result<Vec<ConversationMeta>, ServerFnError> {
     use crate::entities::prelude::*;
-    use actix_identity::Identity;
+    use crate::auth::identity::CurrentUser;
     use iter_tools::Itertools;
     use sea_orm::prelude::*;
     use sea_orm::Condition;
@@ -655,7 +986,7 @@ pub async fn validate_conversation(
     leptos_actix::extract(
         cx,
         move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
-              user: Option<Identity>| {
+              user: Option<CurrentUser>| {
             async move {
                 let data = &data.lock().await.connection;
                 let user = match UserLogin::evaluate_user(user) {
@@ -696,6 +1027,9 @@ pub async fn validate_conversation(
                         created_at: conversation.created_at.to_string(),
                         name: conversation.name,
                         is_group: conversation.is_group,
+                        retention: conversation
+                            .retention_seconds
+                            .and_then(super::Retention::from_seconds),
                         count: user_conversations.len(),
                         other_users: other_users
                             .iter()
@@ -737,7 +1071,14 @@ pub async fn view_messages(
                 )
                 .await;
 
-                let seen_messages = RetrieveConversations::retrieve_seen(&messages, data).await;
+                let urls = messages
+                    .iter()
+                    .filter_map(|message| message.message_body.as_deref())
+                    .flat_map(LinkPreview::extract_urls)
+                    .unique()
+                    .collect_vec();
+                let link_previews =
+                    crate::link_preview::LinkPreviewService::cached(&urls, data).await;
 
                 Ok(messages
                     .iter()
@@ -747,12 +1088,18 @@ pub async fn view_messages(
                         message_body: message.message_body.clone(),
                         created_at: message.message_created_at.to_string(),
                         message_sender_id: message.message_sender_id,
+                        message_system: message.message_system,
                         message_image: message.message_image.clone(),
-                        seen_status: seen_messages
-                            .clone()
-                            .into_iter()
-                            .filter(|seen_messages| {
-                                seen_messages.message_id.unwrap() == message.message_id
+                        message_audio: message.message_audio.clone(),
+                        message_audio_duration: message.message_audio_duration,
+                        link_previews: message
+                            .message_body
+                            .as_deref()
+                            .map(LinkPreview::extract_urls)
+                            .unwrap_or_default()
+                            .iter()
+                            .filter_map(|url| {
+                                link_previews.iter().find(|preview| &preview.url == url).cloned()
                             })
                             .collect(),
                         first_name: message.first_name.clone(),
@@ -767,13 +1114,13 @@ pub async fn view_messages(
 
 #[server(AssociatedConversation, "/api", "Url")]
 pub async fn associated_conversation(cx: Scope, other_user: i32) -> Result<i32, ServerFnError> {
-    use actix_identity::Identity;
+    use crate::auth::identity::CurrentUser;
     use sea_orm::*;
 
     leptos_actix::extract(
         cx,
         move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
-              user: Option<Identity>| {
+              user: Option<CurrentUser>| {
             async move {
                 let data = &data.lock().await.connection;
 
@@ -828,9 +1175,9 @@ pub async fn handle_message_input(
     conversation_id: i32,
     body: Option<String>,
     image: Option<Vec<u8>>,
-) -> Result<Option<String>, ServerFnError> {
+) -> Result<SentMessage, ServerFnError> {
     use crate::entities::message;
-    use actix_identity::Identity;
+    use crate::auth::identity::CurrentUser;
     use image::io::Reader as ImageReader;
 
     if body.is_none() && image.is_none() {
@@ -842,7 +1189,9 @@ pub async fn handle_message_input(
     leptos_actix::extract(
         cx,
         move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
-              user: Option<Identity>| {
+              link_previews: actix_web::web::Data<crate::link_preview::LinkPreviewService>,
+              icon_server: actix_web::web::Data<actix::Addr<crate::web_socket::server::IconWs>>,
+              user: Option<CurrentUser>| {
             let body = body.clone();
             let image = image.clone();
             async move {
@@ -852,6 +1201,10 @@ pub async fn handle_message_input(
                     Err(e) => return Err(e),
                 };
 
+                super::contacts::ensure_can_send(data, user.id, conversation_id)
+                    .await
+                    .map_err(super::contacts::ContactError::into_server_fn_error)?;
+
                 let mut image_location: Option<String> = Default::default();
 
                 if let Some(image_vec) = image {
@@ -881,25 +1234,142 @@ pub async fn handle_message_input(
                     image_location = Some("/upload/".to_string() + &current_time + ".png")
                 };
 
-                AppendDatabase::insert_messages(
+                let urls = body
+                    .as_deref()
+                    .map(LinkPreview::extract_urls)
+                    .unwrap_or_default();
+
+                let message_id = AppendDatabase::insert_messages(
                     data,
                     message::server::ActiveModel {
-                        message_body: sea_orm::ActiveValue::Set(body),
+                        message_body: sea_orm::ActiveValue::Set(body.clone()),
                         message_sender_id: sea_orm::ActiveValue::Set(user.id),
                         message_image: sea_orm::ActiveValue::Set(image_location.clone()),
                         message_conversation_id: sea_orm::ActiveValue::Set(conversation_id),
                         ..Default::default()
                     },
                 )
-                .await;
+                .await?;
+
+                super::delivery::announce(
+                    data,
+                    &icon_server,
+                    &user,
+                    conversation_id,
+                    message_id,
+                    body.as_deref(),
+                )
+                .await?;
+
+                // Warm the preview cache without holding up the message
+                if !urls.is_empty() {
+                    let data = data.clone();
+                    actix_web::rt::spawn(async move {
+                        link_previews.resolve(&urls, &data).await;
+                    });
+                }
+
+                Ok(SentMessage {
+                    message_id,
+                    image: image_location,
+                })
+            }
+        },
+    )
+    .await?
+}
+
+#[server(HandleVoiceNote, "/api", "Url")]
+pub async fn handle_voice_note(
+    cx: Scope,
+    conversation_id: i32,
+    audio: Vec<u8>,
+) -> Result<VoiceNoteUpload, ServerFnError> {
+    use crate::{entities::message, media::audio};
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              icon_server: actix_web::web::Data<actix::Addr<crate::web_socket::server::IconWs>>,
+              user: Option<CurrentUser>| {
+            let audio = audio.clone();
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                super::contacts::ensure_can_send(data, user.id, conversation_id)
+                    .await
+                    .map_err(super::contacts::ContactError::into_server_fn_error)?;
+
+                // Only parsed once the sender is known to be allowed to post here
+                let voice_note =
+                    audio::validate(&audio).map_err(|e| ServerFnError::Args(e.to_string()))?;
+
+                if tokio::fs::metadata("./upload").await.is_err() {
+                    tokio::fs::create_dir_all("./upload").await?;
+                };
+
+                let file_name = format!(
+                    "{}.{}",
+                    std::time::UNIX_EPOCH.elapsed()?.as_millis(),
+                    voice_note.kind.extension()
+                );
+                tokio::fs::write(format!("./upload/{file_name}"), audio).await?;
+                let path = format!("/upload/{file_name}");
+
+                let message_id = AppendDatabase::insert_messages(
+                    data,
+                    message::server::ActiveModel {
+                        message_sender_id: sea_orm::ActiveValue::Set(user.id),
+                        message_audio: sea_orm::ActiveValue::Set(Some(path.clone())),
+                        message_audio_duration: sea_orm::ActiveValue::Set(Some(
+                            voice_note.duration_ms,
+                        )),
+                        message_conversation_id: sea_orm::ActiveValue::Set(conversation_id),
+                        ..Default::default()
+                    },
+                )
+                .await?;
+
+                super::delivery::announce(data, &icon_server, &user, conversation_id, message_id, None)
+                    .await?;
 
-                Ok(image_location)
+                Ok(VoiceNoteUpload {
+                    message_id,
+                    path,
+                    duration_ms: voice_note.duration_ms,
+                })
             }
         },
     )
     .await?
 }
 
+/// Previews for the links of a message the caller can see.
+#[server(GetLinkPreviews, "/api", "Url")]
+pub async fn get_link_previews(
+    cx: Scope,
+    message_id: i32,
+) -> Result<Vec<LinkPreview>, ServerFnError> {
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              link_previews: actix_web::web::Data<crate::link_preview::LinkPreviewService>,
+              user: Option<CurrentUser>| async move {
+            let user = UserLogin::evaluate_user(user)?;
+            let data = data.lock().await.connection.clone();
+            link_previews
+                .for_message(user.id, message_id, &data)
+                .await
+                .map_err(|e| e.into_server_fn_error())
+        },
+    )
+    .await?
+}
+
 #[server(FindImage, "/api", "Url")]
 pub async fn find_image(cx: Scope, image_path: String) -> Result<ImageAvailability, ServerFnError> {
     Ok(
@@ -916,14 +1386,19 @@ pub async fn find_image(cx: Scope, image_path: String) -> Result<ImageAvailabili
     )
 }
 
+/// Marks the conversation read up to its newest message and, unless the reader turned read
+/// receipts off, tells the other members how far they got.
 #[server(HandleSeen, "/api", "Url")]
 pub async fn handle_seen(cx: Scope, conversation_id: i32) -> Result<(), ServerFnError> {
-    use actix_identity::Identity;
+    use crate::entities::prelude::*;
+    use crate::auth::identity::CurrentUser;
+    use sea_orm::*;
 
     leptos_actix::extract(
         cx,
         move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
-              user: Option<Identity>| {
+              icon_server: actix_web::web::Data<actix::Addr<crate::web_socket::server::IconWs>>,
+              user: Option<CurrentUser>| {
             async move {
                 let data = &data.lock().await.connection;
                 let user = match UserLogin::evaluate_user(user) {
@@ -931,14 +1406,43 @@ pub async fn handle_seen(cx: Scope, conversation_id: i32) -> Result<(), ServerFn
                     Err(e) => return Err(e),
                 };
 
-                let messages: Vec<i32> =
-                    RetrieveConversations::retrieve_messages(&vec![conversation_id], data)
-                        .await
-                        .iter()
-                        .map(|messages| messages.message_id)
-                        .collect();
-                log!("MESSAGES {messages:?}");
-                AppendDatabase::insert_seen(data, messages, user.id).await;
+                AppendDatabase::read_mentions(data, conversation_id, user.id).await?;
+                let Some(read_up_to) =
+                    AppendDatabase::mark_read(data, conversation_id, user.id, None).await?
+                else {
+                    return Ok(());
+                };
+                super::unread::notify_member(data, &icon_server, conversation_id, user.id).await?;
+
+                let sends_receipts = Users::find_by_id(user.id)
+                    .one(data)
+                    .await?
+                    .map_or(false, |model| model.send_read_receipts != 0);
+                if !sends_receipts {
+                    return Ok(());
+                }
+
+                let members: Vec<i32> = UserConversation::find()
+                    .filter(user_conversation::server::Column::ConversationId.eq(conversation_id))
+                    .filter(user_conversation::server::Column::UserIds.ne(user.id))
+                    .all(data)
+                    .await?
+                    .into_iter()
+                    .map(|membership| membership.user_ids)
+                    .collect();
+
+                if !members.is_empty() {
+                    icon_server.do_send(crate::web_socket::server::NotifyUsers {
+                        user_ids: members,
+                        message: serde_json::to_string(&ReadReceipt {
+                            receipt_conversation_id: conversation_id,
+                            reader_id: user.id,
+                            reader_first_name: user.first_name,
+                            reader_last_name: user.last_name,
+                            read_up_to,
+                        })?,
+                    });
+                }
                 Ok(())
             }
         },
@@ -946,14 +1450,518 @@ pub async fn handle_seen(cx: Scope, conversation_id: i32) -> Result<(), ServerFn
     .await?
 }
 
-#[server(DeleteConversation, "/api", "Url")]
-pub async fn delete_conversations(cx: Scope, conversation_id: i32) -> Result<(), ServerFnError> {
-    use actix_identity::Identity;
-
-    leptos_actix::extract(
+/// Read watermarks of the members of a conversation the caller belongs to.
+#[server(GetReadReceipts, "/api", "Url")]
+pub async fn get_read_receipts(
+    cx: Scope,
+    conversation_id: i32,
+) -> Result<Vec<ReadReceipt>, ServerFnError> {
+    use crate::entities::prelude::*;
+    use crate::auth::identity::CurrentUser;
+    use sea_orm::*;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              user: Option<CurrentUser>| {
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                let is_member = UserConversation::find()
+                    .filter(user_conversation::server::Column::ConversationId.eq(conversation_id))
+                    .filter(user_conversation::server::Column::UserIds.eq(user.id))
+                    .one(data)
+                    .await?
+                    .is_some();
+                if !is_member {
+                    return Err(ServerFnError::Request(String::from(
+                        "Not a member of this conversation",
+                    )));
+                }
+
+                Ok(RetrieveConversations::retrieve_receipts(&vec![conversation_id], data).await)
+            }
+        },
+    )
+    .await?
+}
+
+/// Turning read receipts off hides the caller's watermark from everyone else, it is still
+/// kept for their own unread state.
+#[server(SetReadReceipts, "/api", "Url")]
+pub async fn set_read_receipts(cx: Scope, enabled: bool) -> Result<(), ServerFnError> {
+    use crate::entities::{prelude::*, users};
+    use crate::auth::identity::CurrentUser;
+    use sea_orm::*;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              user: Option<CurrentUser>| {
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                Users::update_many()
+                    .col_expr(
+                        users::server::Column::SendReadReceipts,
+                        sea_query::Expr::value(enabled as i8),
+                    )
+                    .filter(users::server::Column::Id.eq(user.id))
+                    .exec(data)
+                    .await?;
+                Ok(())
+            }
+        },
+    )
+    .await?
+}
+
+#[server(GetNotificationSettings, "/api", "Url")]
+pub async fn get_notification_settings(cx: Scope) -> Result<NotificationSettings, ServerFnError> {
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              user: Option<CurrentUser>| {
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                Ok(super::notifications::settings(data, user.id).await?)
+            }
+        },
+    )
+    .await?
+}
+
+#[server(SetNotificationSettings, "/api", "Url")]
+pub async fn set_notification_settings(
+    cx: Scope,
+    settings: NotificationSettings,
+) -> Result<(), ServerFnError> {
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              user: Option<CurrentUser>| {
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                Ok(super::notifications::set_settings(
+                    data,
+                    chrono::Utc::now(),
+                    user.id,
+                    settings,
+                )
+                .await?)
+            }
+        },
+    )
+    .await?
+}
+
+#[server(SetConversationFlag, "/api", "Url")]
+pub async fn set_conversation_flag(
+    cx: Scope,
+    conversation_id: i32,
+    flag: ConversationFlag,
+    enabled: bool,
+) -> Result<(), ServerFnError> {
+    use crate::entities::prelude::*;
+    use crate::auth::identity::CurrentUser;
+    use sea_orm::*;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              icon_server: actix_web::web::Data<actix::Addr<crate::web_socket::server::IconWs>>,
+              user: Option<CurrentUser>| {
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                let column = match flag {
+                    ConversationFlag::Pinned => user_conversation::server::Column::Pinned,
+                    ConversationFlag::Archived => user_conversation::server::Column::Archived,
+                    ConversationFlag::Muted => user_conversation::server::Column::Muted,
+                };
+                let updated = UserConversation::update_many()
+                    .col_expr(column, sea_query::Expr::value(enabled as i8))
+                    .filter(user_conversation::server::Column::UserIds.eq(user.id))
+                    .filter(user_conversation::server::Column::ConversationId.eq(conversation_id))
+                    .exec(data)
+                    .await?;
+                if updated.rows_affected == 0
+                    && UserConversation::find_by_id((user.id, conversation_id))
+                        .one(data)
+                        .await?
+                        .is_none()
+                {
+                    return Err(ServerFnError::Request(String::from(
+                        "Not a member of this conversation",
+                    )));
+                }
+
+                // Muting moves the conversation in or out of the unread total
+                if flag == ConversationFlag::Muted {
+                    super::unread::notify_member(data, &icon_server, conversation_id, user.id)
+                        .await?;
+                }
+                Ok(())
+            }
+        },
+    )
+    .await?
+}
+
+/// Pins or unpins a message and pushes the change to every member.
+#[server(PinMessage, "/api", "Url")]
+pub async fn pin_message(cx: Scope, message_id: i32, pinned: bool) -> Result<(), ServerFnError> {
+    use super::pins::{self, PinError};
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              icon_server: actix_web::web::Data<actix::Addr<crate::web_socket::server::IconWs>>,
+              user: Option<CurrentUser>| {
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                let (update, members) = match pinned {
+                    true => pins::pin(data, chrono::Utc::now(), user.id, message_id).await,
+                    false => pins::unpin(data, user.id, message_id).await,
+                }
+                .map_err(PinError::into_server_fn_error)?;
+
+                icon_server.do_send(crate::web_socket::server::NotifyUsers {
+                    user_ids: members,
+                    message: serde_json::to_string(&update)?,
+                });
+                Ok(())
+            }
+        },
+    )
+    .await?
+}
+
+#[server(GetPinnedMessages, "/api", "Url")]
+pub async fn get_pinned_messages(
+    cx: Scope,
+    conversation_id: i32,
+) -> Result<Vec<PinnedMessage>, ServerFnError> {
+    use super::pins::{self, PinError};
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              user: Option<CurrentUser>| {
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                pins::list(data, user.id, conversation_id)
+                    .await
+                    .map_err(PinError::into_server_fn_error)
+            }
+        },
+    )
+    .await?
+}
+
+#[server(ScheduleMessage, "/api", "Url")]
+pub async fn schedule_message(
+    cx: Scope,
+    conversation_id: i32,
+    body: String,
+    deliver_at: i64,
+) -> Result<ScheduledMessage, ServerFnError> {
+    use super::scheduler::{self, Clock, ScheduleError, SystemClock};
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              user: Option<CurrentUser>| {
+            let body = body.clone();
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                scheduler::schedule(
+                    data,
+                    SystemClock.now(),
+                    user.id,
+                    conversation_id,
+                    &body,
+                    deliver_at,
+                )
+                .await
+                .map_err(ScheduleError::into_server_fn_error)
+            }
+        },
+    )
+    .await?
+}
+
+#[server(GetScheduledMessages, "/api", "Url")]
+pub async fn get_scheduled_messages(
+    cx: Scope,
+    conversation_id: i32,
+) -> Result<Vec<ScheduledMessage>, ServerFnError> {
+    use super::scheduler::{self, ScheduleError};
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              user: Option<CurrentUser>| {
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                scheduler::list(data, user.id, conversation_id)
+                    .await
+                    .map_err(ScheduleError::into_server_fn_error)
+            }
+        },
+    )
+    .await?
+}
+
+#[server(EditScheduledMessage, "/api", "Url")]
+pub async fn edit_scheduled_message(
+    cx: Scope,
+    id: i32,
+    body: String,
+    deliver_at: i64,
+) -> Result<ScheduledMessage, ServerFnError> {
+    use super::scheduler::{self, Clock, ScheduleError, SystemClock};
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              user: Option<CurrentUser>| {
+            let body = body.clone();
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                scheduler::edit(data, SystemClock.now(), user.id, id, &body, deliver_at)
+                    .await
+                    .map_err(ScheduleError::into_server_fn_error)
+            }
+        },
+    )
+    .await?
+}
+
+#[server(CancelScheduledMessage, "/api", "Url")]
+pub async fn cancel_scheduled_message(cx: Scope, id: i32) -> Result<(), ServerFnError> {
+    use super::scheduler::{self, ScheduleError};
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              user: Option<CurrentUser>| {
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                scheduler::cancel(data, user.id, id)
+                    .await
+                    .map_err(ScheduleError::into_server_fn_error)
+            }
+        },
+    )
+    .await?
+}
+
+#[server(SetRetention, "/api", "Url")]
+pub async fn set_retention(
+    cx: Scope,
+    conversation_id: i32,
+    retention: Option<Retention>,
+) -> Result<(), ServerFnError> {
+    use super::retention::{self, RetentionError};
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              server: actix_web::web::Data<actix::Addr<crate::web_socket::server::ChatServer>>,
+              user: Option<CurrentUser>| {
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                if let Some(notice) = retention::set(data, &user, conversation_id, retention)
+                    .await
+                    .map_err(RetentionError::into_server_fn_error)?
+                {
+                    super::delivery::broadcast(&server, &notice);
+                }
+                Ok(())
+            }
+        },
+    )
+    .await?
+}
+
+#[server(GetUnreadCounts, "/api", "Url")]
+pub async fn get_unread_counts(cx: Scope) -> Result<UnreadCounts, ServerFnError> {
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              user: Option<CurrentUser>| {
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                Ok(super::unread::for_user(data, user.id).await?)
+            }
+        },
+    )
+    .await?
+}
+
+#[server(UnreadMentions, "/api", "Url")]
+pub async fn unread_mentions(cx: Scope) -> Result<Vec<MentionEvent>, ServerFnError> {
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              user: Option<CurrentUser>| {
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                Ok(RetrieveConversations::retrieve_unread_mentions(&user, data).await)
+            }
+        },
+    )
+    .await?
+}
+
+#[server(SearchMessages, "/api", "Url")]
+pub async fn search_messages(cx: Scope, query: SearchQuery) -> Result<SearchResults, ServerFnError> {
+    use super::search::{self, SearchError};
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              user: Option<CurrentUser>| {
+            let query = query.clone();
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                search::search(&user, &query, data).await.map_err(|e| match e {
+                    SearchError::Database(e) => ServerFnError::ServerError(e.to_string()),
+                    e => ServerFnError::Args(e.to_string()),
+                })
+            }
+        },
+    )
+    .await?
+}
+
+#[server(GetContacts, "/api", "Url")]
+pub async fn get_contacts(cx: Scope) -> Result<Vec<ContactModel>, ServerFnError> {
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              user: Option<CurrentUser>| {
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                Ok(super::contacts::list(data, user.id).await?)
+            }
+        },
+    )
+    .await?
+}
+
+#[server(UpdateContact, "/api", "Url")]
+pub async fn update_contact(
+    cx: Scope,
+    user_id: i32,
+    action: ContactAction,
+) -> Result<(), ServerFnError> {
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              user: Option<CurrentUser>| {
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                super::contacts::apply(data, chrono::Utc::now(), user.id, user_id, action)
+                    .await
+                    .map_err(super::contacts::ContactError::into_server_fn_error)
+            }
+        },
+    )
+    .await?
+}
+
+/// The subset of `user_ids` that is online, users who blocked the caller always read as
+/// offline.
+#[server(GetPresence, "/api", "Url")]
+pub async fn get_presence(cx: Scope, user_ids: Vec<i32>) -> Result<Vec<i32>, ServerFnError> {
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              icon_server: actix_web::web::Data<actix::Addr<crate::web_socket::server::IconWs>>,
+              user: Option<CurrentUser>| {
+            let user_ids = user_ids.clone();
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                let hidden = super::contacts::blocked_by(data, user.id).await?;
+                let user_ids = user_ids
+                    .into_iter()
+                    .filter(|id| !hidden.contains(id))
+                    .collect();
+
+                Ok(icon_server
+                    .send(crate::web_socket::server::OnlineUsers { user_ids })
+                    .await?)
+            }
+        },
+    )
+    .await?
+}
+
+#[server(DeleteConversation, "/api", "Url")]
+pub async fn delete_conversations(cx: Scope, conversation_id: i32) -> Result<(), ServerFnError> {
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
         cx,
         move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
-              user: Option<Identity>| {
+              user: Option<CurrentUser>| {
             async move {
                 let data = &data.lock().await.connection;
                 let user = match UserLogin::evaluate_user(user) {
@@ -971,11 +1979,11 @@ pub async fn delete_conversations(cx: Scope, conversation_id: i32) -> Result<(),
 
 #[server(GetUser, "/api", "Url")]
 pub async fn get_user(cx: Scope) -> Result<UserModel, ServerFnError> {
-    use actix_identity::Identity;
+    use crate::auth::identity::CurrentUser;
     leptos_actix::extract(
         cx,
         move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
-              user: Option<Identity>| {
+              user: Option<CurrentUser>| {
             async move {
                 let data = &data.lock().await.connection;
                 let user = match UserLogin::evaluate_user(user) {
@@ -995,14 +2003,14 @@ pub async fn upload_user_info(
     image: Option<Vec<u8>>,
     first_name: Option<String>,
     last_name: Option<String>,
-) -> Result<(), ServerFnError> {
-    use actix_identity::Identity;
+) -> Result<Option<String>, ServerFnError> {
+    use crate::auth::identity::CurrentUser;
     use image::io::Reader as ImageReader;
     use validator::Validate;
     leptos_actix::extract(
         cx,
         move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
-              user: Option<Identity>| {
+              user: Option<CurrentUser>| {
             let image = image.clone();
             let first_name = first_name.clone();
             let last_name = last_name.clone();
@@ -1076,68 +2084,351 @@ pub async fn upload_user_info(
 
                     AppendDatabase::modify(
                         user,
-                        Some(image_path),
+                        Some(image_path.clone()),
                         data,
                         first_name.clone(),
                         last_name.clone(),
                     )
                     .await;
+
+                    Ok(Some(format!("/{image_path}")))
                 } else {
                     AppendDatabase::modify(user, None, data, first_name.clone(), last_name.clone())
                         .await;
-                }
 
-                Ok(())
+                    Ok(None)
+                }
             }
         },
     )
     .await?
 }
 
-#[server(GetIcon, "/api", "Url")]
-pub async fn get_icon(cx: Scope, id: i32) -> Result<Option<Vec<u8>>, ServerFnError> {
-    use tokio::io::AsyncReadExt;
+#[server(ChangePassword, "/api", "Url")]
+pub async fn change_password(
+    cx: Scope,
+    current_password: String,
+    new_password: crate::app::PasswordSchema,
+) -> Result<(), ServerFnError> {
+    use super::account::AccountError;
+    use crate::auth::identity::CurrentUser;
+
     leptos_actix::extract(
         cx,
-        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>| {
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              request: actix_web::HttpRequest,
+              session: actix_session::Session,
+              limiter: actix_web::web::Data<crate::server_function::rate_limit::RateLimiter>,
+              server: actix_web::web::Data<actix::Addr<crate::web_socket::server::ChatServer>>,
+              icon_server: actix_web::web::Data<actix::Addr<crate::web_socket::server::IconWs>>,
+              user: Option<CurrentUser>| {
+            let current_password = current_password.clone();
+            let new_password = new_password.clone();
             async move {
+                use super::rate_limit::{client_ip, Action, Subject};
+
+                let user = match UserLogin::evaluate_user(user) {
+                    Ok(val) => val,
+                    Err(e) => return Err(e),
+                };
+
+                let ip = client_ip(&request).unwrap_or_default();
+                let subjects = [Subject::Ip(&ip), Subject::Account(&user.email)];
+                let now = chrono::Utc::now();
+                if let Err(limit) = limiter
+                    .attempt(Action::CurrentPassword, &subjects, now)
+                    .await
+                {
+                    return Err(ServerFnError::Request(limit.message()));
+                }
+
                 let data = &data.lock().await.connection;
-                let image = RetrieveConversations::retrieve_images(id, data).await;
-                if let Some(image) = image {
-                         let path = std::env::current_dir()
-                             .unwrap_or_default()
-                             .join(image);
-
-                         if let Ok(mut file) = tokio::fs::File::open(path).await {
-                             let mut buffer = Vec::new();
-                             file.read_to_end(&mut buffer).await.unwrap();
-                             Some(buffer)
-                        } else {
-                             None
-                        }
-                } else {
-                    None
+                match super::account::change_password(
+                    data,
+                    user.id,
+                    &current_password,
+                    &new_password,
+                )
+                .await
+                {
+                    Ok((user, revoked)) => {
+                        limiter.succeeded(Action::CurrentPassword, &subjects).await;
+                        crate::auth::sessions::close_sockets(&server, &icon_server, revoked);
+                        // Every earlier identity is invalid now, including this one
+                        crate::auth::log_in(data, &request, &session, &user, now).await?;
+                        Ok(())
+                    }
+                    Err(AccountError::IncorrectPassword) => {
+                        limiter
+                            .failed(Action::CurrentPassword, &subjects, now)
+                            .await;
+                        Err(AccountError::IncorrectPassword.into_server_fn_error())
+                    }
+                    Err(e) => Err(e.into_server_fn_error()),
                 }
             }
         },
     )
-    .await
+    .await?
 }
 
-#[server(GetImage, "/api", "Url")]
-pub async fn get_image(cx: Scope, path: String) -> Result<Option<Vec<u8>>, ServerFnError> {
-    use tokio::io::AsyncReadExt;
-    let mut path = path;
-    path.remove(0);
-    let path = std::env::current_dir().unwrap().join(path);
-
-    let mut buffer = Vec::new();
-    if let Ok(mut file) = tokio::fs::File::open(path).await {
-        file.read_to_end(&mut buffer).await?;
-        Ok(Some(buffer))
-    } else {
-        Ok(None)
-    }
+/// Emails a verification code to `new_email`, the address changes once
+/// `confirm_email_change` gets the code.
+#[server(RequestEmailChange, "/api", "Url")]
+pub async fn request_email_change(
+    cx: Scope,
+    current_password: String,
+    new_email: EmailSchema,
+) -> Result<(), ServerFnError> {
+    use super::account::AccountError;
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              email_client: actix_web::web::Data<crate::emailing::email_client::EmailClient>,
+              request: actix_web::HttpRequest,
+              limiter: actix_web::web::Data<crate::server_function::rate_limit::RateLimiter>,
+              user: Option<CurrentUser>| {
+            let current_password = current_password.clone();
+            let new_email = new_email.clone();
+            async move {
+                use super::rate_limit::{client_ip, Action, Subject};
+
+                let user = match UserLogin::evaluate_user(user) {
+                    Ok(val) => val,
+                    Err(e) => return Err(e),
+                };
+
+                let ip = client_ip(&request).unwrap_or_default();
+                let subjects = [Subject::Ip(&ip), Subject::Account(&user.email)];
+                let now = chrono::Utc::now();
+                if let Err(limit) = limiter
+                    .attempt(Action::CurrentPassword, &subjects, now)
+                    .await
+                {
+                    return Err(ServerFnError::Request(limit.message()));
+                }
+
+                let requested = super::account::request_email_change(
+                    &data.lock().await.connection,
+                    now,
+                    user.id,
+                    &current_password,
+                    &new_email,
+                )
+                .await;
+                let (first_name, code) = match requested {
+                    Ok(requested) => {
+                        limiter.succeeded(Action::CurrentPassword, &subjects).await;
+                        requested
+                    }
+                    Err(AccountError::IncorrectPassword) => {
+                        limiter
+                            .failed(Action::CurrentPassword, &subjects, now)
+                            .await;
+                        return Err(AccountError::IncorrectPassword.into_server_fn_error());
+                    }
+                    Err(e) => return Err(e.into_server_fn_error()),
+                };
+
+                email_client
+                    .send_verification(&new_email.entry, &first_name, &code)
+                    .await
+                    .map_err(|e| {
+                        ServerFnError::ServerError(format!(
+                            "Error at sending verification email: {e}"
+                        ))
+                    })
+            }
+        },
+    )
+    .await?
+}
+
+/// Switches to the pending email when `code` matches and returns the new address.
+#[server(ConfirmEmailChange, "/api", "Url")]
+pub async fn confirm_email_change(cx: Scope, code: String) -> Result<String, ServerFnError> {
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              user: Option<CurrentUser>| {
+            let code = code.clone();
+            async move {
+                let data = &data.lock().await.connection;
+                let user = match UserLogin::evaluate_user(user) {
+                    Ok(val) => val,
+                    Err(e) => return Err(e),
+                };
+
+                let user =
+                    super::account::confirm_email_change(data, chrono::Utc::now(), user.id, &code)
+                        .await
+                        .map_err(|e| e.into_server_fn_error())?;
+                Ok(user.email)
+            }
+        },
+    )
+    .await?
+}
+
+#[server(GetTwoFactorStatus, "/api", "Url")]
+pub async fn get_two_factor_status(cx: Scope) -> Result<TwoFactorStatus, ServerFnError> {
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              user: Option<CurrentUser>| async move {
+            let data = &data.lock().await.connection;
+            let user = UserLogin::evaluate_user(user)?;
+
+            Ok(super::two_factor::status(data, user.id).await?)
+        },
+    )
+    .await?
+}
+
+/// Starts a 2FA enrollment, the QR code goes to the authenticator app.
+#[server(BeginTwoFactor, "/api", "Url")]
+pub async fn begin_two_factor(cx: Scope) -> Result<TwoFactorEnrollment, ServerFnError> {
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              user: Option<CurrentUser>| async move {
+            let data = &data.lock().await.connection;
+            let user = UserLogin::evaluate_user(user)?;
+
+            super::two_factor::begin(data, user.id)
+                .await
+                .map_err(|e| e.into_server_fn_error())
+        },
+    )
+    .await?
+}
+
+/// Enables 2FA with the first code of the app, returns the recovery codes to write down.
+#[server(EnableTwoFactor, "/api", "Url")]
+pub async fn enable_two_factor(cx: Scope, code: String) -> Result<Vec<String>, ServerFnError> {
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              user: Option<CurrentUser>| {
+            let code = code.clone();
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                super::two_factor::enable(data, chrono::Utc::now(), user.id, &code)
+                    .await
+                    .map_err(|e| e.into_server_fn_error())
+            }
+        },
+    )
+    .await?
+}
+
+#[server(DisableTwoFactor, "/api", "Url")]
+pub async fn disable_two_factor(
+    cx: Scope,
+    current_password: String,
+    code: String,
+) -> Result<(), ServerFnError> {
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              user: Option<CurrentUser>| {
+            let current_password = current_password.clone();
+            let code = code.clone();
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                super::two_factor::disable(
+                    data,
+                    chrono::Utc::now(),
+                    user.id,
+                    &current_password,
+                    &code,
+                )
+                .await
+                .map_err(|e| e.into_server_fn_error())
+            }
+        },
+    )
+    .await?
+}
+
+#[server(RegenerateRecoveryCodes, "/api", "Url")]
+pub async fn regenerate_recovery_codes(
+    cx: Scope,
+    code: String,
+) -> Result<Vec<String>, ServerFnError> {
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              user: Option<CurrentUser>| {
+            let code = code.clone();
+            async move {
+                let data = &data.lock().await.connection;
+                let user = UserLogin::evaluate_user(user)?;
+
+                super::two_factor::regenerate_recovery_codes(
+                    data,
+                    chrono::Utc::now(),
+                    user.id,
+                    &code,
+                )
+                .await
+                .map_err(|e| e.into_server_fn_error())
+            }
+        },
+    )
+    .await?
+}
+
+#[server(SignMediaUrl, "/api", "Url")]
+pub async fn sign_media_url(cx: Scope, path: String) -> Result<String, ServerFnError> {
+    use crate::auth::identity::CurrentUser;
+
+    leptos_actix::extract(
+        cx,
+        move |data: actix_web::web::Data<tokio::sync::Mutex<crate::database::DbConnection>>,
+              user: Option<CurrentUser>| {
+            let path = path.clone();
+            async move {
+                let data = &data.lock().await.connection;
+                let user = match UserLogin::evaluate_user(user) {
+                    Ok(val) => val,
+                    Err(e) => return Err(e),
+                };
+
+                if let Some(file_name) = path.strip_prefix("/upload/") {
+                    crate::media::authorize_attachment(user.id, file_name, data)
+                        .await
+                        .map_err(|e| ServerFnError::ServerError(e.to_string()))?;
+                } else if !path.starts_with("/images/") {
+                    return Err(ServerFnError::Args(format!("Unknown media path {path}")));
+                }
+
+                Ok(crate::media::sign_url(
+                    &path,
+                    chrono::Duration::minutes(crate::media::SIGNED_URL_TTL_MINUTES),
+                ))
+            }
+        },
+    )
+    .await?
 }
 
 #[server(CreateGroupConversation, "/api", "Url")]
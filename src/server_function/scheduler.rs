@@ -0,0 +1,618 @@
+//! Scheduled messages. A pending message waits in `scheduled_messages` until `Scheduler` finds
+//! it due, then goes through the same insert and fan out as a message sent right away. Every
+//! time comparison goes through `Clock`, so the delivery can be driven with a fixed time.
+
+use std::time::Duration;
+
+use actix::{Actor, Addr, AsyncContext, Context, WrapFuture};
+use chrono::{DateTime, TimeZone, Utc};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, DbErr, EntityTrait,
+    QueryFilter, QueryOrder,
+};
+
+use super::{contacts::ContactError, AppendDatabase, ScheduledMessage, UserLogin};
+use crate::entities::{message, prelude::*, scheduled_messages};
+use crate::web_socket::server::{ChatServer, IconWs};
+
+pub trait Clock: Send + Sync + Unpin + 'static {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+#[derive(Debug)]
+pub enum ScheduleError {
+    EmptyBody,
+    InvalidTime,
+    InThePast,
+    UnknownMessage,
+    Contact(ContactError),
+    Database(DbErr),
+}
+
+impl std::fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScheduleError::EmptyBody => write!(f, "Scheduled messages need some text"),
+            ScheduleError::InvalidTime => write!(f, "Invalid delivery time"),
+            ScheduleError::InThePast => write!(f, "Delivery time must be in the future"),
+            ScheduleError::UnknownMessage => write!(f, "Scheduled message does not exist"),
+            ScheduleError::Contact(e) => write!(f, "{e}"),
+            ScheduleError::Database(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ScheduleError {}
+
+impl ScheduleError {
+    pub fn into_server_fn_error(self) -> leptos::ServerFnError {
+        match self {
+            ScheduleError::Contact(e) => e.into_server_fn_error(),
+            ScheduleError::Database(e) => leptos::ServerFnError::ServerError(e.to_string()),
+            e => leptos::ServerFnError::Request(e.to_string()),
+        }
+    }
+}
+
+impl From<DbErr> for ScheduleError {
+    fn from(value: DbErr) -> Self {
+        ScheduleError::Database(value)
+    }
+}
+
+impl From<ContactError> for ScheduleError {
+    fn from(value: ContactError) -> Self {
+        match value {
+            ContactError::Database(e) => ScheduleError::Database(e),
+            e => ScheduleError::Contact(e),
+        }
+    }
+}
+
+impl From<scheduled_messages::server::Model> for ScheduledMessage {
+    fn from(value: scheduled_messages::server::Model) -> Self {
+        Self {
+            id: value.id,
+            conversation_id: value.conversation_id,
+            body: value.body,
+            deliver_at: value.deliver_at.timestamp_millis(),
+        }
+    }
+}
+
+/// Trims the body and turns `deliver_at`, in milliseconds since the epoch, into a time after
+/// `now`.
+pub fn validate(
+    body: &str,
+    deliver_at: i64,
+    now: DateTime<Utc>,
+) -> Result<(String, DateTime<Utc>), ScheduleError> {
+    let body = body.trim();
+    if body.is_empty() {
+        return Err(ScheduleError::EmptyBody);
+    }
+
+    let deliver_at = Utc
+        .timestamp_millis_opt(deliver_at)
+        .single()
+        .ok_or(ScheduleError::InvalidTime)?;
+    if deliver_at <= now {
+        return Err(ScheduleError::InThePast);
+    }
+
+    Ok((body.to_string(), deliver_at))
+}
+
+/// Rows of other users are reported as unknown, their existence is nobody else's business.
+async fn owned(
+    data: &DatabaseConnection,
+    user_id: i32,
+    id: i32,
+) -> Result<scheduled_messages::server::Model, ScheduleError> {
+    ScheduledMessages::find_by_id(id)
+        .one(data)
+        .await?
+        .filter(|scheduled| scheduled.sender_id == user_id)
+        .ok_or(ScheduleError::UnknownMessage)
+}
+
+pub async fn schedule(
+    data: &DatabaseConnection,
+    now: DateTime<Utc>,
+    user_id: i32,
+    conversation_id: i32,
+    body: &str,
+    deliver_at: i64,
+) -> Result<ScheduledMessage, ScheduleError> {
+    let (body, deliver_at) = validate(body, deliver_at, now)?;
+    super::contacts::ensure_can_send(data, user_id, conversation_id).await?;
+
+    let scheduled = scheduled_messages::server::ActiveModel {
+        conversation_id: ActiveValue::Set(conversation_id),
+        sender_id: ActiveValue::Set(user_id),
+        body: ActiveValue::Set(body),
+        deliver_at: ActiveValue::Set(deliver_at),
+        created_at: ActiveValue::Set(now),
+        ..Default::default()
+    }
+    .insert(data)
+    .await?;
+    Ok(scheduled.into())
+}
+
+/// The caller's pending messages in the conversation, next one first.
+pub async fn list(
+    data: &DatabaseConnection,
+    user_id: i32,
+    conversation_id: i32,
+) -> Result<Vec<ScheduledMessage>, ScheduleError> {
+    Ok(ScheduledMessages::find()
+        .filter(scheduled_messages::server::Column::SenderId.eq(user_id))
+        .filter(scheduled_messages::server::Column::ConversationId.eq(conversation_id))
+        .order_by_asc(scheduled_messages::server::Column::DeliverAt)
+        .order_by_asc(scheduled_messages::server::Column::Id)
+        .all(data)
+        .await?
+        .into_iter()
+        .map(Into::into)
+        .collect())
+}
+
+pub async fn edit(
+    data: &DatabaseConnection,
+    now: DateTime<Utc>,
+    user_id: i32,
+    id: i32,
+    body: &str,
+    deliver_at: i64,
+) -> Result<ScheduledMessage, ScheduleError> {
+    let (body, deliver_at) = validate(body, deliver_at, now)?;
+    let mut scheduled: scheduled_messages::server::ActiveModel =
+        owned(data, user_id, id).await?.into();
+
+    scheduled.body = ActiveValue::Set(body);
+    scheduled.deliver_at = ActiveValue::Set(deliver_at);
+    Ok(scheduled.update(data).await?.into())
+}
+
+pub async fn cancel(data: &DatabaseConnection, user_id: i32, id: i32) -> Result<(), ScheduleError> {
+    owned(data, user_id, id).await?;
+    ScheduledMessages::delete_by_id(id).exec(data).await?;
+    Ok(())
+}
+
+/// Sends every message due at `now`, returns how many went out. A row is deleted before its
+/// message is inserted, so overlapping runs never send the same one twice. Messages are stamped
+/// with `now`, those whose sender can no longer post in the conversation are dropped.
+pub async fn deliver_due(
+    data: &DatabaseConnection,
+    now: DateTime<Utc>,
+    chat_server: &Addr<ChatServer>,
+    icon_server: &Addr<IconWs>,
+) -> Result<usize, DbErr> {
+    let due = ScheduledMessages::find()
+        .filter(scheduled_messages::server::Column::DeliverAt.lte(now))
+        .order_by_asc(scheduled_messages::server::Column::DeliverAt)
+        .order_by_asc(scheduled_messages::server::Column::Id)
+        .all(data)
+        .await?;
+
+    let mut delivered = 0;
+    for scheduled in due {
+        let claimed = ScheduledMessages::delete_by_id(scheduled.id).exec(data).await?;
+        if claimed.rows_affected == 0 {
+            continue;
+        }
+        if deliver(data, now, chat_server, icon_server, scheduled).await? {
+            delivered += 1;
+        }
+    }
+    Ok(delivered)
+}
+
+async fn deliver(
+    data: &DatabaseConnection,
+    now: DateTime<Utc>,
+    chat_server: &Addr<ChatServer>,
+    icon_server: &Addr<IconWs>,
+    scheduled: scheduled_messages::server::Model,
+) -> Result<bool, DbErr> {
+    let Some(sender) = Users::find_by_id(scheduled.sender_id).one(data).await? else {
+        return Ok(false);
+    };
+    match super::contacts::ensure_can_send(data, sender.id, scheduled.conversation_id).await {
+        Ok(()) => (),
+        Err(ContactError::Database(e)) => return Err(e),
+        Err(e) => {
+            println!("Dropping scheduled message {}: {e}", scheduled.id);
+            return Ok(false);
+        }
+    }
+
+    let message_id = AppendDatabase::insert_messages(
+        data,
+        message::server::ActiveModel {
+            message_body: ActiveValue::Set(Some(scheduled.body.clone())),
+            message_sender_id: ActiveValue::Set(sender.id),
+            message_conversation_id: ActiveValue::Set(scheduled.conversation_id),
+            message_created_at: ActiveValue::Set(now),
+            message_system: ActiveValue::Set(0),
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    let sender = UserLogin {
+        id: sender.id,
+        email: sender.email,
+        first_name: sender.first_name,
+        last_name: sender.last_name,
+    };
+    super::delivery::announce(
+        data,
+        icon_server,
+        &sender,
+        scheduled.conversation_id,
+        message_id,
+        Some(scheduled.body.as_str()),
+    )
+    .await?;
+
+    super::delivery::broadcast(
+        chat_server,
+        &crate::app::pages::conversation::Message {
+            message: Some(scheduled.body),
+            image: None,
+            audio: None,
+            audio_duration: None,
+            conversation_id: scheduled.conversation_id,
+            user_id: sender.id,
+            first_name: sender.first_name,
+            last_name: sender.last_name,
+            message_id,
+            system: false,
+        },
+    );
+    Ok(true)
+}
+
+/// Background actor that delivers due scheduled messages every `interval`.
+pub struct Scheduler<C: Clock> {
+    data: DatabaseConnection,
+    clock: C,
+    chat_server: Addr<ChatServer>,
+    icon_server: Addr<IconWs>,
+    interval: Duration,
+}
+
+impl<C: Clock> Scheduler<C> {
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(15);
+
+    pub fn new(
+        data: DatabaseConnection,
+        clock: C,
+        chat_server: Addr<ChatServer>,
+        icon_server: Addr<IconWs>,
+    ) -> Self {
+        Self {
+            data,
+            clock,
+            chat_server,
+            icon_server,
+            interval: Self::DEFAULT_INTERVAL,
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+}
+
+impl<C: Clock> Actor for Scheduler<C> {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.interval, |act, ctx| {
+            let data = act.data.clone();
+            let now = act.clock.now();
+            let chat_server = act.chat_server.clone();
+            let icon_server = act.icon_server.clone();
+
+            ctx.spawn(
+                async move {
+                    match deliver_due(&data, now, &chat_server, &icon_server).await {
+                        Ok(0) => (),
+                        Ok(delivered) => println!("Delivered {delivered} scheduled messages"),
+                        Err(e) => println!("Scheduled delivery failed: {e}"),
+                    }
+                }
+                .into_actor(act),
+            );
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+    use crate::entities::{conversation, user_conversation, users};
+    use sea_orm::IntoActiveModel;
+
+    const ALICE: i32 = 1;
+    const BOB: i32 = 2;
+    const GROUP: i32 = 1;
+
+    /// Stands still, so a test decides when a message is due.
+    struct FixedClock(DateTime<Utc>);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> DateTime<Utc> {
+            self.0
+        }
+    }
+
+    fn start() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 7, 1, 12, 0, 0).unwrap()
+    }
+
+    fn minutes(minutes: i64) -> DateTime<Utc> {
+        start() + chrono::Duration::minutes(minutes)
+    }
+
+    struct Fixture {
+        data: DatabaseConnection,
+        chat_server: Addr<ChatServer>,
+        icon_server: Addr<IconWs>,
+    }
+
+    impl Fixture {
+        /// Alice and Bob in a group, so sending does not depend on contacts.
+        async fn new() -> Self {
+            let data = testing::sqlite().await;
+            testing::create_table(&data, Users).await;
+            testing::create_table(&data, Conversation).await;
+            testing::create_table(&data, UserConversation).await;
+            testing::create_table(&data, Message).await;
+            testing::create_table(&data, MessageMentions).await;
+            testing::create_table(&data, ScheduledMessages).await;
+
+            for (id, first_name) in [(ALICE, "Alice"), (BOB, "Bob")] {
+                users::server::Model {
+                    id,
+                    first_name: first_name.to_string(),
+                    last_name: "Tester".to_string(),
+                    email: format!("{first_name}@example.com"),
+                    phone_number: 0,
+                    password: String::new(),
+                    image: None,
+                    send_read_receipts: 1,
+                    email_missed_messages: 0,
+                    email_digest: 0,
+                    notified_until: None,
+                    digest_sent_at: None,
+                    totp_secret: None,
+                    totp_enabled_at: None,
+                    totp_last_step: None,
+                    session_version: 0,
+                }
+                .into_active_model()
+                .insert(&data)
+                .await
+                .unwrap();
+            }
+            conversation::server::Model {
+                id: GROUP,
+                last_message_at: start(),
+                created_at: start(),
+                name: Some("Group".to_string()),
+                is_group: 1,
+                retention_seconds: None,
+            }
+            .into_active_model()
+            .insert(&data)
+            .await
+            .unwrap();
+            for user_ids in [ALICE, BOB] {
+                user_conversation::server::Model {
+                    user_ids,
+                    conversation_id: GROUP,
+                    last_read_message_id: None,
+                    pinned: 0,
+                    archived: 0,
+                    muted: 0,
+                }
+                .into_active_model()
+                .insert(&data)
+                .await
+                .unwrap();
+            }
+
+            Self {
+                data,
+                chat_server: ChatServer::new().start(),
+                icon_server: IconWs::new().start(),
+            }
+        }
+
+        async fn schedule(&self, body: &str, deliver_at: DateTime<Utc>) -> ScheduledMessage {
+            schedule(
+                &self.data,
+                start(),
+                ALICE,
+                GROUP,
+                body,
+                deliver_at.timestamp_millis(),
+            )
+            .await
+            .unwrap()
+        }
+
+        async fn deliver_due(&self, now: DateTime<Utc>) -> usize {
+            deliver_due(&self.data, now, &self.chat_server, &self.icon_server)
+                .await
+                .unwrap()
+        }
+
+        async fn messages(&self) -> Vec<message::server::Model> {
+            Message::find().all(&self.data).await.unwrap()
+        }
+
+        async fn pending(&self) -> Vec<ScheduledMessage> {
+            list(&self.data, ALICE, GROUP).await.unwrap()
+        }
+    }
+
+    #[test]
+    fn validate_rejects_empty_and_past_messages() {
+        let at = minutes(1).timestamp_millis();
+        assert_eq!(
+            validate("  hi  ", at, start()).unwrap(),
+            ("hi".to_string(), minutes(1))
+        );
+        assert!(matches!(
+            validate(" \n ", at, start()),
+            Err(ScheduleError::EmptyBody)
+        ));
+        assert!(matches!(
+            validate("hi", start().timestamp_millis(), start()),
+            Err(ScheduleError::InThePast)
+        ));
+        assert!(matches!(
+            validate("hi", i64::MAX, start()),
+            Err(ScheduleError::InvalidTime)
+        ));
+    }
+
+    #[actix_web::test]
+    async fn due_message_is_delivered_once() {
+        let fixture = Fixture::new().await;
+        fixture.schedule("see you at noon", minutes(5)).await;
+
+        assert_eq!(fixture.deliver_due(minutes(4)).await, 0);
+        assert!(fixture.messages().await.is_empty());
+        assert_eq!(fixture.pending().await.len(), 1);
+
+        assert_eq!(fixture.deliver_due(minutes(5)).await, 1);
+        assert_eq!(fixture.deliver_due(minutes(6)).await, 0);
+
+        let messages = fixture.messages().await;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].message_body.as_deref(), Some("see you at noon"));
+        assert_eq!(messages[0].message_sender_id, ALICE);
+        assert_eq!(messages[0].message_created_at, minutes(5));
+        assert!(fixture.pending().await.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn overlapping_runs_deliver_once() {
+        let fixture = Fixture::new().await;
+        fixture.schedule("only once", minutes(1)).await;
+
+        let (first, second) = futures_util::join!(
+            fixture.deliver_due(minutes(1)),
+            fixture.deliver_due(minutes(1))
+        );
+        assert_eq!(first + second, 1);
+        assert_eq!(fixture.messages().await.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn edit_moves_the_delivery() {
+        let fixture = Fixture::new().await;
+        let scheduled = fixture.schedule("soon", minutes(1)).await;
+
+        assert!(matches!(
+            edit(
+                &fixture.data,
+                start(),
+                BOB,
+                scheduled.id,
+                "mine now",
+                minutes(2).timestamp_millis()
+            )
+            .await,
+            Err(ScheduleError::UnknownMessage)
+        ));
+        let edited = edit(
+            &fixture.data,
+            start(),
+            ALICE,
+            scheduled.id,
+            "later",
+            minutes(30).timestamp_millis(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(edited.deliver_at, minutes(30).timestamp_millis());
+
+        assert_eq!(fixture.deliver_due(minutes(1)).await, 0);
+        assert_eq!(fixture.deliver_due(minutes(30)).await, 1);
+        let messages = fixture.messages().await;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].message_body.as_deref(), Some("later"));
+    }
+
+    #[actix_web::test]
+    async fn cancel_prevents_the_delivery() {
+        let fixture = Fixture::new().await;
+        let scheduled = fixture.schedule("never mind", minutes(1)).await;
+
+        assert!(matches!(
+            cancel(&fixture.data, BOB, scheduled.id).await,
+            Err(ScheduleError::UnknownMessage)
+        ));
+        cancel(&fixture.data, ALICE, scheduled.id).await.unwrap();
+
+        assert_eq!(fixture.deliver_due(minutes(60)).await, 0);
+        assert!(fixture.messages().await.is_empty());
+        assert!(fixture.pending().await.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn messages_of_former_members_are_dropped() {
+        let fixture = Fixture::new().await;
+        fixture.schedule("still here?", minutes(1)).await;
+        UserConversation::delete_many()
+            .filter(user_conversation::server::Column::UserIds.eq(ALICE))
+            .exec(&fixture.data)
+            .await
+            .unwrap();
+
+        assert_eq!(fixture.deliver_due(minutes(1)).await, 0);
+        assert!(fixture.messages().await.is_empty());
+        assert!(fixture.pending().await.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn scheduler_delivers_at_the_clock_time() {
+        let fixture = Fixture::new().await;
+        fixture.schedule("on time", minutes(5)).await;
+        fixture.schedule("too early", minutes(6)).await;
+
+        Scheduler::new(
+            fixture.data.clone(),
+            FixedClock(minutes(5)),
+            fixture.chat_server.clone(),
+            fixture.icon_server.clone(),
+        )
+        .with_interval(Duration::from_millis(10))
+        .start();
+        actix_web::rt::time::sleep(Duration::from_millis(100)).await;
+
+        let messages = fixture.messages().await;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].message_body.as_deref(), Some("on time"));
+        assert_eq!(fixture.pending().await.len(), 1);
+    }
+}
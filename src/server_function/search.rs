@@ -0,0 +1,646 @@
+//! Message search. MySQL uses the `ft_message_body` FULLTEXT index, other backends (and
+//! terms shorter than InnoDB's minimum token size) fall back to `LIKE`.
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use sea_orm::{
+    sea_query::{Expr, LikeExpr},
+    ColumnTrait, Condition, ConnectionTrait, DatabaseBackend, DatabaseConnection, EntityTrait,
+    QueryFilter, QueryOrder, QuerySelect, RelationTrait,
+};
+
+use super::{MessageStruct, SearchHit, SearchQuery, SearchResults, SnippetPart, UserLogin};
+use crate::entities::{message, prelude::*, user_conversation, users};
+
+pub const DEFAULT_LIMIT: u64 = 20;
+pub const MAX_LIMIT: u64 = 50;
+
+/// `innodb_ft_min_token_size`, shorter words are never indexed
+const MIN_FULLTEXT_TERM: usize = 3;
+const MAX_TERMS: usize = 8;
+const SNIPPET_BEFORE: usize = 40;
+const SNIPPET_LENGTH: usize = 160;
+
+#[derive(Debug)]
+pub enum SearchError {
+    EmptyQuery,
+    InvalidDate(String),
+    InvalidCursor,
+    Database(sea_orm::DbErr),
+}
+
+impl std::fmt::Display for SearchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SearchError::EmptyQuery => write!(f, "Search text is empty"),
+            SearchError::InvalidDate(date) => write!(f, "Invalid date {date}, expected YYYY-MM-DD"),
+            SearchError::InvalidCursor => write!(f, "Invalid cursor"),
+            SearchError::Database(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for SearchError {}
+
+impl From<sea_orm::DbErr> for SearchError {
+    fn from(value: sea_orm::DbErr) -> Self {
+        SearchError::Database(value)
+    }
+}
+
+/// Splits the search text into words, dropping characters that carry meaning in a MySQL
+/// boolean mode query or a `LIKE` pattern.
+pub fn terms(text: &str) -> Vec<String> {
+    let mut terms: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        let word: String = word
+            .chars()
+            .filter(|c| c.is_alphanumeric() || matches!(c, '\'' | '.' | '_' | '-'))
+            .collect();
+        let word = word.trim_matches(|c| matches!(c, '\'' | '.' | '_' | '-'));
+        if !word.is_empty() && !terms.iter().any(|term| term.eq_ignore_ascii_case(word)) {
+            terms.push(word.to_string());
+        }
+        if terms.len() == MAX_TERMS {
+            break;
+        }
+    }
+    terms
+}
+
+/// Every term is required and matched as a prefix, e.g. `+deploy* +friday*`.
+pub fn boolean_query(terms: &[String]) -> String {
+    terms
+        .iter()
+        // Hyphens and periods split words in the FULLTEXT parser, quote those terms
+        .map(|term| match term.contains(['-', '.', '\'']) {
+            true => format!("+\"{term}\""),
+            false => format!("+{term}*"),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Escapes the `LIKE` wildcards, the pattern must be used with `ESCAPE '\\'`.
+pub(super) fn escape_like(term: &str) -> String {
+    term.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+fn like_pattern(term: &str) -> String {
+    format!("%{}%", escape_like(term))
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate, SearchError> {
+    NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d")
+        .map_err(|_| SearchError::InvalidDate(date.to_string()))
+}
+
+pub fn encode_cursor(created_at: DateTime<Utc>, message_id: i32) -> String {
+    format!("{}_{}", created_at.timestamp(), message_id)
+}
+
+pub fn decode_cursor(cursor: &str) -> Result<(DateTime<Utc>, i32), SearchError> {
+    let (timestamp, message_id) = cursor.split_once('_').ok_or(SearchError::InvalidCursor)?;
+    let timestamp = timestamp.parse::<i64>().map_err(|_| SearchError::InvalidCursor)?;
+    let message_id = message_id.parse::<i32>().map_err(|_| SearchError::InvalidCursor)?;
+    let created_at = Utc
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .ok_or(SearchError::InvalidCursor)?;
+    Ok((created_at, message_id))
+}
+
+/// Finds the byte ranges of every case-insensitive occurrence of the terms.
+fn matches(body: &str, terms: &[String]) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for (start, _) in body.char_indices() {
+        if ranges.last().map_or(false, |(_, end)| start < *end) {
+            continue;
+        }
+        if let Some(length) = terms
+            .iter()
+            .filter(|term| {
+                body.get(start..start + term.len())
+                    .map_or(false, |candidate| candidate.eq_ignore_ascii_case(term))
+            })
+            .map(|term| term.len())
+            .max()
+        {
+            ranges.push((start, start + length));
+        }
+    }
+    ranges
+}
+
+fn floor_char_boundary(body: &str, mut index: usize) -> usize {
+    index = index.min(body.len());
+    while !body.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// Cuts a window around the first match and splits it into plain and highlighted parts.
+pub fn snippet(body: &str, terms: &[String]) -> Vec<SnippetPart> {
+    let ranges = matches(body, terms);
+    let first = ranges.first().map_or(0, |(start, _)| *start);
+
+    let window_start = floor_char_boundary(body, first.saturating_sub(SNIPPET_BEFORE));
+    let window_end = floor_char_boundary(body, window_start + SNIPPET_LENGTH);
+
+    let mut parts = Vec::new();
+    let mut push = |text: &str, highlighted: bool| {
+        if !text.is_empty() {
+            parts.push(SnippetPart {
+                text: text.to_string(),
+                highlighted,
+            });
+        }
+    };
+
+    if window_start > 0 {
+        push("…", false);
+    }
+    let mut position = window_start;
+    for (start, end) in ranges
+        .into_iter()
+        .filter(|(start, end)| *start >= window_start && *end <= window_end)
+    {
+        push(&body[position..start], false);
+        push(&body[start..end], true);
+        position = end;
+    }
+    push(&body[position..window_end], false);
+    if window_end < body.len() {
+        push("…", false);
+    }
+
+    parts
+}
+
+pub async fn search(
+    user: &UserLogin,
+    query: &SearchQuery,
+    data: &DatabaseConnection,
+) -> Result<SearchResults, SearchError> {
+    let terms = terms(&query.text);
+    if terms.is_empty() {
+        return Err(SearchError::EmptyQuery);
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let member_of: Vec<i32> = UserConversation::find()
+        .filter(user_conversation::server::Column::UserIds.eq(user.id))
+        .all(data)
+        .await?
+        .into_iter()
+        .map(|membership| membership.conversation_id)
+        .filter(|conversation_id| query.conversation_id.map_or(true, |id| id == *conversation_id))
+        .collect();
+
+    if member_of.is_empty() {
+        return Ok(SearchResults {
+            hits: Vec::new(),
+            next_cursor: None,
+        });
+    }
+
+    let mut condition = Condition::all()
+        .add(message::server::Column::MessageConversationId.is_in(member_of))
+        .add(message::server::Column::MessageBody.is_not_null());
+
+    let (fulltext, like): (Vec<String>, Vec<String>) =
+        terms.iter().cloned().partition(|term| {
+            data.get_database_backend() == DatabaseBackend::MySql
+                && term.chars().count() >= MIN_FULLTEXT_TERM
+        });
+    if !fulltext.is_empty() {
+        condition = condition.add(Expr::cust_with_values(
+            "MATCH (`message`.`message_body`) AGAINST (? IN BOOLEAN MODE)",
+            [boolean_query(&fulltext)],
+        ));
+    }
+    for term in &like {
+        condition = condition.add(
+            Expr::col((message::server::Entity, message::server::Column::MessageBody))
+                .like(LikeExpr::new(like_pattern(term)).escape('\\')),
+        );
+    }
+
+    if let Some(sender_id) = query.sender_id {
+        condition = condition.add(message::server::Column::MessageSenderId.eq(sender_id));
+    }
+    if let Some(from) = &query.from {
+        let from = Utc.from_utc_datetime(&parse_date(from)?.and_hms_opt(0, 0, 0).unwrap());
+        condition = condition.add(message::server::Column::MessageCreatedAt.gte(from));
+    }
+    if let Some(to) = &query.to {
+        let to = parse_date(to)?.succ_opt().unwrap_or(NaiveDate::MAX);
+        let to = Utc.from_utc_datetime(&to.and_hms_opt(0, 0, 0).unwrap());
+        condition = condition.add(message::server::Column::MessageCreatedAt.lt(to));
+    }
+    match query.has_image {
+        Some(true) => condition = condition.add(message::server::Column::MessageImage.is_not_null()),
+        Some(false) => condition = condition.add(message::server::Column::MessageImage.is_null()),
+        None => (),
+    }
+    if let Some(cursor) = &query.cursor {
+        let (created_at, message_id) = decode_cursor(cursor)?;
+        condition = condition.add(
+            Condition::any()
+                .add(message::server::Column::MessageCreatedAt.lt(created_at))
+                .add(
+                    Condition::all()
+                        .add(message::server::Column::MessageCreatedAt.eq(created_at))
+                        .add(message::server::Column::MessageId.lt(message_id)),
+                ),
+        );
+    }
+
+    // One extra row tells whether there is another page
+    let mut rows = Message::find()
+        .filter(condition)
+        .join(sea_orm::JoinType::InnerJoin, message::server::Relation::Users.def())
+        .columns::<users::server::Column, Vec<_>>(vec![
+            users::server::Column::FirstName,
+            users::server::Column::LastName,
+        ])
+        .order_by_desc(message::server::Column::MessageCreatedAt)
+        .order_by_desc(message::server::Column::MessageId)
+        .limit(limit + 1)
+        .into_model::<MessageStruct>()
+        .all(data)
+        .await?;
+
+    let next_cursor = if rows.len() as u64 > limit {
+        rows.truncate(limit as usize);
+        rows.last()
+            .map(|row| encode_cursor(row.message_created_at, row.message_id))
+    } else {
+        None
+    };
+
+    Ok(SearchResults {
+        hits: rows
+            .into_iter()
+            .map(|row| SearchHit {
+                message_id: row.message_id,
+                conversation_id: row.message_conversation_id,
+                sender_id: row.message_sender_id,
+                snippet: snippet(row.message_body.as_deref().unwrap_or_default(), &terms),
+                has_image: row.message_image.is_some(),
+                created_at: row.message_created_at.to_string(),
+                first_name: row.first_name,
+                last_name: row.last_name,
+            })
+            .collect(),
+        next_cursor,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+    use crate::entities::conversation;
+    use sea_orm::{ActiveModelTrait, IntoActiveModel};
+
+    fn at(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 5, day, hour, 0, 0).unwrap()
+    }
+
+    fn text(parts: &[SnippetPart]) -> String {
+        parts.iter().map(|part| part.text.as_str()).collect()
+    }
+
+    fn highlighted(parts: &[SnippetPart]) -> Vec<&str> {
+        parts
+            .iter()
+            .filter(|part| part.highlighted)
+            .map(|part| part.text.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn terms_drop_operators_and_duplicates() {
+        assert_eq!(
+            terms("  +deploy* \"Friday\" deploy DEPLOY "),
+            ["deploy", "Friday"]
+        );
+        assert_eq!(
+            terms("e-mail o'brien v1.2 -- ..."),
+            ["e-mail", "o'brien", "v1.2"]
+        );
+        assert_eq!(terms("_snake_case_ 100%"), ["snake_case", "100"]);
+        assert!(terms("*** () @").is_empty());
+        assert_eq!(terms("a b c d e f g h i j").len(), MAX_TERMS);
+    }
+
+    #[test]
+    fn boolean_query_requires_every_term() {
+        let terms = terms("deploy friday e-mail v1.2");
+        assert_eq!(
+            boolean_query(&terms),
+            "+deploy* +friday* +\"e-mail\" +\"v1.2\""
+        );
+        assert_eq!(boolean_query(&[]), "");
+    }
+
+    #[test]
+    fn escape_like_escapes_wildcards() {
+        assert_eq!(escape_like(r"50%_off\"), r"50\%\_off\\");
+    }
+
+    #[test]
+    fn snippet_highlights_matches() {
+        let parts = snippet(
+            "We Deploy on friday, deploying is fun",
+            &terms("deploy friday"),
+        );
+        assert_eq!(text(&parts), "We Deploy on friday, deploying is fun");
+        assert_eq!(highlighted(&parts), ["Deploy", "friday", "deploy"]);
+    }
+
+    #[test]
+    fn snippet_cuts_a_window_around_the_first_match() {
+        let body = format!("{}needle{}", "é".repeat(100), "x".repeat(300));
+        let parts = snippet(&body, &terms("needle"));
+        assert_eq!(parts.first().map(|part| part.text.as_str()), Some("…"));
+        assert_eq!(parts.last().map(|part| part.text.as_str()), Some("…"));
+        assert_eq!(highlighted(&parts), ["needle"]);
+        assert!(text(&parts).len() <= SNIPPET_LENGTH + 2 * "…".len());
+    }
+
+    #[test]
+    fn snippet_without_match_starts_at_the_beginning() {
+        let parts = snippet("nothing to see", &terms("needle"));
+        assert_eq!(
+            parts,
+            [SnippetPart {
+                text: "nothing to see".to_string(),
+                highlighted: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = encode_cursor(at(3, 10), 42);
+        assert_eq!(decode_cursor(&cursor).unwrap(), (at(3, 10), 42));
+        for cursor in ["", "42", "x_1", "1_x", "1_2_3", "99999999999999999_1"] {
+            assert!(matches!(
+                decode_cursor(cursor),
+                Err(SearchError::InvalidCursor)
+            ));
+        }
+    }
+
+    fn user(id: i32, first_name: &str) -> users::server::ActiveModel {
+        users::server::Model {
+            id,
+            first_name: first_name.to_string(),
+            last_name: "Tester".to_string(),
+            email: format!("{first_name}@example.com"),
+            phone_number: 0,
+            password: String::new(),
+            image: None,
+            send_read_receipts: 1,
+            email_missed_messages: 0,
+            email_digest: 0,
+            notified_until: None,
+            digest_sent_at: None,
+            totp_secret: None,
+            totp_enabled_at: None,
+            totp_last_step: None,
+            session_version: 0,
+        }
+        .into_active_model()
+    }
+
+    fn message(
+        id: i32,
+        conversation_id: i32,
+        sender_id: i32,
+        body: &str,
+        image: Option<&str>,
+        created_at: DateTime<Utc>,
+    ) -> message::server::ActiveModel {
+        message::server::Model {
+            message_id: id,
+            message_body: Some(body.to_string()),
+            message_image: image.map(str::to_string),
+            message_audio: None,
+            message_audio_duration: None,
+            message_created_at: created_at,
+            message_conversation_id: conversation_id,
+            message_sender_id: sender_id,
+            message_system: 0,
+        }
+        .into_active_model()
+    }
+
+    /// Alice (1) and Bob (2) share conversation 1, Bob and Carol (3) conversation 2.
+    async fn seed() -> DatabaseConnection {
+        let db = testing::sqlite().await;
+        testing::create_table(&db, Users).await;
+        testing::create_table(&db, Conversation).await;
+        testing::create_table(&db, UserConversation).await;
+        testing::create_table(&db, Message).await;
+
+        for (id, name) in [(1, "Alice"), (2, "Bob"), (3, "Carol")] {
+            user(id, name).insert(&db).await.unwrap();
+        }
+        for id in [1, 2] {
+            conversation::server::Model {
+                id,
+                last_message_at: at(1, 0),
+                created_at: at(1, 0),
+                name: None,
+                is_group: 0,
+                retention_seconds: None,
+            }
+            .into_active_model()
+            .insert(&db)
+            .await
+            .unwrap();
+        }
+        for (user_ids, conversation_id) in [(1, 1), (2, 1), (2, 2), (3, 2)] {
+            user_conversation::server::Model {
+                user_ids,
+                conversation_id,
+                last_read_message_id: None,
+                pinned: 0,
+                archived: 0,
+                muted: 0,
+            }
+            .into_active_model()
+            .insert(&db)
+            .await
+            .unwrap();
+        }
+        for model in [
+            message(1, 1, 1, "Deploy on friday?", None, at(1, 10)),
+            message(2, 1, 2, "The deploy went fine", Some("a.png"), at(2, 10)),
+            message(3, 1, 2, "deploy again", None, at(3, 10)),
+            message(4, 1, 1, "lunch?", None, at(3, 11)),
+            message(5, 2, 3, "deploy the secret", None, at(2, 12)),
+            message(6, 1, 1, "rename snake_case", None, at(4, 10)),
+            message(7, 1, 1, "rename snakeXcase", None, at(4, 11)),
+        ] {
+            model.insert(&db).await.unwrap();
+        }
+        db
+    }
+
+    fn alice() -> UserLogin {
+        UserLogin {
+            id: 1,
+            email: "Alice@example.com".to_string(),
+            first_name: "Alice".to_string(),
+            last_name: "Tester".to_string(),
+        }
+    }
+
+    fn query(text: &str) -> SearchQuery {
+        SearchQuery {
+            text: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    async fn ids(query: SearchQuery, db: &DatabaseConnection) -> Vec<i32> {
+        search(&alice(), &query, db)
+            .await
+            .unwrap()
+            .hits
+            .into_iter()
+            .map(|hit| hit.message_id)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn search_finds_only_member_conversations_newest_first() {
+        let db = seed().await;
+        let results = search(&alice(), &query("DEPLOY"), &db).await.unwrap();
+        let hits: Vec<i32> = results.hits.iter().map(|hit| hit.message_id).collect();
+        assert_eq!(hits, [3, 2, 1]);
+        assert_eq!(results.next_cursor, None);
+        assert_eq!(results.hits[1].first_name, "Bob");
+        assert!(results.hits[1].has_image);
+        assert_eq!(highlighted(&results.hits[1].snippet), ["deploy"]);
+
+        assert!(ids(query("secret"), &db).await.is_empty());
+        let other = SearchQuery {
+            conversation_id: Some(2),
+            ..query("deploy")
+        };
+        assert!(ids(other, &db).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn search_filters() {
+        let db = seed().await;
+        let by_bob = SearchQuery {
+            sender_id: Some(2),
+            ..query("deploy")
+        };
+        assert_eq!(ids(by_bob, &db).await, [3, 2]);
+
+        let one_day = SearchQuery {
+            from: Some("2023-05-02".to_string()),
+            to: Some("2023-05-02".to_string()),
+            ..query("deploy")
+        };
+        assert_eq!(ids(one_day, &db).await, [2]);
+        let from = SearchQuery {
+            from: Some("2023-05-02".to_string()),
+            ..query("deploy")
+        };
+        assert_eq!(ids(from, &db).await, [3, 2]);
+
+        let with_image = SearchQuery {
+            has_image: Some(true),
+            ..query("deploy")
+        };
+        assert_eq!(ids(with_image, &db).await, [2]);
+        let without_image = SearchQuery {
+            has_image: Some(false),
+            ..query("deploy")
+        };
+        assert_eq!(ids(without_image, &db).await, [3, 1]);
+
+        assert_eq!(ids(query("snake_case"), &db).await, [6]);
+        assert_eq!(ids(query("deploy friday"), &db).await, [1]);
+    }
+
+    #[tokio::test]
+    async fn search_pages_with_the_cursor() {
+        let db = seed().await;
+        let first = search(
+            &alice(),
+            &SearchQuery {
+                limit: Some(2),
+                ..query("deploy")
+            },
+            &db,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            first
+                .hits
+                .iter()
+                .map(|hit| hit.message_id)
+                .collect::<Vec<_>>(),
+            [3, 2]
+        );
+        assert!(first.next_cursor.is_some());
+
+        let second = search(
+            &alice(),
+            &SearchQuery {
+                limit: Some(2),
+                cursor: first.next_cursor,
+                ..query("deploy")
+            },
+            &db,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            second
+                .hits
+                .iter()
+                .map(|hit| hit.message_id)
+                .collect::<Vec<_>>(),
+            [1]
+        );
+        assert_eq!(second.next_cursor, None);
+    }
+
+    #[tokio::test]
+    async fn search_rejects_bad_input() {
+        let db = seed().await;
+        assert!(matches!(
+            search(&alice(), &query(" ** "), &db).await,
+            Err(SearchError::EmptyQuery)
+        ));
+        let bad_date = SearchQuery {
+            from: Some("05/02/2023".to_string()),
+            ..query("deploy")
+        };
+        assert!(matches!(
+            search(&alice(), &bad_date, &db).await,
+            Err(SearchError::InvalidDate(_))
+        ));
+        let bad_cursor = SearchQuery {
+            cursor: Some("nope".to_string()),
+            ..query("deploy")
+        };
+        assert!(matches!(
+            search(&alice(), &bad_cursor, &db).await,
+            Err(SearchError::InvalidCursor)
+        ));
+    }
+}
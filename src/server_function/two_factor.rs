@@ -0,0 +1,423 @@
+//! Optional TOTP second factor. Enrollment stores an encrypted secret and enables it once the
+//! first code from the authenticator app comes back, handing out single use recovery codes for
+//! a lost device. From then on a password login only issues the identity after a code, see
+//! `verify_login`.
+
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ActiveValue, ColumnTrait, Condition, DatabaseConnection,
+    DbErr, EntityTrait, PaginatorTrait, QueryFilter,
+};
+
+use super::{TwoFactorEnrollment, TwoFactorStatus};
+use crate::auth::totp;
+use crate::entities::{prelude::*, recovery_codes, users};
+
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+#[derive(Debug)]
+pub enum TwoFactorError {
+    AlreadyEnabled,
+    NotEnrolled,
+    NotEnabled,
+    IncorrectCode,
+    IncorrectPassword,
+    Crypto(totp::CryptoError),
+    QrCode(qrcode::types::QrError),
+    Database(DbErr),
+}
+
+impl std::fmt::Display for TwoFactorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TwoFactorError::AlreadyEnabled => write!(f, "Two factor authentication is already on"),
+            TwoFactorError::NotEnrolled => write!(f, "Start the setup again"),
+            TwoFactorError::NotEnabled => write!(f, "Two factor authentication is off"),
+            TwoFactorError::IncorrectCode => write!(f, "Code is incorrect"),
+            TwoFactorError::IncorrectPassword => write!(f, "Current password is incorrect"),
+            TwoFactorError::Crypto(e) => write!(f, "{e}"),
+            TwoFactorError::QrCode(e) => write!(f, "{e}"),
+            TwoFactorError::Database(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for TwoFactorError {}
+
+impl TwoFactorError {
+    pub fn into_server_fn_error(self) -> leptos::ServerFnError {
+        match self {
+            TwoFactorError::Crypto(_) | TwoFactorError::QrCode(_) | TwoFactorError::Database(_) => {
+                leptos::ServerFnError::ServerError(self.to_string())
+            }
+            e => leptos::ServerFnError::Request(e.to_string()),
+        }
+    }
+}
+
+impl From<DbErr> for TwoFactorError {
+    fn from(value: DbErr) -> Self {
+        TwoFactorError::Database(value)
+    }
+}
+
+impl From<totp::CryptoError> for TwoFactorError {
+    fn from(value: totp::CryptoError) -> Self {
+        TwoFactorError::Crypto(value)
+    }
+}
+
+impl From<qrcode::types::QrError> for TwoFactorError {
+    fn from(value: qrcode::types::QrError) -> Self {
+        TwoFactorError::QrCode(value)
+    }
+}
+
+async fn user(data: &DatabaseConnection, user_id: i32) -> Result<users::server::Model, DbErr> {
+    Users::find_by_id(user_id)
+        .one(data)
+        .await?
+        .ok_or_else(|| DbErr::RecordNotFound(format!("user {user_id}")))
+}
+
+fn secret(user: &users::server::Model) -> Result<Vec<u8>, TwoFactorError> {
+    let stored = user
+        .totp_secret
+        .as_deref()
+        .ok_or(TwoFactorError::NotEnrolled)?;
+    Ok(totp::decrypt_secret(stored)?)
+}
+
+pub async fn status(data: &DatabaseConnection, user_id: i32) -> Result<TwoFactorStatus, DbErr> {
+    let enabled = user(data, user_id).await?.totp_enabled_at.is_some();
+    let recovery_codes_left = RecoveryCodes::find()
+        .filter(recovery_codes::server::Column::UserId.eq(user_id))
+        .filter(recovery_codes::server::Column::UsedAt.is_null())
+        .count(data)
+        .await?;
+    Ok(TwoFactorStatus {
+        enabled,
+        recovery_codes_left,
+    })
+}
+
+/// Stores a new secret for the user, replacing an unconfirmed one.
+pub async fn begin(
+    data: &DatabaseConnection,
+    user_id: i32,
+) -> Result<TwoFactorEnrollment, TwoFactorError> {
+    let user = user(data, user_id).await?;
+    if user.totp_enabled_at.is_some() {
+        return Err(TwoFactorError::AlreadyEnabled);
+    }
+
+    let secret = totp::generate_secret();
+    let otpauth_uri = totp::otpauth_uri(&user.email, &secret);
+    let qr_svg = qrcode::QrCode::new(otpauth_uri.as_bytes())?
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(200, 200)
+        .build();
+
+    let mut user: users::server::ActiveModel = user.into();
+    user.totp_secret = ActiveValue::Set(Some(totp::encrypt_secret(&secret)));
+    user.totp_last_step = ActiveValue::Set(None);
+    user.update(data).await?;
+
+    Ok(TwoFactorEnrollment {
+        otpauth_uri,
+        qr_svg,
+        secret: totp::base32(&secret),
+    })
+}
+
+/// Replaces the user's recovery codes with fresh ones, returned in the clear this once.
+async fn replace_recovery_codes(
+    data: &DatabaseConnection,
+    user_id: i32,
+) -> Result<Vec<String>, DbErr> {
+    RecoveryCodes::delete_many()
+        .filter(recovery_codes::server::Column::UserId.eq(user_id))
+        .exec(data)
+        .await?;
+
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| totp::recovery_code())
+        .collect();
+    RecoveryCodes::insert_many(codes.iter().map(|code| recovery_codes::server::ActiveModel {
+        user_id: ActiveValue::Set(user_id),
+        code_hash: ActiveValue::Set(super::password_reset::hash_token(code)),
+        ..Default::default()
+    }))
+    .exec_without_returning(data)
+    .await?;
+    Ok(codes)
+}
+
+/// Claims the TOTP step of `code`, so the same code does not work twice.
+async fn claim_step(
+    data: &DatabaseConnection,
+    user: &users::server::Model,
+    now: DateTime<Utc>,
+    code: &str,
+) -> Result<(), TwoFactorError> {
+    let step = totp::verify(&secret(user)?, code, now, user.totp_last_step)
+        .ok_or(TwoFactorError::IncorrectCode)?;
+
+    let claimed = Users::update_many()
+        .col_expr(users::server::Column::TotpLastStep, Expr::value(step))
+        .filter(users::server::Column::Id.eq(user.id))
+        .filter(
+            Condition::any()
+                .add(users::server::Column::TotpLastStep.is_null())
+                .add(users::server::Column::TotpLastStep.lt(step)),
+        )
+        .exec(data)
+        .await?;
+    match claimed.rows_affected {
+        0 => Err(TwoFactorError::IncorrectCode),
+        _ => Ok(()),
+    }
+}
+
+/// Claims an unused recovery code of the user.
+async fn claim_recovery_code(
+    data: &DatabaseConnection,
+    user_id: i32,
+    now: DateTime<Utc>,
+    code: &str,
+) -> Result<(), TwoFactorError> {
+    let claimed = RecoveryCodes::update_many()
+        .col_expr(recovery_codes::server::Column::UsedAt, Expr::value(now))
+        .filter(recovery_codes::server::Column::UserId.eq(user_id))
+        .filter(
+            recovery_codes::server::Column::CodeHash.eq(super::password_reset::hash_token(
+                &totp::normalize_recovery_code(code),
+            )),
+        )
+        .filter(recovery_codes::server::Column::UsedAt.is_null())
+        .exec(data)
+        .await?;
+    match claimed.rows_affected {
+        0 => Err(TwoFactorError::IncorrectCode),
+        _ => Ok(()),
+    }
+}
+
+/// Accepts either a TOTP code or a recovery code of an enabled user.
+async fn check_code(
+    data: &DatabaseConnection,
+    user: &users::server::Model,
+    now: DateTime<Utc>,
+    code: &str,
+) -> Result<(), TwoFactorError> {
+    if user.totp_enabled_at.is_none() {
+        return Err(TwoFactorError::NotEnabled);
+    }
+    match totp::is_totp_code(code) {
+        true => claim_step(data, user, now, code).await,
+        false => claim_recovery_code(data, user.id, now, code).await,
+    }
+}
+
+/// Enables 2FA once `code` matches the secret from `begin`. Returns the recovery codes.
+pub async fn enable(
+    data: &DatabaseConnection,
+    now: DateTime<Utc>,
+    user_id: i32,
+    code: &str,
+) -> Result<Vec<String>, TwoFactorError> {
+    let user = user(data, user_id).await?;
+    if user.totp_enabled_at.is_some() {
+        return Err(TwoFactorError::AlreadyEnabled);
+    }
+    claim_step(data, &user, now, code).await?;
+
+    Users::update_many()
+        .col_expr(users::server::Column::TotpEnabledAt, Expr::value(now))
+        .filter(users::server::Column::Id.eq(user_id))
+        .exec(data)
+        .await?;
+    Ok(replace_recovery_codes(data, user_id).await?)
+}
+
+/// Turns 2FA off, which needs both the password and a code.
+pub async fn disable(
+    data: &DatabaseConnection,
+    now: DateTime<Utc>,
+    user_id: i32,
+    current_password: &str,
+    code: &str,
+) -> Result<(), TwoFactorError> {
+    let user = user(data, user_id).await?;
+    if !super::account::verify_password(&user.password, current_password) {
+        return Err(TwoFactorError::IncorrectPassword);
+    }
+    check_code(data, &user, now, code).await?;
+
+    let mut user: users::server::ActiveModel = user.into();
+    user.totp_secret = ActiveValue::Set(None);
+    user.totp_enabled_at = ActiveValue::Set(None);
+    user.totp_last_step = ActiveValue::Set(None);
+    user.update(data).await?;
+    RecoveryCodes::delete_many()
+        .filter(recovery_codes::server::Column::UserId.eq(user_id))
+        .exec(data)
+        .await?;
+    Ok(())
+}
+
+/// Replaces the recovery codes after a TOTP code, e.g. once most of them are used.
+pub async fn regenerate_recovery_codes(
+    data: &DatabaseConnection,
+    now: DateTime<Utc>,
+    user_id: i32,
+    code: &str,
+) -> Result<Vec<String>, TwoFactorError> {
+    let user = user(data, user_id).await?;
+    if user.totp_enabled_at.is_none() {
+        return Err(TwoFactorError::NotEnabled);
+    }
+    claim_step(data, &user, now, code).await?;
+    Ok(replace_recovery_codes(data, user_id).await?)
+}
+
+/// The second login step, returns the user once `code` is accepted.
+pub async fn verify_login(
+    data: &DatabaseConnection,
+    now: DateTime<Utc>,
+    user_id: i32,
+    code: &str,
+) -> Result<users::server::Model, TwoFactorError> {
+    let user = user(data, user_id).await?;
+    check_code(data, &user, now, code).await?;
+    Ok(user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing;
+    use chrono::TimeZone;
+    use sea_orm::IntoActiveModel;
+
+    const SECRET: &[u8] = b"12345678901234567890";
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 5, 1, 12, 0, 0).unwrap()
+    }
+
+    fn code(at: DateTime<Utc>) -> String {
+        totp::code_at(SECRET, totp::step(at))
+    }
+
+    /// A user whose 2FA is enabled, with its recovery codes.
+    async fn enrolled() -> (DatabaseConnection, Vec<String>) {
+        let db = testing::sqlite().await;
+        testing::create_table(&db, Users).await;
+        testing::create_table(&db, RecoveryCodes).await;
+        users::server::Model {
+            id: 1,
+            first_name: String::from("Alice"),
+            last_name: String::from("Tester"),
+            email: String::from("alice@example.com"),
+            phone_number: 0,
+            password: String::new(),
+            image: None,
+            send_read_receipts: 1,
+            email_missed_messages: 0,
+            email_digest: 0,
+            notified_until: None,
+            digest_sent_at: None,
+            totp_secret: Some(totp::encrypt_secret(SECRET)),
+            totp_enabled_at: None,
+            totp_last_step: None,
+            session_version: 0,
+        }
+        .into_active_model()
+        .insert(&db)
+        .await
+        .unwrap();
+
+        let codes = enable(&db, now(), 1, &code(now())).await.unwrap();
+        (db, codes)
+    }
+
+    #[tokio::test]
+    async fn totp_codes_work_once() {
+        let (db, _) = enrolled().await;
+        assert_eq!(
+            user(&db, 1).await.unwrap().totp_last_step,
+            Some(totp::step(now()))
+        );
+
+        // The code that enabled 2FA is spent, and so is every step before it
+        assert!(matches!(
+            verify_login(&db, now(), 1, &code(now())).await,
+            Err(TwoFactorError::IncorrectCode)
+        ));
+        let earlier = now() - chrono::Duration::seconds(totp::STEP_SECONDS);
+        assert!(matches!(
+            verify_login(&db, now(), 1, &code(earlier)).await,
+            Err(TwoFactorError::IncorrectCode)
+        ));
+
+        let later = now() + chrono::Duration::seconds(totp::STEP_SECONDS);
+        assert_eq!(
+            verify_login(&db, later, 1, &code(later)).await.unwrap().id,
+            1
+        );
+        assert!(matches!(
+            verify_login(&db, later, 1, &code(later)).await,
+            Err(TwoFactorError::IncorrectCode)
+        ));
+    }
+
+    #[tokio::test]
+    async fn recovery_codes_work_once() {
+        let (db, codes) = enrolled().await;
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert_eq!(status(&db, 1).await.unwrap().recovery_codes_left, 10);
+
+        verify_login(&db, now(), 1, &codes[0]).await.unwrap();
+        assert!(matches!(
+            verify_login(&db, now(), 1, &codes[0]).await,
+            Err(TwoFactorError::IncorrectCode)
+        ));
+        verify_login(&db, now(), 1, &format!(" {} ", codes[1].to_uppercase()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            verify_login(&db, now(), 1, "aaaaa-aaaaa").await,
+            Err(TwoFactorError::IncorrectCode)
+        ));
+        assert_eq!(status(&db, 1).await.unwrap().recovery_codes_left, 8);
+
+        // New codes replace the old ones, used or not
+        let later = now() + chrono::Duration::seconds(totp::STEP_SECONDS);
+        let fresh = regenerate_recovery_codes(&db, later, 1, &code(later))
+            .await
+            .unwrap();
+        assert!(matches!(
+            verify_login(&db, later, 1, &codes[2]).await,
+            Err(TwoFactorError::IncorrectCode)
+        ));
+        verify_login(&db, later, 1, &fresh[0]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn codes_need_2fa_enabled() {
+        let (db, codes) = enrolled().await;
+        assert!(matches!(
+            enable(&db, now(), 1, &code(now())).await,
+            Err(TwoFactorError::AlreadyEnabled)
+        ));
+
+        let mut disabled: users::server::ActiveModel = user(&db, 1).await.unwrap().into();
+        disabled.totp_enabled_at = ActiveValue::Set(None);
+        disabled.update(&db).await.unwrap();
+        assert!(matches!(
+            verify_login(&db, now(), 1, &codes[0]).await,
+            Err(TwoFactorError::NotEnabled)
+        ));
+    }
+}
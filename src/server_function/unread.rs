@@ -0,0 +1,177 @@
+//! Unread counts. A member's unread messages are the ones other members sent after their
+//! `last_read_message_id` watermark, or all of them when they never opened the conversation.
+//! System messages never count.
+
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    sea_query::Expr, ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait,
+    FromQueryResult, JoinType, QueryFilter, QuerySelect, RelationDef,
+};
+
+use super::{UnreadCount, UnreadCounts};
+use crate::entities::{message, prelude::*, user_conversation};
+use crate::web_socket::server::{IconWs, NotifyUsers};
+
+#[derive(Debug, FromQueryResult)]
+struct UnreadRow {
+    user_ids: i32,
+    conversation_id: i32,
+    unread: i64,
+    muted: bool,
+}
+
+impl From<UnreadRow> for UnreadCount {
+    fn from(value: UnreadRow) -> Self {
+        Self {
+            unread_conversation_id: value.conversation_id,
+            unread: value.unread.max(0) as u64,
+            muted: value.muted,
+        }
+    }
+}
+
+/// Counts per member and conversation, rows with nothing unread are left out.
+async fn count(data: &DatabaseConnection, condition: Condition) -> Result<Vec<UnreadRow>, DbErr> {
+    let membership: RelationDef = Message::belongs_to(UserConversation)
+        .from(message::server::Column::MessageConversationId)
+        .to(user_conversation::server::Column::ConversationId)
+        .into();
+
+    Message::find()
+        .select_only()
+        .column(user_conversation::server::Column::UserIds)
+        .column(user_conversation::server::Column::ConversationId)
+        .column(user_conversation::server::Column::Muted)
+        .column_as(message::server::Column::MessageId.count(), "unread")
+        .join(JoinType::InnerJoin, membership)
+        .filter(condition)
+        .filter(message::server::Column::MessageSystem.eq(0))
+        .filter(
+            Expr::col((Message, message::server::Column::MessageSenderId))
+                .ne(Expr::col((UserConversation, user_conversation::server::Column::UserIds))),
+        )
+        .filter(
+            Condition::any()
+                .add(user_conversation::server::Column::LastReadMessageId.is_null())
+                .add(
+                    Expr::col((Message, message::server::Column::MessageId)).gt(Expr::col((
+                        UserConversation,
+                        user_conversation::server::Column::LastReadMessageId,
+                    ))),
+                ),
+        )
+        .group_by(user_conversation::server::Column::UserIds)
+        .group_by(user_conversation::server::Column::ConversationId)
+        .group_by(user_conversation::server::Column::Muted)
+        .into_model::<UnreadRow>()
+        .all(data)
+        .await
+}
+
+pub async fn for_user(data: &DatabaseConnection, user_id: i32) -> Result<UnreadCounts, DbErr> {
+    let conversations: Vec<UnreadCount> = count(
+        data,
+        Condition::all().add(user_conversation::server::Column::UserIds.eq(user_id)),
+    )
+    .await?
+    .into_iter()
+    .map(Into::into)
+    .collect();
+
+    Ok(UnreadCounts {
+        total: conversations
+            .iter()
+            .filter(|count| !count.muted)
+            .map(|count| count.unread)
+            .sum(),
+        conversations,
+    })
+}
+
+/// Unread counts of `user_id` in the conversations they did not mute, only counting messages
+/// sent after `sent_after` and up to `sent_until`.
+pub async fn unmuted_for_user(
+    data: &DatabaseConnection,
+    user_id: i32,
+    sent_after: Option<DateTime<Utc>>,
+    sent_until: DateTime<Utc>,
+) -> Result<Vec<UnreadCount>, DbErr> {
+    let mut condition = Condition::all()
+        .add(user_conversation::server::Column::UserIds.eq(user_id))
+        .add(user_conversation::server::Column::Muted.eq(0))
+        .add(message::server::Column::MessageCreatedAt.lte(sent_until));
+    if let Some(sent_after) = sent_after {
+        condition = condition.add(message::server::Column::MessageCreatedAt.gt(sent_after));
+    }
+
+    Ok(count(data, condition)
+        .await?
+        .into_iter()
+        .map(Into::into)
+        .collect())
+}
+
+/// Pushes the new count of `conversation_id` to every member but `sender_id`, called after a
+/// message was stored.
+pub async fn notify_members(
+    data: &DatabaseConnection,
+    icon_server: &actix::Addr<IconWs>,
+    conversation_id: i32,
+    sender_id: i32,
+) -> Result<(), DbErr> {
+    let rows = count(
+        data,
+        Condition::all()
+            .add(user_conversation::server::Column::ConversationId.eq(conversation_id))
+            .add(user_conversation::server::Column::UserIds.ne(sender_id)),
+    )
+    .await?;
+
+    for row in rows {
+        let user_id = row.user_ids;
+        if let Ok(message) = serde_json::to_string(&UnreadCount::from(row)) {
+            icon_server.do_send(NotifyUsers {
+                user_ids: vec![user_id],
+                message,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Pushes the caller's own count of `conversation_id` to their open tabs, after they read it
+/// or changed whether it is muted.
+pub async fn notify_member(
+    data: &DatabaseConnection,
+    icon_server: &actix::Addr<IconWs>,
+    conversation_id: i32,
+    user_id: i32,
+) -> Result<(), DbErr> {
+    let count = match count(
+        data,
+        Condition::all()
+            .add(user_conversation::server::Column::ConversationId.eq(conversation_id))
+            .add(user_conversation::server::Column::UserIds.eq(user_id)),
+    )
+    .await?
+    .pop()
+    {
+        Some(row) => row.into(),
+        None => UnreadCount {
+            unread_conversation_id: conversation_id,
+            unread: 0,
+            muted: UserConversation::find_by_id((user_id, conversation_id))
+                .one(data)
+                .await?
+                .map_or(false, |membership| membership.muted != 0),
+        },
+    };
+
+    if let Ok(message) = serde_json::to_string(&count) {
+        icon_server.do_send(NotifyUsers {
+            user_ids: vec![user_id],
+            message,
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,77 @@
+//! Emailing the verification code of a pending signup. The code only ever goes to the address
+//! being verified, and resends are spaced out and capped so the endpoint can not be used to
+//! flood an inbox. Emails go out after the database is released.
+
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, DbErr, EntityTrait,
+    QueryFilter,
+};
+
+use crate::app::ResendValidation;
+use crate::emailing::email_client::EmailClient;
+use crate::entities::{prelude::*, temp_users};
+
+/// Minimum time between two emails to the same pending signup.
+pub const RESEND_COOLDOWN_SECONDS: i64 = 60;
+/// Emails a pending signup gets at most, the first one included.
+pub const MAX_SENDS: i32 = 5;
+
+/// Whether another email may go out at `now`, given the last one went out at `sent_at` and
+/// `sends` went out so far.
+pub fn check_resend(
+    sent_at: DateTime<Utc>,
+    sends: i32,
+    now: DateTime<Utc>,
+) -> Result<(), ResendValidation> {
+    if sends >= MAX_SENDS {
+        return Err(ResendValidation::LimitReached);
+    }
+
+    let retry_after_seconds = RESEND_COOLDOWN_SECONDS - (now - sent_at).num_seconds();
+    match retry_after_seconds > 0 {
+        true => Err(ResendValidation::TooSoon {
+            retry_after_seconds,
+        }),
+        false => Ok(()),
+    }
+}
+
+/// A failed send is only logged, the signup stays and the code can be sent again.
+pub async fn send(email_client: &EmailClient, user: &temp_users::server::Model) {
+    if let Err(e) = email_client
+        .send_verification(&user.email, &user.first_name, &user.verification)
+        .await
+    {
+        println!("Error at sending verification email: {e}");
+    }
+}
+
+/// Records another email to the pending signup for `email` and returns it, the caller sends
+/// the code once the database is released. Addresses without a pending signup come back as
+/// `None` and are to be reported as sent, so the endpoint tells nothing about who is signing
+/// up. A resend also restarts the time the signup is kept for.
+pub async fn claim_resend(
+    data: &DatabaseConnection,
+    now: DateTime<Utc>,
+    email: &str,
+) -> Result<Result<Option<temp_users::server::Model>, ResendValidation>, DbErr> {
+    let Some(user) = TempUsers::find()
+        .filter(temp_users::server::Column::Email.eq(email.trim()))
+        .one(data)
+        .await?
+    else {
+        return Ok(Ok(None));
+    };
+
+    if let Err(rejected) = check_resend(user.verification_sent_at, user.verification_sends, now) {
+        return Ok(Err(rejected));
+    }
+
+    let sends = user.verification_sends + 1;
+    let mut pending: temp_users::server::ActiveModel = user.into();
+    pending.verification_sent_at = ActiveValue::Set(now);
+    pending.verification_sends = ActiveValue::Set(sends);
+    pending.time = ActiveValue::Set(now);
+    Ok(Ok(Some(pending.update(data).await?)))
+}
@@ -38,28 +38,55 @@ pub struct ClientMessage {
     pub room: usize,
 }
 
-/// List of available rooms
-///
-pub struct ListUsers;
+/// Closes a socket whose login session was revoked.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct CloseSocket;
 
-pub struct IconWsListUsers;
-impl actix::Message for IconWsListUsers {
-    type Result = Vec<usize>;
+/// Ties a connected socket to the token of the login session that opened it.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct TrackSocket {
+    pub id: usize,
+    pub session_token: String,
+    pub socket: Recipient<CloseSocket>,
 }
 
-impl actix::Message for ListUsers {
-    type Result = Vec<ConnectedUsers>;
+/// Closes every socket opened by one of these login sessions.
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct CloseSessions {
+    pub session_tokens: Vec<String>,
 }
 
-pub struct ConnectedUsers {
-    pub conversation_id: usize,
-    pub user_id: usize,
-    pub last_name: String,
-    pub first_name: String,
+/// Sockets by the login session that opened them, kept by both servers.
+#[derive(Debug, Default)]
+struct TrackedSockets(HashMap<usize, (String, Recipient<CloseSocket>)>);
+
+impl TrackedSockets {
+    fn track(&mut self, msg: TrackSocket) {
+        self.0.insert(msg.id, (msg.session_token, msg.socket));
+    }
+
+    fn forget(&mut self, id: usize) {
+        self.0.remove(&id);
+    }
+
+    fn close(&mut self, session_tokens: &[String]) {
+        self.0.retain(|_, (session_token, socket)| {
+            let revoked = session_tokens.contains(session_token);
+            if revoked {
+                socket.do_send(CloseSocket);
+            }
+            !revoked
+        });
+    }
+}
+
+pub struct IconWsListUsers;
+impl actix::Message for IconWsListUsers {
+    type Result = Vec<usize>;
 }
-// impl actix::Message for ListUsers {
-//     type Result = Vec<ListUsers>;
-// }
 
 /// Join room, if room does not exists create new one.
 #[derive(Message)]
@@ -86,6 +113,7 @@ pub struct ChatServer {
     rng: ThreadRng,
     // visitor_count: Arc<AtomicUsize>,
     users: HashMap<(usize, usize), (String, String)>,
+    tracked: TrackedSockets,
 }
 
 impl Default for ChatServer {
@@ -106,6 +134,7 @@ impl ChatServer {
             rng: rand::thread_rng(),
             // visitor_count,
             users,
+            tracked: TrackedSockets::default(),
         }
     }
 }
@@ -164,6 +193,8 @@ impl Handler<Disconnect> for ChatServer {
     fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) {
         println!("Someone disconnected");
 
+        self.tracked.forget(msg.id);
+
         // let mut rooms: Vec<usize> = Vec::new();
 
         // remove address
@@ -184,27 +215,6 @@ impl Handler<Disconnect> for ChatServer {
     }
 }
 
-/// Handler for `ListRooms` message.
-impl Handler<ListUsers> for ChatServer {
-    type Result = MessageResult<ListUsers>;
-
-    fn handle(&mut self, _: ListUsers, _: &mut Context<Self>) -> Self::Result {
-        let mut users = Vec::new();
-
-        for ((user_id, conversation_id), (first_name, last_name)) in self.users.iter() {
-            users.push(ConnectedUsers {
-                conversation_id: *conversation_id,
-                user_id: *user_id,
-                first_name: first_name.clone(),
-                last_name: last_name.clone(),
-            })
-        }
-
-        // MessageResult(users)
-        MessageResult(users)
-    }
-}
-
 impl Handler<IconWsListUsers> for IconWs {
     type Result = MessageResult<IconWsListUsers>;
 
@@ -266,6 +276,10 @@ pub struct IconWs {
     users: Vec<usize>,
     rng: ThreadRng,
     sessions: HashMap<usize, Recipient<IconWsMessage>>,
+    /// Sessions a logged in user opened on their own icon channel, used for events that are
+    /// only meant for that user
+    owners: HashMap<usize, i32>,
+    tracked: TrackedSockets,
 }
 
 #[derive(Message, serde::Serialize, serde::Deserialize)]
@@ -274,9 +288,25 @@ pub struct IconWsMessage {
     pub message: String,
 }
 
+/// New icon session, with the channel id and the id of the logged in user if any
 #[derive(Message)]
 #[rtype(result = "usize")]
-pub struct IconConnect(pub Recipient<IconWsMessage>, pub usize);
+pub struct IconConnect(pub Recipient<IconWsMessage>, pub usize, pub Option<i32>);
+
+/// Send a serialized event to the given users only
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct NotifyUsers {
+    pub user_ids: Vec<i32>,
+    pub message: String,
+}
+
+/// Which of the given users currently have a session open on their own icon channel
+#[derive(Message)]
+#[rtype(result = "Vec<i32>")]
+pub struct OnlineUsers {
+    pub user_ids: Vec<i32>,
+}
 
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -309,6 +339,10 @@ impl Handler<IconConnect> for IconWs {
         let id = self.rng.gen::<usize>();
         self.sessions.insert(id, msg.0);
 
+        if let Some(user_id) = msg.2.filter(|user_id| *user_id as usize == msg.1) {
+            self.owners.insert(id, user_id);
+        }
+
         // auto join session to room with conversation_id
         // self.users.push(msg.1);
 
@@ -337,6 +371,8 @@ impl IconWs {
             sessions,
             users,
             rng,
+            owners: HashMap::new(),
+            tracked: TrackedSockets::default(),
         }
     }
 }
@@ -360,12 +396,46 @@ impl Handler<ClientMessage> for IconWs {
     }
 }
 
+impl Handler<NotifyUsers> for IconWs {
+    type Result = ();
+
+    fn handle(&mut self, msg: NotifyUsers, _: &mut Context<Self>) {
+        for (session, _) in self
+            .owners
+            .iter()
+            .filter(|(_, user_id)| msg.user_ids.contains(user_id))
+        {
+            if let Some(recipient) = self.sessions.get(session) {
+                recipient.do_send(IconWsMessage {
+                    message: msg.message.clone(),
+                })
+            }
+        }
+    }
+}
+
+impl Handler<OnlineUsers> for IconWs {
+    type Result = MessageResult<OnlineUsers>;
+
+    fn handle(&mut self, msg: OnlineUsers, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(
+            msg.user_ids
+                .into_iter()
+                .filter(|user_id| self.owners.values().any(|owner| owner == user_id))
+                .collect(),
+        )
+    }
+}
+
 impl Handler<Disconnect> for IconWs {
     type Result = ();
 
     fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) {
         println!("Someone disconnected");
 
+        self.owners.remove(&msg.id);
+        self.tracked.forget(msg.id);
+
         // remove address
         if self.sessions.remove(&msg.id).is_some() {
             // remove session from all rooms
@@ -378,3 +448,35 @@ impl Handler<Disconnect> for IconWs {
         }
     }
 }
+
+impl Handler<TrackSocket> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: TrackSocket, _: &mut Context<Self>) {
+        self.tracked.track(msg);
+    }
+}
+
+impl Handler<CloseSessions> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: CloseSessions, _: &mut Context<Self>) {
+        self.tracked.close(&msg.session_tokens);
+    }
+}
+
+impl Handler<TrackSocket> for IconWs {
+    type Result = ();
+
+    fn handle(&mut self, msg: TrackSocket, _: &mut Context<Self>) {
+        self.tracked.track(msg);
+    }
+}
+
+impl Handler<CloseSessions> for IconWs {
+    type Result = ();
+
+    fn handle(&mut self, msg: CloseSessions, _: &mut Context<Self>) {
+        self.tracked.close(&msg.session_tokens);
+    }
+}
@@ -2,8 +2,6 @@ use std::time::{Duration, Instant};
 
 use actix::prelude::*;
 use actix_web_actors::ws;
-use iter_tools::Itertools;
-use leptos::log;
 
 use crate::app::pages::components::avatar;
 use crate::web_socket::server;
@@ -29,6 +27,9 @@ pub struct WsChatSession {
     /// peer name
     pub name: Option<String>,
 
+    /// Login session that opened the socket, closed when it is revoked
+    pub session_token: Option<String>,
+
     /// Chat server
     pub addr: Addr<server::ChatServer>,
 }
@@ -41,6 +42,12 @@ pub struct WsChatSessionIcon {
     /// otherwise we drop connection.
     pub hb: Instant,
 
+    /// Logged in user that opened the session
+    pub user_id: Option<i32>,
+
+    /// Login session that opened the socket, closed when it is revoked
+    pub session_token: Option<String>,
+
     /// Chat server
     pub addr: Addr<server::IconWs>,
 }
@@ -155,7 +162,16 @@ impl Actor for WsChatSession {
             .into_actor(self)
             .then(|res, act, ctx| {
                 match res {
-                    Ok(res) => act.id = res,
+                    Ok(res) => {
+                        act.id = res;
+                        if let Some(session_token) = act.session_token.clone() {
+                            act.addr.do_send(server::TrackSocket {
+                                id: res,
+                                session_token,
+                                socket: ctx.address().recipient(),
+                            });
+                        }
+                    }
                     // something is wrong with chat server
                     _ => ctx.stop(),
                 }
@@ -187,11 +203,20 @@ impl Actor for WsChatSessionIcon {
         // across all routes within application
         let addr = ctx.address();
         self.addr
-            .send(server::IconConnect(addr.recipient(), self.id))
+            .send(server::IconConnect(addr.recipient(), self.id, self.user_id))
             .into_actor(self)
             .then(|res, act, ctx| {
                 match res {
-                    Ok(res) => act.id = res,
+                    Ok(res) => {
+                        act.id = res;
+                        if let Some(session_token) = act.session_token.clone() {
+                            act.addr.do_send(server::TrackSocket {
+                                id: res,
+                                session_token,
+                                socket: ctx.address().recipient(),
+                            });
+                        }
+                    }
                     // something is wrong with chat server
                     _ => ctx.stop(),
                 }
@@ -224,6 +249,33 @@ impl Handler<server::IconWsMessage> for WsChatSessionIcon {
     }
 }
 
+fn close_revoked<A>(ctx: &mut ws::WebsocketContext<A>)
+where
+    A: Actor<Context = ws::WebsocketContext<A>>,
+{
+    ctx.close(Some(ws::CloseReason {
+        code: ws::CloseCode::Policy,
+        description: Some(String::from("Session revoked")),
+    }));
+    ctx.stop();
+}
+
+impl Handler<server::CloseSocket> for WsChatSession {
+    type Result = ();
+
+    fn handle(&mut self, _: server::CloseSocket, ctx: &mut Self::Context) {
+        close_revoked(ctx);
+    }
+}
+
+impl Handler<server::CloseSocket> for WsChatSessionIcon {
+    type Result = ();
+
+    fn handle(&mut self, _: server::CloseSocket, ctx: &mut Self::Context) {
+        close_revoked(ctx);
+    }
+}
+
 /// WebSocket message handler
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsChatSession {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
@@ -244,7 +296,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsChatSession {
                 self.hb = Instant::now();
             }
             ws::Message::Text(text) => {
-                let mut text: crate::app::pages::conversation::Message =
+                let text: crate::app::pages::conversation::Message =
                     serde_json::from_str(&String::from_utf8(text.into_bytes().to_vec()).unwrap())
                         .unwrap();
                 let crate::app::pages::conversation::Message {
@@ -263,44 +315,12 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsChatSession {
                     user_id: user_id as usize,
                 });
 
-                // ctx.text(text.message)
-                let room = self.room;
-                self.addr
-                    .send(server::ListUsers)
-                    .into_actor(self)
-                    .then(move |res, sess, _ctx| {
-                        match res {
-                            Ok(users) => {
-                                let filtered_users: Vec<_> = users
-                                    .iter()
-                                    .filter_map(|conversations| {
-                                        if conversations.conversation_id == room {
-                                            Some((
-                                                conversations.first_name.clone(),
-                                                conversations.last_name.clone(),
-                                            ))
-                                        } else {
-                                            None
-                                        }
-                                    })
-                                    .sorted()
-                                    .unique()
-                                    .collect();
-                                text.seen = Some(filtered_users);
-                                log!("TEXT RECEIVED {:?}", text);
-                                log!("ROOM {} ID {}", sess.room, sess.id);
-                                sess.addr.do_send(server::ClientMessage {
-                                    id: sess.id,
-                                    msg: serde_json::to_string_pretty(&text).unwrap(),
-                                    room: sess.room,
-                                });
-                            }
-                            _ => println!("Something is wrong"),
-                        }
-                        fut::ready(())
-                    })
-                    .wait(ctx);
-                // ctx.spawn(future);
+                // Receipts travel over the icon channel, the message goes out as it came in
+                self.addr.do_send(server::ClientMessage {
+                    id: self.id,
+                    msg: serde_json::to_string_pretty(&text).unwrap(),
+                    room: self.room,
+                });
             }
             ws::Message::Binary(_) => println!("Unexpected binary"),
             ws::Message::Close(reason) => {